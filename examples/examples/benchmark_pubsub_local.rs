@@ -40,7 +40,7 @@ async fn run_node(node_id: NodeId, seeds: Vec<NodeAddr>) -> (PubsubSdk, NodeAddr
     let transport = Box::new(UdpTransport::new(node_addr_builder.addr(), socket, secure));
     let timer = Arc::new(SystemTimer());
 
-    let router = SharedRouter::new(node_id);
+    let router = SharedRouter::new(node_id, Default::default());
     let manual = ManualBehavior::new(ManualBehaviorConf {
         node_id,
         node_addr: node_addr_builder.addr(),
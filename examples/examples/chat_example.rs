@@ -136,7 +136,7 @@ async fn main() {
 
     // The router is used to route messages to the correct node.
     // It keeps a routing table, which is updated when a node joins or leaves the network.
-    let router = SharedRouter::new(args.node_id);
+    let router = SharedRouter::new(args.node_id, Default::default());
 
     // Now we need to create a network plane, which is used to manage the network.
     // The network plane is composed of multiple services with behaviors, each of which is responsible for a specific task.
@@ -56,7 +56,7 @@ async fn main() {
     let node_addr = node_addr_builder.addr();
     log::info!("Listen on addr {}", node_addr);
 
-    let router = SharedRouter::new(args.node_id);
+    let router = SharedRouter::new(args.node_id, Default::default());
     let spreads_layer_router = LayersSpreadRouterSyncBehavior::new(router.clone());
     let key_value = KeyValueBehavior::<NodeHandleEvent, NodeSdkEvent>::new(args.node_id, 3000, None);
 
@@ -195,7 +195,7 @@ async fn main() {
     log::info!("Listen on addr {}", node_addr);
 
     let timer = Arc::new(SystemTimer());
-    let router = SharedRouter::new(args.node_id);
+    let router = SharedRouter::new(args.node_id, Default::default());
 
     let manual = ManualBehavior::new(ManualBehaviorConf {
         node_id: args.node_id,
@@ -8,9 +8,45 @@ pub use force_node::ForceNodeRouter;
 #[cfg(any(test, feature = "mock"))]
 use mockall::automock;
 
-/// ServiceMeta is using for determine which node will be routed, example node with lowest price or lowest latency, which for future use
+/// ServiceMeta is using for determine which node will be routed, example node with lowest price or lowest latency.
+/// It is encoded from [`ServiceSelector`], 0 is the nearest node.
 pub type ServiceMeta = u32;
 
+/// Which node is selected when sending to a service which is available on many nodes
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ServiceSelector {
+    /// Node with the best path
+    Nearest,
+    /// Node with the lowest advertised load, nearest if same load
+    LeastLoaded,
+    /// Nearest node inside the given tier
+    Tier(u8),
+    /// Spread over the given number of nearest nodes by their load, with power of two choices
+    Balanced(u8),
+}
+
+impl ServiceSelector {
+    /// Highest byte is the selector kind, lowest byte is the param
+    pub fn to_meta(&self) -> ServiceMeta {
+        match self {
+            ServiceSelector::Nearest => 0,
+            ServiceSelector::LeastLoaded => 1 << 24,
+            ServiceSelector::Tier(tier) => 2 << 24 | *tier as u32,
+            ServiceSelector::Balanced(top_n) => 3 << 24 | *top_n as u32,
+        }
+    }
+
+    /// Unknown kind is treated as Nearest, for compatibility with newer nodes
+    pub fn from_meta(meta: ServiceMeta) -> Self {
+        match meta >> 24 {
+            1 => ServiceSelector::LeastLoaded,
+            2 => ServiceSelector::Tier(meta as u8),
+            3 => ServiceSelector::Balanced(meta as u8),
+            _ => ServiceSelector::Nearest,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum RouteRule {
     Direct,
@@ -50,10 +86,29 @@ pub trait RouterTable: Send + Sync {
     fn register_service(&self, service_id: u8);
     /// Determine the next action for the given destination node
     fn path_to_node(&self, dest: NodeId) -> RouteAction;
+    /// Determine the next action for the given destination node, for a flow identified by (from_node, stream_id).
+    /// Routers which support multipath spread flows over near-equal paths, each flow sticks to one path.
+    fn path_to_node_flow(&self, dest: NodeId, _from_node: NodeId, _stream_id: u32) -> RouteAction {
+        self.path_to_node(dest)
+    }
     /// Determine the next action for the given key
     fn path_to_key(&self, key: NodeId) -> RouteAction;
     /// Determine the next action for the given service
     fn path_to_service(&self, service_id: u8) -> RouteAction;
+    /// Determine the next action for the given service, selecting the node with the service meta
+    fn path_to_service_meta(&self, service_id: u8, _meta: ServiceMeta) -> RouteAction {
+        self.path_to_service(service_id)
+    }
+    /// Determine the next action for a service message which is created by this node.
+    /// Routers can spread messages over many nodes here, forwarding nodes use path_to_service_meta.
+    fn path_to_service_anycast(&self, service_id: u8, meta: ServiceMeta) -> RouteAction {
+        self.path_to_service_meta(service_id, meta)
+    }
+    /// Select the destination node for a service message which is created by this node.
+    /// If a node is selected, the message is routed to that node instead of the service, so forwarding nodes don't change the selection.
+    fn service_node_anycast(&self, _service_id: u8, _meta: ServiceMeta) -> Option<NodeId> {
+        None
+    }
     /// Determine next action for incoming messages
     /// given the route rule and service id
     fn derive_action(&self, route: &RouteRule, service_id: u8) -> RouteAction {
@@ -61,7 +116,7 @@ pub trait RouterTable: Send + Sync {
             RouteRule::Direct => RouteAction::Local,
             RouteRule::ToNode(dest) => self.path_to_node(*dest),
             RouteRule::ToKey(key) => self.path_to_key(*key),
-            RouteRule::ToService(_) => self.path_to_service(service_id),
+            RouteRule::ToService(meta) => self.path_to_service_meta(service_id, *meta),
         }
     }
 }
@@ -103,6 +158,27 @@ mod tests {
         assert!(!reject.is_remote());
     }
 
+    #[test]
+    fn test_path_to_node_flow_default() {
+        let router = ForceNodeRouter(ConnId::from_in(1, 1), 2);
+        assert_eq!(router.path_to_node_flow(3, 1, 1), router.path_to_node(3));
+    }
+
+    #[test]
+    fn test_service_selector_meta() {
+        for selector in [
+            ServiceSelector::Nearest,
+            ServiceSelector::LeastLoaded,
+            ServiceSelector::Tier(0),
+            ServiceSelector::Tier(255),
+            ServiceSelector::Balanced(3),
+        ] {
+            assert_eq!(ServiceSelector::from_meta(selector.to_meta()), selector);
+        }
+        assert_eq!(ServiceSelector::from_meta(0), ServiceSelector::Nearest);
+        assert_eq!(ServiceSelector::from_meta(100 << 24), ServiceSelector::Nearest);
+    }
+
     #[test]
     fn test_derive_action_to_service() {
         let router = ForceLocalRouter();
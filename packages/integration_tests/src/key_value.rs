@@ -37,7 +37,7 @@ mod tests {
         let transport = Box::new(atm0s_sdn_transport_vnet::VnetTransport::new(vnet, node_addr.addr()));
         let timer = Arc::new(SystemTimer());
 
-        let router = SharedRouter::new(node_id);
+        let router = SharedRouter::new(node_id, Default::default());
         let manual = ManualBehavior::new(ManualBehaviorConf {
             node_id,
             node_addr: node_addr.addr(),
@@ -48,7 +48,8 @@ mod tests {
 
         let router_sync_behaviour = LayersSpreadRouterSyncBehavior::new(router.clone());
         let kv_sdk = KeyValueSdk::new();
-        let kv_behaviour = KeyValueBehavior::new(node_id, 3000, Some(Box::new(kv_sdk.clone())));
+        let mut kv_behaviour = KeyValueBehavior::new(node_id, 3000, Some(Box::new(kv_sdk.clone())));
+        kv_behaviour.set_router(Arc::new(router.clone()));
 
         let mut plane = NetworkPlane::<ImplBehaviorEvent, ImplHandlerEvent, ImplSdkEvent>::new(NetworkPlaneConfig {
             node_id,
@@ -35,7 +35,7 @@ mod test {
         let transport = Box::new(atm0s_sdn_transport_vnet::VnetTransport::new(vnet, node_addr.addr()));
         let timer = Arc::new(SystemTimer());
 
-        let router = SharedRouter::new(node_id);
+        let router = SharedRouter::new(node_id, Default::default());
         let manual = ManualBehavior::<HE, SE>::new(ManualBehaviorConf {
             node_id,
             node_addr: node_addr.addr(),
@@ -295,7 +295,9 @@ where
                     NetworkBehaviorAction::CloseNode(node) => {
                         self.bus.close_node(node);
                     }
-                    NetworkBehaviorAction::ToSdkService(_, _) => {}
+                    NetworkBehaviorAction::ToSdkService(to_service, msg) => {
+                        self.internal.on_sdk_msg(now_ms, service, to_service, msg).print_error("Should deliver sdk msg");
+                    }
                 },
             }
         }
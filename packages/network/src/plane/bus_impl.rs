@@ -159,9 +159,20 @@ where
         None
     }
 
-    fn to_net(&self, msg: TransportMsg) -> Option<()> {
+    fn to_net(&self, mut msg: TransportMsg) -> Option<()> {
         log::debug!("[PlaneBusImpl {}] send_to_net service: {} route: {:?}", self.node_id, msg.header.to_service_id, msg.header.route);
-        match self.router.derive_action(&msg.header.route, msg.header.to_service_id) {
+        let action = match msg.header.route {
+            RouteRule::ToNode(dest) => self.router.path_to_node_flow(dest, msg.header.from_node.unwrap_or(self.node_id), msg.header.stream_id),
+            RouteRule::ToService(meta) => match self.router.service_node_anycast(msg.header.to_service_id, meta) {
+                Some(dest) => {
+                    msg = TransportMsg::build_raw(msg.header.clone().set_route(RouteRule::ToNode(dest)), msg.payload());
+                    self.router.path_to_node_flow(dest, msg.header.from_node.unwrap_or(self.node_id), msg.header.stream_id)
+                }
+                None => self.router.path_to_service_anycast(msg.header.to_service_id, meta),
+            },
+            _ => self.router.derive_action(&msg.header.route, msg.header.to_service_id),
+        };
+        match action {
             RouteAction::Reject => {
                 log::warn!("[PlaneBusImpl {}] send_to_net reject {} {:?}", self.node_id, msg.header.to_service_id, msg.header.route);
                 None
@@ -203,7 +214,7 @@ where
 
     fn to_net_node(&self, node: NodeId, msg: TransportMsg) -> Option<()> {
         log::debug!("[PlaneBusImpl {}] send_to_net_node service: {} route: ToNode({})", self.node_id, msg.header.to_service_id, node);
-        match self.router.derive_action(&RouteRule::ToNode(node), msg.header.to_service_id) {
+        match self.router.path_to_node_flow(node, msg.header.from_node.unwrap_or(self.node_id), msg.header.stream_id) {
             RouteAction::Reject => {
                 log::warn!("[PlaneBusImpl {}] send_to_net reject {} ToNode({})", self.node_id, msg.header.to_service_id, node);
                 None
@@ -242,7 +253,7 @@ where
 #[cfg(test)]
 mod tests {
     use crate::{
-        msg::TransportMsg,
+        msg::{MsgHeader, TransportMsg},
         plane::{
             bus::{HandleEvent, HandlerRoute, PlaneBus},
             bus_impl::PlaneBusImpl,
@@ -355,7 +366,8 @@ mod tests {
         let local_node_id = 1;
         let (plane_tx, _plane_rx) = unbounded();
         let mut mock_router = MockRouterTable::new();
-        mock_router.expect_derive_action().returning(|_, _| RouteAction::Reject);
+        mock_router.expect_service_node_anycast().returning(|_, _| None);
+        mock_router.expect_path_to_service_anycast().returning(|_, _| RouteAction::Reject);
         let router = Arc::new(mock_router);
 
         let bus = PlaneBusImpl::<BE, HE>::new(local_node_id, router, plane_tx);
@@ -368,7 +380,8 @@ mod tests {
         let local_node_id = 1;
         let (plane_tx, plane_rx) = unbounded();
         let mut mock_router = MockRouterTable::new();
-        mock_router.expect_derive_action().returning(|_, _| RouteAction::Local);
+        mock_router.expect_service_node_anycast().returning(|_, _| None);
+        mock_router.expect_path_to_service_anycast().returning(|_, _| RouteAction::Local);
         let router = Arc::new(mock_router);
 
         let bus = PlaneBusImpl::<BE, HE>::new(local_node_id, router, plane_tx);
@@ -391,7 +404,11 @@ mod tests {
         let local_node_id = 1;
         let (plane_tx, plane_rx) = unbounded();
         let mut mock_router = MockRouterTable::new();
-        mock_router.expect_derive_action().returning(|_, _| RouteAction::Next(ConnId::from_in(1, 1), 2u32));
+        mock_router.expect_service_node_anycast().returning(|_, _| None);
+        mock_router
+            .expect_path_to_service_anycast()
+            .withf(|service_id, meta| *service_id == 1 && *meta == 2)
+            .returning(|_, _| RouteAction::Next(ConnId::from_in(1, 1), 2u32));
         let router = Arc::new(mock_router);
 
         let bus = PlaneBusImpl::<BE, HE>::new(local_node_id, router, plane_tx);
@@ -405,12 +422,60 @@ mod tests {
         assert!(bus.to_net(TransportMsg::build(1, 1, RouteRule::ToService(2), 0, 1, &[1u8])).is_some());
     }
 
+    #[async_std::test]
+    async fn to_net_to_node_should_use_flow() {
+        let local_node_id = 1;
+        let (plane_tx, _plane_rx) = unbounded();
+        let mut mock_router = MockRouterTable::new();
+        mock_router
+            .expect_path_to_node_flow()
+            .withf(|dest, from_node, stream_id| *dest == 3 && *from_node == 5 && *stream_id == 7)
+            .returning(|_, _, _| RouteAction::Next(ConnId::from_in(1, 1), 2u32));
+        let router = Arc::new(mock_router);
+
+        let bus = PlaneBusImpl::<BE, HE>::new(local_node_id, router, plane_tx);
+
+        let sender = create_mock_connection(ConnId::from_in(1, 1), 2u32, NodeAddr::empty(2));
+        let _rx = bus.add_conn(Arc::new(sender)).expect("Should have rx");
+
+        let header = MsgHeader::build(1, 1, RouteRule::ToNode(3)).set_stream_id(7).set_from_node(Some(5));
+        assert!(bus.to_net(TransportMsg::build_raw(header, &[1u8])).is_some());
+    }
+
+    #[async_std::test]
+    async fn to_net_service_should_route_to_selected_node() {
+        let local_node_id = 1;
+        let (plane_tx, plane_rx) = unbounded();
+        let mut mock_router = MockRouterTable::new();
+        mock_router
+            .expect_service_node_anycast()
+            .withf(|service_id, meta| *service_id == 1 && *meta == 2)
+            .returning(|_, _| Some(3));
+        mock_router
+            .expect_path_to_node_flow()
+            .withf(|dest, from_node, _| *dest == 3 && *from_node == 1)
+            .returning(|_, _, _| RouteAction::Local);
+        let router = Arc::new(mock_router);
+
+        let bus = PlaneBusImpl::<BE, HE>::new(local_node_id, router, plane_tx);
+
+        assert!(bus.to_net(TransportMsg::build(1, 1, RouteRule::ToService(2), 0, 1, &[1u8])).is_some());
+        //route is rewritten, so forwarding nodes don't select other service node
+        assert_eq!(
+            plane_rx.try_recv(),
+            Ok(NetworkPlaneInternalEvent::ToBehaviourLocalMsg {
+                service_id: 1,
+                msg: TransportMsg::build(1, 1, RouteRule::ToNode(3), 0, 1, &[1u8]),
+            })
+        );
+    }
+
     #[async_std::test]
     async fn to_net_node_should_process_local() {
         let local_node_id = 1;
         let (plane_tx, plane_rx) = unbounded();
         let mut mock_router = MockRouterTable::new();
-        mock_router.expect_derive_action().returning(|_, _| RouteAction::Local);
+        mock_router.expect_path_to_node_flow().returning(|_, _, _| RouteAction::Local);
         let router = Arc::new(mock_router);
 
         let bus = PlaneBusImpl::<BE, HE>::new(local_node_id, router, plane_tx);
@@ -433,7 +498,10 @@ mod tests {
         let local_node_id = 1;
         let (plane_tx, plane_rx) = unbounded();
         let mut mock_router = MockRouterTable::new();
-        mock_router.expect_derive_action().returning(|_, _| RouteAction::Next(ConnId::from_in(1, 1), 2u32));
+        mock_router
+            .expect_path_to_node_flow()
+            .withf(|dest, from_node, stream_id| *dest == 2 && *from_node == 1 && *stream_id == 1)
+            .returning(|_, _, _| RouteAction::Next(ConnId::from_in(1, 1), 2u32));
         let router = Arc::new(mock_router);
 
         let bus = PlaneBusImpl::<BE, HE>::new(local_node_id, router, plane_tx);
@@ -10,6 +10,9 @@ use crate::{
 
 use super::NetworkPlaneInternalEvent;
 
+/// Max rounds of sdk msgs delivery in one call, for avoiding behaviors which keep sending msgs to each other spin forever
+const MAX_SDK_ROUNDS: usize = 16;
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum PlaneInternalError {
     InvalidServiceId(u8),
@@ -63,6 +66,8 @@ pub struct PlaneInternal<BE, HE, SE> {
     action_queue: VecDeque<PlaneInternalAction<BE, HE, SE>>,
     /// Represents the list of behaviors.
     behaviors: Vec<Option<(Box<dyn NetworkBehavior<BE, HE, SE> + Send + Sync>, BehaviorContext)>>,
+    /// Sdk messages which are left after MAX_SDK_ROUNDS, delivered in the next call.
+    pending_sdk_msgs: VecDeque<(u8, u8, SE)>,
 }
 
 impl<BE, HE, SE> PlaneInternal<BE, HE, SE> {
@@ -82,6 +87,7 @@ impl<BE, HE, SE> PlaneInternal<BE, HE, SE> {
             node_id,
             action_queue: Default::default(),
             behaviors,
+            pending_sdk_msgs: Default::default(),
         }
     }
 
@@ -298,31 +304,75 @@ impl<BE, HE, SE> PlaneInternal<BE, HE, SE> {
         self.action_queue.pop_front()
     }
 
+    /// Delivers a message from one behavior to the sdk of another behavior on the same node.
+    ///
+    /// # Arguments
+    ///
+    /// * `now_ms` - The current time in milliseconds.
+    /// * `from_service` - The service ID of the sending behavior.
+    /// * `to_service` - The service ID of the target behavior.
+    /// * `msg` - The sdk message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no behavior is registered for `to_service`.
+    pub fn on_sdk_msg(&mut self, now_ms: u64, from_service: u8, to_service: u8, msg: SE) -> Result<(), PlaneInternalError> {
+        self.deliver_sdk_msg(now_ms, from_service, to_service, msg)?;
+        self.pop_behaviours_action(now_ms);
+        Ok(())
+    }
+
+    fn deliver_sdk_msg(&mut self, now_ms: u64, from_service: u8, to_service: u8, msg: SE) -> Result<(), PlaneInternalError> {
+        log::debug!("[NetworkPlane {}] deliver sdk msg from service {} to service {}", self.node_id, from_service, to_service);
+        if let Some((to_behaviour, to_context)) = &mut self.behaviors[to_service as usize] {
+            to_behaviour.on_sdk_msg(to_context, now_ms, from_service, msg);
+            Ok(())
+        } else {
+            log::warn!("[NetworkPlane {}] drop sdk msg from service {} to unknown service {}", self.node_id, from_service, to_service);
+            Err(PlaneInternalError::InvalidServiceId(to_service))
+        }
+    }
+
     /// Pops and processes the actions from the behaviors action queue.
     ///
+    /// Sdk messages are delivered in the order they were emitted. Actions produced by a behavior while
+    /// handling an sdk message are popped in the next round, so request-response flows between
+    /// services complete within a single call. At most MAX_SDK_ROUNDS rounds are processed per call, so behaviors
+    /// which keep sending messages to each other can not block the plane; the rest is delivered in the next call.
+    ///
     /// # Arguments
     ///
     /// * `now_ms` - The current time in milliseconds.
     fn pop_behaviours_action(&mut self, now_ms: u64) {
-        let mut sdk_msgs = vec![];
-        for (behaviour, context) in self.behaviors.iter_mut().flatten() {
-            while let Some(action) = behaviour.pop_action() {
-                match action {
-                    NetworkBehaviorAction::ToSdkService(service, msg) => {
-                        sdk_msgs.push((context.service_id, service, msg));
-                    }
-                    _ => {
-                        self.action_queue.push_back(PlaneInternalAction::BehaviorAction(context.service_id, action));
+        let mut sdk_msgs = std::mem::take(&mut self.pending_sdk_msgs);
+        let mut rounds = 0;
+        loop {
+            for (behaviour, context) in self.behaviors.iter_mut().flatten() {
+                while let Some(action) = behaviour.pop_action() {
+                    match action {
+                        NetworkBehaviorAction::ToSdkService(service, msg) => {
+                            sdk_msgs.push_back((context.service_id, service, msg));
+                        }
+                        _ => {
+                            self.action_queue.push_back(PlaneInternalAction::BehaviorAction(context.service_id, action));
+                        }
                     }
                 }
             }
-        }
-        for (from, to, msg) in sdk_msgs {
-            log::debug!("[NetworkPlane {}] received NetworkBehaviorAction::ToSdkService service: {}", self.node_id, from);
-            if let Some((to_behaviour, to_context)) = &mut self.behaviors[to as usize] {
-                to_behaviour.on_sdk_msg(to_context, now_ms, from, msg);
-            } else {
-                debug_assert!(false, "service not found {}", to);
+
+            if sdk_msgs.is_empty() {
+                break;
+            }
+
+            if rounds >= MAX_SDK_ROUNDS {
+                log::warn!("[NetworkPlane {}] too many sdk msg rounds, delay {} msgs to next call", self.node_id, sdk_msgs.len());
+                self.pending_sdk_msgs = sdk_msgs;
+                break;
+            }
+            rounds += 1;
+
+            while let Some((from, to, msg)) = sdk_msgs.pop_front() {
+                let _ = self.deliver_sdk_msg(now_ms, from, to, msg);
             }
         }
     }
@@ -330,10 +380,11 @@ impl<BE, HE, SE> PlaneInternal<BE, HE, SE> {
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{collections::VecDeque, sync::Arc};
 
     use atm0s_sdn_identity::{ConnId, NodeId};
     use atm0s_sdn_utils::awaker::{Awaker, MockAwaker};
+    use parking_lot::Mutex;
 
     use crate::{
         behaviour::MockNetworkBehavior,
@@ -472,6 +523,143 @@ mod tests {
         assert_eq!(internal.pop_action(), None,);
     }
 
+    #[test]
+    fn should_deliver_sdk_msgs_in_order() {
+        let mut mb1_actions: Vec<super::NetworkBehaviorAction<HE, u8>> = vec![
+            super::NetworkBehaviorAction::ToSdkService(2, 3),
+            super::NetworkBehaviorAction::ToSdkService(2, 2),
+            super::NetworkBehaviorAction::ToSdkService(2, 1),
+        ];
+        let received = Arc::new(Mutex::new(vec![]));
+
+        let mut mock_behavior_1 = Box::new(MockNetworkBehavior::<BE, HE, u8>::new());
+        mock_behavior_1.expect_service_id().return_const(1);
+        mock_behavior_1.expect_pop_action().returning(move || mb1_actions.pop());
+        mock_behavior_1.expect_on_sdk_msg().never();
+        let mock_awaker_1: Arc<dyn Awaker> = Arc::new(MockAwaker::default());
+
+        let mut mock_behavior_2 = Box::new(MockNetworkBehavior::<BE, HE, u8>::new());
+        mock_behavior_2.expect_service_id().return_const(2);
+        mock_behavior_2.expect_pop_action().returning(|| None);
+        let received_c = received.clone();
+        mock_behavior_2.expect_on_sdk_msg().times(3).returning(move |_, _, from, msg| received_c.lock().push((from, msg)));
+        let mock_awaker_2: Arc<dyn Awaker> = Arc::new(MockAwaker::default());
+
+        let mut internal = super::PlaneInternal::new(1, vec![(mock_behavior_1, mock_awaker_1.clone()), (mock_behavior_2, mock_awaker_2.clone())]);
+
+        internal.pop_behaviours_action(0);
+
+        assert_eq!(*received.lock(), vec![(1, 1), (1, 2), (1, 3)]);
+        assert_eq!(internal.pop_action(), None);
+    }
+
+    #[test]
+    fn should_pop_actions_produced_by_sdk_msg() {
+        let mb1_actions = Arc::new(Mutex::new(VecDeque::<super::NetworkBehaviorAction<HE, SE>>::new()));
+        let mb2_actions = Arc::new(Mutex::new(VecDeque::<super::NetworkBehaviorAction<HE, SE>>::new()));
+        mb1_actions.lock().push_back(super::NetworkBehaviorAction::ToSdkService(2, ()));
+
+        let mut mock_behavior_1 = Box::new(MockNetworkBehavior::<BE, HE, SE>::new());
+        mock_behavior_1.expect_service_id().return_const(1);
+        let mb1_actions_c = mb1_actions.clone();
+        mock_behavior_1.expect_pop_action().returning(move || mb1_actions_c.lock().pop_front());
+        mock_behavior_1.expect_on_sdk_msg().once().withf(|_, _, from, _| *from == 2).return_const(());
+        let mock_awaker_1: Arc<dyn Awaker> = Arc::new(MockAwaker::default());
+
+        let mut mock_behavior_2 = Box::new(MockNetworkBehavior::<BE, HE, SE>::new());
+        mock_behavior_2.expect_service_id().return_const(2);
+        let mb2_actions_c = mb2_actions.clone();
+        mock_behavior_2.expect_pop_action().returning(move || mb2_actions_c.lock().pop_front());
+        let mb2_actions_c = mb2_actions.clone();
+        mock_behavior_2.expect_on_sdk_msg().once().returning(move |_, _, from, _| {
+            assert_eq!(from, 1);
+            let mut actions = mb2_actions_c.lock();
+            actions.push_back(super::NetworkBehaviorAction::ToSdkService(1, ()));
+            actions.push_back(super::NetworkBehaviorAction::CloseNode(2));
+        });
+        let mock_awaker_2: Arc<dyn Awaker> = Arc::new(MockAwaker::default());
+
+        let mut internal = super::PlaneInternal::new(1, vec![(mock_behavior_1, mock_awaker_1.clone()), (mock_behavior_2, mock_awaker_2.clone())]);
+
+        internal.pop_behaviours_action(0);
+
+        assert_eq!(internal.pop_action(), Some(super::PlaneInternalAction::BehaviorAction(2, super::NetworkBehaviorAction::CloseNode(2))));
+        assert_eq!(internal.pop_action(), None);
+    }
+
+    #[test]
+    fn should_limit_sdk_msg_rounds() {
+        let received_1 = Arc::new(Mutex::new(0));
+        let received_2 = Arc::new(Mutex::new(0));
+        let mb1_actions = Arc::new(Mutex::new(VecDeque::<super::NetworkBehaviorAction<HE, SE>>::new()));
+        let mb2_actions = Arc::new(Mutex::new(VecDeque::<super::NetworkBehaviorAction<HE, SE>>::new()));
+        mb1_actions.lock().push_back(super::NetworkBehaviorAction::ToSdkService(2, ()));
+
+        // two behaviors which always answer each other
+        let mut mock_behavior_1 = Box::new(MockNetworkBehavior::<BE, HE, SE>::new());
+        mock_behavior_1.expect_service_id().return_const(1);
+        let mb1_actions_c = mb1_actions.clone();
+        mock_behavior_1.expect_pop_action().returning(move || mb1_actions_c.lock().pop_front());
+        let (mb1_actions_c, received_c) = (mb1_actions.clone(), received_1.clone());
+        mock_behavior_1.expect_on_sdk_msg().returning(move |_, _, _, _| {
+            *received_c.lock() += 1;
+            mb1_actions_c.lock().push_back(super::NetworkBehaviorAction::ToSdkService(2, ()));
+        });
+        let mock_awaker_1: Arc<dyn Awaker> = Arc::new(MockAwaker::default());
+
+        let mut mock_behavior_2 = Box::new(MockNetworkBehavior::<BE, HE, SE>::new());
+        mock_behavior_2.expect_service_id().return_const(2);
+        let mb2_actions_c = mb2_actions.clone();
+        mock_behavior_2.expect_pop_action().returning(move || mb2_actions_c.lock().pop_front());
+        let (mb2_actions_c, received_c) = (mb2_actions.clone(), received_2.clone());
+        mock_behavior_2.expect_on_sdk_msg().returning(move |_, _, _, _| {
+            *received_c.lock() += 1;
+            mb2_actions_c.lock().push_back(super::NetworkBehaviorAction::ToSdkService(1, ()));
+        });
+        let mock_awaker_2: Arc<dyn Awaker> = Arc::new(MockAwaker::default());
+
+        let mut internal = super::PlaneInternal::new(1, vec![(mock_behavior_1, mock_awaker_1.clone()), (mock_behavior_2, mock_awaker_2.clone())]);
+
+        internal.pop_behaviours_action(0);
+        assert_eq!(*received_1.lock() + *received_2.lock(), super::MAX_SDK_ROUNDS);
+        assert_eq!(internal.pending_sdk_msgs.len(), 1);
+
+        // pending msgs are continued in the next call
+        internal.pop_behaviours_action(0);
+        assert_eq!(*received_1.lock() + *received_2.lock(), 2 * super::MAX_SDK_ROUNDS);
+    }
+
+    #[test]
+    fn should_report_sdk_msg_to_unknown_service() {
+        let mut mb1_actions: Vec<super::NetworkBehaviorAction<HE, SE>> = vec![super::NetworkBehaviorAction::ToSdkService(3, ())];
+
+        let mut mock_behavior_1 = Box::new(MockNetworkBehavior::<BE, HE, SE>::new());
+        mock_behavior_1.expect_service_id().return_const(1);
+        mock_behavior_1.expect_pop_action().returning(move || mb1_actions.pop());
+        mock_behavior_1.expect_on_sdk_msg().never();
+        let mock_awaker_1: Arc<dyn Awaker> = Arc::new(MockAwaker::default());
+
+        let mut internal = super::PlaneInternal::new(1, vec![(mock_behavior_1, mock_awaker_1.clone())]);
+
+        internal.pop_behaviours_action(0);
+        assert_eq!(internal.pop_action(), None);
+
+        assert_eq!(internal.on_sdk_msg(0, 1, 3, ()), Err(super::PlaneInternalError::InvalidServiceId(3)));
+    }
+
+    #[test]
+    fn should_deliver_sdk_msg_from_plane() {
+        let mut mock_behavior_1 = Box::new(MockNetworkBehavior::<BE, HE, SE>::new());
+        mock_behavior_1.expect_service_id().return_const(1);
+        mock_behavior_1.expect_pop_action().returning(|| None);
+        mock_behavior_1.expect_on_sdk_msg().once().withf(|_, _, from, _| *from == 2).return_const(());
+        let mock_awaker_1: Arc<dyn Awaker> = Arc::new(MockAwaker::default());
+
+        let mut internal = super::PlaneInternal::new(1, vec![(mock_behavior_1, mock_awaker_1.clone())]);
+
+        assert_eq!(internal.on_sdk_msg(0, 2, 1, ()), Ok(()));
+    }
+
     #[test]
     fn should_pop_normal_behaviors_actions() {
         let mut mb1_actions: Vec<super::NetworkBehaviorAction<HE, SE>> = vec![super::NetworkBehaviorAction::CloseNode(1)];
@@ -3,11 +3,29 @@ use std::fmt::Debug;
 use atm0s_sdn_identity::NodeId;
 use serde::Serialize;
 
+mod ed25519;
 mod static_key;
 
 pub trait DataSecure: Send + Sync {
     fn sign_msg(&self, remote_node_id: NodeId, data: &[u8]) -> Vec<u8>;
     fn verify_msg(&self, remote_node_id: NodeId, data: &[u8], signature: &[u8]) -> bool;
+    /// Verify a message which is expected to be signed by `signer_node_id`.
+    /// Implementations without per-node identity cannot check the signer and fall back to `verify_msg`.
+    fn verify_msg_from(&self, signer_node_id: NodeId, remote_node_id: NodeId, data: &[u8], signature: &[u8]) -> bool {
+        let _ = signer_node_id;
+        self.verify_msg(remote_node_id, data, signature)
+    }
+    /// Noise static private key derived from the node identity, used by transports for the authenticated Noise_XX handshake.
+    /// Returns None if there is no per-node identity, then transports use the legacy Noise_NN handshake.
+    fn handshake_static_key(&self) -> Option<[u8; 32]> {
+        None
+    }
+    /// Check that `static_key` is the Noise static public key of `node_id`.
+    /// `proof` is a signature which was produced by that node, and verified with `verify_msg_from`.
+    fn verify_handshake_static_key(&self, node_id: NodeId, static_key: &[u8], proof: &[u8]) -> bool {
+        let _ = (node_id, static_key, proof);
+        false
+    }
 }
 
 pub struct ObjectSecure;
@@ -21,6 +39,12 @@ impl ObjectSecure {
         log::info!("verify obj {:?} for remote {}", obj, remote_node_id);
         secure.verify_msg(remote_node_id, &bincode::serialize(obj).expect("Shoukd serialize obj"), signature)
     }
+    pub fn verify_obj_from<T: Debug + Serialize>(secure: &dyn DataSecure, signer_node_id: NodeId, remote_node_id: NodeId, obj: &T, signature: &[u8]) -> bool {
+        log::info!("verify obj {:?} from {} for remote {}", obj, signer_node_id, remote_node_id);
+        secure.verify_msg_from(signer_node_id, remote_node_id, &bincode::serialize(obj).expect("Shoukd serialize obj"), signature)
+    }
 }
 
+pub use ed25519::{ClusterCa, Ed25519Secure, Ed25519SecureError, NodeCertificate, RevocationList};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
 pub use static_key::StaticKeySecure;
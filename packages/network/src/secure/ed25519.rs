@@ -0,0 +1,327 @@
+use std::{collections::HashSet, sync::Arc};
+
+use atm0s_sdn_identity::NodeId;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::DataSecure;
+
+const CERT_DOMAIN: &[u8] = b"atm0s-sdn-node-cert";
+
+/// A certificate which binds a node id to the node's Ed25519 public key, signed by the cluster CA.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeCertificate {
+    pub node_id: NodeId,
+    pub public_key: [u8; 32],
+    pub signature: Vec<u8>,
+}
+
+impl NodeCertificate {
+    fn signed_payload(node_id: NodeId, public_key: &[u8; 32]) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(CERT_DOMAIN.len() + 4 + 32);
+        payload.extend_from_slice(CERT_DOMAIN);
+        payload.extend_from_slice(&node_id.to_be_bytes());
+        payload.extend_from_slice(public_key);
+        payload
+    }
+
+    /// Check that this certificate is signed by the given cluster CA key.
+    pub fn verify(&self, ca_public_key: &VerifyingKey) -> bool {
+        let signature = match Signature::from_slice(&self.signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        ca_public_key.verify_strict(&Self::signed_payload(self.node_id, &self.public_key), &signature).is_ok()
+    }
+}
+
+/// The cluster certificate authority, which issues node certificates.
+/// Only the tooling which provisions nodes should hold this key.
+pub struct ClusterCa {
+    key: SigningKey,
+}
+
+impl ClusterCa {
+    pub fn new(key: SigningKey) -> Self {
+        Self { key }
+    }
+
+    pub fn generate() -> Self {
+        Self::new(SigningKey::generate(&mut rand::rngs::OsRng))
+    }
+
+    pub fn public_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    pub fn issue(&self, node_id: NodeId, node_public_key: &VerifyingKey) -> NodeCertificate {
+        let public_key = node_public_key.to_bytes();
+        let signature = self.key.sign(&NodeCertificate::signed_payload(node_id, &public_key));
+        NodeCertificate {
+            node_id,
+            public_key,
+            signature: signature.to_bytes().to_vec(),
+        }
+    }
+}
+
+/// Revocation list shared by all transports of a node, can be updated at runtime.
+///
+/// A node is accepted if it is not denied and, when an allow list is configured, it is in the allow list.
+#[derive(Default)]
+pub struct RevocationList {
+    allow: RwLock<Option<HashSet<NodeId>>>,
+    deny: RwLock<HashSet<NodeId>>,
+}
+
+impl RevocationList {
+    /// Only accept the given nodes, in addition to the deny list.
+    pub fn set_allow_list(&self, nodes: Option<HashSet<NodeId>>) {
+        *self.allow.write() = nodes;
+    }
+
+    pub fn deny(&self, node_id: NodeId) {
+        self.deny.write().insert(node_id);
+    }
+
+    pub fn undeny(&self, node_id: NodeId) {
+        self.deny.write().remove(&node_id);
+    }
+
+    pub fn is_accepted(&self, node_id: NodeId) -> bool {
+        if self.deny.read().contains(&node_id) {
+            return false;
+        }
+        match &*self.allow.read() {
+            Some(allow) => allow.contains(&node_id),
+            None => true,
+        }
+    }
+}
+
+#[derive(PartialEq, Error, Clone, Debug)]
+pub enum Ed25519SecureError {
+    #[error("Certificate does not match node key")]
+    CertificateMismatch,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedEnvelope {
+    cert: NodeCertificate,
+    signature: Vec<u8>,
+}
+
+/// DataSecure which signs with a per-node Ed25519 key.
+/// Each signature carries the signer certificate, so the receiver only needs the cluster CA public key.
+///
+/// The Noise static key for transport handshakes is the X25519 form of the same key,
+/// so the encrypted channel is bound to the node certificate.
+pub struct Ed25519Secure {
+    key: SigningKey,
+    cert: NodeCertificate,
+    ca_public_key: VerifyingKey,
+    revocation: Arc<RevocationList>,
+    legacy_handshake: bool,
+}
+
+impl Ed25519Secure {
+    /// Return error if the certificate is issued for other key, because other nodes would reject all signatures of this node.
+    pub fn new(key: SigningKey, cert: NodeCertificate, ca_public_key: VerifyingKey, revocation: Arc<RevocationList>) -> Result<Self, Ed25519SecureError> {
+        if cert.public_key != key.verifying_key().to_bytes() {
+            return Err(Ed25519SecureError::CertificateMismatch);
+        }
+        Ok(Self {
+            key,
+            cert,
+            ca_public_key,
+            revocation,
+            legacy_handshake: false,
+        })
+    }
+
+    /// Use the legacy unauthenticated Noise_NN transport handshake, for clusters which still have nodes without it.
+    pub fn with_legacy_handshake(mut self) -> Self {
+        self.legacy_handshake = true;
+        self
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.cert.node_id
+    }
+
+    fn signed_payload(remote_node_id: NodeId, data: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(data.len() + 4);
+        payload.extend_from_slice(data);
+        payload.extend_from_slice(&remote_node_id.to_be_bytes());
+        payload
+    }
+
+    /// Decode the envelope and check the signer certificate.
+    fn open_envelope(&self, signature: &[u8]) -> Option<SignedEnvelope> {
+        let envelope: SignedEnvelope = bincode::deserialize(signature).ok()?;
+        if !envelope.cert.verify(&self.ca_public_key) {
+            log::warn!("[Ed25519Secure] certificate of node {} is not signed by cluster CA", envelope.cert.node_id);
+            return None;
+        }
+        if !self.revocation.is_accepted(envelope.cert.node_id) {
+            log::warn!("[Ed25519Secure] node {} is revoked", envelope.cert.node_id);
+            return None;
+        }
+        Some(envelope)
+    }
+
+    /// Verify and return the signer node id.
+    fn verify_envelope(&self, remote_node_id: NodeId, data: &[u8], signature: &[u8]) -> Option<NodeId> {
+        let envelope = self.open_envelope(signature)?;
+        let signer_key = VerifyingKey::from_bytes(&envelope.cert.public_key).ok()?;
+        let signature = Signature::from_slice(&envelope.signature).ok()?;
+        signer_key.verify_strict(&Self::signed_payload(remote_node_id, data), &signature).ok()?;
+        Some(envelope.cert.node_id)
+    }
+}
+
+impl DataSecure for Ed25519Secure {
+    fn sign_msg(&self, remote_node_id: NodeId, data: &[u8]) -> Vec<u8> {
+        let signature = self.key.sign(&Self::signed_payload(remote_node_id, data));
+        let envelope = SignedEnvelope {
+            cert: self.cert.clone(),
+            signature: signature.to_bytes().to_vec(),
+        };
+        bincode::serialize(&envelope).expect("Should serialize signed envelope")
+    }
+
+    fn verify_msg(&self, remote_node_id: NodeId, data: &[u8], signature: &[u8]) -> bool {
+        self.verify_envelope(remote_node_id, data, signature).is_some()
+    }
+
+    fn verify_msg_from(&self, signer_node_id: NodeId, remote_node_id: NodeId, data: &[u8], signature: &[u8]) -> bool {
+        self.verify_envelope(remote_node_id, data, signature) == Some(signer_node_id)
+    }
+
+    fn handshake_static_key(&self) -> Option<[u8; 32]> {
+        if self.legacy_handshake {
+            None
+        } else {
+            Some(self.key.to_scalar_bytes())
+        }
+    }
+
+    fn verify_handshake_static_key(&self, node_id: NodeId, static_key: &[u8], proof: &[u8]) -> bool {
+        let envelope = match self.open_envelope(proof) {
+            Some(envelope) => envelope,
+            None => return false,
+        };
+        if envelope.cert.node_id != node_id {
+            return false;
+        }
+        match VerifyingKey::from_bytes(&envelope.cert.public_key) {
+            Ok(key) => key.to_montgomery().as_bytes() == static_key,
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_node(ca: &ClusterCa, node_id: NodeId, revocation: Arc<RevocationList>) -> Ed25519Secure {
+        let key = SigningKey::generate(&mut rand::rngs::OsRng);
+        let cert = ca.issue(node_id, &key.verifying_key());
+        Ed25519Secure::new(key, cert, ca.public_key(), revocation).expect("Should create")
+    }
+
+    #[test]
+    fn test_reject_mismatch_certificate() {
+        let ca = ClusterCa::generate();
+        let key = SigningKey::generate(&mut rand::rngs::OsRng);
+        let other_key = SigningKey::generate(&mut rand::rngs::OsRng);
+        let cert = ca.issue(1, &other_key.verifying_key());
+        assert_eq!(Ed25519Secure::new(key, cert, ca.public_key(), Default::default()).err(), Some(Ed25519SecureError::CertificateMismatch));
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let ca = ClusterCa::generate();
+        let node1 = build_node(&ca, 1, Default::default());
+        let node2 = build_node(&ca, 2, Default::default());
+        let data = b"Hello World";
+        let signature = node1.sign_msg(2, data);
+        assert!(node2.verify_msg(2, data, &signature));
+        assert!(node2.verify_msg_from(1, 2, data, &signature));
+        assert!(!node2.verify_msg_from(3, 2, data, &signature));
+        assert!(!node2.verify_msg(3, data, &signature));
+        assert!(!node2.verify_msg(2, b"Hello World 2", &signature));
+    }
+
+    #[test]
+    fn test_reject_other_ca() {
+        let ca = ClusterCa::generate();
+        let other_ca = ClusterCa::generate();
+        let node1 = build_node(&other_ca, 1, Default::default());
+        let node2 = build_node(&ca, 2, Default::default());
+        let data = b"Hello World";
+        let signature = node1.sign_msg(2, data);
+        assert!(!node2.verify_msg(2, data, &signature));
+    }
+
+    #[test]
+    fn test_reject_forged_cert() {
+        let ca = ClusterCa::generate();
+        let node1 = build_node(&ca, 1, Default::default());
+        let node2 = build_node(&ca, 2, Default::default());
+        let data = b"Hello World";
+        let mut envelope: SignedEnvelope = bincode::deserialize(&node1.sign_msg(2, data)).expect("");
+        envelope.cert.node_id = 3;
+        let signature = bincode::serialize(&envelope).expect("");
+        assert!(!node2.verify_msg(2, data, &signature));
+        assert!(!node2.verify_msg(2, data, b"invalid"));
+    }
+
+    #[test]
+    fn test_handshake_static_key() {
+        let ca = ClusterCa::generate();
+        let node1 = build_node(&ca, 1, Default::default());
+        let node2 = build_node(&ca, 2, Default::default());
+        let proof = node1.sign_msg(2, b"Hello World");
+
+        let static_key = node1.handshake_static_key().expect("Should have static key");
+        let static_public = x25519_public(&static_key);
+        assert_eq!(static_public, node1.key.verifying_key().to_montgomery().to_bytes());
+        assert!(node2.verify_handshake_static_key(1, &static_public, &proof));
+        assert!(!node2.verify_handshake_static_key(3, &static_public, &proof));
+
+        let other_public = x25519_public(&node2.handshake_static_key().expect("Should have static key"));
+        assert!(!node2.verify_handshake_static_key(1, &other_public, &proof));
+
+        assert_eq!(node1.with_legacy_handshake().handshake_static_key(), None);
+    }
+
+    fn x25519_public(private_key: &[u8; 32]) -> [u8; 32] {
+        curve25519_dalek::MontgomeryPoint::mul_base_clamped(*private_key).to_bytes()
+    }
+
+    #[test]
+    fn test_revocation_list() {
+        let ca = ClusterCa::generate();
+        let revocation = Arc::new(RevocationList::default());
+        let node1 = build_node(&ca, 1, Default::default());
+        let node2 = build_node(&ca, 2, revocation.clone());
+        let data = b"Hello World";
+        let signature = node1.sign_msg(2, data);
+
+        revocation.deny(1);
+        assert!(!node2.verify_msg(2, data, &signature));
+        revocation.undeny(1);
+        assert!(node2.verify_msg(2, data, &signature));
+
+        revocation.set_allow_list(Some(HashSet::from([3])));
+        assert!(!node2.verify_msg(2, data, &signature));
+        revocation.set_allow_list(Some(HashSet::from([1, 3])));
+        assert!(node2.verify_msg(2, data, &signature));
+        revocation.set_allow_list(None);
+        assert!(node2.verify_msg(2, data, &signature));
+    }
+}
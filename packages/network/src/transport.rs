@@ -8,6 +8,10 @@ use thiserror::Error;
 #[cfg(test)]
 use mockall::automock;
 
+mod stats;
+
+pub use stats::{ConnectionStatsEstimator, OverUseDetector, SeqLossDetector, TrafficCounter, DEFAULT_SEND_EST_KBPS};
+
 /// Enum representing events that can occur in the transport layer.
 pub enum TransportEvent {
     /// `IncomingRequest` represents an incoming request from a node with the given `NodeId` and `ConnId`,
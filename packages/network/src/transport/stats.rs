@@ -0,0 +1,268 @@
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+};
+
+use super::ConnectionStats;
+
+/// Initial and maximum send bandwidth estimate, probing with ping can not prove a link is faster than this.
+pub const DEFAULT_SEND_EST_KBPS: u32 = 100000;
+const MIN_SEND_EST_KBPS: u32 = 50;
+const OVER_USE_WINDOW: usize = 10;
+const OVER_USE_SMOOTHING: f64 = 0.6;
+const OVER_USE_THRESHOLD_MS: f64 = 12.5;
+const OVER_USE_MIN_COUNT: u8 = 2;
+
+/// Bytes and packets counter, shared between the sender and the receiver of a connection.
+#[derive(Debug, Default)]
+pub struct TrafficCounter {
+    bytes: AtomicU64,
+    packets: AtomicU64,
+}
+
+impl TrafficCounter {
+    pub fn on_packet(&self, len: usize) {
+        self.bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn packets(&self) -> u64 {
+        self.packets.load(Ordering::Relaxed)
+    }
+}
+
+/// Detect lost packets from the gaps in received sequence numbers, like RTP receiver reports.
+#[derive(Debug, Default)]
+pub struct SeqLossDetector {
+    first: Option<u64>,
+    highest: u64,
+    received: u64,
+    reported_expected: u64,
+    reported_received: u64,
+}
+
+impl SeqLossDetector {
+    pub fn on_seq(&mut self, seq: u64) {
+        match self.first {
+            None => {
+                self.first = Some(seq);
+                self.highest = seq;
+            }
+            Some(first) if seq < first => {
+                //reordered packet which was sent before the first received one
+                self.first = Some(seq);
+            }
+            Some(_) => self.highest = self.highest.max(seq),
+        }
+        self.received += 1;
+    }
+
+    /// Return the loss percent since the last call.
+    pub fn take_loss_percent(&mut self) -> u32 {
+        let expected = match self.first {
+            Some(first) => self.highest - first + 1,
+            None => 0,
+        };
+        let interval_expected = expected.saturating_sub(self.reported_expected);
+        let interval_received = self.received.saturating_sub(self.reported_received);
+        self.reported_expected = expected;
+        self.reported_received = self.received;
+        (interval_expected.saturating_sub(interval_received) * 100).checked_div(interval_expected).unwrap_or(0) as u32
+    }
+}
+
+/// Delay-gradient over-use detector, like the trendline filter of GCC.
+///
+/// It fits a line to the smoothed rtt samples in a sliding window, the link is over-used
+/// when the rtt grows more than the threshold across the window for consecutive samples.
+#[derive(Debug, Default)]
+pub struct OverUseDetector {
+    samples: VecDeque<(f64, f64)>,
+    smoothed: Option<f64>,
+    over_count: u8,
+}
+
+impl OverUseDetector {
+    /// Add a rtt sample and return the over-use state.
+    pub fn on_rtt(&mut self, now_ms: u64, rtt_ms: u16) -> bool {
+        let smoothed = match self.smoothed {
+            Some(prev) => prev * OVER_USE_SMOOTHING + rtt_ms as f64 * (1.0 - OVER_USE_SMOOTHING),
+            None => rtt_ms as f64,
+        };
+        self.smoothed = Some(smoothed);
+        self.samples.push_back((now_ms as f64, smoothed));
+        if self.samples.len() > OVER_USE_WINDOW {
+            self.samples.pop_front();
+        }
+
+        if self.trend_ms() > OVER_USE_THRESHOLD_MS {
+            self.over_count = self.over_count.saturating_add(1);
+        } else {
+            self.over_count = 0;
+        }
+        self.over_count >= OVER_USE_MIN_COUNT
+    }
+
+    /// Rtt increase across the window, estimated with least squares.
+    fn trend_ms(&self) -> f64 {
+        if self.samples.len() < 3 {
+            return 0.0;
+        }
+        let count = self.samples.len() as f64;
+        let mean_t = self.samples.iter().map(|(t, _)| t).sum::<f64>() / count;
+        let mean_d = self.samples.iter().map(|(_, d)| d).sum::<f64>() / count;
+        let (num, den) = self
+            .samples
+            .iter()
+            .fold((0.0, 0.0), |(num, den), (t, d)| (num + (t - mean_t) * (d - mean_d), den + (t - mean_t) * (t - mean_t)));
+        if den == 0.0 {
+            return 0.0;
+        }
+        let span = self.samples.back().map(|(t, _)| *t).unwrap_or(0.0) - self.samples.front().map(|(t, _)| *t).unwrap_or(0.0);
+        num / den * span
+    }
+}
+
+/// Build ConnectionStats from the sent traffic, the rtt samples and the loss reported by the remote side.
+///
+/// The send estimate is AIMD like GCC: decrease on over-use or high loss, slowly increase otherwise.
+pub struct ConnectionStatsEstimator {
+    sent: Arc<TrafficCounter>,
+    last_ms: u64,
+    last_sent_bytes: u64,
+    send_est_kbps: u32,
+    over_use: OverUseDetector,
+}
+
+impl ConnectionStatsEstimator {
+    pub fn new(now_ms: u64, sent: Arc<TrafficCounter>) -> Self {
+        Self {
+            last_sent_bytes: sent.bytes(),
+            sent,
+            last_ms: now_ms,
+            send_est_kbps: DEFAULT_SEND_EST_KBPS,
+            over_use: OverUseDetector::default(),
+        }
+    }
+
+    /// Called on each rtt sample, `loss_percent` is the loss of sent packets since the previous sample.
+    pub fn on_rtt(&mut self, now_ms: u64, rtt_ms: u16, loss_percent: u32) -> ConnectionStats {
+        let sent_bytes = self.sent.bytes();
+        let elapsed_ms = now_ms.saturating_sub(self.last_ms);
+        let sending_kbps = ((sent_bytes - self.last_sent_bytes) * 8).checked_div(elapsed_ms).unwrap_or(0) as u32;
+        self.last_ms = now_ms;
+        self.last_sent_bytes = sent_bytes;
+
+        let over_use = self.over_use.on_rtt(now_ms, rtt_ms);
+        let est = self.send_est_kbps as u64;
+        let est = if over_use {
+            est * 85 / 100
+        } else if loss_percent > 10 {
+            est * (200 - loss_percent.min(100) as u64) / 200
+        } else if loss_percent < 2 {
+            est * 108 / 100
+        } else {
+            est
+        };
+        self.send_est_kbps = (est as u32).clamp(MIN_SEND_EST_KBPS, DEFAULT_SEND_EST_KBPS);
+
+        ConnectionStats {
+            rtt_ms,
+            sending_kbps,
+            send_est_kbps: self.send_est_kbps,
+            loss_percent,
+            over_use,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{ConnectionStatsEstimator, OverUseDetector, SeqLossDetector, TrafficCounter, DEFAULT_SEND_EST_KBPS};
+
+    #[test]
+    fn loss_detector() {
+        let mut detector = SeqLossDetector::default();
+        assert_eq!(detector.take_loss_percent(), 0);
+
+        for seq in [10, 11, 13, 12, 15, 19] {
+            detector.on_seq(seq);
+        }
+        //10..=19 expected, 6 received
+        assert_eq!(detector.take_loss_percent(), 40);
+
+        for seq in 20..30 {
+            detector.on_seq(seq);
+        }
+        assert_eq!(detector.take_loss_percent(), 0);
+
+        //late packet from previous interval should not make negative loss
+        detector.on_seq(14);
+        detector.on_seq(30);
+        assert_eq!(detector.take_loss_percent(), 0);
+    }
+
+    #[test]
+    fn over_use_stable_rtt() {
+        let mut detector = OverUseDetector::default();
+        for i in 0..20 {
+            assert!(!detector.on_rtt(i * 1000, 50 + (i % 3) as u16));
+        }
+    }
+
+    #[test]
+    fn over_use_growing_rtt() {
+        let mut detector = OverUseDetector::default();
+        let mut over_use = false;
+        for i in 0..10 {
+            over_use = detector.on_rtt(i * 1000, 50 + i as u16 * 20);
+        }
+        assert!(over_use);
+
+        //queue drained
+        for i in 10..30 {
+            over_use = detector.on_rtt(i * 1000, 50);
+        }
+        assert!(!over_use);
+    }
+
+    #[test]
+    fn estimator_aimd() {
+        let sent = Arc::new(TrafficCounter::default());
+        let mut estimator = ConnectionStatsEstimator::new(0, sent.clone());
+
+        for _ in 0..125 {
+            sent.on_packet(1000);
+        }
+        let stats = estimator.on_rtt(1000, 50, 0);
+        assert_eq!(stats.sending_kbps, 1000);
+        assert_eq!(stats.send_est_kbps, DEFAULT_SEND_EST_KBPS);
+        assert!(!stats.over_use);
+        assert_eq!(sent.packets(), 125);
+
+        let stats = estimator.on_rtt(2000, 50, 50);
+        assert_eq!(stats.sending_kbps, 0);
+        assert_eq!(stats.loss_percent, 50);
+        assert_eq!(stats.send_est_kbps, DEFAULT_SEND_EST_KBPS * 3 / 4);
+
+        let stats = estimator.on_rtt(3000, 50, 5);
+        assert_eq!(stats.send_est_kbps, DEFAULT_SEND_EST_KBPS * 3 / 4);
+
+        let stats = estimator.on_rtt(4000, 50, 0);
+        assert_eq!(stats.send_est_kbps, DEFAULT_SEND_EST_KBPS * 3 / 4 * 108 / 100);
+
+        let mut stats = stats;
+        for i in 0..10 {
+            stats = estimator.on_rtt(5000 + i * 1000, 50 + i as u16 * 30, 0);
+        }
+        assert!(stats.over_use);
+        assert!(stats.send_est_kbps < DEFAULT_SEND_EST_KBPS / 2);
+    }
+}
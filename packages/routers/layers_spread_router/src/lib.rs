@@ -1,15 +1,17 @@
 use atm0s_sdn_identity::{ConnId, NodeId};
 
+mod policy;
 mod registry;
 mod router;
 mod shared;
 mod table;
 mod utils;
 
-pub use crate::registry::{Registry, RegistrySync};
+pub use crate::policy::{CostAwarePolicy, LatencyPolicy, RoutingPolicy};
+pub use crate::registry::{Registry, RegistrySync, ServiceInfo};
 pub use crate::router::{Router, RouterSync};
 pub use crate::shared::SharedRouter;
-pub use crate::table::{Metric, Path};
+pub use crate::table::{Metric, MetricCost, Path};
 
 #[derive(PartialEq, Debug)]
 pub enum ServiceDestination {
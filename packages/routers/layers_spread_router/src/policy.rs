@@ -0,0 +1,109 @@
+use std::cmp::Ordering;
+use std::fmt::Debug;
+
+use crate::table::{Metric, MetricCost};
+
+/// Rank paths to the same destination, the first path after sorting with `compare` is used for routing
+/// and is the one announced to neighbours.
+pub trait RoutingPolicy: Debug + Send + Sync {
+    fn compare(&self, a: &Metric, b: &Metric) -> Ordering;
+
+    /// Whether `other` is close enough to `best` to share flows with it in multipath mode,
+    /// `best` is ranked before `other`.
+    fn is_near_equal(&self, best: &Metric, other: &Metric, _tolerance_percent: u8) -> bool {
+        self.compare(best, other) == Ordering::Equal
+    }
+}
+
+fn within_tolerance(best: u32, other: u32, tolerance_percent: u8) -> bool {
+    other as u64 * 100 <= best as u64 * (100 + tolerance_percent as u64)
+}
+
+/// Default policy, composite cost of latency, hops, loss and jitter
+impl RoutingPolicy for MetricCost {
+    fn compare(&self, a: &Metric, b: &Metric) -> Ordering {
+        MetricCost::compare(self, a, b)
+    }
+
+    fn is_near_equal(&self, best: &Metric, other: &Metric, tolerance_percent: u8) -> bool {
+        (best.bandwidth >= self.bandwidth_limit) == (other.bandwidth >= self.bandwidth_limit) && within_tolerance(self.cost(best), self.cost(other), tolerance_percent)
+    }
+}
+
+/// Lowest latency first, ignoring loss, jitter and bandwidth.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LatencyPolicy;
+
+impl RoutingPolicy for LatencyPolicy {
+    fn compare(&self, a: &Metric, b: &Metric) -> Ordering {
+        a.latency.cmp(&b.latency).then_with(|| a.hops.len().cmp(&b.hops.len())).then_with(|| b.bandwidth.cmp(&a.bandwidth))
+    }
+
+    fn is_near_equal(&self, best: &Metric, other: &Metric, tolerance_percent: u8) -> bool {
+        within_tolerance(best.latency as u32, other.latency as u32, tolerance_percent)
+    }
+}
+
+/// Cheapest path first, by the sum of the price of each link, which is set with `Router::set_conn_cost`.
+/// Paths with the same price are ranked by `fallback`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CostAwarePolicy {
+    pub fallback: MetricCost,
+}
+
+impl RoutingPolicy for CostAwarePolicy {
+    fn compare(&self, a: &Metric, b: &Metric) -> Ordering {
+        a.cost.cmp(&b.cost).then_with(|| self.fallback.compare(a, b))
+    }
+
+    fn is_near_equal(&self, best: &Metric, other: &Metric, tolerance_percent: u8) -> bool {
+        within_tolerance(best.cost, other.cost, tolerance_percent) && RoutingPolicy::is_near_equal(&self.fallback, best, other, tolerance_percent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::policy::{CostAwarePolicy, LatencyPolicy, RoutingPolicy};
+    use crate::table::{Metric, MetricCost};
+
+    #[test]
+    fn latency_policy() {
+        let slow_direct = Metric::new(30, vec![1, 0], 100000);
+        let fast_lossy = Metric::new(20, vec![1, 2, 0], 1000).with_quality(20, 10);
+
+        assert_eq!(RoutingPolicy::compare(&MetricCost::default(), &slow_direct, &fast_lossy), Ordering::Less);
+        assert_eq!(LatencyPolicy.compare(&slow_direct, &fast_lossy), Ordering::Greater);
+    }
+
+    #[test]
+    fn cost_aware_policy() {
+        let expensive = Metric::new(10, vec![1, 0], 100000).with_cost(100);
+        let cheap = Metric::new(50, vec![1, 2, 0], 100000).with_cost(10);
+        let cheap_slow = Metric::new(80, vec![1, 3, 0], 100000).with_cost(10);
+
+        let policy = CostAwarePolicy::default();
+        assert_eq!(policy.compare(&cheap, &expensive), Ordering::Less);
+        assert_eq!(policy.compare(&cheap, &cheap_slow), Ordering::Less);
+        assert_eq!(RoutingPolicy::compare(&MetricCost::default(), &cheap, &expensive), Ordering::Greater);
+    }
+
+    #[test]
+    fn near_equal() {
+        let cost = MetricCost::default();
+        let best = Metric::new(80, vec![1, 0], 100000);
+        let near = Metric::new(88, vec![1, 2, 0], 100000);
+        let low_bandwidth = Metric::new(80, vec![1, 3, 0], 1000);
+
+        //cost 100 vs 118
+        assert!(!cost.is_near_equal(&best, &near, 0));
+        assert!(!cost.is_near_equal(&best, &near, 10));
+        assert!(cost.is_near_equal(&best, &near, 20));
+        assert!(cost.is_near_equal(&best, &best.clone(), 0));
+        assert!(!cost.is_near_equal(&best, &low_bandwidth, 100));
+
+        assert!(LatencyPolicy.is_near_equal(&best, &near, 10));
+        assert!(!LatencyPolicy.is_near_equal(&best, &near, 5));
+    }
+}
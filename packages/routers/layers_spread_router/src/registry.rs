@@ -1,45 +1,75 @@
 use atm0s_sdn_identity::{ConnId, NodeId};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::ServiceDestination;
+use atm0s_sdn_router::ServiceSelector;
 use atm0s_sdn_utils::init_array::init_array;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
+use crate::policy::RoutingPolicy;
 use crate::table::{Dest, Metric, Path};
 
 pub const REGISTRY_LOCAL_BW: u32 = 1000000; //1Gbps
+/// Max number of service nodes which are forwarded for each service
+pub const REGISTRY_MAX_SERVICE_NODES: usize = 16;
+
+/// Advertised by the node which runs a service, used for selecting the node with ServiceSelector
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct ServiceInfo {
+    /// In percent
+    pub load: u8,
+    pub tier: u8,
+}
+
+/// Nodes which run a service and their advertised info, ordered from nearest
+pub type ServiceNodes = Vec<(NodeId, ServiceInfo)>;
 
+/// Services with the metric of best path, and service nodes in the same order as services.
+/// Service nodes are empty in syncs from older nodes, which don't advertise service info.
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
-pub struct RegistrySync(pub Vec<(u8, Metric)>);
+pub struct RegistrySync(pub Vec<(u8, Metric)>, pub Vec<ServiceNodes>);
 
 pub struct Registry {
     node_id: NodeId,
-    local_destinations: [bool; 256],
+    local_destinations: [Option<ServiceInfo>; 256],
     remote_destinations: [Dest; 256],
+    remote_infos: [HashMap<ConnId, ServiceNodes>; 256],
 }
 
 impl Registry {
-    pub fn new(node_id: NodeId) -> Self {
+    pub fn new(node_id: NodeId, policy: Arc<dyn RoutingPolicy>) -> Self {
         Registry {
             node_id,
-            local_destinations: init_array!(bool, 256, false),
-            remote_destinations: init_array!(Dest, 256, Default::default()),
+            local_destinations: init_array!(Option<ServiceInfo>, 256, None),
+            remote_destinations: init_array!(Dest, 256, Dest::new(policy.clone())),
+            remote_infos: init_array!(HashMap<ConnId, ServiceNodes>, 256, HashMap::new()),
         }
     }
 
     pub fn add_service(&mut self, service_id: u8) {
-        self.local_destinations[service_id as usize] = true;
+        let info = &mut self.local_destinations[service_id as usize];
+        if info.is_none() {
+            *info = Some(ServiceInfo::default());
+        }
+    }
+
+    /// Update the info which is advertised for a local service, the service is added if not yet
+    pub fn set_service_info(&mut self, service_id: u8, info: ServiceInfo) {
+        self.local_destinations[service_id as usize] = Some(info);
     }
 
     #[allow(unused)]
     pub fn remove_service(&mut self, service_id: u8) {
-        self.local_destinations[service_id as usize] = false;
+        self.local_destinations[service_id as usize] = None;
     }
 
     pub fn del_direct(&mut self, conn: ConnId) {
         for i in 0..256 {
             let pre_empty = self.remote_destinations[i as usize].is_empty();
             self.remote_destinations[i as usize].del_path(conn);
+            self.remote_infos[i as usize].remove(&conn);
             if !pre_empty && self.remote_destinations[i as usize].is_empty() {
                 log::info!("[Registry] removed service {} from dest {} because of direct disconnected", i, conn);
             }
@@ -47,19 +77,90 @@ impl Registry {
     }
 
     pub fn next(&self, service_id: u8, excepts: &[NodeId]) -> Option<ServiceDestination> {
-        if self.local_destinations[service_id as usize] {
+        if self.local_destinations[service_id as usize].is_some() {
             Some(ServiceDestination::Local)
         } else {
             self.remote_destinations[service_id as usize].next(excepts).map(|(c, n)| ServiceDestination::Remote(c, n))
         }
     }
 
+    /// Select the destination with the selector, candidates are the local service and the service nodes which are reachable over each connection.
+    /// Connections to older nodes don't advertise service nodes, they are considered as a single node with default info.
+    pub fn select(&self, service_id: u8, selector: ServiceSelector, excepts: &[NodeId]) -> Option<ServiceDestination> {
+        let local = self.local_destinations[service_id as usize].map(|info| (ServiceDestination::Local, info));
+        let infos = &self.remote_infos[service_id as usize];
+        let remotes =
+            self.remote_destinations[service_id as usize]
+                .paths()
+                .iter()
+                .filter(|path| !excepts.contains(&path.1))
+                .flat_map(|path| match infos.get(&path.0).filter(|nodes| !nodes.is_empty()) {
+                    Some(nodes) => nodes.iter().map(|(_, info)| (ServiceDestination::Remote(path.0, path.1), *info)).collect::<Vec<_>>(),
+                    None => vec![(ServiceDestination::Remote(path.0, path.1), ServiceInfo::default())],
+                });
+        //candidates are ordered from nearest
+        let mut candidates = local.into_iter().chain(remotes);
+        match selector {
+            ServiceSelector::Nearest => candidates.next().map(|(dest, _)| dest),
+            ServiceSelector::LeastLoaded => candidates
+                .fold(None, |selected: Option<(ServiceDestination, ServiceInfo)>, (dest, info)| match selected {
+                    Some(selected) if selected.1.load <= info.load => Some(selected),
+                    _ => Some((dest, info)),
+                })
+                .map(|(dest, _)| dest),
+            ServiceSelector::Tier(tier) => candidates.find(|(_, info)| info.tier == tier).map(|(dest, _)| dest),
+            ServiceSelector::Balanced(top_n) => {
+                let candidates: Vec<_> = candidates.take(top_n.max(1) as usize).collect();
+                Self::power_of_two_choices(candidates, &mut rand::thread_rng())
+            }
+        }
+    }
+
+    /// Select a node between the top_n nearest nodes which run the service, nodes behind the same connection are distinct candidates.
+    /// Connections to older nodes don't advertise service nodes, so they are not candidates.
+    pub fn select_balanced_node(&self, service_id: u8, top_n: u8) -> Option<NodeId> {
+        let top_n = top_n.max(1) as usize;
+        let mut candidates: Vec<(NodeId, ServiceInfo)> = self.local_destinations[service_id as usize].map(|info| (self.node_id, info)).into_iter().collect();
+        let infos = &self.remote_infos[service_id as usize];
+        for path in self.remote_destinations[service_id as usize].paths() {
+            for (node, info) in infos.get(&path.0).into_iter().flatten() {
+                if candidates.len() >= top_n {
+                    return Self::power_of_two_choices(candidates, &mut rand::thread_rng());
+                }
+                if !candidates.iter().any(|(added, _)| added == node) {
+                    candidates.push((*node, *info));
+                }
+            }
+        }
+        Self::power_of_two_choices(candidates, &mut rand::thread_rng())
+    }
+
+    /// Pick two random candidates and select the less loaded one
+    fn power_of_two_choices<T, R: Rng>(mut candidates: Vec<(T, ServiceInfo)>, rng: &mut R) -> Option<T> {
+        if candidates.len() <= 1 {
+            return candidates.pop().map(|(dest, _)| dest);
+        }
+        let first = rng.gen_range(0..candidates.len());
+        let mut second = rng.gen_range(0..candidates.len() - 1);
+        if second >= first {
+            second += 1;
+        }
+        let selected = if candidates[second].1.load < candidates[first].1.load {
+            second
+        } else {
+            first
+        };
+        Some(candidates.swap_remove(selected).0)
+    }
+
     pub fn apply_sync(&mut self, src_conn: ConnId, src: NodeId, src_send_metric: Metric, sync: RegistrySync) {
         log::debug!("apply sync from {} -> {}, sync {:?}", src, self.node_id, sync.0);
-        let mut cached: HashMap<u8, Metric> = HashMap::new();
+        let mut cached: HashMap<u8, (Metric, ServiceNodes)> = HashMap::new();
+        let mut nodes = sync.1.into_iter();
         for (index, metric) in sync.0 {
+            let nodes = nodes.next().unwrap_or_default();
             if let Some(sum) = metric.add(&src_send_metric) {
-                cached.insert(index, sum);
+                cached.insert(index, (sum, nodes));
             }
         }
 
@@ -70,12 +171,14 @@ impl Registry {
                     if dest.del_path(src_conn).is_some() {
                         log::info!("[Registry] removed service {} from dest {} after sync", i, src);
                     }
+                    self.remote_infos[i as usize].remove(&src_conn);
                 }
-                Some(metric) => {
+                Some((metric, nodes)) => {
                     if dest.is_empty() {
                         log::info!("[Registry] added service {} from {} after sync", i, src);
                     }
                     dest.set_path(src_conn, src, metric);
+                    self.remote_infos[i as usize].insert(src_conn, nodes);
                 }
             }
         }
@@ -83,25 +186,45 @@ impl Registry {
 
     pub fn sync_for(&self, node: NodeId) -> RegistrySync {
         let mut res = vec![];
+        let mut res_nodes = vec![];
         for i in 0..=255 {
-            if self.local_destinations[i as usize] {
-                res.push((i, Metric::new(0, vec![self.node_id], REGISTRY_LOCAL_BW)));
-            } else {
-                let dest: &Dest = &self.remote_destinations[i as usize];
-                if !dest.is_empty() {
-                    if let Some(Path(_over, _over_node, metric)) = dest.best_for(node) {
-                        res.push((i, metric));
-                    }
+            let dest: &Dest = &self.remote_destinations[i as usize];
+            let metric = match self.local_destinations[i as usize] {
+                Some(_) => Some(Metric::new(0, vec![self.node_id], REGISTRY_LOCAL_BW)),
+                None => dest.best_for(node).map(|Path(_, _, metric)| metric),
+            };
+            if let Some(metric) = metric {
+                res.push((i, metric));
+                res_nodes.push(self.service_nodes_for(i, node));
+            }
+        }
+        RegistrySync(res, res_nodes)
+    }
+
+    /// Local service and service nodes which are learned from paths not going through the node, nearest first
+    fn service_nodes_for(&self, service_id: u8, node: NodeId) -> ServiceNodes {
+        let mut res: ServiceNodes = self.local_destinations[service_id as usize].map(|info| (self.node_id, info)).into_iter().collect();
+        let infos = &self.remote_infos[service_id as usize];
+        for path in self.remote_destinations[service_id as usize].paths() {
+            if path.1 == node || path.2.contain_in_hops(node) {
+                continue;
+            }
+            for (service_node, info) in infos.get(&path.0).into_iter().flatten() {
+                if res.len() >= REGISTRY_MAX_SERVICE_NODES {
+                    return res;
+                }
+                if *service_node != node && !res.iter().any(|(added, _)| added == service_node) {
+                    res.push((*service_node, *info));
                 }
             }
         }
-        RegistrySync(res)
+        res
     }
 
     pub fn log_dump(&self) {
         let mut local_services = vec![];
         for (index, service_id) in self.local_destinations.iter().enumerate() {
-            if *service_id {
+            if service_id.is_some() {
                 local_services.push(index);
             }
         }
@@ -120,7 +243,7 @@ impl Registry {
     pub fn print_dump(&self) {
         let mut local_services = vec![];
         for (index, service_id) in self.local_destinations.iter().enumerate() {
-            if *service_id {
+            if service_id.is_some() {
                 local_services.push(index);
             }
         }
@@ -139,15 +262,18 @@ impl Registry {
 
 #[cfg(test)]
 mod tests {
-    use crate::registry::{Registry, RegistrySync, REGISTRY_LOCAL_BW};
-    use crate::table::Metric;
+    use std::sync::Arc;
+
+    use crate::registry::{Registry, RegistrySync, ServiceInfo, REGISTRY_LOCAL_BW};
+    use crate::table::{Metric, MetricCost};
     use crate::ServiceDestination;
     use atm0s_sdn_identity::{ConnId, NodeId};
+    use atm0s_sdn_router::ServiceSelector;
 
     #[test]
     fn create_manual() {
         let node0: NodeId = 0x0;
-        let mut registry = Registry::new(node0);
+        let mut registry = Registry::new(node0, Arc::new(MetricCost::default()));
         let node1: NodeId = 0x1;
         let _node2: NodeId = 0x2;
         let _node3: NodeId = 0x3;
@@ -158,7 +284,7 @@ mod tests {
         // assert_eq!(registry.next(1, &[0]), None);
 
         let sync = registry.sync_for(node1);
-        assert_eq!(sync.0, vec![(1, Metric::new(0, vec![0], REGISTRY_LOCAL_BW))]);
+        assert_eq!(sync, RegistrySync(vec![(1, Metric::new(0, vec![0], REGISTRY_LOCAL_BW))], vec![vec![(node0, ServiceInfo::default())]]));
     }
 
     #[test]
@@ -169,10 +295,10 @@ mod tests {
         let conn1: ConnId = ConnId::from_out(0, 0x1);
         let node1: NodeId = 0x1;
 
-        let mut registry = Registry::new(node0);
+        let mut registry = Registry::new(node0, Arc::new(MetricCost::default()));
 
         assert_eq!(registry.next(1, &[]), None);
-        registry.apply_sync(conn1, node1, Metric::new(1, vec![1, 0], 1), RegistrySync(vec![(1, Metric::new(1, vec![1], 1))]));
+        registry.apply_sync(conn1, node1, Metric::new(1, vec![1, 0], 1), RegistrySync(vec![(1, Metric::new(1, vec![1], 1))], vec![]));
         assert_eq!(registry.next(1, &[]), Some(ServiceDestination::Remote(conn1, node1)));
 
         registry.del_direct(conn1);
@@ -182,7 +308,7 @@ mod tests {
     #[test]
     fn apply_sync() {
         let node0: NodeId = 0x0;
-        let mut registry = Registry::new(node0);
+        let mut registry = Registry::new(node0, Arc::new(MetricCost::default()));
 
         let conn1: ConnId = ConnId::from_out(0, 0x1);
         let node1: NodeId = 0x1;
@@ -190,14 +316,14 @@ mod tests {
         let _node3: NodeId = 0x3;
 
         let sync = vec![(2, Metric::new(1, vec![node1], 1)), (3, Metric::new(1, vec![node1], 1))];
-        registry.apply_sync(conn1, node1, Metric::new(1, vec![node1, node0], 2), RegistrySync(sync));
+        registry.apply_sync(conn1, node1, Metric::new(1, vec![node1, node0], 2), RegistrySync(sync, vec![]));
 
         assert_eq!(registry.next(1, &[]), None);
         assert_eq!(registry.next(2, &[]), Some(ServiceDestination::Remote(conn1, node1)));
         assert_eq!(registry.next(3, &[]), Some(ServiceDestination::Remote(conn1, node1)));
 
         let sync = vec![(3, Metric::new(1, vec![node1], 1))];
-        registry.apply_sync(conn1, node1, Metric::new(1, vec![node1, node0], 1), RegistrySync(sync));
+        registry.apply_sync(conn1, node1, Metric::new(1, vec![node1, node0], 1), RegistrySync(sync, vec![]));
         assert_eq!(registry.next(1, &[]), None);
         assert_eq!(registry.next(2, &[]), None);
         assert_eq!(registry.next(3, &[]), Some(ServiceDestination::Remote(conn1, node1)));
@@ -206,7 +332,7 @@ mod tests {
     #[test]
     fn remove_from_sync() {
         let node0: NodeId = 0x0;
-        let mut registry = Registry::new(node0);
+        let mut registry = Registry::new(node0, Arc::new(MetricCost::default()));
         let conn1: ConnId = ConnId::from_out(0, 0x1);
         let node1: NodeId = 0x1;
         let node2: NodeId = 0x2;
@@ -214,13 +340,182 @@ mod tests {
         let node4: NodeId = 0x4;
 
         let sync = vec![(2, Metric::new(1, vec![node3, node2, node1], 1))];
-        registry.apply_sync(conn1, node1, Metric::new(1, vec![node1, node0], 2), RegistrySync(sync));
+        registry.apply_sync(conn1, node1, Metric::new(1, vec![node1, node0], 2), RegistrySync(sync, vec![vec![(node3, ServiceInfo::default())]]));
 
         assert_eq!(registry.next(2, &[]), Some(ServiceDestination::Remote(conn1, node1)));
-        assert_eq!(registry.sync_for(node1), RegistrySync(vec![]));
-        assert_eq!(registry.sync_for(node2), RegistrySync(vec![]));
-        assert_eq!(registry.sync_for(node3), RegistrySync(vec![]));
-        assert_eq!(registry.sync_for(node4), RegistrySync(vec![(2, Metric::new(2, vec![node3, node2, node1, node0], 1))]));
+        assert_eq!(registry.sync_for(node1), RegistrySync(vec![], vec![]));
+        assert_eq!(registry.sync_for(node2), RegistrySync(vec![], vec![]));
+        assert_eq!(registry.sync_for(node3), RegistrySync(vec![], vec![]));
+        assert_eq!(
+            registry.sync_for(node4),
+            RegistrySync(vec![(2, Metric::new(2, vec![node3, node2, node1, node0], 1))], vec![vec![(node3, ServiceInfo::default())]])
+        );
+    }
+
+    #[test]
+    fn select_by_selector() {
+        let node0: NodeId = 0x0;
+        let conn1: ConnId = ConnId::from_out(0, 0x1);
+        let node1: NodeId = 0x1;
+        let conn2: ConnId = ConnId::from_out(0, 0x2);
+        let node2: NodeId = 0x2;
+        let node3: NodeId = 0x3;
+
+        let mut registry = Registry::new(node0, Arc::new(MetricCost::default()));
+        registry.set_service_info(1, ServiceInfo { load: 80, tier: 1 });
+        registry.apply_sync(
+            conn1,
+            node1,
+            Metric::new(1, vec![node1, node0], 100000),
+            RegistrySync(vec![(1, Metric::new(0, vec![node1], 100000))], vec![vec![(node1, ServiceInfo { load: 10, tier: 2 })]]),
+        );
+        registry.apply_sync(
+            conn2,
+            node2,
+            Metric::new(20, vec![node2, node0], 100000),
+            RegistrySync(vec![(1, Metric::new(0, vec![node2], 100000))], vec![vec![(node2, ServiceInfo { load: 10, tier: 3 })]]),
+        );
+
+        assert_eq!(registry.select(1, ServiceSelector::Nearest, &[]), Some(ServiceDestination::Local));
+        assert_eq!(registry.select(1, ServiceSelector::LeastLoaded, &[]), Some(ServiceDestination::Remote(conn1, node1)));
+        assert_eq!(registry.select(1, ServiceSelector::LeastLoaded, &[node1]), Some(ServiceDestination::Remote(conn2, node2)));
+        assert_eq!(registry.select(1, ServiceSelector::Tier(1), &[]), Some(ServiceDestination::Local));
+        assert_eq!(registry.select(1, ServiceSelector::Tier(3), &[]), Some(ServiceDestination::Remote(conn2, node2)));
+        assert_eq!(registry.select(1, ServiceSelector::Tier(4), &[]), None);
+
+        //local info is advertised, remote info is forwarded
+        assert_eq!(
+            registry.sync_for(node3).1[0],
+            vec![
+                (node0, ServiceInfo { load: 80, tier: 1 }),
+                (node1, ServiceInfo { load: 10, tier: 2 }),
+                (node2, ServiceInfo { load: 10, tier: 3 })
+            ]
+        );
+        assert_eq!(
+            registry.sync_for(node1).1[0],
+            vec![(node0, ServiceInfo { load: 80, tier: 1 }), (node2, ServiceInfo { load: 10, tier: 3 })]
+        );
+        registry.remove_service(1);
+        assert_eq!(
+            registry.sync_for(node3).1[0],
+            vec![(node1, ServiceInfo { load: 10, tier: 2 }), (node2, ServiceInfo { load: 10, tier: 3 })]
+        );
+
+        registry.del_direct(conn1);
+        assert_eq!(registry.select(1, ServiceSelector::Tier(2), &[]), None);
+        assert_eq!(registry.select(1, ServiceSelector::LeastLoaded, &[]), Some(ServiceDestination::Remote(conn2, node2)));
+    }
+
+    #[test]
+    fn select_balanced() {
+        let node0: NodeId = 0x0;
+        let mut registry = Registry::new(node0, Arc::new(MetricCost::default()));
+        let conns: Vec<(ConnId, NodeId)> = (1..=4).map(|i| (ConnId::from_out(0, i as u64), i)).collect();
+        let loads = [50, 0, 0, 0];
+        for (index, (conn, node)) in conns.iter().enumerate() {
+            registry.apply_sync(
+                *conn,
+                *node,
+                Metric::new(index as u16 * 10, vec![*node, node0], 100000),
+                RegistrySync(vec![(1, Metric::new(0, vec![*node], 100000))], vec![vec![(*node, ServiceInfo { load: loads[index], tier: 0 })]]),
+            );
+        }
+
+        let mut counts = [0; 4];
+        for _ in 0..1000 {
+            match registry.select(1, ServiceSelector::Balanced(3), &[]) {
+                Some(ServiceDestination::Remote(_, node)) => counts[node as usize - 1] += 1,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        //only top 3 nearest are used, the loaded nearest one always loses its pair
+        assert_eq!(counts[0], 0);
+        assert_eq!(counts[3], 0);
+        assert!(counts[1] > 300 && counts[2] > 300, "{:?}", counts);
+
+        assert_eq!(registry.select(1, ServiceSelector::Balanced(1), &[]), Some(ServiceDestination::Remote(conns[0].0, conns[0].1)));
+        assert_eq!(registry.select(1, ServiceSelector::Balanced(3), &[1, 2, 3]), Some(ServiceDestination::Remote(conns[3].0, conns[3].1)));
+        assert_eq!(registry.select(2, ServiceSelector::Balanced(3), &[]), None);
+    }
+
+    #[test]
+    fn select_balanced_node_behind_same_conn() {
+        // A -1- B, B -1- C, B -1- D, C and D run the service
+        let node_a: NodeId = 0x1;
+        let node_b: NodeId = 0x2;
+        let node_c: NodeId = 0x3;
+        let node_d: NodeId = 0x4;
+        let conn_ab = ConnId::from_out(0, 0x12);
+
+        let mut registry_a = Registry::new(node_a, Arc::new(MetricCost::default()));
+        registry_a.apply_sync(
+            conn_ab,
+            node_b,
+            Metric::new(1, vec![node_b, node_a], 100000),
+            RegistrySync(
+                vec![(1, Metric::new(1, vec![node_c, node_b], 100000))],
+                vec![vec![(node_c, ServiceInfo { load: 10, tier: 0 }), (node_d, ServiceInfo { load: 10, tier: 0 })]],
+            ),
+        );
+
+        //all requests go over the same connection, but they are spread over both service nodes
+        let mut counts = [0; 2];
+        for _ in 0..1000 {
+            match registry_a.select_balanced_node(1, 2) {
+                Some(node) if node == node_c => counts[0] += 1,
+                Some(node) if node == node_d => counts[1] += 1,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(counts[0] > 300 && counts[1] > 300, "{:?}", counts);
+        assert_eq!(registry_a.select_balanced_node(1, 1), Some(node_c));
+        assert_eq!(registry_a.select_balanced_node(2, 2), None);
+
+        //nodes from older nodes are unknown
+        registry_a.apply_sync(
+            conn_ab,
+            node_b,
+            Metric::new(1, vec![node_b, node_a], 100000),
+            RegistrySync(vec![(1, Metric::new(1, vec![node_c, node_b], 100000))], vec![]),
+        );
+        assert_eq!(registry_a.select_balanced_node(1, 2), None);
+    }
+
+    #[test]
+    fn select_over_multi_hops() {
+        // A -1- B -1- C, B runs tier 1 with high load, C runs tier 2 with low load
+        let node_a: NodeId = 0x1;
+        let node_b: NodeId = 0x2;
+        let node_c: NodeId = 0x3;
+        let conn_ab = ConnId::from_out(0, 0x12);
+        let conn_bc = ConnId::from_out(0, 0x23);
+
+        let mut registry_a = Registry::new(node_a, Arc::new(MetricCost::default()));
+        let mut registry_b = Registry::new(node_b, Arc::new(MetricCost::default()));
+        let mut registry_c = Registry::new(node_c, Arc::new(MetricCost::default()));
+        registry_b.set_service_info(1, ServiceInfo { load: 90, tier: 1 });
+        registry_c.set_service_info(1, ServiceInfo { load: 10, tier: 2 });
+
+        registry_b.apply_sync(conn_bc, node_c, Metric::new(1, vec![node_c, node_b], 100000), registry_c.sync_for(node_b));
+        registry_a.apply_sync(conn_ab, node_b, Metric::new(1, vec![node_b, node_a], 100000), registry_b.sync_for(node_a));
+
+        //A only has the connection to B, but it knows both service nodes behind it
+        assert_eq!(registry_a.select(1, ServiceSelector::Tier(2), &[]), Some(ServiceDestination::Remote(conn_ab, node_b)));
+        assert_eq!(registry_a.select(1, ServiceSelector::Tier(3), &[]), None);
+        //B forwards to C instead of its local service
+        assert_eq!(registry_b.select(1, ServiceSelector::Tier(2), &[]), Some(ServiceDestination::Remote(conn_bc, node_c)));
+        assert_eq!(registry_b.select(1, ServiceSelector::LeastLoaded, &[]), Some(ServiceDestination::Remote(conn_bc, node_c)));
+
+        //service nodes are not sent back to the node which they are learned from
+        assert_eq!(registry_b.sync_for(node_c).1[0], vec![(node_b, ServiceInfo { load: 90, tier: 1 })]);
+        assert_eq!(registry_a.sync_for(node_b), RegistrySync(vec![], vec![]));
+
+        //C is removed after B lost the connection
+        registry_b.del_direct(conn_bc);
+        registry_a.apply_sync(conn_ab, node_b, Metric::new(1, vec![node_b, node_a], 100000), registry_b.sync_for(node_a));
+        assert_eq!(registry_a.select(1, ServiceSelector::Tier(2), &[]), None);
+        assert_eq!(registry_a.select(1, ServiceSelector::Tier(1), &[]), Some(ServiceDestination::Remote(conn_ab, node_b)));
     }
 
     //TODO test multi connections with same node
@@ -1,30 +1,136 @@
-use atm0s_sdn_identity::{ConnId, NodeId, NodeIdType};
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use crate::registry::{Registry, RegistrySync};
-use crate::table::{Metric, NodeIndex, Path, Table, TableSync};
+use atm0s_sdn_identity::{ConnId, NodeId, NodeIdType};
+use atm0s_sdn_router::ServiceSelector;
+use serde::de::{SeqAccess, Visitor};
+use serde::ser::SerializeTupleStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::policy::RoutingPolicy;
+use crate::registry::{Registry, RegistrySync, ServiceInfo, ServiceNodes};
+use crate::table::{Metric, MetricCost, NodeIndex, Path, Table, TableSync};
 use crate::ServiceDestination;
 
 /// Which layer in node id space, in this case is 0 -> 3
 pub type Layer = u8;
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(PartialEq, Debug)]
 pub struct RouterSync(pub RegistrySync, pub [Option<TableSync>; 4]);
 
+impl RouterSync {
+    fn metrics(&self) -> impl Iterator<Item = &Metric> {
+        self.0
+             .0
+            .iter()
+            .map(|(_, metric)| metric)
+            .chain(self.1.iter().flatten().flat_map(|table| table.0.iter()).map(|(_, metric)| metric))
+    }
+
+    fn metrics_mut(&mut self) -> impl Iterator<Item = &mut Metric> {
+        self.0
+             .0
+            .iter_mut()
+            .map(|(_, metric)| metric)
+            .chain(self.1.iter_mut().flatten().flat_map(|table| table.0.iter_mut()).map(|(_, metric)| metric))
+    }
+}
+
+/// The registry services are encoded as legacy field, then the extension fields are appended after the legacy fields:
+/// a (lost, jitter) list and a cost list in the same order as registry entries then table entries, and the service nodes list
+/// in the same order as registry entries. Old nodes ignore the trailing bytes, and syncs from old nodes are decoded with zero values.
+impl Serialize for RouterSync {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let quality: Vec<(u8, u16)> = self.metrics().map(|metric| (metric.lost, metric.jitter)).collect();
+        let costs: Vec<u32> = self.metrics().map(|metric| metric.cost).collect();
+        let mut state = serializer.serialize_tuple_struct("RouterSync", 5)?;
+        state.serialize_field(&self.0 .0)?;
+        state.serialize_field(&self.1)?;
+        state.serialize_field(&quality)?;
+        state.serialize_field(&costs)?;
+        state.serialize_field(&self.0 .1)?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for RouterSync {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RouterSyncVisitor;
+
+        impl<'de> Visitor<'de> for RouterSyncVisitor {
+            type Value = RouterSync;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("tuple struct RouterSync")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let registry: Vec<(u8, Metric)> = seq.next_element()?.ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+                let tables = seq.next_element()?.ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
+                let mut sync = RouterSync(RegistrySync(registry, vec![]), tables);
+
+                //older nodes don't send all extension fields, they are read in order until the first missing one
+                let quality = seq.next_element::<Vec<(u8, u16)>>().ok().flatten();
+                let costs = match quality {
+                    Some(_) => seq.next_element::<Vec<u32>>().ok().flatten(),
+                    None => None,
+                };
+                let service_nodes = match costs {
+                    Some(_) => seq.next_element::<Vec<ServiceNodes>>().ok().flatten(),
+                    None => None,
+                };
+
+                let count = sync.metrics().count();
+                if let Some(quality) = quality.filter(|quality| quality.len() == count) {
+                    for (metric, (lost, jitter)) in sync.metrics_mut().zip(quality) {
+                        //lost is a percent, it is not trusted from remote
+                        metric.lost = lost.min(100);
+                        metric.jitter = jitter;
+                    }
+                }
+                if let Some(costs) = costs.filter(|costs| costs.len() == count) {
+                    for (metric, cost) in sync.metrics_mut().zip(costs) {
+                        metric.cost = cost;
+                    }
+                }
+                if let Some(service_nodes) = service_nodes.filter(|service_nodes| service_nodes.len() == sync.0 .0.len()) {
+                    sync.0 .1 = service_nodes;
+                }
+                Ok(sync)
+            }
+        }
+
+        deserializer.deserialize_tuple_struct("RouterSync", 5, RouterSyncVisitor)
+    }
+}
+
 pub struct Router {
     local_node_id: NodeId,
     tables: [Table; 4],
     service_registry: Registry,
+    conn_costs: HashMap<ConnId, u32>,
+    multipath_tolerance: Option<u8>,
 }
 
 impl Router {
-    pub fn new(local_node_id: NodeId) -> Self {
-        let tables = [Table::new(local_node_id, 0), Table::new(local_node_id, 1), Table::new(local_node_id, 2), Table::new(local_node_id, 3)];
+    pub fn new(local_node_id: NodeId, cost: MetricCost) -> Self {
+        Self::with_policy(local_node_id, Arc::new(cost))
+    }
+
+    pub fn with_policy(local_node_id: NodeId, policy: Arc<dyn RoutingPolicy>) -> Self {
+        let tables = [
+            Table::new(local_node_id, 0, policy.clone()),
+            Table::new(local_node_id, 1, policy.clone()),
+            Table::new(local_node_id, 2, policy.clone()),
+            Table::new(local_node_id, 3, policy.clone()),
+        ];
 
         Router {
             local_node_id,
             tables,
-            service_registry: Registry::new(local_node_id),
+            service_registry: Registry::new(local_node_id, policy),
+            conn_costs: HashMap::new(),
+            multipath_tolerance: None,
         }
     }
 
@@ -44,11 +150,40 @@ impl Router {
         self.service_registry.add_service(service_id);
     }
 
+    pub fn set_service_info(&mut self, service_id: u8, info: ServiceInfo) {
+        self.service_registry.set_service_info(service_id, info);
+    }
+
     pub fn service_next(&self, service_id: u8, excepts: &[NodeId]) -> Option<ServiceDestination> {
         self.service_registry.next(service_id, excepts)
     }
 
+    pub fn service_select(&self, service_id: u8, selector: ServiceSelector, excepts: &[NodeId]) -> Option<ServiceDestination> {
+        self.service_registry.select(service_id, selector, excepts)
+    }
+
+    pub fn service_balanced_node(&self, service_id: u8, top_n: u8) -> Option<NodeId> {
+        self.service_registry.select_balanced_node(service_id, top_n)
+    }
+
+    /// Annotate the price of a connection, it is added to the metric cost of all paths over this connection
+    /// from the next set_direct or apply_sync.
+    pub fn set_conn_cost(&mut self, over: ConnId, cost: u32) {
+        self.conn_costs.insert(over, cost);
+    }
+
+    fn with_conn_cost(&self, over: ConnId, metric: Metric) -> Metric {
+        match self.conn_costs.get(&over) {
+            Some(cost) => {
+                let cost = metric.cost.saturating_add(*cost);
+                metric.with_cost(cost)
+            }
+            None => metric,
+        }
+    }
+
     pub fn set_direct(&mut self, over: ConnId, over_node: NodeId, metric: Metric) {
+        let metric = self.with_conn_cost(over, metric);
         let eq_util_layer = self.local_node_id.eq_util_layer(&over_node) as usize;
         log::debug!(
             "[Router {}] set_direct {}/{} with metric {:?}, eq_util_layer {}",
@@ -67,6 +202,7 @@ impl Router {
 
     pub fn del_direct(&mut self, over: ConnId) {
         log::debug!("[Router {}] del_direct {}", self.local_node_id, over);
+        self.conn_costs.remove(&over);
         for table in &mut self.tables {
             table.del_direct(over);
         }
@@ -83,6 +219,27 @@ impl Router {
         }
     }
 
+    /// Enable multipath forwarding, paths with cost within `tolerance_percent` of the best path share the flows.
+    /// None for single path.
+    pub fn set_multipath(&mut self, tolerance_percent: Option<u8>) {
+        self.multipath_tolerance = tolerance_percent;
+    }
+
+    /// Same as next but in multipath mode, a flow is always sent over the same path while the paths don't change.
+    pub fn next_flow(&self, dest: NodeId, excepts: &[NodeId], flow: u64) -> Option<(ConnId, NodeId)> {
+        let tolerance_percent = match self.multipath_tolerance {
+            Some(tolerance_percent) => tolerance_percent,
+            None => return self.next(dest, excepts),
+        };
+        let eq_util_layer = self.local_node_id.eq_util_layer(&dest) as usize;
+        debug_assert!(eq_util_layer <= 4);
+        if eq_util_layer == 0 {
+            None
+        } else {
+            self.tables.get(eq_util_layer - 1)?.next_multipath(dest, excepts, flow, tolerance_percent)
+        }
+    }
+
     pub fn next_path(&self, dest: NodeId, excepts: &[NodeId]) -> Option<Path> {
         let eq_util_layer = self.local_node_id.eq_util_layer(&dest) as usize;
         debug_assert!(eq_util_layer <= 4);
@@ -152,6 +309,7 @@ impl Router {
     }
 
     pub fn apply_sync(&mut self, conn: ConnId, src: NodeId, src_send_metric: Metric, sync: RouterSync) {
+        let src_send_metric = self.with_conn_cost(conn, src_send_metric);
         self.service_registry.apply_sync(conn, src, src_send_metric.clone(), sync.0);
         for (index, table_sync) in sync.1.into_iter().enumerate() {
             if let Some(table_sync) = table_sync {
@@ -181,9 +339,11 @@ impl Router {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
     use std::vec;
 
-    use crate::registry::{RegistrySync, REGISTRY_LOCAL_BW};
+    use crate::policy::CostAwarePolicy;
+    use crate::registry::{RegistrySync, ServiceInfo, REGISTRY_LOCAL_BW};
     use crate::router::{Router, RouterSync};
     use crate::table::{Metric, Path, TableSync};
     use crate::ServiceDestination;
@@ -200,7 +360,7 @@ mod tests {
         let z_node1_conn: ConnId = ConnId::from_out(0, 0x01000001);
         let z_node2: NodeId = 0x01000002;
 
-        let mut router = Router::new(node0);
+        let mut router = Router::new(node0, Default::default());
 
         assert_eq!(router.node_id(), node0);
         assert_eq!(router.size(), 0);
@@ -221,7 +381,7 @@ mod tests {
     }
 
     fn create_router(node_id: NodeId) -> (NodeId, ConnId, Router) {
-        (node_id, ConnId::from_out(0, node_id as u64), Router::new(node_id))
+        (node_id, ConnId::from_out(0, node_id as u64), Router::new(node_id, Default::default()))
     }
 
     #[test]
@@ -235,7 +395,7 @@ mod tests {
             ConnId::from_in(0, 0),
             1,
             Metric::new(0, vec![1, 2], 0),
-            RouterSync(RegistrySync(vec![]), [Some(TableSync(vec![(3, Metric::new(0, vec![3, 1], 0))])), None, None, None]),
+            RouterSync(RegistrySync(vec![], vec![]), [Some(TableSync(vec![(3, Metric::new(0, vec![3, 1], 0))])), None, None, None]),
         );
         assert_eq!(router2.tables[0].slots(), vec![1, 3]);
     }
@@ -280,7 +440,7 @@ mod tests {
         assert_eq!(
             sync_a_b,
             RouterSync(
-                RegistrySync(vec![(1, metric_registry_local.clone())]),
+                RegistrySync(vec![(1, metric_registry_local.clone())], vec![vec![(node_a, ServiceInfo::default())]]),
                 [
                     Some(TableSync(vec![(4, Metric::new(1, vec![node_d, node_a], 1))])),
                     Some(empty_sync.clone()),
@@ -458,6 +618,93 @@ mod tests {
         assert_eq!(router_a.closest_node(NodeId::build(2, 6, 0, 4), &[]), None);
     }
 
+    #[test]
+    fn sync_quality_backward_compatible() {
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct LegacyRouterSync(Vec<(u8, Metric)>, [Option<TableSync>; 4]);
+
+        let registry = RegistrySync(vec![(1, Metric::new(1, vec![1, 0], 1).with_quality(5, 3))], vec![vec![(1, ServiceInfo { load: 30, tier: 2 })]]);
+        let table = TableSync(vec![(3, Metric::new(2, vec![3, 0], 1).with_quality(10, 7).with_cost(9))]);
+
+        let sync = RouterSync(registry.clone(), [Some(table.clone()), None, None, None]);
+        let decoded: RouterSync = bincode::deserialize(&bincode::serialize(&sync).unwrap()).unwrap();
+        assert_eq!(decoded, sync);
+        assert_eq!(decoded.0 .0[0].1.lost, 5);
+        assert_eq!(decoded.1[0].as_ref().unwrap().0[0].1.jitter, 7);
+        assert_eq!(decoded.1[0].as_ref().unwrap().0[0].1.cost, 9);
+        assert_eq!(decoded.0 .1, vec![vec![(1, ServiceInfo { load: 30, tier: 2 })]]);
+
+        //invalid lost is clamped
+        let mut invalid = RouterSync(registry.clone(), [Some(table.clone()), None, None, None]);
+        invalid.0 .0[0].1.lost = 200;
+        let decoded: RouterSync = bincode::deserialize(&bincode::serialize(&invalid).unwrap()).unwrap();
+        assert_eq!(decoded.0 .0[0].1.lost, 100);
+
+        //new node receive sync from old node
+        let legacy = bincode::serialize(&LegacyRouterSync(vec![(1, Metric::new(1, vec![1, 0], 1))], [Some(table.clone()), None, None, None])).unwrap();
+        let decoded: RouterSync = bincode::deserialize(&legacy).unwrap();
+        assert_eq!(
+            decoded,
+            RouterSync(
+                RegistrySync(vec![(1, Metric::new(1, vec![1, 0], 1))], vec![]),
+                [Some(TableSync(vec![(3, Metric::new(2, vec![3, 0], 1))])), None, None, None]
+            )
+        );
+
+        //old node receive sync from new node
+        let legacy: LegacyRouterSync = bincode::deserialize(&bincode::serialize(&sync).unwrap()).unwrap();
+        assert_eq!(legacy.0, vec![(1, Metric::new(1, vec![1, 0], 1))]);
+        assert_eq!(legacy.1[0].as_ref().unwrap().0.len(), 1);
+
+        //registry field keeps the legacy layout, so old node decodes it without extension fields in both directions
+        let legacy_registry: Vec<(u8, Metric)> = bincode::deserialize(&bincode::serialize(&sync).unwrap()).unwrap();
+        assert_eq!(legacy_registry, vec![(1, Metric::new(1, vec![1, 0], 1))]);
+        let decoded: RouterSync = bincode::deserialize(&bincode::serialize(&legacy).unwrap()).unwrap();
+        assert_eq!(decoded.0, RegistrySync(vec![(1, Metric::new(1, vec![1, 0], 1))], vec![]));
+    }
+
+    #[test]
+    fn cost_aware_policy() {
+        let node0: NodeId = 0x0;
+        let node1: NodeId = 0x1;
+        let node2: NodeId = 0x2;
+
+        let conn1 = ConnId::from_out(0, 0x1);
+        let conn2 = ConnId::from_out(0, 0x2);
+
+        let mut router = Router::with_policy(node0, Arc::new(CostAwarePolicy::default()));
+        router.set_conn_cost(conn1, 100);
+        router.set_conn_cost(conn2, 10);
+        router.set_direct(conn1, node1, Metric::new(1, vec![node1, node0], 1));
+        router.set_direct(conn2, node2, Metric::new(1, vec![node2, node0], 1));
+        router.apply_sync(
+            conn2,
+            node2,
+            Metric::new(1, vec![node2, node0], 1),
+            RouterSync(
+                RegistrySync(vec![], vec![]),
+                [Some(TableSync(vec![(1, Metric::new(10, vec![node1, node2], 1).with_cost(5))])), None, None, None],
+            ),
+        );
+
+        //direct path is faster but the link is expensive
+        assert_eq!(router.next(node1, &[]), Some((conn2, node2)));
+        assert_eq!(router.next_path(node1, &[]).map(|p| p.2.cost), Some(15));
+        assert_eq!(router.next(node1, &[node2]), Some((conn1, node1)));
+
+        //same topology with default policy
+        let mut router = Router::new(node0, Default::default());
+        router.set_direct(conn1, node1, Metric::new(1, vec![node1, node0], 1));
+        router.set_direct(conn2, node2, Metric::new(1, vec![node2, node0], 1));
+        router.apply_sync(
+            conn2,
+            node2,
+            Metric::new(1, vec![node2, node0], 1),
+            RouterSync(RegistrySync(vec![], vec![]), [Some(TableSync(vec![(1, Metric::new(10, vec![node1, node2], 1))])), None, None, None]),
+        );
+        assert_eq!(router.next(node1, &[]), Some((conn1, node1)));
+    }
+
     #[test]
     fn random_test_closest() {
         //TODO
@@ -1,8 +1,10 @@
+use crate::policy::RoutingPolicy;
+use crate::registry::ServiceInfo;
 use crate::router::{Router, RouterSync};
-use crate::table::{Metric, Path};
+use crate::table::{Metric, MetricCost, Path};
 use crate::ServiceDestination;
 use atm0s_sdn_identity::{ConnId, NodeId};
-use atm0s_sdn_router::{RouteAction, RouterTable};
+use atm0s_sdn_router::{RouteAction, RouterTable, ServiceMeta, ServiceSelector};
 use parking_lot::RwLock;
 use std::sync::Arc;
 
@@ -13,10 +15,19 @@ pub struct SharedRouter {
 }
 
 impl SharedRouter {
-    pub fn new(node_id: NodeId) -> Self {
+    /// Create a router, paths are ordered by the composite cost with the given weights.
+    pub fn new(node_id: NodeId, cost: MetricCost) -> Self {
         Self {
             node_id,
-            router: Arc::new(RwLock::new(Router::new(node_id))),
+            router: Arc::new(RwLock::new(Router::new(node_id, cost))),
+        }
+    }
+
+    /// Create a router which ranks paths with a custom policy.
+    pub fn with_policy(node_id: NodeId, policy: Arc<dyn RoutingPolicy>) -> Self {
+        Self {
+            node_id,
+            router: Arc::new(RwLock::new(Router::with_policy(node_id, policy))),
         }
     }
 
@@ -28,14 +39,31 @@ impl SharedRouter {
         self.router.read().size()
     }
 
+    /// Update the load and tier which this node advertises for a local service
+    pub fn set_service_info(&self, service_id: u8, info: ServiceInfo) {
+        self.router.write().set_service_info(service_id, info);
+    }
+
     pub fn service_next(&self, service_id: u8, excepts: &[NodeId]) -> Option<ServiceDestination> {
         self.router.read().service_next(service_id, excepts)
     }
 
+    pub fn service_select(&self, service_id: u8, selector: ServiceSelector, excepts: &[NodeId]) -> Option<ServiceDestination> {
+        self.router.read().service_select(service_id, selector, excepts)
+    }
+
+    pub fn service_balanced_node(&self, service_id: u8, top_n: u8) -> Option<NodeId> {
+        self.router.read().service_balanced_node(service_id, top_n)
+    }
+
     pub fn set_direct(&self, over: ConnId, over_node: NodeId, metric: Metric) {
         self.router.write().set_direct(over, over_node, metric);
     }
 
+    pub fn set_conn_cost(&self, over: ConnId, cost: u32) {
+        self.router.write().set_conn_cost(over, cost);
+    }
+
     pub fn del_direct(&self, over: ConnId) {
         self.router.write().del_direct(over);
     }
@@ -44,6 +72,14 @@ impl SharedRouter {
         self.router.read().next(dest, excepts)
     }
 
+    pub fn set_multipath(&self, tolerance_percent: Option<u8>) {
+        self.router.write().set_multipath(tolerance_percent);
+    }
+
+    pub fn next_flow(&self, dest: NodeId, excepts: &[NodeId], flow: u64) -> Option<(ConnId, NodeId)> {
+        self.router.read().next_flow(dest, excepts, flow)
+    }
+
     pub fn next_path(&self, dest: NodeId, excepts: &[NodeId]) -> Option<Path> {
         self.router.read().next_path(dest, excepts)
     }
@@ -84,6 +120,17 @@ impl RouterTable for SharedRouter {
         }
     }
 
+    fn path_to_node_flow(&self, dest: NodeId, from_node: NodeId, stream_id: u32) -> RouteAction {
+        if self.node_id == dest {
+            return RouteAction::Local;
+        }
+        let flow = (from_node as u64) << 32 | stream_id as u64;
+        match self.next_flow(dest, &[], flow) {
+            Some((conn, node)) => RouteAction::Next(conn, node),
+            None => RouteAction::Reject,
+        }
+    }
+
     fn path_to_key(&self, key: NodeId) -> RouteAction {
         match self.closest_node(key, &[]) {
             Some((conn, node, _layer, _node_index)) => RouteAction::Next(conn, node),
@@ -100,22 +147,119 @@ impl RouterTable for SharedRouter {
             None => RouteAction::Reject,
         }
     }
+
+    fn path_to_service_meta(&self, service_id: u8, meta: ServiceMeta) -> RouteAction {
+        let selector = match ServiceSelector::from_meta(meta) {
+            //only the source node balances, forwarding nodes keep the nearest path for avoiding loops
+            ServiceSelector::Balanced(_) => ServiceSelector::Nearest,
+            selector => selector,
+        };
+        match self.service_select(service_id, selector, &[]) {
+            Some(dest) => match dest {
+                ServiceDestination::Local => RouteAction::Local,
+                ServiceDestination::Remote(conn, node) => RouteAction::Next(conn, node),
+            },
+            None => RouteAction::Reject,
+        }
+    }
+
+    fn service_node_anycast(&self, service_id: u8, meta: ServiceMeta) -> Option<NodeId> {
+        match ServiceSelector::from_meta(meta) {
+            ServiceSelector::Balanced(top_n) => self.service_balanced_node(service_id, top_n),
+            _ => None,
+        }
+    }
+
+    fn path_to_service_anycast(&self, service_id: u8, meta: ServiceMeta) -> RouteAction {
+        match self.service_select(service_id, ServiceSelector::from_meta(meta), &[]) {
+            Some(dest) => match dest {
+                ServiceDestination::Local => RouteAction::Local,
+                ServiceDestination::Remote(conn, node) => RouteAction::Next(conn, node),
+            },
+            None => RouteAction::Reject,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::SharedRouter;
-    use atm0s_sdn_identity::NodeId;
+    use crate::{Metric, RegistrySync, RouterSync, ServiceInfo, SharedRouter};
+    use atm0s_sdn_identity::{ConnId, NodeId};
+    use atm0s_sdn_router::{RouteAction, RouterTable, ServiceSelector};
 
     #[test]
     fn log_dump_test() {
-        let router = SharedRouter::new(NodeId::from(1u32));
+        let router = SharedRouter::new(NodeId::from(1u32), Default::default());
         router.log_dump();
     }
 
     #[test]
     fn print_dump_test() {
-        let router = SharedRouter::new(NodeId::from(1u32));
+        let router = SharedRouter::new(NodeId::from(1u32), Default::default());
         router.print_dump();
     }
+
+    #[test]
+    fn path_to_node_flow() {
+        let node0: NodeId = 0x0;
+        let node1: NodeId = 0x1;
+        let node2: NodeId = 0x2;
+        let conn1 = ConnId::from_out(0, 0x1);
+        let conn2 = ConnId::from_out(0, 0x2);
+
+        let router = SharedRouter::new(node0, Default::default());
+        //direct path to node1 and same cost path over node2
+        router.set_direct(conn1, node1, Metric::new(20, vec![node1, node0], 100000));
+        router.set_direct(conn2, node2, Metric::new(5, vec![node2, node0], 100000));
+        router.apply_sync(
+            conn2,
+            node2,
+            Metric::new(5, vec![node2, node0], 100000),
+            RouterSync(
+                RegistrySync(vec![], vec![]),
+                [Some(crate::table::TableSync(vec![(1, Metric::new(5, vec![node1, node2], 100000))])), None, None, None],
+            ),
+        );
+
+        assert_eq!(router.path_to_node_flow(node0, node0, 1), RouteAction::Local);
+        let flows = |router: &SharedRouter| (0..100).map(|stream_id| router.path_to_node_flow(node1, node0, stream_id)).collect::<Vec<_>>();
+        assert!(flows(&router).iter().all(|action| *action == router.path_to_node(node1)));
+
+        router.set_multipath(Some(0));
+        let actions = flows(&router);
+        assert!(actions.contains(&RouteAction::Next(conn1, node1)));
+        assert!(actions.contains(&RouteAction::Next(conn2, node2)));
+        assert_eq!(actions, flows(&router));
+    }
+
+    #[test]
+    fn path_to_service_balanced() {
+        let node0: NodeId = 0x0;
+        let router = SharedRouter::new(node0, Default::default());
+        for node in [1, 2] {
+            let conn = ConnId::from_out(0, node as u64);
+            router.set_direct(conn, node, Metric::new(node as u16, vec![node, node0], 100000));
+            router.apply_sync(
+                conn,
+                node,
+                Metric::new(node as u16, vec![node, node0], 100000),
+                RouterSync(
+                    RegistrySync(vec![(1, Metric::new(0, vec![node], 100000))], vec![vec![(node, ServiceInfo::default())]]),
+                    [None, None, None, None],
+                ),
+            );
+        }
+
+        let meta = ServiceSelector::Balanced(2).to_meta();
+        let nearest = RouteAction::Next(ConnId::from_out(0, 1), 1);
+        //forwarding always use nearest
+        assert!((0..100).all(|_| router.path_to_service_meta(1, meta) == nearest));
+        assert!((0..100).any(|_| router.path_to_service_anycast(1, meta) != nearest));
+        assert_eq!(router.path_to_service_anycast(1, ServiceSelector::Nearest.to_meta()), nearest);
+
+        //source node selects the destination node, then the message is routed to it
+        assert!((0..100).any(|_| router.service_node_anycast(1, meta) == Some(1)));
+        assert!((0..100).any(|_| router.service_node_anycast(1, meta) == Some(2)));
+        assert_eq!(router.service_node_anycast(1, ServiceSelector::Nearest.to_meta()), None);
+    }
 }
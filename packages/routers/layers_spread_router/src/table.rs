@@ -1,11 +1,14 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use atm0s_sdn_identity::{ConnId, NodeId, NodeIdType};
 use atm0s_sdn_utils::init_array::init_array;
 use serde::{Deserialize, Serialize};
 
+use crate::policy::RoutingPolicy;
+
 pub use dest::Dest;
-pub use metric::{Metric, BANDWIDTH_LIMIT};
+pub use metric::{Metric, MetricCost};
 pub use path::Path;
 
 mod dest;
@@ -26,8 +29,8 @@ pub struct Table {
 }
 
 impl Table {
-    pub fn new(node_id: NodeId, layer: u8) -> Self {
-        let dests = init_array!(Dest, 256, Default::default());
+    pub fn new(node_id: NodeId, layer: u8, policy: Arc<dyn RoutingPolicy>) -> Self {
+        let dests = init_array!(Dest, 256, Dest::new(policy.clone()));
         Table { node_id, layer, dests, slots: vec![] }
     }
 
@@ -80,6 +83,11 @@ impl Table {
         self.dests[index as usize].next_path(excepts)
     }
 
+    pub fn next_multipath(&self, dest: NodeId, excepts: &[NodeId], flow: u64, tolerance_percent: u8) -> Option<(ConnId, NodeId)> {
+        let index = dest.layer(self.layer);
+        self.dests[index as usize].next_multipath(excepts, flow, tolerance_percent)
+    }
+
     pub fn closest_for(&self, key: u8, excepts: &[NodeId]) -> Option<(NodeIndex, ConnId, NodeId)> {
         let mut closest_distance: u16 = 256;
         let mut res = None;
@@ -179,13 +187,15 @@ impl Table {
 
 #[cfg(test)]
 mod tests {
-    use crate::table::{Metric, Path, Table, TableSync};
+    use std::sync::Arc;
+
+    use crate::table::{Metric, MetricCost, Path, Table, TableSync};
     use atm0s_sdn_identity::{ConnId, NodeId, NodeIdType};
 
     #[test]
     fn create_manual() {
         let node0: NodeId = 0x0;
-        let mut table = Table::new(node0, 0);
+        let mut table = Table::new(node0, 0, Arc::new(MetricCost::default()));
         let node1: NodeId = 0x1;
         let node2: NodeId = 0x2;
         let node3: NodeId = 0x3;
@@ -213,7 +223,7 @@ mod tests {
     #[test]
     fn create_manual_other_layer() {
         let node0: NodeId = 0x0;
-        let table = Table::new(node0, 0);
+        let table = Table::new(node0, 0, Arc::new(MetricCost::default()));
         assert_eq!(table.sync_for(0x10000000), None);
     }
 
@@ -221,7 +231,7 @@ mod tests {
     // #[test]
     // fn apply_sync_me() {
     //     let node0: NodeId = 0x0;
-    //     let mut table = Table::new(node0, 0);
+    //     let mut table = Table::new(node0, 0, Arc::new(MetricCost::default()));
     //
     //     let sync = vec![(0, Metric::new(1, vec![0], 1))];
     //     table.apply_sync(node0, Metric::new(1, vec![0], 1), TableSync(sync));
@@ -233,7 +243,7 @@ mod tests {
     #[test]
     fn apply_sync() {
         let node0: NodeId = 0x0;
-        let mut table = Table::new(node0, 0);
+        let mut table = Table::new(node0, 0, Arc::new(MetricCost::default()));
         let node1: NodeId = 0x1;
         let node2: NodeId = 0x2;
         let node3: NodeId = 0x3;
@@ -276,7 +286,7 @@ mod tests {
         let conn_c: ConnId = ConnId::from_out(0, 0x2);
         let _conn_d: ConnId = ConnId::from_out(0, 0x3);
 
-        let mut table_a = Table::new(node_a, 0);
+        let mut table_a = Table::new(node_a, 0, Arc::new(MetricCost::default()));
 
         table_a.add_direct(conn_b, node_b, Metric::new(1, vec![node_b, node_a], 1));
         table_a.add_direct(conn_c, node_c, Metric::new(1, vec![node_c, node_a], 1));
@@ -308,7 +318,7 @@ mod tests {
     #[test]
     fn closest_key() {
         let node0: NodeId = 0x0;
-        let mut table = Table::new(node0, 0);
+        let mut table = Table::new(node0, 0, Arc::new(MetricCost::default()));
 
         assert_eq!(table.closest_for(0, &[]), None);
         assert_eq!(table.closest_for(100, &[]), None);
@@ -1,13 +1,29 @@
-use crate::table::metric::Metric;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::policy::RoutingPolicy;
+use crate::table::metric::{Metric, MetricCost};
 use crate::table::Path;
 use atm0s_sdn_identity::{ConnId, NodeId};
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Dest {
+    policy: Arc<dyn RoutingPolicy>,
     paths: Vec<Path>,
 }
 
+impl Default for Dest {
+    fn default() -> Self {
+        Self::new(Arc::new(MetricCost::default()))
+    }
+}
+
 impl Dest {
+    pub fn new(policy: Arc<dyn RoutingPolicy>) -> Self {
+        Self { policy, paths: vec![] }
+    }
+
     pub fn set_path(&mut self, over: ConnId, over_node: NodeId, metric: Metric) {
         match self.index_of(over) {
             Some(index) => match self.paths.get_mut(index) {
@@ -22,7 +38,8 @@ impl Dest {
                 self.paths.push(Path(over, over_node, metric));
             }
         }
-        self.paths.sort();
+        let policy = &self.policy;
+        self.paths.sort_by(|a, b| policy.compare(&a.2, &b.2));
     }
 
     pub fn del_path(&mut self, over: ConnId) -> Option<()> {
@@ -35,6 +52,11 @@ impl Dest {
         }
     }
 
+    /// All paths, sorted from the best one
+    pub fn paths(&self) -> &[Path] {
+        &self.paths
+    }
+
     pub fn is_empty(&self) -> bool {
         self.paths.is_empty()
     }
@@ -49,6 +71,24 @@ impl Dest {
         None
     }
 
+    /// get next node to dest for a flow, flows are spread over the paths which are near-equal to the best one.
+    /// Each flow sticks to a path with rendezvous hashing, so a path change only moves the flows of that path.
+    pub fn next_multipath(&self, excepts: &[NodeId], flow: u64, tolerance_percent: u8) -> Option<(ConnId, NodeId)> {
+        let mut candidates = self.paths.iter().filter(|path| !excepts.contains(&path.1));
+        let best = candidates.next()?;
+        let mut selected = (flow_weight(flow, best.1), best);
+        for path in candidates {
+            if !self.policy.is_near_equal(&best.2, &path.2, tolerance_percent) {
+                break;
+            }
+            let weight = flow_weight(flow, path.1);
+            if weight > selected.0 {
+                selected = (weight, path);
+            }
+        }
+        Some((selected.1 .0, selected.1 .1))
+    }
+
     pub fn best_for(&self, neighbour_id: NodeId) -> Option<Path> {
         for path in self.paths.iter() {
             if path.1 != neighbour_id && !path.2.contain_in_hops(neighbour_id) {
@@ -88,6 +128,12 @@ impl Dest {
     }
 }
 
+fn flow_weight(flow: u64, node: NodeId) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (flow, node).hash(&mut hasher);
+    hasher.finish()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::table::{Dest, Metric, Path};
@@ -162,4 +208,49 @@ mod tests {
         assert_eq!(dest.best_for(node1), None);
         assert_eq!(dest.best_for(node2), None);
     }
+
+    #[test]
+    fn multipath() {
+        let conn1: ConnId = ConnId::from_out(0, 0x1);
+        let node1: NodeId = 0x1;
+
+        let conn2: ConnId = ConnId::from_out(0, 0x2);
+        let node2: NodeId = 0x2;
+
+        let conn3: ConnId = ConnId::from_out(0, 0x3);
+        let node3: NodeId = 0x3;
+
+        let mut dest = Dest::default();
+        dest.set_path(conn1, node1, Metric::new(10, vec![4, 1], 100000));
+        dest.set_path(conn2, node2, Metric::new(10, vec![4, 2], 100000));
+        dest.set_path(conn3, node3, Metric::new(50, vec![4, 3], 100000));
+
+        let mut used = [0; 3];
+        for flow in 0..1000 {
+            let next = dest.next_multipath(&[], flow, 0);
+            assert_eq!(next, dest.next_multipath(&[], flow, 0), "flow should be sticky");
+            match next {
+                Some((_, 1)) => used[0] += 1,
+                Some((_, 2)) => used[1] += 1,
+                _ => used[2] += 1,
+            }
+        }
+        assert!(used[0] > 300 && used[1] > 300, "{:?}", used);
+        assert_eq!(used[2], 0);
+
+        //near-equal path only used with enough tolerance
+        assert!((0..1000).any(|flow| dest.next_multipath(&[], flow, 200) == Some((conn3, node3))));
+
+        assert_eq!(dest.next_multipath(&[node1], 1, 0), Some((conn2, node2)));
+        assert_eq!(dest.next_multipath(&[node1, node2, node3], 1, 0), None);
+
+        //removing a path only moves its own flows
+        let before: Vec<_> = (0..100).map(|flow| dest.next_multipath(&[], flow, 0)).collect();
+        dest.del_path(conn1);
+        for (flow, next) in before.into_iter().enumerate() {
+            if next == Some((conn2, node2)) {
+                assert_eq!(dest.next_multipath(&[], flow as u64, 0), next);
+            }
+        }
+    }
 }
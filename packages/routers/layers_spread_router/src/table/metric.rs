@@ -26,18 +26,42 @@ pub fn concat_hops(a: &[NodeId], b: &[NodeId]) -> Option<Vec<NodeId>> {
 /// Example with local connection : A -> A => hops: [A],
 /// Example with direct connection : A -> B => hops: [B, A],
 /// Example with indirect connection : A -> B -> C => hops: [C, B, B],
+///
+/// `lost`, `jitter` and `cost` are not in the serialized form, they are synced separately in RouterSync for compatibility with older nodes.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Metric {
     pub latency: u16,      //in milliseconds
     pub hops: Vec<NodeId>, //in hops, from 1 (direct)
     pub bandwidth: u32,    //in kbps
-                           // pub lost: f32,
-                           // pub jitter: u16,
+    #[serde(skip)]
+    pub lost: u8, //in percent
+    #[serde(skip)]
+    pub jitter: u16, //in milliseconds
+    #[serde(skip)]
+    pub cost: u32, //sum of links price, see CostAwarePolicy
 }
 
 impl Metric {
     pub fn new(latency: u16, hops: Vec<NodeId>, bandwidth: u32) -> Self {
-        Metric { latency, hops, bandwidth }
+        Metric {
+            latency,
+            hops,
+            bandwidth,
+            lost: 0,
+            jitter: 0,
+            cost: 0,
+        }
+    }
+
+    pub fn with_quality(mut self, lost: u8, jitter: u16) -> Self {
+        self.lost = lost.min(100);
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_cost(mut self, cost: u32) -> Self {
+        self.cost = cost;
+        self
     }
 
     pub fn contain_in_hops(&self, node_id: NodeId) -> bool {
@@ -49,46 +73,78 @@ impl Metric {
             latency: self.latency + other.latency,
             hops: concat_hops(&self.hops, &other.hops)?,
             bandwidth: std::cmp::min(self.bandwidth, other.bandwidth),
+            lost: 100u16.saturating_sub(100u16.saturating_sub(self.lost as u16) * 100u16.saturating_sub(other.lost as u16) / 100) as u8,
+            jitter: self.jitter.saturating_add(other.jitter),
+            cost: self.cost.saturating_add(other.cost),
         })
     }
 }
 
+/// Weights of the composite path cost, which is latency plus penalties in milliseconds.
+///
+/// Paths with bandwidth under `bandwidth_limit` are only used when there is no other path,
+/// ties are broken by hop count then by bandwidth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricCost {
+    /// Penalty of each hop
+    pub hop_ms: u32,
+    /// Penalty of each percent of loss
+    pub lost_ms: u32,
+    /// Multiplier of the jitter
+    pub jitter_factor: u32,
+    /// In kbps
+    pub bandwidth_limit: u32,
+}
+
+impl Default for MetricCost {
+    fn default() -> Self {
+        Self {
+            hop_ms: HOP_PLUS_RTT as u32,
+            lost_ms: 20,
+            jitter_factor: 1,
+            bandwidth_limit: BANDWIDTH_LIMIT,
+        }
+    }
+}
+
+impl MetricCost {
+    pub fn cost(&self, metric: &Metric) -> u32 {
+        (metric.latency as u32)
+            .saturating_add((metric.hops.len() as u32).saturating_mul(self.hop_ms))
+            .saturating_add((metric.lost as u32).saturating_mul(self.lost_ms))
+            .saturating_add((metric.jitter as u32).saturating_mul(self.jitter_factor))
+    }
+
+    pub fn compare(&self, a: &Metric, b: &Metric) -> Ordering {
+        let a_bw = a.bandwidth >= self.bandwidth_limit;
+        let b_bw = b.bandwidth >= self.bandwidth_limit;
+        match (a_bw, b_bw) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => self
+                .cost(a)
+                .cmp(&self.cost(b))
+                .then_with(|| a.hops.len().cmp(&b.hops.len()))
+                .then_with(|| b.bandwidth.cmp(&a.bandwidth)),
+        }
+    }
+}
+
 impl PartialOrd for Metric {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let self_bw = self.bandwidth >= BANDWIDTH_LIMIT;
-        let other_bw = other.bandwidth >= BANDWIDTH_LIMIT;
-        match (self_bw, other_bw) {
-            (true, true) | (false, false) => {
-                let res = match (self.latency + (self.hops.len() as u16 * HOP_PLUS_RTT)).cmp(&(other.latency + (other.hops.len() as u16 * HOP_PLUS_RTT))) {
-                    Ordering::Less => Ordering::Less,
-                    Ordering::Greater => Ordering::Greater,
-                    Ordering::Equal => match self.hops.len().cmp(&other.hops.len()) {
-                        Ordering::Less => Ordering::Less,
-                        Ordering::Greater => Ordering::Greater,
-                        Ordering::Equal => match self.bandwidth.cmp(&other.bandwidth) {
-                            Ordering::Less => Ordering::Greater,
-                            Ordering::Greater => Ordering::Less,
-                            Ordering::Equal => Ordering::Equal,
-                        },
-                    },
-                };
-                Some(res)
-            }
-            (true, false) => Some(Ordering::Less),
-            (false, true) => Some(Ordering::Greater),
-        }
+        Some(MetricCost::default().compare(self, other))
     }
 }
 
 impl PartialEq<Self> for Metric {
     fn eq(&self, other: &Self) -> bool {
-        self.latency == other.latency && self.hops.len() == other.hops.len() && self.bandwidth == other.bandwidth
+        self.latency == other.latency && self.hops.len() == other.hops.len() && self.bandwidth == other.bandwidth && self.lost == other.lost && self.jitter == other.jitter && self.cost == other.cost
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::table::metric::{concat_hops, Metric};
+    use crate::table::metric::{concat_hops, Metric, MetricCost};
 
     #[test]
     fn eq() {
@@ -160,4 +216,53 @@ mod tests {
         assert!(m3 > m4);
         assert!(m4 < m3);
     }
+
+    #[test]
+    fn add_quality() {
+        let m1 = Metric::new(1, vec![1, 2], 10000).with_quality(10, 5);
+        let m2 = Metric::new(2, vec![2, 3], 20000).with_quality(10, 3);
+
+        assert_eq!(m1.add(&m2), Some(Metric::new(3, vec![1, 2, 3], 10000).with_quality(19, 8)));
+
+        let m3 = Metric::new(1, vec![1, 2], 10000).with_cost(5);
+        let m4 = Metric::new(2, vec![2, 3], 20000).with_cost(u32::MAX);
+        assert_eq!(m3.add(&m2).map(|m| m.cost), Some(5));
+        assert_eq!(m3.add(&m4).map(|m| m.cost), Some(u32::MAX));
+
+        //out of range lost from remote does not underflow
+        let mut m5 = Metric::new(2, vec![2, 3], 20000);
+        m5.lost = 200;
+        assert_eq!(m1.add(&m5).map(|m| m.lost), Some(100));
+    }
+
+    #[test]
+    fn cost_saturating() {
+        let cost = MetricCost {
+            lost_ms: u32::MAX,
+            jitter_factor: u32::MAX,
+            ..Default::default()
+        };
+        let metric = Metric::new(1, vec![1, 2], 10000).with_quality(10, 5);
+        assert_eq!(cost.cost(&metric), u32::MAX);
+    }
+
+    #[test]
+    fn compare_lost_jitter() {
+        //low latency path with 5% loss should lose against a slightly slower clean path
+        let lossy = Metric::new(20, vec![1, 2], 10000).with_quality(5, 0);
+        let clean = Metric::new(50, vec![3, 2], 10000);
+        assert!(clean < lossy);
+
+        let jitter = Metric::new(20, vec![1, 2], 10000).with_quality(0, 40);
+        assert!(clean < jitter);
+
+        //but not with a cost which ignores loss and jitter
+        let cost = MetricCost {
+            lost_ms: 0,
+            jitter_factor: 0,
+            ..Default::default()
+        };
+        assert_eq!(cost.compare(&lossy, &clean), std::cmp::Ordering::Less);
+        assert_eq!(cost.compare(&jitter, &clean), std::cmp::Ordering::Less);
+    }
 }
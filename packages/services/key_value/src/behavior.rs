@@ -1,10 +1,12 @@
 use crate::handler::KeyValueConnectionHandler;
 use crate::msg::{KeyValueBehaviorEvent, KeyValueMsg, KeyValueSdkEvent};
+use crate::storage::persistent::PersistentStorage;
 use crate::{ExternalControl, KEY_VALUE_SERVICE_ID};
 use atm0s_sdn_identity::{ConnId, NodeId};
 use atm0s_sdn_network::behaviour::{BehaviorContext, ConnectionHandler, NetworkBehavior, NetworkBehaviorAction};
 use atm0s_sdn_network::msg::{MsgHeader, TransportMsg};
 use atm0s_sdn_network::transport::{ConnectionRejectReason, ConnectionSender, OutgoingConnectionError};
+use atm0s_sdn_router::RouterTable;
 use std::collections::VecDeque;
 use std::sync::Arc;
 
@@ -13,14 +15,19 @@ use self::hashmap_remote::HashmapRemoteStorage;
 use self::simple_local::SimpleLocalStorage;
 use self::simple_remote::SimpleRemoteStorage;
 
+mod counter;
 mod event_acks;
+mod handoff;
 mod hashmap_local;
 mod hashmap_remote;
+mod replication;
 mod sdk;
 mod simple_local;
 mod simple_remote;
 
-pub use sdk::KeyValueSdk;
+pub use counter::{decode_counter, encode_counter};
+pub use replication::{ReadConsistency, ReplicationConfig};
+pub use sdk::{DistributedLock, DistributedLockError, KeyValueSdk, LeaderElection, LockGuard};
 
 #[allow(unused)]
 pub struct KeyValueBehavior<HE, SE> {
@@ -52,6 +59,46 @@ where
         }
     }
 
+    /// Same as `new` but data of remote storages is persisted and restored after restart, each storage need a separated persistent storage
+    #[allow(unused)]
+    pub fn new_persistent(
+        node_id: NodeId,
+        sync_each_ms: u64,
+        external: Option<Box<dyn ExternalControl>>,
+        simple_storage: Box<dyn PersistentStorage>,
+        hashmap_storage: Box<dyn PersistentStorage>,
+    ) -> Self {
+        log::info!("[KeyValueBehaviour {}] created persistent with sync_each_ms {}", node_id, sync_each_ms);
+        Self {
+            node_id,
+            simple_remote: SimpleRemoteStorage::new_persistent(simple_storage),
+            simple_local: SimpleLocalStorage::new(sync_each_ms),
+            hashmap_remote: HashmapRemoteStorage::new_persistent(node_id, hashmap_storage),
+            hashmap_local: HashmapLocalStorage::new(sync_each_ms),
+            outputs: VecDeque::new(),
+            external,
+        }
+    }
+
+    /// Store each key in many nodes, must be same in all nodes of the network
+    #[allow(unused)]
+    pub fn set_replication(&mut self, replication: ReplicationConfig) {
+        log::info!("[KeyValueBehaviour {}] set replication {:?}", self.node_id, replication);
+        self.simple_remote.set_replicas(replication.replicas);
+        self.simple_local.set_replication(replication);
+        self.hashmap_remote.set_replicas(replication.replicas);
+        self.hashmap_local.set_replication(replication);
+    }
+
+    /// Hand off stored keys to the new owners when they are no longer routed to this node, should be the router of the network plane.
+    /// Without router, keys are kept until expired and the new owners are filled by writers each sync_each_ms
+    #[allow(unused)]
+    pub fn set_router(&mut self, router: Arc<dyn RouterTable>) {
+        log::info!("[KeyValueBehaviour {}] set router for key handoff", self.node_id);
+        self.simple_remote.set_router(router.clone());
+        self.hashmap_remote.set_router(router);
+    }
+
     fn pop_all_events(&mut self, _ctx: &BehaviorContext, now_ms: u64) {
         while let Some(action) = self.simple_remote.pop_action(now_ms) {
             log::debug!("[KeyValueBehavior {}] pop_all_events simple remote: {:?}", self.node_id, action);
@@ -60,6 +107,13 @@ where
                 .push_back(NetworkBehaviorAction::ToNet(TransportMsg::from_payload_bincode(header, &KeyValueMsg::SimpleLocal(action.0))));
         }
 
+        while let Some((msg, route)) = self.simple_remote.pop_replica_action() {
+            log::debug!("[KeyValueBehavior {}] pop_all_events simple replica: {:?}", self.node_id, msg);
+            let header = MsgHeader::build(KEY_VALUE_SERVICE_ID, KEY_VALUE_SERVICE_ID, route).set_from_node(Some(self.node_id));
+            self.outputs
+                .push_back(NetworkBehaviorAction::ToNet(TransportMsg::from_payload_bincode(header, &KeyValueMsg::SimpleRemote(msg))));
+        }
+
         while let Some(action) = self.simple_local.pop_action() {
             match action {
                 simple_local::LocalStorageAction::SendNet(msg, route) => {
@@ -89,6 +143,29 @@ where
                         self.outputs.push_back(NetworkBehaviorAction::ToSdkService(service_id, KeyValueSdkEvent::OnGet(uuid, key, res).into()));
                     }
                 }
+                simple_local::LocalStorageAction::LocalOnIncr(service_id, uuid, key, res) => {
+                    if service_id == KEY_VALUE_SERVICE_ID {
+                        if let Some(external) = &self.external {
+                            external.on_event(KeyValueSdkEvent::OnIncr(uuid, key, res));
+                        }
+                    } else {
+                        self.outputs.push_back(NetworkBehaviorAction::ToSdkService(service_id, KeyValueSdkEvent::OnIncr(uuid, key, res).into()));
+                    }
+                }
+                simple_local::LocalStorageAction::LocalOnSynced(key, version, sent_at) => {
+                    if let Some(external) = &self.external {
+                        external.on_event(KeyValueSdkEvent::OnSynced(key, version, now_ms.saturating_sub(sent_at)));
+                    }
+                }
+                simple_local::LocalStorageAction::LocalOnCas(service_id, uuid, key, res) => {
+                    if service_id == KEY_VALUE_SERVICE_ID {
+                        if let Some(external) = &self.external {
+                            external.on_event(KeyValueSdkEvent::OnCas(uuid, key, res));
+                        }
+                    } else {
+                        self.outputs.push_back(NetworkBehaviorAction::ToSdkService(service_id, KeyValueSdkEvent::OnCas(uuid, key, res).into()));
+                    }
+                }
             }
         }
 
@@ -99,6 +176,13 @@ where
                 .push_back(NetworkBehaviorAction::ToNet(TransportMsg::from_payload_bincode(header, &KeyValueMsg::HashmapLocal(action.0))));
         }
 
+        while let Some((msg, route)) = self.hashmap_remote.pop_replica_action() {
+            log::debug!("[KeyValueBehavior {}] pop_all_events hashmap replica: {:?}", self.node_id, msg);
+            let header = MsgHeader::build(KEY_VALUE_SERVICE_ID, KEY_VALUE_SERVICE_ID, route).set_from_node(Some(self.node_id));
+            self.outputs
+                .push_back(NetworkBehaviorAction::ToNet(TransportMsg::from_payload_bincode(header, &KeyValueMsg::HashmapRemote(msg))));
+        }
+
         while let Some(action) = self.hashmap_local.pop_action() {
             match action {
                 hashmap_local::LocalStorageAction::SendNet(msg, route) => {
@@ -128,6 +212,26 @@ where
                         self.outputs.push_back(NetworkBehaviorAction::ToSdkService(service_id, KeyValueSdkEvent::OnGetH(uuid, key, res).into()));
                     }
                 }
+                hashmap_local::LocalStorageAction::LocalOnIncr(service_id, uuid, key, sub_key, res) => {
+                    if service_id == KEY_VALUE_SERVICE_ID {
+                        if let Some(external) = &self.external {
+                            external.on_event(KeyValueSdkEvent::OnIncrH(uuid, key, sub_key, res));
+                        }
+                    } else {
+                        self.outputs
+                            .push_back(NetworkBehaviorAction::ToSdkService(service_id, KeyValueSdkEvent::OnIncrH(uuid, key, sub_key, res).into()));
+                    }
+                }
+                hashmap_local::LocalStorageAction::LocalOnQuery(service_id, uuid, key, res) => {
+                    if service_id == KEY_VALUE_SERVICE_ID {
+                        if let Some(external) = &self.external {
+                            external.on_event(KeyValueSdkEvent::OnQueryH(uuid, key, res));
+                        }
+                    } else {
+                        self.outputs
+                            .push_back(NetworkBehaviorAction::ToSdkService(service_id, KeyValueSdkEvent::OnQueryH(uuid, key, res).into()));
+                    }
+                }
             }
         }
     }
@@ -177,9 +281,33 @@ where
             KeyValueSdkEvent::Del(key) => {
                 self.simple_local.del(key);
             }
+            KeyValueSdkEvent::Incr(req_id, key, delta, ex, timeout_ms) => {
+                self.simple_local.incr(now_ms, key, delta, ex, req_id, from_service, timeout_ms);
+            }
+            KeyValueSdkEvent::IncrH(req_id, key, sub_key, delta, ex, timeout_ms) => {
+                self.hashmap_local.incr(now_ms, key, sub_key, delta, ex, req_id, from_service, timeout_ms);
+            }
+            KeyValueSdkEvent::Cas(req_id, key, op, timeout_ms) => {
+                self.simple_local.cas(now_ms, key, op, req_id, from_service, timeout_ms);
+            }
             KeyValueSdkEvent::DelH(key, sub_key) => {
                 self.hashmap_local.del(key, sub_key);
             }
+            KeyValueSdkEvent::QueryH(req_id, key, query, timeout_ms) => {
+                self.hashmap_local.query(now_ms, key, query, req_id, from_service, timeout_ms);
+            }
+            KeyValueSdkEvent::KeysByPrefixH(req_id, prefix, prefix_bits) => {
+                // answered from this node storage, without network request
+                let keys = self.hashmap_remote.keys_by_prefix(prefix, prefix_bits);
+                if from_service == KEY_VALUE_SERVICE_ID {
+                    if let Some(external) = &self.external {
+                        external.on_event(KeyValueSdkEvent::OnKeysByPrefixH(req_id, keys));
+                    }
+                } else {
+                    self.outputs
+                        .push_back(NetworkBehaviorAction::ToSdkService(from_service, KeyValueSdkEvent::OnKeysByPrefixH(req_id, keys).into()));
+                }
+            }
             KeyValueSdkEvent::Sub(uuid, key, ex) => {
                 self.simple_local.subscribe(key, ex, uuid, from_service);
             }
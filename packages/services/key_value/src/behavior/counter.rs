@@ -0,0 +1,51 @@
+/// Counters are stored as normal values, which are 8 bytes big-endian i64.
+/// Increments are applied in the authoritative node of key (replica 0), so concurrent increments from many nodes are not lost.
+use crate::{KeyVersion, ValueType};
+
+pub fn encode_counter(value: i64) -> ValueType {
+    value.to_be_bytes().to_vec()
+}
+
+/// Return None if value is not a counter
+pub fn decode_counter(value: &[u8]) -> Option<i64> {
+    Some(i64::from_be_bytes(value.try_into().ok()?))
+}
+
+/// New value of counter, absent counter is 0. Return None if current value is not a counter or overflow
+pub fn incr_counter(current: Option<&[u8]>, delta: i64) -> Option<i64> {
+    match current {
+        Some(current) => decode_counter(current)?.checked_add(delta),
+        None => Some(delta),
+    }
+}
+
+/// Version which is generated like local versions but always newer than current version, incase of clock skew between nodes
+pub fn counter_version(now_ms: u64, current: Option<KeyVersion>) -> KeyVersion {
+    let version = now_ms << 16;
+    match current {
+        Some(current) => version.max(current + 1),
+        None => version,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{counter_version, decode_counter, encode_counter, incr_counter};
+
+    #[test]
+    fn incr_and_decode() {
+        assert_eq!(decode_counter(&encode_counter(-5)), Some(-5));
+        assert_eq!(decode_counter(&[1, 2, 3]), None);
+        assert_eq!(incr_counter(None, 3), Some(3));
+        assert_eq!(incr_counter(Some(&encode_counter(3)), -5), Some(-2));
+        assert_eq!(incr_counter(Some(&[1]), 1), None);
+        assert_eq!(incr_counter(Some(&encode_counter(i64::MAX)), 1), None);
+    }
+
+    #[test]
+    fn version_newer_than_current() {
+        assert_eq!(counter_version(1, None), 1 << 16);
+        assert_eq!(counter_version(1, Some(10)), 1 << 16);
+        assert_eq!(counter_version(1, Some(2 << 16)), (2 << 16) + 1);
+    }
+}
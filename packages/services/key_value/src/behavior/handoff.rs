@@ -0,0 +1,454 @@
+/// Handoff of keys when the authoritative node of a key changes, which happens when a new node joins closer to the key or the owner leaves.
+/// The old owner checks stored keys each HANDOFF_CHECK_MS, keys which are no longer routed to this node are streamed to the new owners
+/// in batches, each handoff message is acked by the new owner and unacked keys are resent each HANDOFF_CHECK_MS. Keys are kept for
+/// HANDOFF_KEEP_MS for answering fallback reads and only dropped after all of their handoff messages are acked.
+/// The new owner remembers nodes which handed off keys to it, and in the transition window reads of missing keys fall back to them.
+/// A newly joined node does not know previous owners before the first handoff arrives, so in its first HANDOFF_KEEP_MS reads of
+/// missing keys wait up to HANDOFF_CHECK_MS for a previous owner, only if some key space is routed to other nodes.
+/// A single node never waits because no other node can hand off keys to it.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use atm0s_sdn_identity::NodeId;
+use atm0s_sdn_router::{RouteAction, RouterTable};
+
+use crate::{KeyId, ReqId};
+
+use super::replication::replica_key;
+
+pub const HANDOFF_CHECK_MS: u64 = 1000;
+/// Number of keys which are sent in each tick
+pub const HANDOFF_BATCH_KEYS: usize = 100;
+/// Transition window, after that the old owner drops handed off keys and the new owner stops falling back
+pub const HANDOFF_KEEP_MS: u64 = 10000;
+pub const FALLBACK_TIMEOUT_MS: u64 = 1000;
+/// Number of keys which are spread over key space for checking if other nodes own a part of it
+const NEIGHBOUR_SAMPLE_KEYS: u32 = 16;
+
+/// A moved key which is kept until the transition window is over and all of its handoff messages are acked
+struct MovingKey {
+    drop_at: u64,
+    /// Time of the last send, None when waiting in the sending queue
+    sent_at: Option<u64>,
+    unacked: usize,
+}
+
+/// A read which is waiting for answers from previous owners, then answered to the requester
+pub struct FallbackRead {
+    pub from: NodeId,
+    pub req_id: ReqId,
+    pub key: KeyId,
+    waiting: usize,
+    timeout_at: u64,
+}
+
+pub struct KeyHandoff {
+    router: Option<Arc<dyn RouterTable>>,
+    last_check_ms: Option<u64>,
+    moving: HashMap<KeyId, MovingKey>,
+    sending: VecDeque<KeyId>,
+    handoff_seed: ReqId,
+    /// Handoff messages which are waiting for ack from the new owner
+    handoff_reqs: HashMap<ReqId, KeyId>,
+    /// End of join window, which is started at the first tick with router
+    join_until: Option<u64>,
+    /// Nodes which handed off keys to this node, with end of transition
+    previous_owners: HashMap<NodeId, u64>,
+    fallback_seed: ReqId,
+    fallback_reads: HashMap<ReqId, FallbackRead>,
+}
+
+impl KeyHandoff {
+    pub fn new() -> Self {
+        Self {
+            router: None,
+            last_check_ms: None,
+            moving: HashMap::new(),
+            sending: VecDeque::new(),
+            handoff_seed: 0,
+            handoff_reqs: HashMap::new(),
+            join_until: None,
+            previous_owners: HashMap::new(),
+            fallback_seed: 0,
+            fallback_reads: HashMap::new(),
+        }
+    }
+
+    /// Without router, all stored keys are considered owned by this node
+    pub fn set_router(&mut self, router: Arc<dyn RouterTable>) {
+        self.router = Some(router);
+    }
+
+    /// This node is one of replicas of key. Rejected routes are considered owned because the new owner is unknown
+    pub fn is_owner(&self, key: KeyId, replicas: u8) -> bool {
+        match &self.router {
+            Some(router) => (0..replicas.max(1)).any(|index| !matches!(router.path_to_key(replica_key(key, index)), RouteAction::Next(_, _))),
+            None => true,
+        }
+    }
+
+    /// Some part of key space is routed to other nodes, then keys of this node may be owned by them before it joined
+    fn has_neighbours(&self) -> bool {
+        match &self.router {
+            Some(router) => (0..NEIGHBOUR_SAMPLE_KEYS).any(|i| matches!(router.path_to_key(i * (u32::MAX / NEIGHBOUR_SAMPLE_KEYS)), RouteAction::Next(_, _))),
+            None => false,
+        }
+    }
+
+    /// Check stored keys each HANDOFF_CHECK_MS, return keys which should be sent to new owners in this tick and keys which should be dropped
+    pub fn tick(&mut self, now_ms: u64, replicas: u8, keys: impl Iterator<Item = KeyId>) -> (Vec<KeyId>, Vec<KeyId>) {
+        if self.router.is_none() {
+            return (vec![], vec![]);
+        }
+        self.join_until.get_or_insert(now_ms + HANDOFF_KEEP_MS);
+
+        let should_check = match self.last_check_ms {
+            Some(last) => now_ms >= last + HANDOFF_CHECK_MS,
+            None => true,
+        };
+        if should_check {
+            self.last_check_ms = Some(now_ms);
+            let moved = keys.filter(|key| !self.moving.contains_key(key) && !self.is_owner(*key, replicas)).collect::<Vec<_>>();
+            // keys which are routed to this node again are kept
+            let returned = self.moving.keys().filter(|key| self.is_owner(**key, replicas)).copied().collect::<Vec<_>>();
+            for key in returned {
+                log::info!("[KeyHandoff] key {} is owned again, cancel handoff", key);
+                self.moving.remove(&key);
+                self.handoff_reqs.retain(|_, req_key| *req_key != key);
+            }
+            // keys which are not fully acked in HANDOFF_CHECK_MS are sent again
+            let unacked = self
+                .moving
+                .iter()
+                .filter(|(_, moving)| moving.unacked > 0 && matches!(moving.sent_at, Some(sent_at) if sent_at + HANDOFF_CHECK_MS <= now_ms))
+                .map(|(key, _)| *key)
+                .collect::<Vec<_>>();
+            for key in unacked {
+                log::warn!("[KeyHandoff] key {} handoff is not acked, resend", key);
+                self.handoff_reqs.retain(|_, req_key| *req_key != key);
+                if let Some(moving) = self.moving.get_mut(&key) {
+                    moving.sent_at = None;
+                    moving.unacked = 0;
+                }
+                self.sending.push_back(key);
+            }
+            for key in moved {
+                log::info!("[KeyHandoff] key {} is moved to other node, start handoff", key);
+                self.moving.insert(
+                    key,
+                    MovingKey {
+                        drop_at: now_ms + HANDOFF_KEEP_MS,
+                        sent_at: None,
+                        unacked: 0,
+                    },
+                );
+                self.sending.push_back(key);
+            }
+        }
+        self.previous_owners.retain(|_, until| *until > now_ms);
+
+        let mut send = Vec::new();
+        while send.len() < HANDOFF_BATCH_KEYS {
+            match self.sending.pop_front() {
+                Some(key) => {
+                    if let Some(moving) = self.moving.get_mut(&key) {
+                        if moving.sent_at.is_none() {
+                            moving.sent_at = Some(now_ms);
+                            send.push(key);
+                        }
+                    }
+                }
+                None => break,
+            }
+        }
+        // keys which are sent in this tick are not counted as unacked yet
+        let drop = self
+            .moving
+            .iter()
+            .filter(|(key, moving)| moving.drop_at <= now_ms && moving.sent_at.is_some() && moving.unacked == 0 && !send.contains(key))
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in &drop {
+            self.moving.remove(key);
+        }
+        (send, drop)
+    }
+
+    /// A handoff message of key is sent to the new owner, return req_id which should be acked
+    pub fn on_sent(&mut self, key: KeyId) -> ReqId {
+        let req_id = self.handoff_seed;
+        self.handoff_seed += 1;
+        self.handoff_reqs.insert(req_id, key);
+        if let Some(moving) = self.moving.get_mut(&key) {
+            moving.unacked += 1;
+        }
+        req_id
+    }
+
+    /// The new owner acked a handoff message
+    pub fn on_handoff_ack(&mut self, req_id: ReqId) {
+        if let Some(key) = self.handoff_reqs.remove(&req_id) {
+            if let Some(moving) = self.moving.get_mut(&key) {
+                moving.unacked = moving.unacked.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Received handoff keys from previous owner, return reads which were waiting for a previous owner and should be asked from it
+    pub fn on_handoff(&mut self, now_ms: u64, from: NodeId) -> Vec<(ReqId, KeyId)> {
+        self.previous_owners.insert(from, now_ms + HANDOFF_KEEP_MS);
+        self.fallback_reads
+            .iter_mut()
+            .filter(|(_, read)| read.waiting == 0)
+            .map(|(fallback_req_id, read)| {
+                read.waiting = 1;
+                read.timeout_at = now_ms + FALLBACK_TIMEOUT_MS;
+                (*fallback_req_id, read.key)
+            })
+            .collect()
+    }
+
+    /// Start a fallback read if in transition, return fallback req_id and previous owners which should be asked.
+    /// In the join window, a read of `missing` key without known previous owners waits for the first handoff
+    pub fn fallback_read(&mut self, now_ms: u64, from: NodeId, req_id: ReqId, key: KeyId, missing: bool) -> Option<(ReqId, Vec<NodeId>)> {
+        let nodes = self.previous_owners.iter().filter(|(_, until)| **until > now_ms).map(|(node, _)| *node).collect::<Vec<_>>();
+        let joining = missing && nodes.is_empty() && matches!(self.join_until, Some(until) if until > now_ms) && self.has_neighbours();
+        if nodes.is_empty() && !joining {
+            return None;
+        }
+        let fallback_req_id = self.fallback_seed;
+        self.fallback_seed += 1;
+        self.fallback_reads.insert(
+            fallback_req_id,
+            FallbackRead {
+                from,
+                req_id,
+                key,
+                waiting: nodes.len(),
+                timeout_at: now_ms
+                    + if nodes.is_empty() {
+                        HANDOFF_CHECK_MS
+                    } else {
+                        FALLBACK_TIMEOUT_MS
+                    },
+            },
+        );
+        Some((fallback_req_id, nodes))
+    }
+
+    /// Answer from a previous owner, return the read if it should be answered now, which is when all answered or `done`
+    pub fn on_fallback_answer(&mut self, fallback_req_id: ReqId, done: bool) -> Option<FallbackRead> {
+        let read = self.fallback_reads.get_mut(&fallback_req_id)?;
+        read.waiting = read.waiting.saturating_sub(1);
+        if done || read.waiting == 0 {
+            self.fallback_reads.remove(&fallback_req_id)
+        } else {
+            None
+        }
+    }
+
+    /// Fallback reads which are timeout, they are answered with data of this node
+    pub fn pop_fallback_timeouts(&mut self, now_ms: u64) -> Vec<FallbackRead> {
+        let timeout = self.fallback_reads.iter().filter(|(_, read)| read.timeout_at <= now_ms).map(|(req_id, _)| *req_id).collect::<Vec<_>>();
+        timeout.into_iter().filter_map(|req_id| self.fallback_reads.remove(&req_id)).collect()
+    }
+}
+
+/// Remaining time of an absolute expire time, which is sent as ex
+pub fn remain_ex(now_ms: u64, expire_at: Option<u64>) -> Option<u64> {
+    expire_at.map(|expire_at| expire_at.saturating_sub(now_ms))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use atm0s_sdn_identity::{ConnId, NodeId};
+    use atm0s_sdn_router::{RouteAction, RouterTable};
+    use parking_lot::RwLock;
+
+    use super::{KeyHandoff, HANDOFF_BATCH_KEYS, HANDOFF_CHECK_MS, HANDOFF_KEEP_MS};
+
+    /// Router which routes keys in set to other node, other keys are local
+    #[derive(Default)]
+    pub(crate) struct MovedKeysRouter {
+        pub(crate) moved: RwLock<HashSet<u32>>,
+    }
+
+    impl RouterTable for MovedKeysRouter {
+        fn register_service(&self, _service_id: u8) {}
+
+        fn path_to_node(&self, dest: NodeId) -> RouteAction {
+            RouteAction::Next(ConnId::from_in(1, 1), dest)
+        }
+
+        fn path_to_key(&self, key: NodeId) -> RouteAction {
+            if self.moved.read().contains(&key) {
+                RouteAction::Next(ConnId::from_in(1, 1), 2)
+            } else {
+                RouteAction::Local
+            }
+        }
+
+        fn path_to_service(&self, _service_id: u8) -> RouteAction {
+            RouteAction::Local
+        }
+    }
+
+    #[test]
+    fn without_router_keep_all_keys() {
+        let mut handoff = KeyHandoff::new();
+        assert!(handoff.is_owner(1, 1));
+        assert_eq!(handoff.tick(0, 1, [1, 2].into_iter()), (vec![], vec![]));
+    }
+
+    #[test]
+    fn moved_keys_sent_then_dropped() {
+        let router = Arc::new(MovedKeysRouter::default());
+        let mut handoff = KeyHandoff::new();
+        handoff.set_router(router.clone());
+
+        assert_eq!(handoff.tick(0, 1, [1, 2].into_iter()), (vec![], vec![]));
+
+        // moved keys are only checked each HANDOFF_CHECK_MS
+        router.moved.write().insert(1);
+        assert_eq!(handoff.tick(100, 1, [1, 2].into_iter()), (vec![], vec![]));
+        assert_eq!(handoff.tick(HANDOFF_CHECK_MS, 1, [1, 2].into_iter()), (vec![1], vec![]));
+        assert_eq!(handoff.tick(2 * HANDOFF_CHECK_MS, 1, [1, 2].into_iter()), (vec![], vec![]));
+
+        assert_eq!(handoff.tick(HANDOFF_CHECK_MS + HANDOFF_KEEP_MS, 1, [1, 2].into_iter()), (vec![], vec![1]));
+    }
+
+    #[test]
+    fn drop_only_acked_keys() {
+        let router = Arc::new(MovedKeysRouter::default());
+        router.moved.write().insert(1);
+        let mut handoff = KeyHandoff::new();
+        handoff.set_router(router);
+
+        assert_eq!(handoff.tick(0, 1, [1].into_iter()), (vec![1], vec![]));
+        let req1 = handoff.on_sent(1);
+        let req2 = handoff.on_sent(1);
+        handoff.on_handoff_ack(req1);
+
+        // not fully acked, send again
+        assert_eq!(handoff.tick(HANDOFF_CHECK_MS, 1, [1].into_iter()), (vec![1], vec![]));
+        handoff.on_sent(1);
+        // ack of previous send is ignored, so it is not dropped after transition window
+        handoff.on_handoff_ack(req2);
+        assert_eq!(handoff.tick(HANDOFF_KEEP_MS, 1, [1].into_iter()), (vec![1], vec![]));
+
+        let req4 = handoff.on_sent(1);
+        handoff.on_handoff_ack(req4);
+        assert_eq!(handoff.tick(HANDOFF_KEEP_MS + 1, 1, [1].into_iter()), (vec![], vec![1]));
+    }
+
+    #[test]
+    fn owned_again_cancel_handoff() {
+        let router = Arc::new(MovedKeysRouter::default());
+        router.moved.write().insert(1);
+        let mut handoff = KeyHandoff::new();
+        handoff.set_router(router.clone());
+
+        assert_eq!(handoff.tick(0, 1, [1].into_iter()), (vec![1], vec![]));
+        router.moved.write().clear();
+        assert_eq!(handoff.tick(HANDOFF_KEEP_MS, 1, [1].into_iter()), (vec![], vec![]));
+    }
+
+    #[test]
+    fn send_in_batches() {
+        let router = Arc::new(MovedKeysRouter::default());
+        let keys = 0..(HANDOFF_BATCH_KEYS as u64 + 1);
+        router.moved.write().extend(keys.clone().map(|key| key as u32));
+        let mut handoff = KeyHandoff::new();
+        handoff.set_router(router);
+
+        assert_eq!(handoff.tick(0, 1, keys.clone()).0.len(), HANDOFF_BATCH_KEYS);
+        assert_eq!(handoff.tick(1, 1, keys).0.len(), 1);
+    }
+
+    #[test]
+    fn replica_keeps_key() {
+        let router = Arc::new(MovedKeysRouter::default());
+        router.moved.write().insert(1);
+        let handoff = {
+            let mut handoff = KeyHandoff::new();
+            handoff.set_router(router);
+            handoff
+        };
+        assert!(!handoff.is_owner(1, 1));
+        // replica 1 of key is still routed to this node
+        assert!(handoff.is_owner(1, 2));
+    }
+
+    #[test]
+    fn fallback_read() {
+        let mut handoff = KeyHandoff::new();
+        assert_eq!(handoff.fallback_read(0, 1000, 1, 10, true), None);
+
+        handoff.on_handoff(0, 2);
+        handoff.on_handoff(0, 3);
+        let (fallback_req_id, mut nodes) = handoff.fallback_read(0, 1000, 1, 10, true).expect("Should fallback");
+        nodes.sort();
+        assert_eq!(nodes, vec![2, 3]);
+        assert!(handoff.on_fallback_answer(fallback_req_id, false).is_none());
+        let read = handoff.on_fallback_answer(fallback_req_id, false).expect("Should finish");
+        assert_eq!((read.from, read.req_id, read.key), (1000, 1, 10));
+
+        // done with first answer
+        let (fallback_req_id, _) = handoff.fallback_read(0, 1000, 2, 10, true).expect("Should fallback");
+        assert!(handoff.on_fallback_answer(fallback_req_id, true).is_some());
+
+        // timeout
+        let (_, _) = handoff.fallback_read(0, 1000, 3, 10, true).expect("Should fallback");
+        assert_eq!(handoff.pop_fallback_timeouts(super::FALLBACK_TIMEOUT_MS).len(), 1);
+
+        // after transition window
+        assert_eq!(handoff.fallback_read(HANDOFF_KEEP_MS, 1000, 4, 10, true), None);
+    }
+
+    #[test]
+    fn single_node_should_not_wait_handoff() {
+        let mut handoff = KeyHandoff::new();
+        handoff.set_router(Arc::new(MovedKeysRouter::default()));
+        handoff.tick(0, 1, [].into_iter());
+        assert_eq!(handoff.fallback_read(10, 1000, 1, 10, true), None);
+    }
+
+    /// Router of a joined node, which routes a part of key space to the neighbour
+    fn joined_router() -> Arc<MovedKeysRouter> {
+        let router = Arc::new(MovedKeysRouter::default());
+        router.moved.write().insert(0);
+        router
+    }
+
+    #[test]
+    fn joined_node_wait_first_handoff() {
+        let mut handoff = KeyHandoff::new();
+        handoff.set_router(joined_router());
+        handoff.tick(0, 1, [].into_iter());
+
+        // existing key is answered directly
+        assert_eq!(handoff.fallback_read(10, 1000, 1, 10, false), None);
+
+        // missing key waits for previous owner
+        let (fallback_req_id, nodes) = handoff.fallback_read(10, 1000, 2, 10, true).expect("Should wait");
+        assert_eq!(nodes, vec![]);
+        assert_eq!(handoff.on_handoff(20, 2), vec![(fallback_req_id, 10)]);
+        let read = handoff.on_fallback_answer(fallback_req_id, false).expect("Should finish");
+        assert_eq!((read.from, read.req_id, read.key), (1000, 2, 10));
+
+        // without previous owner, answered after HANDOFF_CHECK_MS
+        let mut handoff = KeyHandoff::new();
+        handoff.set_router(joined_router());
+        handoff.tick(0, 1, [].into_iter());
+        handoff.fallback_read(10, 1000, 3, 10, true).expect("Should wait");
+        assert_eq!(handoff.pop_fallback_timeouts(9 + HANDOFF_CHECK_MS).len(), 0);
+        assert_eq!(handoff.pop_fallback_timeouts(10 + HANDOFF_CHECK_MS).len(), 1);
+
+        // after join window
+        assert_eq!(handoff.fallback_read(HANDOFF_KEEP_MS, 1000, 4, 10, true), None);
+    }
+}
@@ -1,10 +1,12 @@
 use crate::{
-    msg::{HashmapLocalEvent, HashmapRemoteEvent, KeyValueSdkEventError},
+    msg::{HashmapLocalEvent, HashmapRemoteEvent, KeyValueHashmapQuery, KeyValueHashmapQueryResult, KeyValueSdkEventError, SubKeysWithEx},
     KeyId, KeySource, KeyVersion, ReqId, SubKeyId, ValueType,
 };
 use atm0s_sdn_identity::NodeId;
 use atm0s_sdn_router::RouteRule;
 use small_map::SmallMap;
+
+use super::replication::{replica_route, ReplicaAnswers, ReplicationConfig};
 /// This hashmap local storage is used for storing and act with remote storage
 /// Main idea is we using sdk to act with local storage, and local storage will sync that data to remote
 /// Local storage allow us to set/get/del/subscribe/unsubscribe
@@ -45,18 +47,50 @@ pub enum HashmapKeyValueGetError {
     InternalError,
 }
 
+type HashmapValues = Vec<(SubKeyId, ValueType, KeyVersion, KeySource)>;
+/// Value of a sub key with remaining ex, which is merged from answers of replicas
+type MergedValue = (ValueType, KeyVersion, KeySource, Option<u64>);
+
 struct KeySlotGetCallback {
     key: KeyId,
     timeout_after_ts: u64,
     uuid: u64,
     service_id: u8,
+    answers: ReplicaAnswers<SubKeysWithEx>,
+    replica_reqs: Vec<ReqId>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HashmapKeyValueIncrError {
+    NetworkError,
+    Timeout,
+    InternalError,
+    /// Value is not a counter or overflow
+    InvalidValue,
+}
+
+struct KeySlotIncrCallback {
+    key: KeyId,
+    sub_key: SubKeyId,
+    timeout_after_ts: u64,
+    uuid: u64,
+    service_id: u8,
+}
+
+struct KeySlotQueryCallback {
+    key: KeyId,
+    timeout_after_ts: u64,
+    uuid: u64,
+    service_id: u8,
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum LocalStorageAction {
     SendNet(HashmapRemoteEvent, RouteRule),
     LocalOnChanged(u8, u64, KeyId, SubKeyId, Option<ValueType>, KeyVersion, KeySource),
-    LocalOnGet(u8, u64, KeyId, Result<Option<Vec<(SubKeyId, ValueType, KeyVersion, KeySource)>>, KeyValueSdkEventError>),
+    LocalOnGet(u8, u64, KeyId, Result<Option<HashmapValues>, KeyValueSdkEventError>),
+    LocalOnQuery(u8, u64, KeyId, Result<KeyValueHashmapQueryResult, KeyValueSdkEventError>),
+    LocalOnIncr(u8, u64, KeyId, SubKeyId, Result<Option<i64>, KeyValueSdkEventError>),
 }
 
 pub struct HashmapLocalStorage {
@@ -67,6 +101,10 @@ pub struct HashmapLocalStorage {
     subscribe: HashMap<KeyId, KeySlotSubscribe>,
     output_events: VecDeque<LocalStorageAction>,
     get_queue: HashMap<ReqId, KeySlotGetCallback>,
+    get_replica_reqs: HashMap<ReqId, (ReqId, u8)>,
+    query_queue: HashMap<ReqId, KeySlotQueryCallback>,
+    incr_queue: HashMap<ReqId, KeySlotIncrCallback>,
+    replication: ReplicationConfig,
 }
 
 impl HashmapLocalStorage {
@@ -80,9 +118,18 @@ impl HashmapLocalStorage {
             subscribe: HashMap::new(),
             output_events: VecDeque::new(),
             get_queue: HashMap::new(),
+            get_replica_reqs: HashMap::new(),
+            query_queue: HashMap::new(),
+            incr_queue: HashMap::new(),
+            replication: ReplicationConfig::default(),
         }
     }
 
+    /// Replication config, which is used for reading from all replicas
+    pub fn set_replication(&mut self, replication: ReplicationConfig) {
+        self.replication = replication;
+    }
+
     fn gen_req_id(&self) -> u64 {
         return self.req_id_seed.fetch_add(1, Ordering::SeqCst);
     }
@@ -189,12 +236,49 @@ impl HashmapLocalStorage {
             }
         }
 
-        // we clear timeout getter
+        // we clear timeout getter, answers from a part of replicas are only used with Any consistency
         for req_id in timeout_gets {
-            if let Some(slot) = self.get_queue.remove(&req_id) {
+            if let Some(mut slot) = self.get_queue.remove(&req_id) {
                 log::debug!("[HashmapLocal] get key {} timeout", req_id);
+                if slot.answers.resolve() {
+                    let res = if slot.answers.allow_partial() {
+                        Ok(Self::merge_answers(&slot.answers).map(Self::without_ex))
+                    } else {
+                        Err(KeyValueSdkEventError::Timeout)
+                    };
+                    self.output_events.push_back(LocalStorageAction::LocalOnGet(slot.service_id, slot.uuid, slot.key, res));
+                }
+                self.finish_get(slot);
+            }
+        }
+
+        let mut timeout_queries = Vec::new();
+        for (req_id, slot) in self.query_queue.iter() {
+            if now >= slot.timeout_after_ts {
+                timeout_queries.push(*req_id);
+            }
+        }
+
+        for req_id in timeout_queries {
+            if let Some(slot) = self.query_queue.remove(&req_id) {
+                log::debug!("[HashmapLocal] query key {} timeout", req_id);
                 self.output_events
-                    .push_back(LocalStorageAction::LocalOnGet(slot.service_id, slot.uuid, slot.key, Err(KeyValueSdkEventError::Timeout)));
+                    .push_back(LocalStorageAction::LocalOnQuery(slot.service_id, slot.uuid, slot.key, Err(KeyValueSdkEventError::Timeout)));
+            }
+        }
+
+        let mut timeout_incrs = Vec::new();
+        for (req_id, slot) in self.incr_queue.iter() {
+            if now >= slot.timeout_after_ts {
+                timeout_incrs.push(*req_id);
+            }
+        }
+
+        for req_id in timeout_incrs {
+            if let Some(slot) = self.incr_queue.remove(&req_id) {
+                log::debug!("[HashmapLocal] incr key {} timeout", req_id);
+                self.output_events
+                    .push_back(LocalStorageAction::LocalOnIncr(slot.service_id, slot.uuid, slot.key, slot.sub_key, Err(KeyValueSdkEventError::Timeout)));
             }
         }
 
@@ -231,9 +315,17 @@ impl HashmapLocalStorage {
                 }
             }
             HashmapLocalEvent::GetAck(req_id, _key, value) => {
-                if let Some(slot) = self.get_queue.remove(&req_id) {
-                    self.output_events.push_back(LocalStorageAction::LocalOnGet(slot.service_id, slot.uuid, slot.key, Ok(value)));
-                } else {
+                let (req_id, index) = self.get_replica_reqs.remove(&req_id).unwrap_or((req_id, 0));
+                if let Some(slot) = self.get_queue.get_mut(&req_id) {
+                    if slot.answers.on_answer(index, value) {
+                        let res = Self::merge_answers(&slot.answers).map(Self::without_ex);
+                        self.output_events.push_back(LocalStorageAction::LocalOnGet(slot.service_id, slot.uuid, slot.key, Ok(res)));
+                    }
+                    if slot.answers.is_finished() {
+                        if let Some(slot) = self.get_queue.remove(&req_id) {
+                            self.finish_get(slot);
+                        }
+                    }
                 }
             }
             HashmapLocalEvent::DelAck(_req_id, key, sub_key, version) => {
@@ -291,6 +383,28 @@ impl HashmapLocalStorage {
                     }
                 }
             }
+            HashmapLocalEvent::ScanAck(req_id, _key, sub_keys, next_cursor) => {
+                if let Some(slot) = self.query_queue.remove(&req_id) {
+                    self.output_events.push_back(LocalStorageAction::LocalOnQuery(
+                        slot.service_id,
+                        slot.uuid,
+                        slot.key,
+                        Ok(KeyValueHashmapQueryResult::Scan(sub_keys, next_cursor)),
+                    ));
+                }
+            }
+            HashmapLocalEvent::IncrAck(req_id, _key, _sub_key, value) => {
+                if let Some(slot) = self.incr_queue.remove(&req_id) {
+                    self.output_events
+                        .push_back(LocalStorageAction::LocalOnIncr(slot.service_id, slot.uuid, slot.key, slot.sub_key, Ok(value)));
+                }
+            }
+            HashmapLocalEvent::LenAck(req_id, _key, len) => {
+                if let Some(slot) = self.query_queue.remove(&req_id) {
+                    self.output_events
+                        .push_back(LocalStorageAction::LocalOnQuery(slot.service_id, slot.uuid, slot.key, Ok(KeyValueHashmapQueryResult::Len(len))));
+                }
+            }
         }
     }
 
@@ -319,9 +433,19 @@ impl HashmapLocalStorage {
         ));
     }
 
+    /// Get from all replicas, each replica is requested with a separated req_id
     pub fn get(&mut self, now_ms: u64, key: KeyId, uuid: u64, service_id: u8, timeout_ms: u64) {
         let req_id = self.gen_req_id();
         log::debug!("[HashmapLocal] get key {} with req_id {}", key, req_id);
+        self.output_events.push_back(LocalStorageAction::SendNet(HashmapRemoteEvent::Get(req_id, key), replica_route(key, 0)));
+        let mut replica_reqs = vec![];
+        for index in 1..self.replication.replicas {
+            let replica_req_id = self.gen_req_id();
+            self.get_replica_reqs.insert(replica_req_id, (req_id, index));
+            replica_reqs.push(replica_req_id);
+            self.output_events
+                .push_back(LocalStorageAction::SendNet(HashmapRemoteEvent::Get(replica_req_id, key), replica_route(key, index)));
+        }
         self.get_queue.insert(
             req_id,
             KeySlotGetCallback {
@@ -329,10 +453,111 @@ impl HashmapLocalStorage {
                 timeout_after_ts: now_ms + timeout_ms,
                 uuid,
                 service_id,
+                answers: ReplicaAnswers::new(self.replication),
+                replica_reqs,
+            },
+        );
+    }
+
+    /// Increase counter in the authoritative node of key, the counter is not synced like set because it is not owned by this node
+    #[allow(clippy::too_many_arguments)]
+    pub fn incr(&mut self, now_ms: u64, key: KeyId, sub_key: SubKeyId, delta: i64, ex: Option<u64>, uuid: u64, service_id: u8, timeout_ms: u64) {
+        let req_id = self.gen_req_id();
+        log::debug!("[HashmapLocal] incr key {} sub_key {} by {} with req_id {}", key, sub_key, delta, req_id);
+        self.incr_queue.insert(
+            req_id,
+            KeySlotIncrCallback {
+                key,
+                sub_key,
+                timeout_after_ts: now_ms + timeout_ms,
+                uuid,
+                service_id,
             },
         );
         self.output_events
-            .push_back(LocalStorageAction::SendNet(HashmapRemoteEvent::Get(req_id, key), RouteRule::ToKey(key as u32)));
+            .push_back(LocalStorageAction::SendNet(HashmapRemoteEvent::Incr(req_id, key, sub_key, delta, ex), replica_route(key, 0)));
+    }
+
+    /// Query part of key from replica 0, without merging from other replicas like get
+    pub fn query(&mut self, now_ms: u64, key: KeyId, query: KeyValueHashmapQuery, uuid: u64, service_id: u8, timeout_ms: u64) {
+        let req_id = self.gen_req_id();
+        log::debug!("[HashmapLocal] query key {} {:?} with req_id {}", key, query, req_id);
+        let event = match query {
+            KeyValueHashmapQuery::Scan(cursor, limit) => HashmapRemoteEvent::Scan(req_id, key, cursor, limit),
+            KeyValueHashmapQuery::Len => HashmapRemoteEvent::Len(req_id, key),
+        };
+        self.query_queue.insert(
+            req_id,
+            KeySlotQueryCallback {
+                key,
+                timeout_after_ts: now_ms + timeout_ms,
+                uuid,
+                service_id,
+            },
+        );
+        self.output_events.push_back(LocalStorageAction::SendNet(event, replica_route(key, 0)));
+    }
+
+    /// Merge answers of replicas by taking the highest version of each sub key, a single answer is returned as it is
+    fn merge_answers(answers: &ReplicaAnswers<SubKeysWithEx>) -> Option<SubKeysWithEx> {
+        let mut values = answers.answers().iter().filter_map(|(_, answer)| answer.as_ref());
+        let first = values.next()?;
+        let mut merged: Option<HashMap<SubKeyId, MergedValue>> = None;
+        for other in values {
+            let merged = merged.get_or_insert_with(|| first.iter().map(|(sub_key, value, version, source, ex)| (*sub_key, (value.clone(), *version, *source, *ex))).collect());
+            for (sub_key, value, version, source, ex) in other {
+                if merged.get(sub_key).map(|(_, v, ..)| v < version).unwrap_or(true) {
+                    merged.insert(*sub_key, (value.clone(), *version, *source, *ex));
+                }
+            }
+        }
+        match merged {
+            Some(merged) => {
+                let mut res = merged
+                    .into_iter()
+                    .map(|(sub_key, (value, version, source, ex))| (sub_key, value, version, source, ex))
+                    .collect::<Vec<_>>();
+                res.sort_by_key(|(sub_key, ..)| *sub_key);
+                Some(res)
+            }
+            None => Some(first.clone()),
+        }
+    }
+
+    fn without_ex(values: SubKeysWithEx) -> HashmapValues {
+        values.into_iter().map(|(sub_key, value, version, source, _)| (sub_key, value, version, source)).collect()
+    }
+
+    /// Repair replicas which answered older versions of sub keys, then clear the request.
+    /// Replicas which answered without a sub key are not repaired, because it may be deleted there after the other replicas were written
+    fn finish_get(&mut self, slot: KeySlotGetCallback) {
+        for replica_req_id in slot.replica_reqs.iter() {
+            self.get_replica_reqs.remove(replica_req_id);
+        }
+        if self.replication.replicas <= 1 {
+            return;
+        }
+        let merged = match Self::merge_answers(&slot.answers) {
+            Some(merged) => merged,
+            None => return,
+        };
+        for (index, answer) in slot.answers.answers() {
+            // remaining ex of the answer is kept, so sub keys without expiry like counters are not expired by repairing
+            for (sub_key, value, version, source, ex) in merged.iter() {
+                let stale = answer
+                    .as_ref()
+                    .and_then(|values| values.iter().find(|(s, ..)| s == sub_key))
+                    .map(|(_, _, v, ..)| v < version)
+                    .unwrap_or(false);
+                if stale {
+                    log::info!("[HashmapLocal] repair key {} sub_key {} replica {} with version {}", slot.key, sub_key, index, version);
+                    self.output_events.push_back(LocalStorageAction::SendNet(
+                        HashmapRemoteEvent::ReplicaSet(slot.key, *sub_key, value.clone(), *version, *source, *ex),
+                        replica_route(slot.key, *index),
+                    ));
+                }
+            }
+        }
     }
 
     pub fn del(&mut self, key: KeyId, sub_key: SubKeyId) {
@@ -404,8 +629,11 @@ mod tests {
     use atm0s_sdn_router::RouteRule;
 
     use crate::{
-        behavior::hashmap_local::LocalStorageAction,
-        msg::{HashmapLocalEvent, HashmapRemoteEvent, KeyValueSdkEventError},
+        behavior::{
+            hashmap_local::LocalStorageAction,
+            replication::{replica_route, ReadConsistency, ReplicationConfig},
+        },
+        msg::{HashmapLocalEvent, HashmapRemoteEvent, KeyValueHashmapQuery, KeyValueHashmapQueryResult, KeyValueSdkEventError},
     };
 
     use super::HashmapLocalStorage;
@@ -728,7 +956,7 @@ mod tests {
         assert_eq!(storage.pop_action(), None);
 
         //fake received result
-        storage.on_event(2, HashmapLocalEvent::GetAck(0, 1, Some(vec![(2, vec![1], 0, 1000)])));
+        storage.on_event(2, HashmapLocalEvent::GetAck(0, 1, Some(vec![(2, vec![1], 0, 1000, None)])));
 
         assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnGet(10, 11111, 1, Ok(Some(vec![(2, vec![1], 0, 1000)])))));
         assert_eq!(storage.pop_action(), None);
@@ -748,4 +976,61 @@ mod tests {
         assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnGet(10, 11111, 1, Err(KeyValueSdkEventError::Timeout))));
         assert_eq!(storage.pop_action(), None);
     }
+
+    #[test]
+    fn replicated_get_should_merge_sub_keys_and_repair() {
+        let mut storage = HashmapLocalStorage::new(10000);
+        storage.set_replication(ReplicationConfig {
+            replicas: 2,
+            read: ReadConsistency::Quorum,
+        });
+        storage.get(0, 1, 11111, 10, 1000);
+
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(HashmapRemoteEvent::Get(0, 1), RouteRule::ToKey(1))));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(HashmapRemoteEvent::Get(1, 1), replica_route(1, 1))));
+        assert_eq!(storage.pop_action(), None);
+
+        storage.on_event(2, HashmapLocalEvent::GetAck(0, 1, Some(vec![(1, vec![1], 1, 1000, None), (2, vec![2], 5, 1000, None)])));
+        assert_eq!(storage.pop_action(), None);
+
+        storage.on_event(3, HashmapLocalEvent::GetAck(1, 1, Some(vec![(1, vec![3], 3, 1001, None)])));
+        assert_eq!(
+            storage.pop_action(),
+            Some(LocalStorageAction::LocalOnGet(10, 11111, 1, Ok(Some(vec![(1, vec![3], 3, 1001), (2, vec![2], 5, 1000)]))))
+        );
+        //sub key without expiry like a counter is repaired without expiry
+        assert_eq!(
+            storage.pop_action(),
+            Some(LocalStorageAction::SendNet(HashmapRemoteEvent::ReplicaSet(1, 1, vec![3], 3, 1001, None), RouteRule::ToKey(1)))
+        );
+        //sub key 2 is missing in replica 1, it may be deleted there so it is not repaired
+        assert_eq!(storage.pop_action(), None);
+    }
+
+    #[test]
+    fn query_should_callback_correct_value() {
+        let mut storage = HashmapLocalStorage::new(10000);
+        storage.query(0, 1, KeyValueHashmapQuery::Scan(0, 10), 11111, 10, 1000);
+        storage.query(0, 1, KeyValueHashmapQuery::Len, 11112, 10, 1000);
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(HashmapRemoteEvent::Scan(0, 1, 0, 10), RouteRule::ToKey(1))));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(HashmapRemoteEvent::Len(1, 1), RouteRule::ToKey(1))));
+        assert_eq!(storage.pop_action(), None);
+
+        storage.on_event(2, HashmapLocalEvent::ScanAck(0, 1, vec![(2, vec![1], 0, 1000)], Some(3)));
+        assert_eq!(
+            storage.pop_action(),
+            Some(LocalStorageAction::LocalOnQuery(
+                10,
+                11111,
+                1,
+                Ok(KeyValueHashmapQueryResult::Scan(vec![(2, vec![1], 0, 1000)], Some(3)))
+            ))
+        );
+        assert_eq!(storage.pop_action(), None);
+
+        //after timeout should callback error
+        storage.tick(1000);
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnQuery(10, 11112, 1, Err(KeyValueSdkEventError::Timeout))));
+        assert_eq!(storage.pop_action(), None);
+    }
 }
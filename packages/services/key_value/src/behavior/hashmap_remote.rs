@@ -1,18 +1,32 @@
 /// This remote storage is a hashmap key value storage, it will store all key value in memory, and send event to other node when key value changed
 /// Each event is attached with a req_id and wait for ack, if ack not receive, it will resend the event each tick util ack received or tick_count is 0
 use crate::storage::hashmap::{HashmapKeyValue, OutputEvent};
+use crate::storage::persistent::{PersistentLog, PersistentStorage, StorageRecord};
 use crate::SubKeyId;
 use crate::{
     msg::{HashmapLocalEvent, HashmapRemoteEvent},
     KeyId, ValueType,
 };
 use atm0s_sdn_identity::NodeId;
-use atm0s_sdn_router::RouteRule;
+use atm0s_sdn_router::{RouteRule, RouterTable};
 use std::collections::VecDeque;
+use std::sync::Arc;
 
 use super::event_acks::EventAckManager;
+use super::{
+    counter::{counter_version, encode_counter, incr_counter},
+    handoff::{remain_ex, KeyHandoff},
+    replication::replica_route,
+};
 
 const RETRY_COUNT: u8 = 5;
+/// Max number of sub keys in a scan response, larger limits are capped
+pub const SCAN_MAX_LIMIT: u32 = 32;
+/// Max size of values in a scan response, more sub keys are not added after it is reached.
+/// It is not a hard bound, the first sub key is always returned even if its value is larger
+pub const SCAN_MAX_BYTES: usize = 1000;
+/// Encoded size of sub key, version, source and value length of each scanned sub key
+const SCAN_ENTRY_OVERHEAD: usize = 28;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RemoteStorageAction(pub(crate) HashmapLocalEvent, pub(crate) RouteRule);
@@ -23,6 +37,10 @@ pub struct HashmapRemoteStorage {
     storage: HashmapKeyValue<KeyId, SubKeyId, ValueType, NodeId, NodeId>,
     event_acks: EventAckManager<RemoteStorageAction>,
     output_events: VecDeque<RemoteStorageAction>,
+    replica_events: VecDeque<(HashmapRemoteEvent, RouteRule)>,
+    replicas: u8,
+    persistent: Option<PersistentLog>,
+    handoff: KeyHandoff,
 }
 
 impl HashmapRemoteStorage {
@@ -33,12 +51,80 @@ impl HashmapRemoteStorage {
             storage: HashmapKeyValue::new(),
             event_acks: EventAckManager::new(),
             output_events: VecDeque::new(),
+            replica_events: VecDeque::new(),
+            replicas: 1,
+            persistent: None,
+            handoff: KeyHandoff::new(),
+        }
+    }
+
+    /// Create storage which persists all changes and restores them from the persistent storage.
+    /// Records are replayed with their original time, so keys which expired while stopped are cleared in the first tick.
+    pub fn new_persistent(node_id: NodeId, storage: Box<dyn PersistentStorage>) -> Self {
+        let (persistent, records) = PersistentLog::new(storage);
+        let mut this = Self::new(node_id);
+        log::info!("[HashmapRemote {}] restore {} records from persistent storage", node_id, records.len());
+        for record in records {
+            match record {
+                StorageRecord::Set {
+                    at,
+                    key,
+                    sub_key: Some(sub_key),
+                    value,
+                    version,
+                    source,
+                    ex,
+                } => {
+                    this.storage.set(at, key, sub_key, value, version, source, ex);
+                }
+                StorageRecord::Del {
+                    key, sub_key: Some(sub_key), version, ..
+                } => {
+                    this.storage.del(&key, &sub_key, version);
+                }
+                _ => {
+                    log::warn!("[HashmapRemote {}] restore record without sub_key {:?}", node_id, record);
+                }
+            }
         }
+        this.persistent = Some(persistent);
+        this
+    }
+
+    /// Number of replicas, writes from writers are forwarded to other replicas
+    pub fn set_replicas(&mut self, replicas: u8) {
+        self.replicas = replicas;
+    }
+
+    /// Router for checking which keys are still owned by this node, moved keys are handed off to the new owners
+    pub fn set_router(&mut self, router: Arc<dyn RouterTable>) {
+        self.handoff.set_router(router);
     }
 
     pub fn tick(&mut self, now_ms: u64) {
         self.storage.tick(now_ms);
         self.event_acks.tick(now_ms);
+        self.handoff_tick(now_ms);
+        if let Some(persistent) = &mut self.persistent {
+            if persistent.should_compact() {
+                let snapshot = self
+                    .storage
+                    .snapshot()
+                    .into_iter()
+                    .map(|(key, sub_key, value, version, source, expire_at)| StorageRecord::Set {
+                        at: now_ms,
+                        key,
+                        sub_key: Some(sub_key),
+                        value,
+                        version,
+                        source,
+                        ex: expire_at.map(|expire_at| expire_at.saturating_sub(now_ms)),
+                    })
+                    .collect();
+                persistent.compact(snapshot);
+            }
+            persistent.flush(now_ms);
+        }
     }
 
     pub fn on_event(&mut self, now_ms: u64, from: NodeId, event: HashmapRemoteEvent) {
@@ -54,23 +140,75 @@ impl HashmapRemoteStorage {
                     version,
                     ex
                 );
-                let setted = self.storage.set(now_ms, key, sub_key, value, version, from, ex);
+                for index in 1..self.replicas {
+                    self.replica_events
+                        .push_back((HashmapRemoteEvent::ReplicaSet(key, sub_key, value.clone(), version, from, ex), replica_route(key, index)));
+                }
+                let setted = self.store_set(now_ms, (key, sub_key), value, version, from, ex);
                 self.output_events
                     .push_back(RemoteStorageAction(HashmapLocalEvent::SetAck(req_id, key, sub_key, version, setted), RouteRule::ToNode(from)));
             }
             HashmapRemoteEvent::Get(req_id, key) => {
-                if let Some(sub_keys) = self.storage.get(&key) {
-                    log::debug!("[HashmapRemote {}] receive get event from {} has {} keys", self.node_id, from, sub_keys.len());
-                    let sub_keys_clone = sub_keys
-                        .into_iter()
-                        .map(|(sub_key, value, version, source)| (sub_key, value.clone(), version, source))
-                        .collect::<Vec<_>>();
-                    self.output_events
-                        .push_back(RemoteStorageAction(HashmapLocalEvent::GetAck(req_id, key, Some(sub_keys_clone)), RouteRule::ToNode(from)));
+                // sub keys may be partially handed off, so merge with previous owners in the transition window
+                let missing = self.storage.get(&key).is_none();
+                if let Some((fallback_req_id, nodes)) = self.handoff.fallback_read(now_ms, from, req_id, key, missing) {
+                    log::debug!("[HashmapRemote {}] receive get event from {} key {}, fallback to previous owners {:?}", self.node_id, from, key, nodes);
+                    for node in nodes {
+                        self.replica_events.push_back((HashmapRemoteEvent::HandoffGet(fallback_req_id, key), RouteRule::ToNode(node)));
+                    }
                 } else {
-                    log::debug!("[HashmapRemote {}] receive get event from {} key {} value None", self.node_id, from, key);
-                    self.output_events.push_back(RemoteStorageAction(HashmapLocalEvent::GetAck(req_id, key, None), RouteRule::ToNode(from)));
+                    self.answer_get(now_ms, from, req_id, key);
+                }
+            }
+            HashmapRemoteEvent::Scan(req_id, key, cursor, limit) => {
+                let limit = limit.min(SCAN_MAX_LIMIT);
+                let (sub_keys, next_cursor) = self.storage.scan(&key, &cursor, limit as usize, SCAN_MAX_BYTES, |value| value.len() + SCAN_ENTRY_OVERHEAD);
+                log::debug!(
+                    "[HashmapRemote {}] receive scan event from {} key {} cursor {} limit {} has {} keys next {:?}",
+                    self.node_id,
+                    from,
+                    key,
+                    cursor,
+                    limit,
+                    sub_keys.len(),
+                    next_cursor
+                );
+                let sub_keys = sub_keys
+                    .into_iter()
+                    .map(|(sub_key, value, version, source)| (sub_key, value.clone(), version, source))
+                    .collect::<Vec<_>>();
+                self.output_events
+                    .push_back(RemoteStorageAction(HashmapLocalEvent::ScanAck(req_id, key, sub_keys, next_cursor), RouteRule::ToNode(from)));
+            }
+            HashmapRemoteEvent::Incr(req_id, key, sub_key, delta, ex) => {
+                let current = self.storage.get_sub(&key, &sub_key).map(|(value, version, _)| (value.clone(), version));
+                let new_value = incr_counter(current.as_ref().map(|(value, _)| value.as_slice()), delta);
+                log::debug!(
+                    "[HashmapRemote {}] receive incr event from {} key {} sub_key {} delta {} ex {:?} new value {:?}",
+                    self.node_id,
+                    from,
+                    key,
+                    sub_key,
+                    delta,
+                    ex,
+                    new_value
+                );
+                if let Some(new_value) = new_value {
+                    let version = counter_version(now_ms, current.map(|(_, version)| version));
+                    let value = encode_counter(new_value);
+                    for index in 1..self.replicas {
+                        self.replica_events
+                            .push_back((HashmapRemoteEvent::ReplicaSet(key, sub_key, value.clone(), version, from, ex), replica_route(key, index)));
+                    }
+                    self.store_set(now_ms, (key, sub_key), value, version, from, ex);
                 }
+                self.output_events
+                    .push_back(RemoteStorageAction(HashmapLocalEvent::IncrAck(req_id, key, sub_key, new_value), RouteRule::ToNode(from)));
+            }
+            HashmapRemoteEvent::Len(req_id, key) => {
+                let len = self.storage.map_len(&key) as u64;
+                log::debug!("[HashmapRemote {}] receive len event from {} key {} len {}", self.node_id, from, key, len);
+                self.output_events.push_back(RemoteStorageAction(HashmapLocalEvent::LenAck(req_id, key, len), RouteRule::ToNode(from)));
             }
             HashmapRemoteEvent::Del(req_id, key, sub_key, req_version) => {
                 log::debug!(
@@ -81,7 +219,10 @@ impl HashmapRemoteStorage {
                     sub_key,
                     req_version
                 );
-                let version = self.storage.del(&key, &sub_key, req_version).map(|(_, version, _)| version);
+                for index in 1..self.replicas {
+                    self.replica_events.push_back((HashmapRemoteEvent::ReplicaDel(key, sub_key, req_version), replica_route(key, index)));
+                }
+                let version = self.store_del(now_ms, key, sub_key, req_version);
                 self.output_events
                     .push_back(RemoteStorageAction(HashmapLocalEvent::DelAck(req_id, key, sub_key, version), RouteRule::ToNode(from)));
             }
@@ -104,9 +245,176 @@ impl HashmapRemoteStorage {
                 log::debug!("[HashmapRemote {}] receive on_key_del_ack event from {}, self.node_id, req_id {}", self.node_id, from, req_id);
                 self.event_acks.on_ack(req_id);
             }
+            HashmapRemoteEvent::ReplicaSet(key, sub_key, value, version, source, ex) => {
+                log::debug!(
+                    "[HashmapRemote {}] receive replica set event from {} key {} sub_key {} version {} source {} ex {:?}",
+                    self.node_id,
+                    from,
+                    key,
+                    sub_key,
+                    version,
+                    source,
+                    ex
+                );
+                self.store_set(now_ms, (key, sub_key), value, version, source, ex);
+            }
+            HashmapRemoteEvent::ReplicaDel(key, sub_key, version) => {
+                log::debug!(
+                    "[HashmapRemote {}] receive replica del event from {} key {} sub_key {} version {}",
+                    self.node_id,
+                    from,
+                    key,
+                    sub_key,
+                    version
+                );
+                self.store_del(now_ms, key, sub_key, version);
+            }
+            HashmapRemoteEvent::Handoff(req_id, key, sub_key, value, version, source, ex) => {
+                log::debug!(
+                    "[HashmapRemote {}] receive handoff event from {} key {} sub_key {} version {} source {} ex {:?}",
+                    self.node_id,
+                    from,
+                    key,
+                    sub_key,
+                    version,
+                    source,
+                    ex
+                );
+                for (fallback_req_id, read_key) in self.handoff.on_handoff(now_ms, from) {
+                    self.replica_events.push_back((HashmapRemoteEvent::HandoffGet(fallback_req_id, read_key), RouteRule::ToNode(from)));
+                }
+                self.store_set(now_ms, (key, sub_key), value, version, source, ex);
+                self.replica_events.push_back((HashmapRemoteEvent::HandoffAck(req_id), RouteRule::ToNode(from)));
+            }
+            HashmapRemoteEvent::HandoffAck(req_id) => {
+                log::debug!("[HashmapRemote {}] receive handoff ack from {} req_id {}", self.node_id, from, req_id);
+                self.handoff.on_handoff_ack(req_id);
+            }
+            HashmapRemoteEvent::HandoffGet(req_id, key) => {
+                let sub_keys = self
+                    .storage
+                    .get_with_expire(&key)
+                    .into_iter()
+                    .map(|(sub_key, value, version, source, expire_at)| (sub_key, value.clone(), version, source, remain_ex(now_ms, expire_at)))
+                    .collect::<Vec<_>>();
+                log::debug!("[HashmapRemote {}] receive handoff get event from {} key {} has {} keys", self.node_id, from, key, sub_keys.len());
+                self.replica_events.push_back((HashmapRemoteEvent::HandoffGetAck(req_id, key, sub_keys), RouteRule::ToNode(from)));
+            }
+            HashmapRemoteEvent::HandoffGetAck(req_id, key, sub_keys) => {
+                log::debug!("[HashmapRemote {}] receive handoff get ack from {} key {} has {} keys", self.node_id, from, key, sub_keys.len());
+                for (sub_key, value, version, source, ex) in sub_keys {
+                    self.store_set(now_ms, (key, sub_key), value, version, source, ex);
+                }
+                if let Some(read) = self.handoff.on_fallback_answer(req_id, false) {
+                    self.answer_get(now_ms, read.from, read.req_id, read.key);
+                }
+            }
         }
     }
 
+    fn answer_get(&mut self, now_ms: u64, from: NodeId, req_id: u64, key: KeyId) {
+        let sub_keys = self.storage.get_with_expire(&key);
+        if !sub_keys.is_empty() {
+            log::debug!("[HashmapRemote {}] answer get from {} has {} keys", self.node_id, from, sub_keys.len());
+            let sub_keys_clone = sub_keys
+                .into_iter()
+                .map(|(sub_key, value, version, source, expire_at)| (sub_key, value.clone(), version, source, remain_ex(now_ms, expire_at)))
+                .collect::<Vec<_>>();
+            self.output_events
+                .push_back(RemoteStorageAction(HashmapLocalEvent::GetAck(req_id, key, Some(sub_keys_clone)), RouteRule::ToNode(from)));
+        } else {
+            log::debug!("[HashmapRemote {}] answer get from {} key {} value None", self.node_id, from, key);
+            self.output_events.push_back(RemoteStorageAction(HashmapLocalEvent::GetAck(req_id, key, None), RouteRule::ToNode(from)));
+        }
+    }
+
+    /// Stream moved keys to new owners, drop them after transition window once acked and answer timeout fallback reads
+    fn handoff_tick(&mut self, now_ms: u64) {
+        let (send, drop) = self.handoff.tick(now_ms, self.replicas, self.storage.keys().copied());
+        for key in send {
+            for (sub_key, value, version, source, expire_at) in self.storage.get_with_expire(&key) {
+                for index in 0..self.replicas.max(1) {
+                    let req_id = self.handoff.on_sent(key);
+                    self.replica_events.push_back((
+                        HashmapRemoteEvent::Handoff(req_id, key, sub_key, value.clone(), version, source, remain_ex(now_ms, expire_at)),
+                        replica_route(key, index),
+                    ));
+                }
+            }
+        }
+        for key in drop {
+            let removed = self.storage.remove(&key);
+            log::info!("[HashmapRemote {}] drop key {} with {} sub keys after handoff", self.node_id, key, removed.len());
+            if let Some(persistent) = &mut self.persistent {
+                for (sub_key, version) in removed {
+                    persistent.append(StorageRecord::Del {
+                        at: now_ms,
+                        key,
+                        sub_key: Some(sub_key),
+                        version,
+                    });
+                }
+            }
+        }
+        for read in self.handoff.pop_fallback_timeouts(now_ms) {
+            self.answer_get(now_ms, read.from, read.req_id, read.key);
+        }
+    }
+
+    /// Keys which are stored in this node and have same first prefix_bits bits with prefix
+    pub fn keys_by_prefix(&self, prefix: KeyId, prefix_bits: u8) -> Vec<KeyId> {
+        let shift = 64 - prefix_bits.min(64) as u32;
+        let mut keys = self
+            .storage
+            .keys()
+            .filter(|key| prefix.checked_shr(shift).unwrap_or(0) == key.checked_shr(shift).unwrap_or(0))
+            .copied()
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    /// Replica writes which are sent to other replicas, and handoff messages which are sent to other owners
+    pub fn pop_replica_action(&mut self) -> Option<(HashmapRemoteEvent, RouteRule)> {
+        self.replica_events.pop_front()
+    }
+
+    /// Only changes are appended to persistent log, which are new versions or switching between with and without expiry.
+    /// Re-syncs of a stored version which only refresh expiry are not appended, the writer syncs them again after restart
+    fn store_set(&mut self, now_ms: u64, (key, sub_key): (KeyId, SubKeyId), value: ValueType, version: u64, source: NodeId, ex: Option<u64>) -> bool {
+        let old_expire_at = self.storage.sub_expire_at(&key, &sub_key);
+        let record_value = self.persistent.as_ref().map(|_| value.clone());
+        let setted = self.storage.set(now_ms, key, sub_key, value, version, source, ex);
+        let expiry_changed = old_expire_at.is_some_and(|expire_at| expire_at.is_some() != ex.is_some());
+        if let (Some(persistent), Some(value)) = (&mut self.persistent, record_value) {
+            if setted || expiry_changed {
+                persistent.append(StorageRecord::Set {
+                    at: now_ms,
+                    key,
+                    sub_key: Some(sub_key),
+                    value,
+                    version,
+                    source,
+                    ex,
+                });
+            }
+        }
+        setted
+    }
+
+    fn store_del(&mut self, now_ms: u64, key: KeyId, sub_key: SubKeyId, req_version: u64) -> Option<u64> {
+        let version = self.storage.del(&key, &sub_key, req_version).map(|(_, version, _)| version);
+        if let (Some(persistent), Some(_)) = (&mut self.persistent, version) {
+            persistent.append(StorageRecord::Del {
+                at: now_ms,
+                key,
+                sub_key: Some(sub_key),
+                version: req_version,
+            });
+        }
+        version
+    }
+
     pub fn pop_action(&mut self, now_ms: u64) -> Option<RemoteStorageAction> {
         //first pop from output_events, if not exits then pop from event_acks
         if let Some(e) = self.output_events.pop_front() {
@@ -136,8 +444,13 @@ impl HashmapRemoteStorage {
 mod tests {
     use super::RemoteStorageAction;
     use crate::{
-        behavior::event_acks::RESEND_AFTER_MS,
+        behavior::{
+            event_acks::RESEND_AFTER_MS,
+            handoff::{tests::MovedKeysRouter, HANDOFF_KEEP_MS},
+            replication::replica_route,
+        },
         msg::{HashmapLocalEvent, HashmapRemoteEvent},
+        storage::persistent::MemoryStorage,
     };
     use atm0s_sdn_router::RouteRule;
 
@@ -271,7 +584,7 @@ mod tests {
         remote_storage.on_event(0, 1001, HashmapRemoteEvent::Get(2, 2));
         assert_eq!(
             remote_storage.pop_action(0),
-            Some(RemoteStorageAction(HashmapLocalEvent::GetAck(2, 2, Some(vec![(3, vec![1], 10, 1000)])), RouteRule::ToNode(1001)))
+            Some(RemoteStorageAction(HashmapLocalEvent::GetAck(2, 2, Some(vec![(3, vec![1], 10, 1000, None)])), RouteRule::ToNode(1001)))
         );
         assert_eq!(remote_storage.pop_action(0), None);
 
@@ -406,4 +719,211 @@ mod tests {
         );
         assert_eq!(remote_storage.pop_action(RESEND_AFTER_MS), None);
     }
+
+    #[test]
+    fn persistent_restore_after_restart() {
+        let persistent = MemoryStorage::default();
+        let mut remote_storage = super::HashmapRemoteStorage::new_persistent(0, Box::new(persistent.clone()));
+        remote_storage.on_event(0, 1000, HashmapRemoteEvent::Set(1, 1, 11, vec![1], 10, None));
+        remote_storage.on_event(0, 1000, HashmapRemoteEvent::Set(2, 1, 12, vec![2], 20, Some(1000)));
+        remote_storage.on_event(0, 1000, HashmapRemoteEvent::Set(3, 1, 13, vec![3], 30, None));
+        remote_storage.on_event(100, 1000, HashmapRemoteEvent::Del(4, 1, 13, 30));
+        drop(remote_storage);
+
+        let mut remote_storage = super::HashmapRemoteStorage::new_persistent(0, Box::new(persistent));
+        remote_storage.tick(500);
+        remote_storage.on_event(500, 1001, HashmapRemoteEvent::Get(5, 1));
+        match remote_storage.pop_action(500) {
+            Some(RemoteStorageAction(HashmapLocalEvent::GetAck(5, 1, Some(mut sub_keys)), RouteRule::ToNode(1001))) => {
+                sub_keys.sort();
+                assert_eq!(sub_keys, vec![(11, vec![1], 10, 1000, None), (12, vec![2], 20, 1000, Some(500))]);
+            }
+            _ => panic!("Should return GetAck"),
+        }
+
+        // sub_key 12 expires at the original time
+        remote_storage.tick(1000);
+        remote_storage.on_event(1000, 1001, HashmapRemoteEvent::Get(6, 1));
+        assert_eq!(
+            remote_storage.pop_action(1000),
+            Some(RemoteStorageAction(HashmapLocalEvent::GetAck(6, 1, Some(vec![(11, vec![1], 10, 1000, None)])), RouteRule::ToNode(1001)))
+        );
+
+        // version is kept, older version is rejected
+        remote_storage.on_event(1000, 1000, HashmapRemoteEvent::Set(7, 1, 11, vec![9], 5, None));
+        assert_eq!(
+            remote_storage.pop_action(1000),
+            Some(RemoteStorageAction(HashmapLocalEvent::SetAck(7, 1, 11, 5, false), RouteRule::ToNode(1000)))
+        );
+    }
+
+    #[test]
+    fn receive_scan_and_len() {
+        let mut remote_storage = super::HashmapRemoteStorage::new(0);
+        for sub_key in [3, 1, 2] {
+            remote_storage.on_event(0, 1000, HashmapRemoteEvent::Set(sub_key, 1, sub_key, vec![sub_key as u8], 10, None));
+            remote_storage.pop_action(0);
+        }
+
+        remote_storage.on_event(0, 1001, HashmapRemoteEvent::Scan(4, 1, 0, 2));
+        assert_eq!(
+            remote_storage.pop_action(0),
+            Some(RemoteStorageAction(
+                HashmapLocalEvent::ScanAck(4, 1, vec![(1, vec![1], 10, 1000), (2, vec![2], 10, 1000)], Some(3)),
+                RouteRule::ToNode(1001)
+            ))
+        );
+        remote_storage.on_event(0, 1001, HashmapRemoteEvent::Scan(5, 1, 3, 2));
+        assert_eq!(
+            remote_storage.pop_action(0),
+            Some(RemoteStorageAction(HashmapLocalEvent::ScanAck(5, 1, vec![(3, vec![3], 10, 1000)], None), RouteRule::ToNode(1001)))
+        );
+
+        remote_storage.on_event(0, 1001, HashmapRemoteEvent::Len(6, 1));
+        assert_eq!(remote_storage.pop_action(0), Some(RemoteStorageAction(HashmapLocalEvent::LenAck(6, 1, 3), RouteRule::ToNode(1001))));
+        remote_storage.on_event(0, 1001, HashmapRemoteEvent::Len(7, 2));
+        assert_eq!(remote_storage.pop_action(0), Some(RemoteStorageAction(HashmapLocalEvent::LenAck(7, 2, 0), RouteRule::ToNode(1001))));
+        assert_eq!(remote_storage.pop_action(0), None);
+    }
+
+    #[test]
+    fn scan_should_cap_limit_and_size() {
+        let mut remote_storage = super::HashmapRemoteStorage::new(0);
+        for sub_key in 0..200 {
+            remote_storage.on_event(0, 1000, HashmapRemoteEvent::Set(sub_key, 1, sub_key, vec![1], 10, None));
+            remote_storage.pop_action(0);
+        }
+        for sub_key in 1000..1005 {
+            remote_storage.on_event(0, 1000, HashmapRemoteEvent::Set(sub_key, 1, sub_key, vec![1; 400], 10, None));
+            remote_storage.pop_action(0);
+        }
+
+        remote_storage.on_event(0, 1001, HashmapRemoteEvent::Scan(1, 1, 0, u32::MAX));
+        match remote_storage.pop_action(0) {
+            Some(RemoteStorageAction(HashmapLocalEvent::ScanAck(1, 1, sub_keys, next_cursor), _)) => {
+                assert_eq!(sub_keys.len(), super::SCAN_MAX_LIMIT as usize);
+                assert_eq!(next_cursor, Some(super::SCAN_MAX_LIMIT as u64));
+            }
+            res => panic!("Should be scan ack {:?}", res),
+        }
+
+        remote_storage.on_event(0, 1001, HashmapRemoteEvent::Scan(2, 1, 1000, 10));
+        match remote_storage.pop_action(0) {
+            Some(RemoteStorageAction(HashmapLocalEvent::ScanAck(2, 1, sub_keys, next_cursor), _)) => {
+                assert_eq!(sub_keys.iter().map(|(sub_key, ..)| *sub_key).collect::<Vec<_>>(), vec![1000, 1001]);
+                assert_eq!(next_cursor, Some(1002));
+            }
+            res => panic!("Should be scan ack {:?}", res),
+        }
+    }
+
+    #[test]
+    fn keys_by_prefix() {
+        let mut remote_storage = super::HashmapRemoteStorage::new(0);
+        for key in [0x0100_0000_0000_0001, 0x0100_0000_0000_0002, 0x0200_0000_0000_0001] {
+            remote_storage.on_event(0, 1000, HashmapRemoteEvent::Set(1, key, 1, vec![1], 10, None));
+        }
+
+        assert_eq!(remote_storage.keys_by_prefix(0x0100_0000_0000_0000, 8), vec![0x0100_0000_0000_0001, 0x0100_0000_0000_0002]);
+        assert_eq!(remote_storage.keys_by_prefix(0x0100_0000_0000_0002, 64), vec![0x0100_0000_0000_0002]);
+        assert_eq!(remote_storage.keys_by_prefix(0, 0).len(), 3);
+        assert_eq!(remote_storage.keys_by_prefix(0x0300_0000_0000_0000, 8), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn receive_incr() {
+        let mut remote_storage = super::HashmapRemoteStorage::new(0);
+
+        remote_storage.on_event(1, 1000, HashmapRemoteEvent::Incr(1, 1, 2, 5, None));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(HashmapLocalEvent::IncrAck(1, 1, 2, Some(5)), RouteRule::ToNode(1000)))
+        );
+        remote_storage.on_event(1, 1001, HashmapRemoteEvent::Incr(2, 1, 2, 5, None));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(HashmapLocalEvent::IncrAck(2, 1, 2, Some(10)), RouteRule::ToNode(1001)))
+        );
+        remote_storage.on_event(1, 1001, HashmapRemoteEvent::Get(3, 1));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(
+                HashmapLocalEvent::GetAck(3, 1, Some(vec![(2, 10i64.to_be_bytes().to_vec(), (1 << 16) + 1, 1001, None)])),
+                RouteRule::ToNode(1001)
+            ))
+        );
+
+        remote_storage.on_event(1, 1001, HashmapRemoteEvent::Incr(4, 1, 2, i64::MAX, None));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(HashmapLocalEvent::IncrAck(4, 1, 2, None), RouteRule::ToNode(1001)))
+        );
+        assert_eq!(remote_storage.pop_action(1), None);
+    }
+
+    #[test]
+    fn handoff_moved_keys_then_drop() {
+        let router = std::sync::Arc::new(MovedKeysRouter::default());
+        let mut remote_storage = super::HashmapRemoteStorage::new(0);
+        remote_storage.set_router(router.clone());
+
+        remote_storage.on_event(0, 1000, HashmapRemoteEvent::Set(1, 1, 11, vec![1], 10, Some(50000)));
+        while remote_storage.pop_action(0).is_some() {}
+        remote_storage.tick(0);
+        assert_eq!(remote_storage.pop_replica_action(), None);
+
+        router.moved.write().insert(1);
+        remote_storage.tick(1000);
+        assert_eq!(
+            remote_storage.pop_replica_action(),
+            Some((HashmapRemoteEvent::Handoff(0, 1, 11, vec![1], 10, 1000, Some(49000)), replica_route(1, 0)))
+        );
+        assert_eq!(remote_storage.pop_replica_action(), None);
+
+        //not acked, keep and resend
+        remote_storage.tick(1000 + HANDOFF_KEEP_MS);
+        assert_eq!(remote_storage.storage.len(), 1);
+        assert_eq!(
+            remote_storage.pop_replica_action(),
+            Some((HashmapRemoteEvent::Handoff(1, 1, 11, vec![1], 10, 1000, Some(39000)), replica_route(1, 0)))
+        );
+
+        remote_storage.on_event(1000 + HANDOFF_KEEP_MS, 2, HashmapRemoteEvent::HandoffAck(1));
+        remote_storage.tick(1001 + HANDOFF_KEEP_MS);
+        assert_eq!(remote_storage.storage.len(), 0);
+        assert_eq!(remote_storage.pop_action(1001 + HANDOFF_KEEP_MS), None);
+    }
+
+    #[test]
+    fn fallback_get_merge_previous_owner() {
+        let mut remote_storage = super::HashmapRemoteStorage::new(0);
+
+        //sub key 11 is handed off, sub key 12 is written after the owner changed
+        remote_storage.on_event(0, 2000, HashmapRemoteEvent::Handoff(7, 1, 11, vec![1], 10, 1000, None));
+        assert_eq!(remote_storage.pop_replica_action(), Some((HashmapRemoteEvent::HandoffAck(7), RouteRule::ToNode(2000))));
+        remote_storage.on_event(0, 1000, HashmapRemoteEvent::Set(1, 1, 12, vec![2], 20, None));
+        while remote_storage.pop_action(0).is_some() {}
+
+        remote_storage.on_event(0, 1001, HashmapRemoteEvent::Get(2, 1));
+        assert_eq!(remote_storage.pop_action(0), None);
+        assert_eq!(remote_storage.pop_replica_action(), Some((HashmapRemoteEvent::HandoffGet(0, 1), RouteRule::ToNode(2000))));
+        remote_storage.on_event(10, 2000, HashmapRemoteEvent::HandoffGetAck(0, 1, vec![(11, vec![1], 10, 1000, None), (13, vec![3], 30, 1000, None)]));
+        match remote_storage.pop_action(10) {
+            Some(RemoteStorageAction(HashmapLocalEvent::GetAck(2, 1, Some(mut sub_keys)), RouteRule::ToNode(1001))) => {
+                sub_keys.sort();
+                assert_eq!(sub_keys, vec![(11, vec![1], 10, 1000, None), (12, vec![2], 20, 1000, None), (13, vec![3], 30, 1000, None)]);
+            }
+            _ => panic!("Should return GetAck"),
+        }
+
+        //previous owner answers get of itself
+        remote_storage.on_event(10, 2000, HashmapRemoteEvent::HandoffGet(5, 1));
+        match remote_storage.pop_replica_action() {
+            Some((HashmapRemoteEvent::HandoffGetAck(5, 1, mut sub_keys), RouteRule::ToNode(2000))) => {
+                sub_keys.sort();
+                assert_eq!(sub_keys, vec![(11, vec![1], 10, 1000, None), (12, vec![2], 20, 1000, None), (13, vec![3], 30, 1000, None)]);
+            }
+            _ => panic!("Should return HandoffGetAck"),
+        }
+    }
 }
@@ -0,0 +1,158 @@
+/// Replication of keys to many nodes.
+/// Replica 0 is the node which is closest to the key, other replicas are nodes which are closest to keys derived from the key,
+/// which are spread over all layers of the key space, so each replica most likely lands on a different node.
+///
+/// Writes are sent to replica 0 as before, then it forwards replica writes to other replicas.
+/// Reads are sent to all replicas, the answer with the highest version wins and stale replicas are repaired.
+use atm0s_sdn_router::RouteRule;
+
+use crate::KeyId;
+
+/// Multiplier for spreading derived keys over all layers of key space, golden ratio of u32
+const REPLICA_KEY_SPREAD: u32 = 0x9E37_79B9;
+
+/// Which answers are needed before a get request is resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadConsistency {
+    /// Resolve with the first replica which has the value, or when all replicas answered without value
+    Any,
+    /// Resolve when the majority of replicas answered, with the highest version
+    Quorum,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplicationConfig {
+    /// Number of nodes which store each key, 1 is no replication
+    pub replicas: u8,
+    pub read: ReadConsistency,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            replicas: 1,
+            read: ReadConsistency::Any,
+        }
+    }
+}
+
+/// Key in key space of a replica
+pub fn replica_key(key: KeyId, index: u8) -> u32 {
+    (key as u32) ^ (index as u32).wrapping_mul(REPLICA_KEY_SPREAD)
+}
+
+pub fn replica_route(key: KeyId, index: u8) -> RouteRule {
+    RouteRule::ToKey(replica_key(key, index))
+}
+
+/// Answers of a get request from replicas, None is an answer without value
+pub struct ReplicaAnswers<T> {
+    config: ReplicationConfig,
+    answers: Vec<(u8, Option<T>)>,
+    resolved: bool,
+}
+
+impl<T> ReplicaAnswers<T> {
+    pub fn new(config: ReplicationConfig) -> Self {
+        Self {
+            config,
+            answers: Vec::with_capacity(config.replicas as usize),
+            resolved: false,
+        }
+    }
+
+    /// Add an answer, return true if the request should be resolved now
+    pub fn on_answer(&mut self, index: u8, value: Option<T>) -> bool {
+        if self.answers.iter().any(|(i, _)| *i == index) {
+            return false;
+        }
+        let has_value = value.is_some();
+        self.answers.push((index, value));
+        if self.resolved {
+            return false;
+        }
+        self.resolved = match self.config.read {
+            ReadConsistency::Any => has_value || self.is_finished(),
+            ReadConsistency::Quorum => self.answers.len() > self.config.replicas as usize / 2,
+        };
+        self.resolved
+    }
+
+    /// Mark as resolved, return false if it was already resolved
+    pub fn resolve(&mut self) -> bool {
+        !std::mem::replace(&mut self.resolved, true)
+    }
+
+    /// All replicas answered, after that stale replicas can be repaired
+    pub fn is_finished(&self) -> bool {
+        self.answers.len() >= self.config.replicas as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.answers.is_empty()
+    }
+
+    /// Answers from a part of replicas can resolve a timed out request only with Any consistency, Quorum needs a majority
+    pub fn allow_partial(&self) -> bool {
+        self.config.read == ReadConsistency::Any && !self.is_empty()
+    }
+
+    pub fn answers(&self) -> &[(u8, Option<T>)] {
+        &self.answers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{replica_key, ReadConsistency, ReplicaAnswers, ReplicationConfig};
+
+    #[test]
+    fn replica_keys_spread_over_layers() {
+        assert_eq!(replica_key(0x0102_0304, 0), 0x0102_0304);
+        let key1 = replica_key(0x0102_0304, 1);
+        let key2 = replica_key(0x0102_0304, 2);
+        assert_ne!(key1 >> 24, 0x01);
+        assert_ne!(key2 >> 24, 0x01);
+        assert_ne!(key1 >> 24, key2 >> 24);
+    }
+
+    #[test]
+    fn any_read() {
+        let config = ReplicationConfig {
+            replicas: 3,
+            read: ReadConsistency::Any,
+        };
+        let mut answers = ReplicaAnswers::<u32>::new(config);
+        assert!(!answers.on_answer(0, None));
+        assert!(!answers.on_answer(0, Some(1)));
+        assert!(answers.on_answer(1, Some(1)));
+        assert!(!answers.is_finished());
+        assert!(!answers.on_answer(2, Some(2)));
+        assert!(answers.is_finished());
+
+        let mut answers = ReplicaAnswers::<u32>::new(config);
+        assert!(!answers.on_answer(0, None));
+        assert!(!answers.on_answer(1, None));
+        assert!(answers.on_answer(2, None));
+    }
+
+    #[test]
+    fn quorum_read() {
+        let config = ReplicationConfig {
+            replicas: 3,
+            read: ReadConsistency::Quorum,
+        };
+        let mut answers = ReplicaAnswers::<u32>::new(config);
+        assert!(!answers.on_answer(0, Some(1)));
+        assert!(answers.on_answer(1, None));
+        assert!(!answers.on_answer(2, Some(2)));
+        assert!(!answers.resolve());
+    }
+
+    #[test]
+    fn single_replica() {
+        let mut answers = ReplicaAnswers::<u32>::new(ReplicationConfig::default());
+        assert!(answers.on_answer(0, None));
+        assert!(answers.is_finished());
+    }
+}
@@ -1,20 +1,35 @@
 use std::{
     collections::{HashMap, VecDeque},
     sync::{atomic::AtomicU64, Arc},
+    time::{Duration, Instant},
 };
 
 use async_std::channel::Sender;
 use atm0s_sdn_utils::awaker::Awaker;
 use parking_lot::{Mutex, RwLock};
 
-use crate::{msg::KeyValueSdkEventError, ExternalControl, KeyId, KeySource, KeyValueSdkEvent, KeyVersion, SubKeyId, ValueType};
+use crate::{
+    msg::{KeyValueCasOp, KeyValueCasResult, KeyValueHashmapQuery, KeyValueHashmapQueryResult, KeyValueSdkEventError},
+    ExternalControl, KeyId, KeySource, KeyValueSdkEvent, KeyVersion, SubKeyId, ValueType,
+};
 
-use super::{hashmap_local::HashmapKeyValueGetError, simple_local::SimpleKeyValueGetError};
+use super::{
+    hashmap_local::{HashmapKeyValueGetError, HashmapKeyValueIncrError},
+    simple_local::{SimpleKeyValueCasError, SimpleKeyValueGetError, SimpleKeyValueIncrError},
+};
 
+mod lock;
 mod pub_sub;
 
+pub use lock::{DistributedLock, DistributedLockError, LeaderElection, LockGuard};
+
 pub type SimpleKeyValueSubscriber = pub_sub::Subscriber<u64, (KeyId, Option<ValueType>, KeyVersion, KeySource)>;
 pub type HashmapKeyValueSubscriber = pub_sub::Subscriber<u64, (KeyId, SubKeyId, Option<ValueType>, KeyVersion, KeySource)>;
+type SimpleCasSender = Sender<Result<KeyValueCasResult, SimpleKeyValueCasError>>;
+type SimpleIncrSender = Sender<Result<Option<i64>, SimpleKeyValueIncrError>>;
+type HashmapIncrSender = Sender<Result<Option<i64>, HashmapKeyValueIncrError>>;
+type HashmapQuerySender = Sender<Result<KeyValueHashmapQueryResult, HashmapKeyValueGetError>>;
+type SyncedKeys = HashMap<KeyId, Option<(KeyVersion, Instant)>>;
 
 #[derive(Clone)]
 pub struct KeyValueSdk {
@@ -25,6 +40,13 @@ pub struct KeyValueSdk {
     hashmap_publisher: Arc<pub_sub::PublisherManager<u64, (KeyId, SubKeyId, Option<ValueType>, KeyVersion, KeySource)>>,
     simple_get_queue: Arc<Mutex<HashMap<u64, Sender<Result<Option<(ValueType, KeyVersion, KeySource)>, SimpleKeyValueGetError>>>>>,
     hashmap_get_queue: Arc<Mutex<HashMap<u64, Sender<Result<Option<Vec<(SubKeyId, ValueType, KeyVersion, KeySource)>>, HashmapKeyValueGetError>>>>>,
+    simple_cas_queue: Arc<Mutex<HashMap<u64, SimpleCasSender>>>,
+    hashmap_query_queue: Arc<Mutex<HashMap<u64, HashmapQuerySender>>>,
+    simple_incr_queue: Arc<Mutex<HashMap<u64, SimpleIncrSender>>>,
+    hashmap_incr_queue: Arc<Mutex<HashMap<u64, HashmapIncrSender>>>,
+    hashmap_keys_queue: Arc<Mutex<HashMap<u64, Sender<Vec<KeyId>>>>>,
+    /// Last synced version and time of watched keys, which are held by lock guards
+    simple_synced: Arc<Mutex<SyncedKeys>>,
     actions: Arc<RwLock<VecDeque<crate::KeyValueSdkEvent>>>,
 }
 
@@ -39,6 +61,12 @@ impl KeyValueSdk {
             actions: Arc::new(RwLock::new(VecDeque::new())),
             simple_get_queue: Arc::new(Mutex::new(HashMap::new())),
             hashmap_get_queue: Arc::new(Mutex::new(HashMap::new())),
+            simple_cas_queue: Arc::new(Mutex::new(HashMap::new())),
+            hashmap_query_queue: Arc::new(Mutex::new(HashMap::new())),
+            simple_incr_queue: Arc::new(Mutex::new(HashMap::new())),
+            hashmap_incr_queue: Arc::new(Mutex::new(HashMap::new())),
+            hashmap_keys_queue: Arc::new(Mutex::new(HashMap::new())),
+            simple_synced: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -61,6 +89,63 @@ impl KeyValueSdk {
         self.awaker.read().as_ref().unwrap().notify();
     }
 
+    /// Increase counter in the authoritative node of key and return new value, absent counter is 0.
+    /// Ex is same with `set`, which is reset each time the counter is changed
+    pub async fn incr_by(&self, key: KeyId, delta: i64, ex: Option<u64>, timeout_ms: u64) -> Result<i64, SimpleKeyValueIncrError> {
+        let req_id = self.req_id_gen.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let (tx, rx) = async_std::channel::bounded(1);
+        self.simple_incr_queue.lock().insert(req_id, tx);
+        self.actions.write().push_back(crate::KeyValueSdkEvent::Incr(req_id, key, delta, ex, timeout_ms));
+        self.awaker.read().as_ref().unwrap().notify();
+        rx.recv().await.map_err(|_| SimpleKeyValueIncrError::InternalError)??.ok_or(SimpleKeyValueIncrError::InvalidValue)
+    }
+
+    pub async fn decr_by(&self, key: KeyId, delta: i64, ex: Option<u64>, timeout_ms: u64) -> Result<i64, SimpleKeyValueIncrError> {
+        let delta = delta.checked_neg().ok_or(SimpleKeyValueIncrError::InvalidValue)?;
+        self.incr_by(key, delta, ex, timeout_ms).await
+    }
+
+    /// Set only if current version of key equals version, which is usually got from `get`
+    pub async fn set_if_version(&self, key: KeyId, value: Vec<u8>, version: KeyVersion, ex: Option<u64>, timeout_ms: u64) -> Result<KeyValueCasResult, SimpleKeyValueCasError> {
+        self.cas(key, KeyValueCasOp::Set(value, Some(version), ex), timeout_ms).await
+    }
+
+    /// Set only if key is absent
+    pub async fn set_if_absent(&self, key: KeyId, value: Vec<u8>, ex: Option<u64>, timeout_ms: u64) -> Result<KeyValueCasResult, SimpleKeyValueCasError> {
+        self.cas(key, KeyValueCasOp::Set(value, None, ex), timeout_ms).await
+    }
+
+    /// Delete only if current version of key equals version
+    pub async fn delete_if_version(&self, key: KeyId, version: KeyVersion, timeout_ms: u64) -> Result<KeyValueCasResult, SimpleKeyValueCasError> {
+        self.cas(key, KeyValueCasOp::Del(version), timeout_ms).await
+    }
+
+    async fn cas(&self, key: KeyId, op: KeyValueCasOp, timeout_ms: u64) -> Result<KeyValueCasResult, SimpleKeyValueCasError> {
+        let req_id = self.req_id_gen.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let (tx, rx) = async_std::channel::bounded(1);
+        self.simple_cas_queue.lock().insert(req_id, tx);
+        self.actions.write().push_back(crate::KeyValueSdkEvent::Cas(req_id, key, op, timeout_ms));
+        self.awaker.read().as_ref().unwrap().notify();
+        rx.recv().await.map_err(|_| SimpleKeyValueCasError::InternalError)?
+    }
+
+    /// Start recording when the value of key which is set by this node is acked by remote
+    pub(crate) fn watch_synced(&self, key: KeyId) {
+        self.simple_synced.lock().insert(key, None);
+    }
+
+    pub(crate) fn unwatch_synced(&self, key: KeyId) {
+        self.simple_synced.lock().remove(&key);
+    }
+
+    /// Sent time of the last acked value of key with version, None if not acked yet
+    pub(crate) fn synced_at(&self, key: KeyId, version: KeyVersion) -> Option<Instant> {
+        match self.simple_synced.lock().get(&key) {
+            Some(Some((synced_version, at))) if *synced_version == version => Some(*at),
+            _ => None,
+        }
+    }
+
     pub fn subscribe(&self, key: KeyId, ex: Option<u64>) -> SimpleKeyValueSubscriber {
         let sub_uuid = self.uuid_gen.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         let actions = self.actions.clone();
@@ -93,6 +178,53 @@ impl KeyValueSdk {
         rx.recv().await.map_err(|_| HashmapKeyValueGetError::InternalError)?
     }
 
+    /// Increase counter in sub key like `incr_by`
+    pub async fn hincr_by(&self, key: KeyId, sub_key: SubKeyId, delta: i64, ex: Option<u64>, timeout_ms: u64) -> Result<i64, HashmapKeyValueIncrError> {
+        let req_id = self.req_id_gen.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let (tx, rx) = async_std::channel::bounded(1);
+        self.hashmap_incr_queue.lock().insert(req_id, tx);
+        self.actions.write().push_back(crate::KeyValueSdkEvent::IncrH(req_id, key, sub_key, delta, ex, timeout_ms));
+        self.awaker.read().as_ref().unwrap().notify();
+        rx.recv().await.map_err(|_| HashmapKeyValueIncrError::InternalError)??.ok_or(HashmapKeyValueIncrError::InvalidValue)
+    }
+
+    /// Get at most limit sub keys from cursor in sorted order, cursor 0 is from the first sub key.
+    /// Return sub keys and next cursor, None if no more sub keys. The response is capped by count and size of values, so it may have fewer sub keys than limit with next cursor.
+    /// At least one sub key is returned, so a single large value is returned as it is
+    pub async fn hscan(&self, key: KeyId, cursor: SubKeyId, limit: u32, timeout_ms: u64) -> Result<(Vec<(SubKeyId, ValueType, KeyVersion, KeySource)>, Option<SubKeyId>), HashmapKeyValueGetError> {
+        match self.hquery(key, KeyValueHashmapQuery::Scan(cursor, limit), timeout_ms).await? {
+            KeyValueHashmapQueryResult::Scan(sub_keys, next_cursor) => Ok((sub_keys, next_cursor)),
+            _ => Err(HashmapKeyValueGetError::InternalError),
+        }
+    }
+
+    /// Number of sub keys
+    pub async fn hlen(&self, key: KeyId, timeout_ms: u64) -> Result<u64, HashmapKeyValueGetError> {
+        match self.hquery(key, KeyValueHashmapQuery::Len, timeout_ms).await? {
+            KeyValueHashmapQueryResult::Len(len) => Ok(len),
+            _ => Err(HashmapKeyValueGetError::InternalError),
+        }
+    }
+
+    async fn hquery(&self, key: KeyId, query: KeyValueHashmapQuery, timeout_ms: u64) -> Result<KeyValueHashmapQueryResult, HashmapKeyValueGetError> {
+        let req_id = self.req_id_gen.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let (tx, rx) = async_std::channel::bounded(1);
+        self.hashmap_query_queue.lock().insert(req_id, tx);
+        self.actions.write().push_back(crate::KeyValueSdkEvent::QueryH(req_id, key, query, timeout_ms));
+        self.awaker.read().as_ref().unwrap().notify();
+        rx.recv().await.map_err(|_| HashmapKeyValueGetError::InternalError)?
+    }
+
+    /// Hashmap keys which are stored in this node only, with same first prefix_bits bits with prefix
+    pub async fn keys_by_prefix(&self, prefix: KeyId, prefix_bits: u8) -> Result<Vec<KeyId>, HashmapKeyValueGetError> {
+        let req_id = self.req_id_gen.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let (tx, rx) = async_std::channel::bounded(1);
+        self.hashmap_keys_queue.lock().insert(req_id, tx);
+        self.actions.write().push_back(crate::KeyValueSdkEvent::KeysByPrefixH(req_id, prefix, prefix_bits));
+        self.awaker.read().as_ref().unwrap().notify();
+        rx.recv().await.map_err(|_| HashmapKeyValueGetError::InternalError)
+    }
+
     pub fn hdel(&self, key: KeyId, sub_key: SubKeyId) {
         self.actions.write().push_back(crate::KeyValueSdkEvent::DelH(key, sub_key));
         self.awaker.read().as_ref().unwrap().notify();
@@ -164,6 +296,67 @@ impl ExternalControl for KeyValueSdk {
                     }
                 }
             }
+            KeyValueSdkEvent::OnCas(req_id, key, res) => {
+                if let Some(tx) = self.simple_cas_queue.lock().remove(&req_id) {
+                    if let Err(e) = tx.try_send(res.map_err(|e| match e {
+                        KeyValueSdkEventError::NetworkError => SimpleKeyValueCasError::NetworkError,
+                        KeyValueSdkEventError::Timeout => SimpleKeyValueCasError::Timeout,
+                        KeyValueSdkEventError::InternalError => SimpleKeyValueCasError::InternalError,
+                    })) {
+                        log::error!("[KeyValueSdk] send cas result request {req_id} for key {key} error: {:?}", e);
+                    }
+                }
+            }
+            KeyValueSdkEvent::OnQueryH(req_id, key, res) => {
+                if let Some(tx) = self.hashmap_query_queue.lock().remove(&req_id) {
+                    if let Err(e) = tx.try_send(res.map_err(|e| match e {
+                        KeyValueSdkEventError::NetworkError => HashmapKeyValueGetError::NetworkError,
+                        KeyValueSdkEventError::Timeout => HashmapKeyValueGetError::Timeout,
+                        KeyValueSdkEventError::InternalError => HashmapKeyValueGetError::InternalError,
+                    })) {
+                        log::error!("[KeyValueSdk] send query result request {req_id} for key {key} error: {:?}", e);
+                    }
+                }
+            }
+            KeyValueSdkEvent::OnIncr(req_id, key, res) => {
+                if let Some(tx) = self.simple_incr_queue.lock().remove(&req_id) {
+                    if let Err(e) = tx.try_send(res.map_err(|e| match e {
+                        KeyValueSdkEventError::NetworkError => SimpleKeyValueIncrError::NetworkError,
+                        KeyValueSdkEventError::Timeout => SimpleKeyValueIncrError::Timeout,
+                        KeyValueSdkEventError::InternalError => SimpleKeyValueIncrError::InternalError,
+                    })) {
+                        log::error!("[KeyValueSdk] send incr result request {req_id} for key {key} error: {:?}", e);
+                    }
+                }
+            }
+            KeyValueSdkEvent::OnIncrH(req_id, key, sub_key, res) => {
+                if let Some(tx) = self.hashmap_incr_queue.lock().remove(&req_id) {
+                    if let Err(e) = tx.try_send(res.map_err(|e| match e {
+                        KeyValueSdkEventError::NetworkError => HashmapKeyValueIncrError::NetworkError,
+                        KeyValueSdkEventError::Timeout => HashmapKeyValueIncrError::Timeout,
+                        KeyValueSdkEventError::InternalError => HashmapKeyValueIncrError::InternalError,
+                    })) {
+                        log::error!("[KeyValueSdk] send incr result request {req_id} for key {key} sub_key {sub_key} error: {:?}", e);
+                    }
+                }
+            }
+            KeyValueSdkEvent::OnSynced(key, version, elapsed_ms) => {
+                // counted from when the acked value was sent, which is not later than the start of the remote lease
+                let sent_at = Instant::now().checked_sub(Duration::from_millis(elapsed_ms));
+                if let (Some(synced), Some(sent_at)) = (self.simple_synced.lock().get_mut(&key), sent_at) {
+                    match synced {
+                        Some((synced_version, at)) if *synced_version == version && *at >= sent_at => {}
+                        _ => *synced = Some((version, sent_at)),
+                    }
+                }
+            }
+            KeyValueSdkEvent::OnKeysByPrefixH(req_id, keys) => {
+                if let Some(tx) = self.hashmap_keys_queue.lock().remove(&req_id) {
+                    if let Err(e) = tx.try_send(keys) {
+                        log::error!("[KeyValueSdk] send keys result request {req_id} error: {:?}", e);
+                    }
+                }
+            }
             _ => {}
         }
     }
@@ -179,7 +372,9 @@ mod test {
 
     use atm0s_sdn_utils::awaker::{Awaker, MockAwaker};
 
-    use crate::{ExternalControl, KeyValueSdk, KeyValueSdkEvent};
+    use crate::behavior::hashmap_local::HashmapKeyValueIncrError;
+
+    use crate::{ExternalControl, KeyValueCasOp, KeyValueCasResult, KeyValueHashmapQuery, KeyValueHashmapQueryResult, KeyValueSdk, KeyValueSdkEvent};
 
     #[async_std::test]
     async fn sdk_get_should_fire_awaker_and_action() {
@@ -279,4 +474,83 @@ mod test {
         assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::UnsubH(0, 1000)));
         assert_eq!(sdk.pop_action(), None);
     }
+
+    #[async_std::test]
+    async fn sdk_cas_should_fire_awaker_and_action() {
+        let sdk = KeyValueSdk::new();
+        let awaker = Arc::new(MockAwaker::default());
+
+        sdk.set_awaker(awaker.clone());
+
+        async_std::future::timeout(Duration::from_millis(100), sdk.set_if_absent(1000, vec![1], None, 100))
+            .await
+            .expect_err("Should timeout");
+        assert_eq!(awaker.pop_awake_count(), 1);
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Cas(0, 1000, KeyValueCasOp::Set(vec![1], None, None), 100)));
+
+        async_std::future::timeout(Duration::from_millis(100), sdk.set_if_version(1000, vec![1], 10, Some(20000), 100))
+            .await
+            .expect_err("Should timeout");
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Cas(1, 1000, KeyValueCasOp::Set(vec![1], Some(10), Some(20000)), 100)));
+
+        let sdk2 = sdk.clone();
+        let task = async_std::task::spawn(async move { sdk2.delete_if_version(1000, 10, 100).await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Cas(2, 1000, KeyValueCasOp::Del(10), 100)));
+        sdk.on_event(KeyValueSdkEvent::OnCas(2, 1000, Ok(KeyValueCasResult::Applied(10))));
+        assert_eq!(task.await, Ok(KeyValueCasResult::Applied(10)));
+    }
+
+    #[async_std::test]
+    async fn sdk_hscan_hlen_keys_should_fire_awaker_and_action() {
+        let sdk = KeyValueSdk::new();
+        let awaker = Arc::new(MockAwaker::default());
+
+        sdk.set_awaker(awaker.clone());
+
+        let sdk2 = sdk.clone();
+        let task = async_std::task::spawn(async move { sdk2.hscan(1000, 0, 10, 100).await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(awaker.pop_awake_count(), 1);
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::QueryH(0, 1000, KeyValueHashmapQuery::Scan(0, 10), 100)));
+        sdk.on_event(KeyValueSdkEvent::OnQueryH(0, 1000, Ok(KeyValueHashmapQueryResult::Scan(vec![(1, vec![1], 10, 1)], Some(2)))));
+        assert_eq!(task.await, Ok((vec![(1, vec![1], 10, 1)], Some(2))));
+
+        let sdk2 = sdk.clone();
+        let task = async_std::task::spawn(async move { sdk2.hlen(1000, 100).await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::QueryH(1, 1000, KeyValueHashmapQuery::Len, 100)));
+        sdk.on_event(KeyValueSdkEvent::OnQueryH(1, 1000, Ok(KeyValueHashmapQueryResult::Len(5))));
+        assert_eq!(task.await, Ok(5));
+
+        let sdk2 = sdk.clone();
+        let task = async_std::task::spawn(async move { sdk2.keys_by_prefix(1000, 8).await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::KeysByPrefixH(2, 1000, 8)));
+        sdk.on_event(KeyValueSdkEvent::OnKeysByPrefixH(2, vec![1000, 1001]));
+        assert_eq!(task.await, Ok(vec![1000, 1001]));
+    }
+
+    #[async_std::test]
+    async fn sdk_incr_should_fire_awaker_and_action() {
+        let sdk = KeyValueSdk::new();
+        let awaker = Arc::new(MockAwaker::default());
+
+        sdk.set_awaker(awaker.clone());
+
+        let sdk2 = sdk.clone();
+        let task = async_std::task::spawn(async move { sdk2.decr_by(1000, 2, Some(1000), 100).await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(awaker.pop_awake_count(), 1);
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Incr(0, 1000, -2, Some(1000), 100)));
+        sdk.on_event(KeyValueSdkEvent::OnIncr(0, 1000, Ok(Some(3))));
+        assert_eq!(task.await, Ok(3));
+
+        let sdk2 = sdk.clone();
+        let task = async_std::task::spawn(async move { sdk2.hincr_by(1000, 1, 2, None, 100).await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::IncrH(1, 1000, 1, 2, None, 100)));
+        sdk.on_event(KeyValueSdkEvent::OnIncrH(1, 1000, 1, Ok(None)));
+        assert_eq!(task.await, Err(HashmapKeyValueIncrError::InvalidValue));
+    }
 }
@@ -0,0 +1,340 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    behavior::simple_local::{SimpleKeyValueCasError, SimpleKeyValueGetError},
+    KeyId, KeySource, KeyValueCasResult, KeyVersion, ValueType,
+};
+
+use super::{KeyValueSdk, SimpleKeyValueSubscriber};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DistributedLockError {
+    /// Lease must be longer than sync_each_ms, otherwise it expires before being refreshed by the next sync
+    LeaseTooShort,
+}
+
+/// Lock on a key, which is acquired by setting the key if absent with an expiry lease.
+/// While the holder's node is up, the lease is kept alive by the local storage, which syncs acquired value to remote each sync_each_ms.
+/// If the holder's node is down or disconnected, the key expires after lease_ms and other nodes can acquire it.
+///
+/// Fencing token is the KeyVersion of the acquired key, which is assigned by replica 0 of the key.
+/// It is always greater than any version stored there before, even if the key was deleted or expired, so a later holder has a greater token.
+pub struct DistributedLock {
+    sdk: KeyValueSdk,
+    key: KeyId,
+    lease_ms: u64,
+    timeout_ms: u64,
+}
+
+impl DistributedLock {
+    /// sync_each_ms must be same as the key value behaviour, lease_ms must be longer than it
+    pub fn new(sdk: KeyValueSdk, key: KeyId, lease_ms: u64, sync_each_ms: u64, timeout_ms: u64) -> Result<Self, DistributedLockError> {
+        if lease_ms <= sync_each_ms {
+            return Err(DistributedLockError::LeaseTooShort);
+        }
+        Ok(Self { sdk, key, lease_ms, timeout_ms })
+    }
+
+    /// Try acquire once, return None if the lock is held by other
+    pub async fn try_acquire(&self, value: ValueType) -> Result<Option<LockGuard>, SimpleKeyValueCasError> {
+        let requested_at = Instant::now();
+        let (token, subscriber) = self.try_acquire_inner(value).await?;
+        Ok(token.map(|token| self.guard(token, requested_at, subscriber)))
+    }
+
+    /// Wait until acquired, retry when the holder released or lost the lock
+    pub async fn acquire(&self, value: ValueType) -> Result<LockGuard, SimpleKeyValueCasError> {
+        loop {
+            let requested_at = Instant::now();
+            let (token, mut subscriber) = self.try_acquire_inner(value.clone()).await?;
+            if let Some(token) = token {
+                return Ok(self.guard(token, requested_at, subscriber));
+            }
+
+            // we also retry after lease_ms incase of missing deleted event
+            let _ = async_std::future::timeout(Duration::from_millis(self.lease_ms), async {
+                while let Some((_, value, _, _)) = subscriber.recv().await {
+                    if value.is_none() {
+                        break;
+                    }
+                }
+            })
+            .await;
+        }
+    }
+
+    /// Lease of guard is counted from the time of acquire request, which is not later than the remote lease
+    fn guard(&self, token: KeyVersion, requested_at: Instant, subscriber: SimpleKeyValueSubscriber) -> LockGuard {
+        self.sdk.watch_synced(self.key);
+        LockGuard {
+            sdk: self.sdk.clone(),
+            key: self.key,
+            token,
+            lease_ms: self.lease_ms,
+            acquired_at: requested_at,
+            timeout_ms: self.timeout_ms,
+            subscriber,
+            held: true,
+        }
+    }
+
+    /// Subscribe before setting, so changes after acquired are not missed
+    async fn try_acquire_inner(&self, value: ValueType) -> Result<(Option<KeyVersion>, SimpleKeyValueSubscriber), SimpleKeyValueCasError> {
+        let subscriber = self.sdk.subscribe(self.key, None);
+        match self.sdk.set_if_absent(self.key, value, Some(self.lease_ms), self.timeout_ms).await? {
+            KeyValueCasResult::Applied(version) => {
+                log::info!("[DistributedLock] acquired key {} with fencing token {}", self.key, version);
+                Ok((Some(version), subscriber))
+            }
+            KeyValueCasResult::Rejected(current) => {
+                log::debug!("[DistributedLock] key {} is held by {:?}", self.key, current.map(|(_, version, source)| (version, source)));
+                Ok((None, subscriber))
+            }
+        }
+    }
+}
+
+/// Acquired lock, which is released when dropped
+pub struct LockGuard {
+    sdk: KeyValueSdk,
+    key: KeyId,
+    token: KeyVersion,
+    lease_ms: u64,
+    acquired_at: Instant,
+    timeout_ms: u64,
+    subscriber: SimpleKeyValueSubscriber,
+    held: bool,
+}
+
+impl LockGuard {
+    pub fn key(&self) -> KeyId {
+        self.key
+    }
+
+    /// Token which should be sent with each write to protected resources, the resources reject writes with older tokens
+    pub fn fencing_token(&self) -> KeyVersion {
+        self.token
+    }
+
+    /// Local lease deadline, which is extended each time the value is synced to remote. It is counted from when the acked value was sent,
+    /// because the remote lease starts after that
+    fn lease_deadline(&self) -> Instant {
+        let synced_at = self.sdk.synced_at(self.key, self.token).map_or(self.acquired_at, |at| at.max(self.acquired_at));
+        synced_at + Duration::from_millis(self.lease_ms)
+    }
+
+    /// Wait until the lock is lost, which is caused by lease expired or other holder after this node was disconnected.
+    /// The lease is considered expired if the value is not synced to remote in lease_ms, even if no other holder is seen
+    pub async fn lost(&mut self) {
+        while self.held {
+            let now = Instant::now();
+            let deadline = self.lease_deadline();
+            if now >= deadline {
+                log::warn!("[DistributedLock] lost key {} with fencing token {}, not synced in {} ms", self.key, self.token, self.lease_ms);
+                self.stop_keep_alive();
+                break;
+            }
+            // after waiting until deadline, it is checked again because it may be extended by syncs
+            let event = match async_std::future::timeout(deadline - now, self.subscriber.recv()).await {
+                Ok(event) => event,
+                Err(_) => continue,
+            };
+            match event {
+                Some((_, value, version, _)) => {
+                    // events of older holders or our own value are ignored
+                    let lost = match value {
+                        Some(_) => version > self.token,
+                        None => version >= self.token,
+                    };
+                    if lost {
+                        log::warn!("[DistributedLock] lost key {} with fencing token {}, current version {}", self.key, self.token, version);
+                        self.stop_keep_alive();
+                    }
+                }
+                None => self.stop_keep_alive(),
+            }
+        }
+    }
+
+    /// Release the lock if still held, return false if it was already lost
+    pub async fn release(mut self) -> Result<bool, SimpleKeyValueCasError> {
+        // if error, local value is deleted when dropped
+        match self.sdk.delete_if_version(self.key, self.token, self.timeout_ms).await? {
+            KeyValueCasResult::Applied(_) => {
+                self.held = false;
+                Ok(true)
+            }
+            KeyValueCasResult::Rejected(_) => {
+                self.stop_keep_alive();
+                Ok(false)
+            }
+        }
+    }
+
+    /// Delete in local storage, which only deletes remote value if it is not newer than ours
+    fn stop_keep_alive(&mut self) {
+        if self.held {
+            self.held = false;
+            self.sdk.del(self.key);
+        }
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        self.stop_keep_alive();
+        self.sdk.unwatch_synced(self.key);
+    }
+}
+
+/// Leader election on a key, the leader is the holder of the lock
+pub struct LeaderElection {
+    lock: DistributedLock,
+}
+
+impl LeaderElection {
+    /// Same parameters as `DistributedLock::new`
+    pub fn new(sdk: KeyValueSdk, key: KeyId, lease_ms: u64, sync_each_ms: u64, timeout_ms: u64) -> Result<Self, DistributedLockError> {
+        Ok(Self {
+            lock: DistributedLock::new(sdk, key, lease_ms, sync_each_ms, timeout_ms)?,
+        })
+    }
+
+    /// Wait until this node becomes leader, value is usually info of this node for other nodes
+    pub async fn campaign(&self, value: ValueType) -> Result<LockGuard, SimpleKeyValueCasError> {
+        self.lock.acquire(value).await
+    }
+
+    /// Current leader with value, fencing token and node
+    pub async fn leader(&self) -> Result<Option<(ValueType, KeyVersion, KeySource)>, SimpleKeyValueGetError> {
+        self.lock.sdk.get(self.lock.key, self.lock.timeout_ms).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use atm0s_sdn_utils::awaker::MockAwaker;
+
+    use crate::{ExternalControl, KeyValueCasOp, KeyValueCasResult, KeyValueSdk, KeyValueSdkEvent};
+
+    use super::{DistributedLock, DistributedLockError};
+
+    #[async_std::test]
+    async fn acquire_and_lost() {
+        let sdk = KeyValueSdk::new();
+        sdk.set_awaker(Arc::new(MockAwaker::default()));
+
+        let lock = DistributedLock::new(sdk.clone(), 1000, 10000, 1000, 1000).expect("Should create");
+        let task = async_std::task::spawn(async move { lock.try_acquire(vec![1]).await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Sub(0, 1000, None)));
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Cas(0, 1000, KeyValueCasOp::Set(vec![1], None, Some(10000)), 1000)));
+        sdk.on_event(KeyValueSdkEvent::OnCas(0, 1000, Ok(KeyValueCasResult::Applied(100))));
+
+        let mut guard = task.await.expect("Should ok").expect("Should acquire");
+        assert_eq!(guard.fencing_token(), 100);
+
+        //our own value and deleted older value are not lost
+        sdk.on_event(KeyValueSdkEvent::OnKeyChanged(0, 1000, Some(vec![1]), 100, 1));
+        sdk.on_event(KeyValueSdkEvent::OnKeyChanged(0, 1000, None, 50, 2));
+        async_std::future::timeout(Duration::from_millis(50), guard.lost()).await.expect_err("Should not lost");
+
+        //other holder after our lease expired
+        sdk.on_event(KeyValueSdkEvent::OnKeyChanged(0, 1000, Some(vec![2]), 200, 2));
+        async_std::future::timeout(Duration::from_millis(50), guard.lost()).await.expect("Should lost");
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Del(1000)));
+
+        //after lost, drop only unsubscribes
+        drop(guard);
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Unsub(0, 1000)));
+        assert_eq!(sdk.pop_action(), None);
+    }
+
+    #[async_std::test]
+    async fn acquire_should_wait_holder_release() {
+        let sdk = KeyValueSdk::new();
+        sdk.set_awaker(Arc::new(MockAwaker::default()));
+
+        let lock = DistributedLock::new(sdk.clone(), 1000, 10000, 1000, 1000).expect("Should create");
+        let task = async_std::task::spawn(async move { lock.acquire(vec![1]).await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Sub(0, 1000, None)));
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Cas(0, 1000, KeyValueCasOp::Set(vec![1], None, Some(10000)), 1000)));
+        sdk.on_event(KeyValueSdkEvent::OnCas(0, 1000, Ok(KeyValueCasResult::Rejected(Some((vec![2], 100, 2))))));
+
+        //holder released, should retry
+        sdk.on_event(KeyValueSdkEvent::OnKeyChanged(0, 1000, None, 100, 2));
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Unsub(0, 1000)));
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Sub(1, 1000, None)));
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Cas(1, 1000, KeyValueCasOp::Set(vec![1], None, Some(10000)), 1000)));
+        sdk.on_event(KeyValueSdkEvent::OnCas(1, 1000, Ok(KeyValueCasResult::Applied(200))));
+
+        let guard = task.await.expect("Should acquire");
+        assert_eq!(guard.fencing_token(), 200);
+
+        let sdk2 = sdk.clone();
+        let task = async_std::task::spawn(async move { guard.release().await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(sdk2.pop_action(), Some(KeyValueSdkEvent::Cas(2, 1000, KeyValueCasOp::Del(200), 1000)));
+        sdk2.on_event(KeyValueSdkEvent::OnCas(2, 1000, Ok(KeyValueCasResult::Applied(200))));
+        assert_eq!(task.await, Ok(true));
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Unsub(1, 1000)));
+        assert_eq!(sdk.pop_action(), None);
+    }
+
+    #[test]
+    fn lease_should_be_longer_than_sync() {
+        let sdk = KeyValueSdk::new();
+        assert_eq!(DistributedLock::new(sdk.clone(), 1000, 1000, 1000, 1000).err(), Some(DistributedLockError::LeaseTooShort));
+        assert!(DistributedLock::new(sdk, 1000, 1001, 1000, 1000).is_ok());
+    }
+
+    #[async_std::test]
+    async fn lost_after_lease_without_sync() {
+        let sdk = KeyValueSdk::new();
+        sdk.set_awaker(Arc::new(MockAwaker::default()));
+
+        let lock = DistributedLock::new(sdk.clone(), 1000, 200, 100, 1000).expect("Should create");
+        let task = async_std::task::spawn(async move { lock.try_acquire(vec![1]).await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Sub(0, 1000, None)));
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Cas(0, 1000, KeyValueCasOp::Set(vec![1], None, Some(200)), 1000)));
+        sdk.on_event(KeyValueSdkEvent::OnCas(0, 1000, Ok(KeyValueCasResult::Applied(100))));
+        let mut guard = task.await.expect("Should ok").expect("Should acquire");
+
+        //synced value extends the lease
+        async_std::task::sleep(Duration::from_millis(140)).await;
+        sdk.on_event(KeyValueSdkEvent::OnSynced(1000, 100, 0));
+        async_std::future::timeout(Duration::from_millis(100), guard.lost()).await.expect_err("Should not lost");
+
+        //without next sync, lost after lease
+        async_std::future::timeout(Duration::from_millis(300), guard.lost()).await.expect("Should lost");
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Del(1000)));
+        drop(guard);
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Unsub(0, 1000)));
+        assert_eq!(sdk.pop_action(), None);
+    }
+
+    #[async_std::test]
+    async fn late_sync_ack_should_not_extend_lease() {
+        let sdk = KeyValueSdk::new();
+        sdk.set_awaker(Arc::new(MockAwaker::default()));
+
+        let lock = DistributedLock::new(sdk.clone(), 1000, 200, 100, 1000).expect("Should create");
+        let task = async_std::task::spawn(async move { lock.try_acquire(vec![1]).await });
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Sub(0, 1000, None)));
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Cas(0, 1000, KeyValueCasOp::Set(vec![1], None, Some(200)), 1000)));
+        sdk.on_event(KeyValueSdkEvent::OnCas(0, 1000, Ok(KeyValueCasResult::Applied(100))));
+        let mut guard = task.await.expect("Should ok").expect("Should acquire");
+
+        //ack arrives 140ms after the sync was sent at acquire time, so lease is not extended
+        async_std::task::sleep(Duration::from_millis(140)).await;
+        sdk.on_event(KeyValueSdkEvent::OnSynced(1000, 100, 140));
+        async_std::future::timeout(Duration::from_millis(100), guard.lost()).await.expect("Should lost");
+        assert_eq!(sdk.pop_action(), Some(KeyValueSdkEvent::Del(1000)));
+    }
+}
@@ -1,10 +1,12 @@
 use crate::{
-    msg::{KeyValueSdkEventError, SimpleLocalEvent, SimpleRemoteEvent},
+    msg::{KeyValueCasOp, KeyValueCasResult, KeyValueSdkEventError, SimpleLocalEvent, SimpleRemoteEvent},
     KeyId, KeySource, KeyVersion, ReqId, ValueType,
 };
 use atm0s_sdn_identity::NodeId;
 use atm0s_sdn_router::RouteRule;
 use small_map::SmallMap;
+
+use super::replication::{replica_route, ReplicaAnswers, ReplicationConfig};
 /// This simple local storage is used for storing and act with remote storage
 /// Main idea is we using sdk to act with local storage, and local storage will sync that data to remote
 /// Local storage allow us to set/get/del/subscribe/unsubscribe
@@ -27,6 +29,8 @@ struct KeySlotData {
     version: KeyVersion,
     last_sync: u64,
     acked: bool,
+    /// Last sent Set with time, the remote lease is counted from the time it was sent, not from its ack
+    sync_req: Option<(ReqId, u64)>,
 }
 
 struct KeySlotSubscribe {
@@ -45,11 +49,49 @@ pub enum SimpleKeyValueGetError {
     InternalError,
 }
 
+/// Value which is answered by a replica, with remaining ex
+type ReplicaValue = (ValueType, KeyVersion, KeySource, Option<u64>);
+
 struct KeySlotGetCallback {
     key: KeyId,
     timeout_after_ts: u64,
     uuid: u64,
     service_id: u8,
+    answers: ReplicaAnswers<ReplicaValue>,
+    replica_reqs: Vec<ReqId>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SimpleKeyValueCasError {
+    NetworkError,
+    Timeout,
+    InternalError,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SimpleKeyValueIncrError {
+    NetworkError,
+    Timeout,
+    InternalError,
+    /// Value is not a counter or overflow
+    InvalidValue,
+}
+
+struct KeySlotIncrCallback {
+    key: KeyId,
+    timeout_after_ts: u64,
+    uuid: u64,
+    service_id: u8,
+}
+
+/// Conditional write which is waiting for ack, after rejected it is waiting for current value of key
+struct KeySlotCasCallback {
+    key: KeyId,
+    op: KeyValueCasOp,
+    requested_at: u64,
+    timeout_after_ts: u64,
+    uuid: u64,
+    service_id: u8,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -57,6 +99,10 @@ pub enum LocalStorageAction {
     SendNet(SimpleRemoteEvent, RouteRule),
     LocalOnChanged(u8, u64, KeyId, Option<ValueType>, KeyVersion, KeySource),
     LocalOnGet(u8, u64, KeyId, Result<Option<(ValueType, KeyVersion, KeySource)>, KeyValueSdkEventError>),
+    LocalOnCas(u8, u64, KeyId, Result<KeyValueCasResult, KeyValueSdkEventError>),
+    LocalOnIncr(u8, u64, KeyId, Result<Option<i64>, KeyValueSdkEventError>),
+    /// Value which is set by this node is acked by remote with version and the time the acked Set was sent, used for keeping lock leases
+    LocalOnSynced(KeyId, KeyVersion, u64),
 }
 
 pub struct SimpleLocalStorage {
@@ -67,6 +113,10 @@ pub struct SimpleLocalStorage {
     subscribe: HashMap<KeyId, KeySlotSubscribe>,
     output_events: VecDeque<LocalStorageAction>,
     get_queue: HashMap<ReqId, KeySlotGetCallback>,
+    get_replica_reqs: HashMap<ReqId, (ReqId, u8)>,
+    cas_queue: HashMap<ReqId, KeySlotCasCallback>,
+    incr_queue: HashMap<ReqId, KeySlotIncrCallback>,
+    replication: ReplicationConfig,
 }
 
 impl SimpleLocalStorage {
@@ -80,9 +130,18 @@ impl SimpleLocalStorage {
             subscribe: HashMap::new(),
             output_events: VecDeque::new(),
             get_queue: HashMap::new(),
+            get_replica_reqs: HashMap::new(),
+            cas_queue: HashMap::new(),
+            incr_queue: HashMap::new(),
+            replication: ReplicationConfig::default(),
         }
     }
 
+    /// Replication config, which is used for reading from all replicas
+    pub fn set_replication(&mut self, replication: ReplicationConfig) {
+        self.replication = replication;
+    }
+
     fn gen_req_id(&self) -> u64 {
         return self.req_id_seed.fetch_add(1, Ordering::SeqCst);
     }
@@ -95,12 +154,14 @@ impl SimpleLocalStorage {
 
     /// Resend key releated event if not acked
     pub fn tick(&mut self, now: u64) {
+        let mut sync_reqs = Vec::new();
         for (key, slot) in self.data.iter() {
             // we resend event each tick if not acked. If has data => Set, no data => Del
             if !slot.acked {
                 let req_id = self.gen_req_id();
                 if let Some(value) = &slot.value {
                     log::debug!("[SimpleLocal] resend set key {} with version {}", key, slot.version);
+                    sync_reqs.push((*key, req_id));
                     self.output_events.push_back(LocalStorageAction::SendNet(
                         SimpleRemoteEvent::Set(req_id, *key, value.clone(), slot.version, slot.ex.clone()),
                         RouteRule::ToKey(*key as u32),
@@ -136,6 +197,7 @@ impl SimpleLocalStorage {
                 let req_id = self.gen_req_id();
                 if let Some(value) = &slot.value {
                     log::debug!("[SimpleLocal] sync set key {} with version {}", key, slot.version);
+                    sync_reqs.push((*key, req_id));
                     self.output_events.push_back(LocalStorageAction::SendNet(
                         SimpleRemoteEvent::Set(req_id, *key, value.clone(), slot.version, slot.ex.clone()),
                         RouteRule::ToKey(*key as u32),
@@ -154,6 +216,11 @@ impl SimpleLocalStorage {
                 slot.last_sync = now;
             }
         }
+        for (key, req_id) in sync_reqs {
+            if let Some(slot) = self.data.get_mut(&key) {
+                slot.sync_req = Some((req_id, now));
+            }
+        }
 
         let mut unsub_keys = Vec::new();
         // we sync subscribe each sync_each_ms with each subscribe which acked
@@ -187,12 +254,49 @@ impl SimpleLocalStorage {
             }
         }
 
-        // we clear timeout getter
+        // we clear timeout getter, answers from a part of replicas are only used with Any consistency
         for req_id in timeout_gets {
-            if let Some(slot) = self.get_queue.remove(&req_id) {
+            if let Some(mut slot) = self.get_queue.remove(&req_id) {
                 log::debug!("[SimpleLocal] get key {} timeout", req_id);
+                if slot.answers.resolve() {
+                    let res = if slot.answers.allow_partial() {
+                        Ok(Self::best_answer(&slot.answers).map(Self::without_ex))
+                    } else {
+                        Err(KeyValueSdkEventError::Timeout)
+                    };
+                    self.output_events.push_back(LocalStorageAction::LocalOnGet(slot.service_id, slot.uuid, slot.key, res));
+                }
+                self.finish_get(slot);
+            }
+        }
+
+        let mut timeout_cas = Vec::new();
+        for (req_id, slot) in self.cas_queue.iter() {
+            if now >= slot.timeout_after_ts {
+                timeout_cas.push(*req_id);
+            }
+        }
+
+        for req_id in timeout_cas {
+            if let Some(slot) = self.cas_queue.remove(&req_id) {
+                log::debug!("[SimpleLocal] cas key {} timeout", req_id);
+                self.output_events
+                    .push_back(LocalStorageAction::LocalOnCas(slot.service_id, slot.uuid, slot.key, Err(KeyValueSdkEventError::Timeout)));
+            }
+        }
+
+        let mut timeout_incrs = Vec::new();
+        for (req_id, slot) in self.incr_queue.iter() {
+            if now >= slot.timeout_after_ts {
+                timeout_incrs.push(*req_id);
+            }
+        }
+
+        for req_id in timeout_incrs {
+            if let Some(slot) = self.incr_queue.remove(&req_id) {
+                log::debug!("[SimpleLocal] incr key {} timeout", req_id);
                 self.output_events
-                    .push_back(LocalStorageAction::LocalOnGet(slot.service_id, slot.uuid, slot.key, Err(KeyValueSdkEventError::Timeout)));
+                    .push_back(LocalStorageAction::LocalOnIncr(slot.service_id, slot.uuid, slot.key, Err(KeyValueSdkEventError::Timeout)));
             }
         }
 
@@ -209,12 +313,21 @@ impl SimpleLocalStorage {
         log::debug!("[SimpleLocal] on_event from {} {:?}", from, event);
 
         match event {
-            SimpleLocalEvent::SetAck(_req_id, key, version, success) => {
-                if success {
+            SimpleLocalEvent::SetAck(req_id, key, version, success) => {
+                if let Some(slot) = self.cas_queue.remove(&req_id) {
+                    self.on_cas_ack(req_id, slot, success.then_some(version));
+                } else if success {
                     if let Some(slot) = self.data.get_mut(&key) {
                         // we acked if version match
                         if slot.version == version {
                             slot.acked = true;
+                            // late acks of older syncs don't extend the lease
+                            match slot.sync_req {
+                                Some((sync_req_id, sent_at)) if sync_req_id == req_id && slot.value.is_some() => {
+                                    self.output_events.push_back(LocalStorageAction::LocalOnSynced(key, version, sent_at));
+                                }
+                                _ => {}
+                            }
                         }
                     }
                 } else {
@@ -229,13 +342,32 @@ impl SimpleLocalStorage {
                 }
             }
             SimpleLocalEvent::GetAck(req_id, _key, value) => {
-                if let Some(slot) = self.get_queue.remove(&req_id) {
-                    self.output_events.push_back(LocalStorageAction::LocalOnGet(slot.service_id, slot.uuid, slot.key, Ok(value)));
-                } else {
+                if let Some(slot) = self.cas_queue.remove(&req_id) {
+                    self.output_events.push_back(LocalStorageAction::LocalOnCas(
+                        slot.service_id,
+                        slot.uuid,
+                        slot.key,
+                        Ok(KeyValueCasResult::Rejected(value.as_ref().map(Self::without_ex))),
+                    ));
+                    return;
+                }
+                let (req_id, index) = self.get_replica_reqs.remove(&req_id).unwrap_or((req_id, 0));
+                if let Some(slot) = self.get_queue.get_mut(&req_id) {
+                    if slot.answers.on_answer(index, value) {
+                        let res = Self::best_answer(&slot.answers).map(Self::without_ex);
+                        self.output_events.push_back(LocalStorageAction::LocalOnGet(slot.service_id, slot.uuid, slot.key, Ok(res)));
+                    }
+                    if slot.answers.is_finished() {
+                        if let Some(slot) = self.get_queue.remove(&req_id) {
+                            self.finish_get(slot);
+                        }
+                    }
                 }
             }
-            SimpleLocalEvent::DelAck(_req_id, key, version) => {
-                if let Some(slot) = self.data.get_mut(&key) {
+            SimpleLocalEvent::DelAck(req_id, key, version) => {
+                if let Some(slot) = self.cas_queue.remove(&req_id) {
+                    self.on_cas_ack(req_id, slot, version);
+                } else if let Some(slot) = self.data.get_mut(&key) {
                     if let Some(deleted_version) = version {
                         // we acked if deleted version older than current version
                         if slot.version >= deleted_version {
@@ -247,6 +379,11 @@ impl SimpleLocalStorage {
                     }
                 }
             }
+            SimpleLocalEvent::IncrAck(req_id, _key, value) => {
+                if let Some(slot) = self.incr_queue.remove(&req_id) {
+                    self.output_events.push_back(LocalStorageAction::LocalOnIncr(slot.service_id, slot.uuid, slot.key, Ok(value)));
+                }
+            }
             SimpleLocalEvent::SubAck(_req_id, key_id) => {
                 if let Some(slot) = self.subscribe.get_mut(&key_id) {
                     if slot.sub {
@@ -307,6 +444,7 @@ impl SimpleLocalStorage {
                 version,
                 last_sync: 0,
                 acked: false,
+                sync_req: Some((req_id, now_ms)),
             },
         );
 
@@ -314,9 +452,19 @@ impl SimpleLocalStorage {
             .push_back(LocalStorageAction::SendNet(SimpleRemoteEvent::Set(req_id, key, value, version, ex), RouteRule::ToKey(key as u32)));
     }
 
+    /// Get from all replicas, each replica is requested with a separated req_id
     pub fn get(&mut self, now_ms: u64, key: KeyId, uuid: u64, service_id: u8, timeout_ms: u64) {
         let req_id = self.gen_req_id();
         log::debug!("[SimpleLocal] get key {} with req_id {}", key, req_id);
+        self.output_events.push_back(LocalStorageAction::SendNet(SimpleRemoteEvent::Get(req_id, key), replica_route(key, 0)));
+        let mut replica_reqs = vec![];
+        for index in 1..self.replication.replicas {
+            let replica_req_id = self.gen_req_id();
+            self.get_replica_reqs.insert(replica_req_id, (req_id, index));
+            replica_reqs.push(replica_req_id);
+            self.output_events
+                .push_back(LocalStorageAction::SendNet(SimpleRemoteEvent::Get(replica_req_id, key), replica_route(key, index)));
+        }
         self.get_queue.insert(
             req_id,
             KeySlotGetCallback {
@@ -324,10 +472,117 @@ impl SimpleLocalStorage {
                 timeout_after_ts: now_ms + timeout_ms,
                 uuid,
                 service_id,
+                answers: ReplicaAnswers::new(self.replication),
+                replica_reqs,
             },
         );
+    }
+
+    fn best_answer(answers: &ReplicaAnswers<ReplicaValue>) -> Option<&ReplicaValue> {
+        answers.answers().iter().filter_map(|(_, answer)| answer.as_ref()).max_by_key(|(_, version, ..)| *version)
+    }
+
+    fn without_ex((value, version, source, _): &ReplicaValue) -> (ValueType, KeyVersion, KeySource) {
+        (value.clone(), *version, *source)
+    }
+
+    /// Repair replicas which answered an older version, then clear the request.
+    /// Replicas which answered without value are not repaired, because the key may be deleted there after the other replicas were written
+    fn finish_get(&mut self, slot: KeySlotGetCallback) {
+        for replica_req_id in slot.replica_reqs.iter() {
+            self.get_replica_reqs.remove(replica_req_id);
+        }
+        if let Some((value, version, source, ex)) = Self::best_answer(&slot.answers) {
+            for (index, answer) in slot.answers.answers() {
+                if matches!(answer, Some((_, v, ..)) if v < version) {
+                    log::info!("[SimpleLocal] repair key {} replica {} with version {}", slot.key, index, version);
+                    // remaining ex of the answer is kept, so values without expiry like counters are not expired by repairing
+                    self.output_events.push_back(LocalStorageAction::SendNet(
+                        SimpleRemoteEvent::ReplicaSet(slot.key, value.clone(), *version, *source, *ex),
+                        replica_route(slot.key, *index),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Conditional write which is checked by replica 0. If applied, a set value is synced like a normal set and a deleted key is no longer synced.
+    /// If rejected, the current value is fetched from replica 0 for the result
+    pub fn cas(&mut self, now_ms: u64, key: KeyId, op: KeyValueCasOp, uuid: u64, service_id: u8, timeout_ms: u64) {
+        let req_id = self.gen_req_id();
+        let event = match &op {
+            KeyValueCasOp::Set(value, expected, ex) => {
+                log::debug!("[SimpleLocal] set key {} if version {:?}", key, expected);
+                SimpleRemoteEvent::SetIf(req_id, key, value.clone(), *ex, *expected)
+            }
+            KeyValueCasOp::Del(expected) => {
+                log::debug!("[SimpleLocal] del key {} if version {}", key, expected);
+                SimpleRemoteEvent::DelIf(req_id, key, *expected)
+            }
+        };
+        self.cas_queue.insert(
+            req_id,
+            KeySlotCasCallback {
+                key,
+                op,
+                requested_at: now_ms,
+                timeout_after_ts: now_ms + timeout_ms,
+                uuid,
+                service_id,
+            },
+        );
+        self.output_events.push_back(LocalStorageAction::SendNet(event, replica_route(key, 0)));
+    }
+
+    /// Increase counter in the authoritative node of key, the counter is not synced like set because it is not owned by this node
+    #[allow(clippy::too_many_arguments)]
+    pub fn incr(&mut self, now_ms: u64, key: KeyId, delta: i64, ex: Option<u64>, uuid: u64, service_id: u8, timeout_ms: u64) {
+        let req_id = self.gen_req_id();
+        log::debug!("[SimpleLocal] incr key {} by {} with req_id {}", key, delta, req_id);
+        self.incr_queue.insert(
+            req_id,
+            KeySlotIncrCallback {
+                key,
+                timeout_after_ts: now_ms + timeout_ms,
+                uuid,
+                service_id,
+            },
+        );
+        self.output_events
+            .push_back(LocalStorageAction::SendNet(SimpleRemoteEvent::Incr(req_id, key, delta, ex), replica_route(key, 0)));
+    }
+
+    fn on_cas_ack(&mut self, req_id: ReqId, slot: KeySlotCasCallback, applied: Option<KeyVersion>) {
+        let version = match applied {
+            Some(version) => version,
+            None => {
+                log::debug!("[SimpleLocal] cas key {} rejected, fetching current value", slot.key);
+                self.output_events
+                    .push_back(LocalStorageAction::SendNet(SimpleRemoteEvent::Get(req_id, slot.key), replica_route(slot.key, 0)));
+                self.cas_queue.insert(req_id, slot);
+                return;
+            }
+        };
+        match slot.op {
+            KeyValueCasOp::Set(value, _, ex) => {
+                self.data.insert(
+                    slot.key,
+                    KeySlotData {
+                        value: Some(value),
+                        ex,
+                        version,
+                        last_sync: slot.requested_at,
+                        acked: true,
+                        sync_req: None,
+                    },
+                );
+            }
+            KeyValueCasOp::Del(_) => {
+                self.data.remove(&slot.key);
+            }
+        }
         self.output_events
-            .push_back(LocalStorageAction::SendNet(SimpleRemoteEvent::Get(req_id, key), RouteRule::ToKey(key as u32)));
+            .push_back(LocalStorageAction::LocalOnCas(slot.service_id, slot.uuid, slot.key, Ok(KeyValueCasResult::Applied(version))));
     }
 
     pub fn del(&mut self, key: KeyId) {
@@ -398,8 +653,11 @@ mod tests {
     use atm0s_sdn_router::RouteRule;
 
     use crate::{
-        behavior::simple_local::LocalStorageAction,
-        msg::{KeyValueSdkEventError, SimpleLocalEvent, SimpleRemoteEvent},
+        behavior::{
+            replication::{replica_route, ReadConsistency, ReplicationConfig},
+            simple_local::LocalStorageAction,
+        },
+        msg::{KeyValueCasOp, KeyValueCasResult, KeyValueSdkEventError, SimpleLocalEvent, SimpleRemoteEvent},
     };
 
     use super::SimpleLocalStorage;
@@ -417,6 +675,7 @@ mod tests {
         assert_eq!(storage.pop_action(), None);
 
         storage.on_event(2, SimpleLocalEvent::SetAck(0, 1, 0, true));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnSynced(1, 0, 0)));
 
         //after received ack should not resend event
         storage.tick(100);
@@ -461,6 +720,7 @@ mod tests {
         assert_eq!(storage.pop_action(), None);
 
         storage.on_event(2, SimpleLocalEvent::SetAck(1, 1, 65536001, true));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnSynced(1, 65536001, 1000)));
 
         //after received ack should not resend event
         storage.tick(1000);
@@ -493,6 +753,7 @@ mod tests {
         assert!(storage.pop_action().is_none());
 
         storage.on_event(2, SimpleLocalEvent::SetAck(0, 1, 0, true));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnSynced(1, 0, 0)));
 
         //after received ack should not resend event
         storage.tick(0);
@@ -504,6 +765,14 @@ mod tests {
             storage.pop_action(),
             Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Set(1, 1, vec![1], 0, None), RouteRule::ToKey(1)))
         );
+
+        //synced time is the time of sending, and late ack of older sync is ignored
+        storage.tick(20001);
+        assert!(storage.pop_action().is_some());
+        storage.on_event(2, SimpleLocalEvent::SetAck(1, 1, 0, true));
+        assert_eq!(storage.pop_action(), None);
+        storage.on_event(2, SimpleLocalEvent::SetAck(2, 1, 0, true));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnSynced(1, 0, 20001)));
     }
 
     #[test]
@@ -514,6 +783,7 @@ mod tests {
         assert!(storage.pop_action().is_some());
         assert!(storage.pop_action().is_none());
         storage.on_event(2, SimpleLocalEvent::SetAck(0, 1, 0, true));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnSynced(1, 0, 0)));
 
         storage.del(1);
         assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Del(1, 1, 0), RouteRule::ToKey(1))));
@@ -533,6 +803,7 @@ mod tests {
         assert!(storage.pop_action().is_some());
         assert!(storage.pop_action().is_none());
         storage.on_event(2, SimpleLocalEvent::SetAck(0, 1, 0, true));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnSynced(1, 0, 0)));
 
         storage.set(1000, 1, vec![2], None);
         assert!(storage.pop_action().is_some());
@@ -557,6 +828,7 @@ mod tests {
         assert!(storage.pop_action().is_some());
         assert!(storage.pop_action().is_none());
         storage.on_event(2, SimpleLocalEvent::SetAck(0, 1, 0, true));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnSynced(1, 0, 0)));
 
         storage.del(1);
         assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Del(1, 1, 0), RouteRule::ToKey(1))));
@@ -721,7 +993,7 @@ mod tests {
         assert_eq!(storage.pop_action(), None);
 
         //fake received result
-        storage.on_event(2, SimpleLocalEvent::GetAck(0, 1, Some((vec![1], 0, 1000))));
+        storage.on_event(2, SimpleLocalEvent::GetAck(0, 1, Some((vec![1], 0, 1000, None))));
 
         assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnGet(10, 11111, 1, Ok(Some((vec![1], 0, 1000))))));
         assert_eq!(storage.pop_action(), None);
@@ -742,4 +1014,191 @@ mod tests {
         assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnGet(10, 11111, 1, Err(KeyValueSdkEventError::Timeout))));
         assert_eq!(storage.pop_action(), None);
     }
+
+    #[test]
+    fn replicated_get_quorum_should_take_highest_version_and_repair() {
+        let mut storage = SimpleLocalStorage::new(10000);
+        storage.set_replication(ReplicationConfig {
+            replicas: 3,
+            read: ReadConsistency::Quorum,
+        });
+        storage.get(0, 1, 11111, 10, 1000);
+
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Get(0, 1), RouteRule::ToKey(1))));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Get(1, 1), replica_route(1, 1))));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Get(2, 1), replica_route(1, 2))));
+        assert_eq!(storage.pop_action(), None);
+
+        //first answer is not enough for quorum
+        storage.on_event(2, SimpleLocalEvent::GetAck(0, 1, Some((vec![1], 1, 1000, None))));
+        assert_eq!(storage.pop_action(), None);
+
+        storage.on_event(3, SimpleLocalEvent::GetAck(2, 1, Some((vec![2], 2, 1001, Some(5000)))));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnGet(10, 11111, 1, Ok(Some((vec![2], 2, 1001))))));
+        assert_eq!(storage.pop_action(), None);
+
+        //after all replicas answered, stale replicas are repaired with remaining ex but the replica without value is not, the key may be deleted there
+        storage.on_event(4, SimpleLocalEvent::GetAck(1, 1, None));
+        assert_eq!(
+            storage.pop_action(),
+            Some(LocalStorageAction::SendNet(SimpleRemoteEvent::ReplicaSet(1, vec![2], 2, 1001, Some(5000)), RouteRule::ToKey(1)))
+        );
+        assert_eq!(storage.pop_action(), None);
+
+        //duplicated answer is ignored
+        storage.on_event(4, SimpleLocalEvent::GetAck(1, 1, None));
+        assert_eq!(storage.pop_action(), None);
+    }
+
+    #[test]
+    fn replicated_get_any_should_resolve_with_first_value() {
+        let mut storage = SimpleLocalStorage::new(10000);
+        storage.set_replication(ReplicationConfig {
+            replicas: 2,
+            read: ReadConsistency::Any,
+        });
+        storage.get(0, 1, 11111, 10, 1000);
+
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Get(0, 1), RouteRule::ToKey(1))));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Get(1, 1), replica_route(1, 1))));
+        assert_eq!(storage.pop_action(), None);
+
+        storage.on_event(3, SimpleLocalEvent::GetAck(1, 1, Some((vec![1], 1, 1000, None))));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnGet(10, 11111, 1, Ok(Some((vec![1], 1, 1000))))));
+        assert_eq!(storage.pop_action(), None);
+
+        //after timeout the replica without answer is not repaired, only answered replicas are known
+        storage.tick(1001);
+        assert_eq!(storage.pop_action(), None);
+    }
+
+    #[test]
+    fn replicated_get_quorum_timeout_should_not_use_partial_answers() {
+        let mut storage = SimpleLocalStorage::new(10000);
+        storage.set_replication(ReplicationConfig {
+            replicas: 3,
+            read: ReadConsistency::Quorum,
+        });
+        storage.get(0, 1, 11111, 10, 1000);
+        while storage.pop_action().is_some() {}
+
+        storage.on_event(2, SimpleLocalEvent::GetAck(0, 1, Some((vec![1], 1, 1000, None))));
+        assert_eq!(storage.pop_action(), None);
+
+        storage.tick(1001);
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnGet(10, 11111, 1, Err(KeyValueSdkEventError::Timeout))));
+        assert_eq!(storage.pop_action(), None);
+    }
+
+    #[test]
+    fn replicated_get_any_timeout_should_use_partial_answers() {
+        let mut storage = SimpleLocalStorage::new(10000);
+        storage.set_replication(ReplicationConfig {
+            replicas: 3,
+            read: ReadConsistency::Any,
+        });
+        storage.get(0, 1, 11111, 10, 1000);
+        while storage.pop_action().is_some() {}
+
+        storage.on_event(2, SimpleLocalEvent::GetAck(0, 1, None));
+        assert_eq!(storage.pop_action(), None);
+
+        storage.tick(1001);
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnGet(10, 11111, 1, Ok(None))));
+        assert_eq!(storage.pop_action(), None);
+    }
+
+    #[test]
+    fn cas_set_applied_should_sync_like_set() {
+        let mut storage = SimpleLocalStorage::new(10000);
+        storage.cas(1000, 1, KeyValueCasOp::Set(vec![1], None, Some(5000)), 11111, 10, 1000);
+
+        //version is assigned by replica 0
+        let version = 1000 << 16;
+        assert_eq!(
+            storage.pop_action(),
+            Some(LocalStorageAction::SendNet(SimpleRemoteEvent::SetIf(0, 1, vec![1], Some(5000), None), RouteRule::ToKey(1)))
+        );
+        assert_eq!(storage.pop_action(), None);
+
+        storage.on_event(2, SimpleLocalEvent::SetAck(0, 1, version, true));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnCas(10, 11111, 1, Ok(KeyValueCasResult::Applied(version)))));
+        assert_eq!(storage.pop_action(), None);
+
+        //applied value is synced each sync_each_ms
+        storage.tick(1000 + 9999);
+        assert_eq!(storage.pop_action(), None);
+        storage.tick(1000 + 10000);
+        assert_eq!(
+            storage.pop_action(),
+            Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Set(1, 1, vec![1], version, Some(5000)), RouteRule::ToKey(1)))
+        );
+        assert_eq!(storage.pop_action(), None);
+
+        //delete if version should stop syncing
+        storage.cas(20000, 1, KeyValueCasOp::Del(version), 11112, 10, 1000);
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::DelIf(2, 1, version), RouteRule::ToKey(1))));
+        storage.on_event(2, SimpleLocalEvent::DelAck(2, 1, Some(version)));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnCas(10, 11112, 1, Ok(KeyValueCasResult::Applied(version)))));
+        storage.tick(40000);
+        assert_eq!(storage.pop_action(), None);
+    }
+
+    #[test]
+    fn cas_rejected_should_fetch_current_value() {
+        let mut storage = SimpleLocalStorage::new(10000);
+        storage.cas(1000, 1, KeyValueCasOp::Set(vec![1], Some(100), None), 11111, 10, 1000);
+        assert_eq!(
+            storage.pop_action(),
+            Some(LocalStorageAction::SendNet(SimpleRemoteEvent::SetIf(0, 1, vec![1], None, Some(100)), RouteRule::ToKey(1)))
+        );
+
+        storage.on_event(2, SimpleLocalEvent::SetAck(0, 1, 200, false));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Get(0, 1), RouteRule::ToKey(1))));
+        assert_eq!(storage.pop_action(), None);
+
+        storage.on_event(2, SimpleLocalEvent::GetAck(0, 1, Some((vec![2], 200, 1001, None))));
+        assert_eq!(
+            storage.pop_action(),
+            Some(LocalStorageAction::LocalOnCas(10, 11111, 1, Ok(KeyValueCasResult::Rejected(Some((vec![2], 200, 1001))))))
+        );
+        assert_eq!(storage.pop_action(), None);
+
+        //rejected value is not synced
+        storage.tick(20000);
+        assert_eq!(storage.pop_action(), None);
+    }
+
+    #[test]
+    fn cas_should_timeout_after_no_ack() {
+        let mut storage = SimpleLocalStorage::new(10000);
+        storage.cas(0, 1, KeyValueCasOp::Del(100), 11111, 10, 1000);
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::DelIf(0, 1, 100), RouteRule::ToKey(1))));
+
+        storage.tick(1000);
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnCas(10, 11111, 1, Err(KeyValueSdkEventError::Timeout))));
+        assert_eq!(storage.pop_action(), None);
+    }
+
+    #[test]
+    fn incr_should_callback_new_value() {
+        let mut storage = SimpleLocalStorage::new(10000);
+        storage.incr(0, 1, 5, Some(1000), 11111, 10, 1000);
+        storage.incr(0, 1, 5, None, 11112, 10, 1000);
+        assert_eq!(
+            storage.pop_action(),
+            Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Incr(0, 1, 5, Some(1000)), RouteRule::ToKey(1)))
+        );
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::SendNet(SimpleRemoteEvent::Incr(1, 1, 5, None), RouteRule::ToKey(1))));
+        assert_eq!(storage.pop_action(), None);
+
+        storage.on_event(2, SimpleLocalEvent::IncrAck(0, 1, Some(5)));
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnIncr(10, 11111, 1, Ok(Some(5)))));
+        assert_eq!(storage.pop_action(), None);
+
+        //counter is not synced, and not acked request is timeout
+        storage.tick(1000);
+        assert_eq!(storage.pop_action(), Some(LocalStorageAction::LocalOnIncr(10, 11112, 1, Err(KeyValueSdkEventError::Timeout))));
+        assert_eq!(storage.pop_action(), None);
+    }
 }
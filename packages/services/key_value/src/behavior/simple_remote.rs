@@ -1,15 +1,22 @@
 /// This remote storage is a simple key value storage, it will store all key value in memory, and send event to other node when key value changed
 /// Each event is attached with a req_id and wait for ack, if ack not receive, it will resend the event each tick util ack received or tick_count is 0
+use crate::storage::persistent::{PersistentLog, PersistentStorage, StorageRecord};
 use crate::storage::simple::{OutputEvent, SimpleKeyValue};
 use crate::{
     msg::{SimpleLocalEvent, SimpleRemoteEvent},
-    KeyId, ValueType,
+    KeyId, KeyVersion, ValueType,
 };
 use atm0s_sdn_identity::NodeId;
-use atm0s_sdn_router::RouteRule;
-use std::collections::VecDeque;
+use atm0s_sdn_router::{RouteRule, RouterTable};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 
 use super::event_acks::EventAckManager;
+use super::{
+    counter::{counter_version, encode_counter, incr_counter},
+    handoff::{remain_ex, KeyHandoff},
+    replication::replica_route,
+};
 
 const RETRY_COUNT: u8 = 5;
 
@@ -21,6 +28,12 @@ pub struct SimpleRemoteStorage {
     storage: SimpleKeyValue<KeyId, ValueType, NodeId, NodeId>,
     event_acks: EventAckManager<RemoteStorageAction>,
     output_events: VecDeque<RemoteStorageAction>,
+    replica_events: VecDeque<(SimpleRemoteEvent, RouteRule)>,
+    replicas: u8,
+    persistent: Option<PersistentLog>,
+    handoff: KeyHandoff,
+    /// Highest stored version of each key, which is kept after deleted or expired for assigning increasing versions
+    version_marks: HashMap<KeyId, KeyVersion>,
 }
 
 impl SimpleRemoteStorage {
@@ -30,37 +43,106 @@ impl SimpleRemoteStorage {
             storage: SimpleKeyValue::new(),
             event_acks: EventAckManager::new(),
             output_events: VecDeque::new(),
+            replica_events: VecDeque::new(),
+            replicas: 1,
+            persistent: None,
+            handoff: KeyHandoff::new(),
+            version_marks: HashMap::new(),
+        }
+    }
+
+    /// Create storage which persists all changes and restores them from the persistent storage.
+    /// Records are replayed with their original time, so keys which expired while stopped are cleared in the first tick.
+    pub fn new_persistent(storage: Box<dyn PersistentStorage>) -> Self {
+        let (persistent, records) = PersistentLog::new(storage);
+        let mut this = Self::new();
+        log::info!("[SimpleRemote] restore {} records from persistent storage", records.len());
+        for record in records {
+            match record {
+                StorageRecord::Set {
+                    at, key, value, version, source, ex, ..
+                } => {
+                    this.mark_version(key, version);
+                    this.storage.set(at, key, value, version, source, ex);
+                }
+                StorageRecord::Del { key, version, .. } => {
+                    this.storage.del(&key, version);
+                }
+            }
         }
+        this.persistent = Some(persistent);
+        this
+    }
+
+    /// Number of replicas, writes from writers are forwarded to other replicas
+    pub fn set_replicas(&mut self, replicas: u8) {
+        self.replicas = replicas;
+    }
+
+    /// Router for checking which keys are still owned by this node, moved keys are handed off to the new owners
+    pub fn set_router(&mut self, router: Arc<dyn RouterTable>) {
+        self.handoff.set_router(router);
     }
 
     pub fn tick(&mut self, now_ms: u64) {
         self.storage.tick(now_ms);
+        // marks older than current time are not needed, new versions are generated from time
+        self.version_marks.retain(|_, version| *version >= now_ms << 16);
         self.event_acks.tick(now_ms);
+        self.handoff_tick(now_ms);
+        if let Some(persistent) = &mut self.persistent {
+            if persistent.should_compact() {
+                let snapshot = self
+                    .storage
+                    .snapshot()
+                    .into_iter()
+                    .map(|(key, value, version, source, expire_at)| StorageRecord::Set {
+                        at: now_ms,
+                        key,
+                        sub_key: None,
+                        value,
+                        version,
+                        source,
+                        ex: expire_at.map(|expire_at| expire_at.saturating_sub(now_ms)),
+                    })
+                    .collect();
+                persistent.compact(snapshot);
+            }
+            persistent.flush(now_ms);
+        }
     }
 
     pub fn on_event(&mut self, now_ms: u64, from: NodeId, event: SimpleRemoteEvent) {
         match event {
             SimpleRemoteEvent::Set(req_id, key, value, version, ex) => {
                 log::debug!("[SimpleRemote] receive set event from {} key {} value {:?} version {} ex {:?}", from, key, value, version, ex);
-                let setted = self.storage.set(now_ms, key, value, version, from, ex);
+                for index in 1..self.replicas {
+                    self.replica_events
+                        .push_back((SimpleRemoteEvent::ReplicaSet(key, value.clone(), version, from, ex), replica_route(key, index)));
+                }
+                // re-syncs of the stored version are also acked, which keep the writer's lease alive
+                let setted = self.store_set(now_ms, key, value, version, from, ex) || self.storage.get(&key).map(|(_, version, _)| version) == Some(version);
                 self.output_events
                     .push_back(RemoteStorageAction(SimpleLocalEvent::SetAck(req_id, key, version, setted), RouteRule::ToNode(from)));
             }
             SimpleRemoteEvent::Get(req_id, key) => {
-                if let Some((value, version, source)) = self.storage.get(&key) {
-                    log::debug!("[SimpleRemote] receive get event from {} key {} value {:?} version {}", from, key, value, version);
-                    self.output_events.push_back(RemoteStorageAction(
-                        SimpleLocalEvent::GetAck(req_id, key, Some((value.clone(), version, source))),
-                        RouteRule::ToNode(from),
-                    ));
-                } else {
-                    log::debug!("[SimpleRemote] receive get event from {} key {} value None", from, key);
-                    self.output_events.push_back(RemoteStorageAction(SimpleLocalEvent::GetAck(req_id, key, None), RouteRule::ToNode(from)));
+                if self.storage.get(&key).is_none() {
+                    if let Some((fallback_req_id, nodes)) = self.handoff.fallback_read(now_ms, from, req_id, key, true) {
+                        log::debug!("[SimpleRemote] receive get event from {} key {} value None, fallback to previous owners {:?}", from, key, nodes);
+                        for node in nodes {
+                            self.replica_events.push_back((SimpleRemoteEvent::HandoffGet(fallback_req_id, key), RouteRule::ToNode(node)));
+                        }
+                        return;
+                    }
                 }
+                self.answer_get(now_ms, from, req_id, key);
             }
             SimpleRemoteEvent::Del(req_id, key, req_version) => {
                 log::debug!("[SimpleRemote] receive del event from {} key {} version {:?}", from, key, req_version);
-                let version = self.storage.del(&key, req_version).map(|(_, version, _)| version);
+                for index in 1..self.replicas {
+                    self.replica_events.push_back((SimpleRemoteEvent::ReplicaDel(key, req_version), replica_route(key, index)));
+                }
+                let version = self.store_del(now_ms, key, req_version);
                 self.output_events
                     .push_back(RemoteStorageAction(SimpleLocalEvent::DelAck(req_id, key, version), RouteRule::ToNode(from)));
             }
@@ -83,9 +165,202 @@ impl SimpleRemoteStorage {
                 log::debug!("[SimpleRemote] receive on_key_del_ack event from {}, req_id {}", from, req_id);
                 self.event_acks.on_ack(req_id);
             }
+            SimpleRemoteEvent::ReplicaSet(key, value, version, source, ex) => {
+                log::debug!("[SimpleRemote] receive replica set event from {} key {} version {} source {} ex {:?}", from, key, version, source, ex);
+                self.store_set(now_ms, key, value, version, source, ex);
+            }
+            SimpleRemoteEvent::ReplicaDel(key, version) => {
+                log::debug!("[SimpleRemote] receive replica del event from {} key {} version {}", from, key, version);
+                self.store_del(now_ms, key, version);
+            }
+            SimpleRemoteEvent::SetIf(req_id, key, value, ex, expected) => {
+                let current = self.storage.get(&key).map(|(_, version, _)| version);
+                let version = self.next_version(now_ms, key);
+                log::debug!(
+                    "[SimpleRemote] receive set_if event from {} key {} version {} expected {:?} current {:?}",
+                    from,
+                    key,
+                    version,
+                    expected,
+                    current
+                );
+                let setted = current == expected && self.store_set(now_ms, key, value.clone(), version, from, ex);
+                if setted {
+                    for index in 1..self.replicas {
+                        self.replica_events
+                            .push_back((SimpleRemoteEvent::ReplicaSet(key, value.clone(), version, from, ex), replica_route(key, index)));
+                    }
+                }
+                let ack_version = if setted {
+                    version
+                } else {
+                    current.unwrap_or(0)
+                };
+                self.output_events
+                    .push_back(RemoteStorageAction(SimpleLocalEvent::SetAck(req_id, key, ack_version, setted), RouteRule::ToNode(from)));
+            }
+            SimpleRemoteEvent::Incr(req_id, key, delta, ex) => {
+                let current = self.storage.get(&key).map(|(value, _, _)| value.clone());
+                let new_value = incr_counter(current.as_deref(), delta);
+                log::debug!("[SimpleRemote] receive incr event from {} key {} delta {} ex {:?} new value {:?}", from, key, delta, ex, new_value);
+                if let Some(new_value) = new_value {
+                    let version = self.next_version(now_ms, key);
+                    let value = encode_counter(new_value);
+                    for index in 1..self.replicas {
+                        self.replica_events
+                            .push_back((SimpleRemoteEvent::ReplicaSet(key, value.clone(), version, from, ex), replica_route(key, index)));
+                    }
+                    self.store_set(now_ms, key, value, version, from, ex);
+                }
+                self.output_events
+                    .push_back(RemoteStorageAction(SimpleLocalEvent::IncrAck(req_id, key, new_value), RouteRule::ToNode(from)));
+            }
+            SimpleRemoteEvent::DelIf(req_id, key, expected) => {
+                let current = self.storage.get(&key).map(|(_, version, _)| version);
+                log::debug!("[SimpleRemote] receive del_if event from {} key {} expected {} current {:?}", from, key, expected, current);
+                let version = if current == Some(expected) {
+                    self.store_del(now_ms, key, expected)
+                } else {
+                    None
+                };
+                if version.is_some() {
+                    for index in 1..self.replicas {
+                        self.replica_events.push_back((SimpleRemoteEvent::ReplicaDel(key, expected), replica_route(key, index)));
+                    }
+                }
+                self.output_events
+                    .push_back(RemoteStorageAction(SimpleLocalEvent::DelAck(req_id, key, version), RouteRule::ToNode(from)));
+            }
+            SimpleRemoteEvent::Handoff(req_id, key, value, version, source, ex) => {
+                log::debug!("[SimpleRemote] receive handoff event from {} key {} version {} source {} ex {:?}", from, key, version, source, ex);
+                for (fallback_req_id, read_key) in self.handoff.on_handoff(now_ms, from) {
+                    self.replica_events.push_back((SimpleRemoteEvent::HandoffGet(fallback_req_id, read_key), RouteRule::ToNode(from)));
+                }
+                self.store_set(now_ms, key, value, version, source, ex);
+                self.replica_events.push_back((SimpleRemoteEvent::HandoffAck(req_id), RouteRule::ToNode(from)));
+            }
+            SimpleRemoteEvent::HandoffAck(req_id) => {
+                log::debug!("[SimpleRemote] receive handoff ack from {} req_id {}", from, req_id);
+                self.handoff.on_handoff_ack(req_id);
+            }
+            SimpleRemoteEvent::HandoffGet(req_id, key) => {
+                let value = self
+                    .storage
+                    .get_with_expire(&key)
+                    .map(|(value, version, source, expire_at)| (value.clone(), version, source, remain_ex(now_ms, expire_at)));
+                log::debug!("[SimpleRemote] receive handoff get event from {} key {} version {:?}", from, key, value.as_ref().map(|v| v.1));
+                self.replica_events.push_back((SimpleRemoteEvent::HandoffGetAck(req_id, key, value), RouteRule::ToNode(from)));
+            }
+            SimpleRemoteEvent::HandoffGetAck(req_id, key, value) => {
+                log::debug!("[SimpleRemote] receive handoff get ack from {} key {} version {:?}", from, key, value.as_ref().map(|v| v.1));
+                let done = value.is_some();
+                if let Some((value, version, source, ex)) = value {
+                    self.store_set(now_ms, key, value, version, source, ex);
+                }
+                if let Some(read) = self.handoff.on_fallback_answer(req_id, done) {
+                    self.answer_get(now_ms, read.from, read.req_id, read.key);
+                }
+            }
+        }
+    }
+
+    fn answer_get(&mut self, now_ms: u64, from: NodeId, req_id: u64, key: KeyId) {
+        if let Some((value, version, source, expire_at)) = self.storage.get_with_expire(&key) {
+            log::debug!("[SimpleRemote] answer get from {} key {} value {:?} version {}", from, key, value, version);
+            self.output_events.push_back(RemoteStorageAction(
+                SimpleLocalEvent::GetAck(req_id, key, Some((value.clone(), version, source, remain_ex(now_ms, expire_at)))),
+                RouteRule::ToNode(from),
+            ));
+        } else {
+            log::debug!("[SimpleRemote] answer get from {} key {} value None", from, key);
+            self.output_events.push_back(RemoteStorageAction(SimpleLocalEvent::GetAck(req_id, key, None), RouteRule::ToNode(from)));
+        }
+    }
+
+    /// Stream moved keys to new owners, drop them after transition window once acked and answer timeout fallback reads
+    fn handoff_tick(&mut self, now_ms: u64) {
+        let (send, drop) = self.handoff.tick(now_ms, self.replicas, self.storage.keys().copied());
+        for key in send {
+            if let Some((value, version, source, expire_at)) = self.storage.get_with_expire(&key) {
+                for index in 0..self.replicas.max(1) {
+                    let req_id = self.handoff.on_sent(key);
+                    self.replica_events.push_back((
+                        SimpleRemoteEvent::Handoff(req_id, key, value.clone(), version, source, remain_ex(now_ms, expire_at)),
+                        replica_route(key, index),
+                    ));
+                }
+            }
+        }
+        for key in drop {
+            if let Some(version) = self.storage.remove(&key) {
+                log::info!("[SimpleRemote] drop key {} version {} after handoff", key, version);
+                if let Some(persistent) = &mut self.persistent {
+                    persistent.append(StorageRecord::Del {
+                        at: now_ms,
+                        key,
+                        sub_key: None,
+                        version,
+                    });
+                }
+            }
+        }
+        for read in self.handoff.pop_fallback_timeouts(now_ms) {
+            self.answer_get(now_ms, read.from, read.req_id, read.key);
         }
     }
 
+    /// Replica writes which are sent to other replicas, and handoff messages which are sent to other owners
+    pub fn pop_replica_action(&mut self) -> Option<(SimpleRemoteEvent, RouteRule)> {
+        self.replica_events.pop_front()
+    }
+
+    /// Version which is generated from time like local versions but always greater than any stored version of key, incase of clock skew between nodes
+    fn next_version(&self, now_ms: u64, key: KeyId) -> KeyVersion {
+        counter_version(now_ms, self.version_marks.get(&key).copied())
+    }
+
+    fn mark_version(&mut self, key: KeyId, version: KeyVersion) {
+        let mark = self.version_marks.entry(key).or_default();
+        *mark = (*mark).max(version);
+    }
+
+    /// Only changes are appended to persistent log, which are new versions or switching between with and without expiry.
+    /// Re-syncs of a stored version which only refresh expiry are not appended, the writer syncs them again after restart
+    fn store_set(&mut self, now_ms: u64, key: KeyId, value: ValueType, version: u64, source: NodeId, ex: Option<u64>) -> bool {
+        self.mark_version(key, version);
+        let old_expire_at = self.storage.get_with_expire(&key).map(|(_, _, _, expire_at)| expire_at);
+        let record_value = self.persistent.as_ref().map(|_| value.clone());
+        let setted = self.storage.set(now_ms, key, value, version, source, ex);
+        let expiry_changed = old_expire_at.is_some_and(|expire_at| expire_at.is_some() != ex.is_some());
+        if let (Some(persistent), Some(value)) = (&mut self.persistent, record_value) {
+            if setted || expiry_changed {
+                persistent.append(StorageRecord::Set {
+                    at: now_ms,
+                    key,
+                    sub_key: None,
+                    value,
+                    version,
+                    source,
+                    ex,
+                });
+            }
+        }
+        setted
+    }
+
+    fn store_del(&mut self, now_ms: u64, key: KeyId, req_version: u64) -> Option<u64> {
+        let version = self.storage.del(&key, req_version).map(|(_, version, _)| version);
+        if let (Some(persistent), Some(_)) = (&mut self.persistent, version) {
+            persistent.append(StorageRecord::Del {
+                at: now_ms,
+                key,
+                sub_key: None,
+                version: req_version,
+            });
+        }
+        version
+    }
+
     pub fn pop_action(&mut self, now_ms: u64) -> Option<RemoteStorageAction> {
         //first pop from output_events, if not exits then pop from event_acks
         if let Some(e) = self.output_events.pop_front() {
@@ -111,8 +386,14 @@ impl SimpleRemoteStorage {
 mod tests {
     use super::RemoteStorageAction;
     use crate::{
-        behavior::event_acks::RESEND_AFTER_MS,
+        behavior::{
+            counter::encode_counter,
+            event_acks::RESEND_AFTER_MS,
+            handoff::{tests::MovedKeysRouter, FALLBACK_TIMEOUT_MS, HANDOFF_CHECK_MS, HANDOFF_KEEP_MS},
+            replication::replica_route,
+        },
         msg::{SimpleLocalEvent, SimpleRemoteEvent},
+        storage::persistent::MemoryStorage,
     };
     use atm0s_sdn_router::RouteRule;
 
@@ -235,7 +516,7 @@ mod tests {
         remote_storage.on_event(0, 1001, SimpleRemoteEvent::Get(2, 1));
         assert_eq!(
             remote_storage.pop_action(0),
-            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(2, 1, Some((vec![1], 10, 1000))), RouteRule::ToNode(1001)))
+            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(2, 1, Some((vec![1], 10, 1000, None))), RouteRule::ToNode(1001)))
         );
         assert_eq!(remote_storage.pop_action(0), None);
 
@@ -367,4 +648,415 @@ mod tests {
         );
         assert_eq!(remote_storage.pop_action(RESEND_AFTER_MS), None);
     }
+
+    #[test]
+    fn persistent_append_only_changes() {
+        let persistent = MemoryStorage::default();
+        let mut remote_storage = super::SimpleRemoteStorage::new_persistent(Box::new(persistent.clone()));
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Set(1, 1, vec![1], 10, Some(1000)));
+        assert_eq!(persistent.records.lock().len(), 1);
+
+        //re-sync of same version and older version are not appended
+        remote_storage.on_event(500, 1000, SimpleRemoteEvent::Set(2, 1, vec![1], 10, Some(1000)));
+        remote_storage.on_event(500, 1000, SimpleRemoteEvent::Set(3, 1, vec![0], 5, Some(1000)));
+        assert_eq!(persistent.records.lock().len(), 1);
+
+        //removing expiry and new version are appended
+        remote_storage.on_event(600, 1000, SimpleRemoteEvent::Set(4, 1, vec![1], 10, None));
+        assert_eq!(persistent.records.lock().len(), 2);
+        remote_storage.on_event(700, 1000, SimpleRemoteEvent::Set(5, 1, vec![2], 20, None));
+        assert_eq!(persistent.records.lock().len(), 3);
+    }
+
+    #[test]
+    fn persistent_restore_after_restart() {
+        let persistent = MemoryStorage::default();
+        let mut remote_storage = super::SimpleRemoteStorage::new_persistent(Box::new(persistent.clone()));
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Set(1, 1, vec![1], 10, None));
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Set(2, 2, vec![2], 20, Some(1000)));
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Set(3, 3, vec![3], 30, None));
+        remote_storage.on_event(100, 1000, SimpleRemoteEvent::Del(4, 3, 30));
+        drop(remote_storage);
+
+        let mut remote_storage = super::SimpleRemoteStorage::new_persistent(Box::new(persistent));
+        remote_storage.tick(500);
+        remote_storage.on_event(500, 1001, SimpleRemoteEvent::Get(5, 1));
+        remote_storage.on_event(500, 1001, SimpleRemoteEvent::Get(6, 2));
+        remote_storage.on_event(500, 1001, SimpleRemoteEvent::Get(7, 3));
+        assert_eq!(
+            remote_storage.pop_action(500),
+            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(5, 1, Some((vec![1], 10, 1000, None))), RouteRule::ToNode(1001)))
+        );
+        assert_eq!(
+            remote_storage.pop_action(500),
+            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(6, 2, Some((vec![2], 20, 1000, Some(500)))), RouteRule::ToNode(1001)))
+        );
+        assert_eq!(remote_storage.pop_action(500), Some(RemoteStorageAction(SimpleLocalEvent::GetAck(7, 3, None), RouteRule::ToNode(1001))));
+
+        // key 2 expires at the original time
+        remote_storage.tick(1000);
+        remote_storage.on_event(1000, 1001, SimpleRemoteEvent::Get(8, 2));
+        assert_eq!(
+            remote_storage.pop_action(1000),
+            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(8, 2, None), RouteRule::ToNode(1001)))
+        );
+
+        // version is kept, older version is rejected
+        remote_storage.on_event(1000, 1000, SimpleRemoteEvent::Set(9, 1, vec![9], 5, None));
+        assert_eq!(
+            remote_storage.pop_action(1000),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(9, 1, 5, false), RouteRule::ToNode(1000)))
+        );
+    }
+
+    #[test]
+    fn replicas_receive_forwarded_writes() {
+        let mut remote_storage = super::SimpleRemoteStorage::new();
+        remote_storage.set_replicas(3);
+
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Set(1, 1, vec![1], 10, Some(5000)));
+        assert_eq!(
+            remote_storage.pop_action(0),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(1, 1, 10, true), RouteRule::ToNode(1000)))
+        );
+        assert_eq!(
+            remote_storage.pop_replica_action(),
+            Some((SimpleRemoteEvent::ReplicaSet(1, vec![1], 10, 1000, Some(5000)), replica_route(1, 1)))
+        );
+        assert_eq!(
+            remote_storage.pop_replica_action(),
+            Some((SimpleRemoteEvent::ReplicaSet(1, vec![1], 10, 1000, Some(5000)), replica_route(1, 2)))
+        );
+        assert_eq!(remote_storage.pop_replica_action(), None);
+
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Del(2, 1, 10));
+        assert_eq!(
+            remote_storage.pop_action(0),
+            Some(RemoteStorageAction(SimpleLocalEvent::DelAck(2, 1, Some(10)), RouteRule::ToNode(1000)))
+        );
+        assert_eq!(remote_storage.pop_replica_action(), Some((SimpleRemoteEvent::ReplicaDel(1, 10), replica_route(1, 1))));
+        assert_eq!(remote_storage.pop_replica_action(), Some((SimpleRemoteEvent::ReplicaDel(1, 10), replica_route(1, 2))));
+        assert_eq!(remote_storage.pop_replica_action(), None);
+
+        //replica keeps the original source and is not forwarded again
+        let mut replica_storage = super::SimpleRemoteStorage::new();
+        replica_storage.set_replicas(3);
+        replica_storage.on_event(0, 2000, SimpleRemoteEvent::ReplicaSet(1, vec![1], 10, 1000, None));
+        assert_eq!(replica_storage.pop_action(0), None);
+        assert_eq!(replica_storage.pop_replica_action(), None);
+
+        replica_storage.on_event(0, 1001, SimpleRemoteEvent::Get(3, 1));
+        assert_eq!(
+            replica_storage.pop_action(0),
+            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(3, 1, Some((vec![1], 10, 1000, None))), RouteRule::ToNode(1001)))
+        );
+    }
+
+    #[test]
+    fn receive_set_if_and_del_if() {
+        let mut remote_storage = super::SimpleRemoteStorage::new();
+        let v1 = 1 << 16;
+
+        //set if absent, version is assigned by receiver
+        remote_storage.on_event(1, 1000, SimpleRemoteEvent::SetIf(1, 1, vec![1], None, None));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(1, 1, v1, true), RouteRule::ToNode(1000)))
+        );
+        remote_storage.on_event(1, 1001, SimpleRemoteEvent::SetIf(2, 1, vec![2], None, None));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(2, 1, v1, false), RouteRule::ToNode(1001)))
+        );
+
+        //set if version, new version is greater than current version even in same ms
+        remote_storage.on_event(1, 1001, SimpleRemoteEvent::SetIf(3, 1, vec![2], None, Some(9)));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(3, 1, v1, false), RouteRule::ToNode(1001)))
+        );
+        remote_storage.on_event(1, 1001, SimpleRemoteEvent::SetIf(4, 1, vec![2], None, Some(v1)));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(4, 1, v1 + 1, true), RouteRule::ToNode(1001)))
+        );
+
+        //del if version
+        remote_storage.on_event(1, 1000, SimpleRemoteEvent::DelIf(5, 1, v1));
+        assert_eq!(remote_storage.pop_action(1), Some(RemoteStorageAction(SimpleLocalEvent::DelAck(5, 1, None), RouteRule::ToNode(1000))));
+        remote_storage.on_event(1, 1000, SimpleRemoteEvent::DelIf(6, 1, v1 + 1));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(SimpleLocalEvent::DelAck(6, 1, Some(v1 + 1)), RouteRule::ToNode(1000)))
+        );
+        assert_eq!(remote_storage.pop_action(1), None);
+    }
+
+    #[test]
+    fn resync_same_version_should_ack() {
+        let mut remote_storage = super::SimpleRemoteStorage::new();
+
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Set(1, 1, vec![1], 10, None));
+        assert_eq!(
+            remote_storage.pop_action(0),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(1, 1, 10, true), RouteRule::ToNode(1000)))
+        );
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Set(2, 1, vec![1], 10, None));
+        assert_eq!(
+            remote_storage.pop_action(0),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(2, 1, 10, true), RouteRule::ToNode(1000)))
+        );
+        remote_storage.on_event(0, 1001, SimpleRemoteEvent::Set(3, 1, vec![2], 9, None));
+        assert_eq!(
+            remote_storage.pop_action(0),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(3, 1, 9, false), RouteRule::ToNode(1001)))
+        );
+    }
+
+    #[test]
+    fn set_if_version_should_increase_after_delete_and_expire() {
+        let mut remote_storage = super::SimpleRemoteStorage::new();
+        let v1 = 10 << 16;
+
+        remote_storage.on_event(10, 1000, SimpleRemoteEvent::SetIf(1, 1, vec![1], Some(1000), None));
+        assert_eq!(
+            remote_storage.pop_action(10),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(1, 1, v1, true), RouteRule::ToNode(1000)))
+        );
+        remote_storage.on_event(10, 1000, SimpleRemoteEvent::DelIf(2, 1, v1));
+        assert_eq!(
+            remote_storage.pop_action(10),
+            Some(RemoteStorageAction(SimpleLocalEvent::DelAck(2, 1, Some(v1)), RouteRule::ToNode(1000)))
+        );
+
+        //deleted version is still known, even if clock of this node is behind
+        remote_storage.on_event(5, 1001, SimpleRemoteEvent::SetIf(3, 1, vec![2], Some(1), None));
+        assert_eq!(
+            remote_storage.pop_action(5),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(3, 1, v1 + 1, true), RouteRule::ToNode(1001)))
+        );
+
+        //expired version is still known
+        remote_storage.tick(8);
+        remote_storage.on_event(8, 1002, SimpleRemoteEvent::Get(4, 1));
+        assert_eq!(remote_storage.pop_action(8), Some(RemoteStorageAction(SimpleLocalEvent::GetAck(4, 1, None), RouteRule::ToNode(1002))));
+        remote_storage.on_event(8, 1002, SimpleRemoteEvent::SetIf(5, 1, vec![3], None, None));
+        assert_eq!(
+            remote_storage.pop_action(8),
+            Some(RemoteStorageAction(SimpleLocalEvent::SetAck(5, 1, v1 + 2, true), RouteRule::ToNode(1002)))
+        );
+    }
+
+    #[test]
+    fn receive_incr() {
+        let mut remote_storage = super::SimpleRemoteStorage::new();
+        remote_storage.set_replicas(2);
+
+        remote_storage.on_event(1, 1000, SimpleRemoteEvent::Incr(1, 1, 5, Some(1000)));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(SimpleLocalEvent::IncrAck(1, 1, Some(5)), RouteRule::ToNode(1000)))
+        );
+        assert_eq!(
+            remote_storage.pop_replica_action(),
+            Some((SimpleRemoteEvent::ReplicaSet(1, encode_counter(5), 1 << 16, 1000, Some(1000)), replica_route(1, 1)))
+        );
+
+        //version is always increased even in same ms
+        remote_storage.on_event(1, 1001, SimpleRemoteEvent::Incr(2, 1, -7, Some(1000)));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(SimpleLocalEvent::IncrAck(2, 1, Some(-2)), RouteRule::ToNode(1001)))
+        );
+        remote_storage.on_event(1, 1001, SimpleRemoteEvent::Get(3, 1));
+        assert_eq!(
+            remote_storage.pop_action(1),
+            Some(RemoteStorageAction(
+                SimpleLocalEvent::GetAck(3, 1, Some((encode_counter(-2), (1 << 16) + 1, 1001, Some(1000)))),
+                RouteRule::ToNode(1001)
+            ))
+        );
+
+        //expire like set
+        remote_storage.tick(1001);
+        remote_storage.on_event(1001, 1001, SimpleRemoteEvent::Get(4, 1));
+        assert_eq!(
+            remote_storage.pop_action(1001),
+            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(4, 1, None), RouteRule::ToNode(1001)))
+        );
+
+        //not a counter
+        remote_storage.on_event(1001, 1000, SimpleRemoteEvent::Set(5, 2, vec![1], 10, None));
+        remote_storage.pop_action(1001);
+        remote_storage.on_event(1001, 1000, SimpleRemoteEvent::Incr(6, 2, 1, None));
+        assert_eq!(
+            remote_storage.pop_action(1001),
+            Some(RemoteStorageAction(SimpleLocalEvent::IncrAck(6, 2, None), RouteRule::ToNode(1000)))
+        );
+        assert_eq!(remote_storage.pop_action(1001), None);
+    }
+
+    #[test]
+    fn handoff_moved_keys_then_drop() {
+        let persistent = MemoryStorage::default();
+        let router = std::sync::Arc::new(MovedKeysRouter::default());
+        let mut remote_storage = super::SimpleRemoteStorage::new_persistent(Box::new(persistent.clone()));
+        remote_storage.set_router(router.clone());
+
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Set(1, 1, vec![1], 10, Some(50000)));
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Set(2, 2, vec![2], 20, None));
+        while remote_storage.pop_action(0).is_some() {}
+        remote_storage.tick(0);
+        assert_eq!(remote_storage.pop_replica_action(), None);
+
+        //key 1 is routed to other node, it is streamed with remaining ex
+        router.moved.write().insert(1);
+        remote_storage.tick(1000);
+        assert_eq!(
+            remote_storage.pop_replica_action(),
+            Some((SimpleRemoteEvent::Handoff(0, 1, vec![1], 10, 1000, Some(49000)), replica_route(1, 0)))
+        );
+        assert_eq!(remote_storage.pop_replica_action(), None);
+        remote_storage.on_event(1000, 2, SimpleRemoteEvent::HandoffAck(0));
+
+        //still answer reads in transition window, then drop without del event
+        remote_storage.on_event(1000, 1001, SimpleRemoteEvent::HandoffGet(5, 1));
+        assert_eq!(
+            remote_storage.pop_replica_action(),
+            Some((SimpleRemoteEvent::HandoffGetAck(5, 1, Some((vec![1], 10, 1000, Some(49000)))), RouteRule::ToNode(1001)))
+        );
+        remote_storage.tick(1000 + HANDOFF_KEEP_MS);
+        assert_eq!(remote_storage.storage.len(), 1);
+        assert_eq!(remote_storage.pop_action(1000 + HANDOFF_KEEP_MS), None);
+
+        //dropped key is not restored after restart
+        let remote_storage = super::SimpleRemoteStorage::new_persistent(Box::new(persistent));
+        assert_eq!(remote_storage.storage.get(&1), None);
+        assert_eq!(remote_storage.storage.get(&2), Some((&vec![2], 20, 1000)));
+    }
+
+    #[test]
+    fn fallback_get_to_previous_owner() {
+        let mut remote_storage = super::SimpleRemoteStorage::new();
+
+        //without previous owner, answer directly
+        remote_storage.on_event(0, 1001, SimpleRemoteEvent::Get(1, 1));
+        assert_eq!(remote_storage.pop_action(0), Some(RemoteStorageAction(SimpleLocalEvent::GetAck(1, 1, None), RouteRule::ToNode(1001))));
+
+        remote_storage.on_event(0, 2000, SimpleRemoteEvent::Handoff(7, 2, vec![2], 20, 1000, None));
+        assert_eq!(remote_storage.pop_action(0), None);
+        assert_eq!(remote_storage.pop_replica_action(), Some((SimpleRemoteEvent::HandoffAck(7), RouteRule::ToNode(2000))));
+
+        //handed off key is answered directly
+        remote_storage.on_event(0, 1001, SimpleRemoteEvent::Get(2, 2));
+        assert_eq!(
+            remote_storage.pop_action(0),
+            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(2, 2, Some((vec![2], 20, 1000, None))), RouteRule::ToNode(1001)))
+        );
+
+        //missing key falls back to previous owner
+        remote_storage.on_event(0, 1001, SimpleRemoteEvent::Get(3, 1));
+        assert_eq!(remote_storage.pop_action(0), None);
+        assert_eq!(remote_storage.pop_replica_action(), Some((SimpleRemoteEvent::HandoffGet(0, 1), RouteRule::ToNode(2000))));
+        remote_storage.on_event(10, 2000, SimpleRemoteEvent::HandoffGetAck(0, 1, Some((vec![1], 10, 1000, None))));
+        assert_eq!(
+            remote_storage.pop_action(10),
+            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(3, 1, Some((vec![1], 10, 1000, None))), RouteRule::ToNode(1001)))
+        );
+
+        //previous owner does not answer
+        remote_storage.on_event(10, 1001, SimpleRemoteEvent::Get(4, 3));
+        assert_eq!(remote_storage.pop_replica_action(), Some((SimpleRemoteEvent::HandoffGet(1, 3), RouteRule::ToNode(2000))));
+        remote_storage.tick(10 + FALLBACK_TIMEOUT_MS);
+        assert_eq!(
+            remote_storage.pop_action(10 + FALLBACK_TIMEOUT_MS),
+            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(4, 3, None), RouteRule::ToNode(1001)))
+        );
+    }
+
+    #[test]
+    fn node_join_read_in_transition() {
+        let old_router = std::sync::Arc::new(MovedKeysRouter::default());
+        let mut old_owner = super::SimpleRemoteStorage::new();
+        old_owner.set_router(old_router.clone());
+        old_owner.on_event(0, 1000, SimpleRemoteEvent::Set(1, 1, vec![1], 10, None));
+        while old_owner.pop_action(0).is_some() {}
+        old_owner.tick(0);
+
+        //node 2 joins closer to key 1, it is routed to node 2 before the old owner hands it off. Other part of key space is routed to node 1
+        let new_router = std::sync::Arc::new(MovedKeysRouter::default());
+        new_router.moved.write().insert(0);
+        let mut new_owner = super::SimpleRemoteStorage::new();
+        new_owner.set_router(new_router);
+        new_owner.tick(0);
+        old_router.moved.write().insert(1);
+
+        new_owner.on_event(100, 1001, SimpleRemoteEvent::Get(1, 1));
+        assert_eq!(new_owner.pop_action(100), None);
+        assert_eq!(new_owner.pop_replica_action(), None);
+
+        //first handoff arrives, the waiting read asks the old owner
+        old_owner.tick(HANDOFF_CHECK_MS);
+        let (event, _) = old_owner.pop_replica_action().expect("Should send handoff");
+        new_owner.on_event(HANDOFF_CHECK_MS, 1, event);
+        let mut acks = vec![];
+        while let Some((event, route)) = new_owner.pop_replica_action() {
+            assert_eq!(route, RouteRule::ToNode(1));
+            old_owner.on_event(HANDOFF_CHECK_MS, 2, event);
+            while let Some((event, route)) = old_owner.pop_replica_action() {
+                assert_eq!(route, RouteRule::ToNode(2));
+                acks.push(event);
+            }
+        }
+        for event in acks {
+            new_owner.on_event(HANDOFF_CHECK_MS, 1, event);
+        }
+        assert_eq!(
+            new_owner.pop_action(HANDOFF_CHECK_MS),
+            Some(RemoteStorageAction(SimpleLocalEvent::GetAck(1, 1, Some((vec![1], 10, 1000, None))), RouteRule::ToNode(1001)))
+        );
+
+        //handoff is acked, so the old owner drops key after transition window
+        old_owner.tick(HANDOFF_CHECK_MS + HANDOFF_KEEP_MS);
+        assert_eq!(old_owner.pop_replica_action(), None);
+        assert_eq!(old_owner.storage.len(), 0);
+        assert_eq!(new_owner.storage.get(&1), Some((&vec![1], 10, 1000)));
+    }
+
+    #[test]
+    fn single_node_get_missing_key_without_waiting() {
+        let mut remote_storage = super::SimpleRemoteStorage::new();
+        remote_storage.set_router(std::sync::Arc::new(MovedKeysRouter::default()));
+        remote_storage.tick(0);
+
+        remote_storage.on_event(10, 1000, SimpleRemoteEvent::Get(1, 1));
+        assert_eq!(remote_storage.pop_action(10), Some(RemoteStorageAction(SimpleLocalEvent::GetAck(1, 1, None), RouteRule::ToNode(1000))));
+        assert_eq!(remote_storage.pop_replica_action(), None);
+    }
+
+    #[test]
+    fn handoff_not_acked_should_resend() {
+        let router = std::sync::Arc::new(MovedKeysRouter::default());
+        router.moved.write().insert(1);
+        let mut remote_storage = super::SimpleRemoteStorage::new();
+        remote_storage.set_router(router);
+        remote_storage.on_event(0, 1000, SimpleRemoteEvent::Set(1, 1, vec![1], 10, None));
+        while remote_storage.pop_action(0).is_some() {}
+
+        remote_storage.tick(0);
+        assert_eq!(
+            remote_storage.pop_replica_action(),
+            Some((SimpleRemoteEvent::Handoff(0, 1, vec![1], 10, 1000, None), replica_route(1, 0)))
+        );
+        remote_storage.tick(HANDOFF_KEEP_MS);
+        assert_eq!(
+            remote_storage.pop_replica_action(),
+            Some((SimpleRemoteEvent::Handoff(1, 1, vec![1], 10, 1000, None), replica_route(1, 0)))
+        );
+        assert_eq!(remote_storage.storage.len(), 1);
+
+        remote_storage.on_event(HANDOFF_KEEP_MS, 2, SimpleRemoteEvent::HandoffAck(1));
+        remote_storage.tick(HANDOFF_KEEP_MS + 1);
+        assert_eq!(remote_storage.storage.len(), 0);
+    }
 }
@@ -17,9 +17,16 @@ use atm0s_sdn_identity::NodeId;
 use atm0s_sdn_utils::awaker::Awaker;
 pub use behavior::KeyValueBehavior;
 pub use behavior::KeyValueSdk;
+pub use behavior::{decode_counter, encode_counter};
+pub use behavior::{DistributedLock, DistributedLockError, LeaderElection, LockGuard};
+pub use behavior::{ReadConsistency, ReplicationConfig};
 #[cfg(test)]
 use mockall::automock;
-pub use msg::{KeyValueBehaviorEvent, KeyValueHandlerEvent, KeyValueMsg, KeyValueSdkEvent};
+pub use msg::{KeyValueBehaviorEvent, KeyValueCasOp, KeyValueCasResult, KeyValueHandlerEvent, KeyValueHashmapQuery, KeyValueHashmapQueryResult, KeyValueMsg, KeyValueSdkEvent};
+pub use storage::{
+    append_log::{AppendLogStorage, APPEND_LOG_SYNC_MS},
+    persistent::{PersistentStorage, StorageRecord},
+};
 
 #[cfg_attr(test, automock)]
 pub trait ExternalControl: Send + Sync {
@@ -18,6 +18,39 @@ pub enum KeyValueSdkEventError {
     InternalError,
 }
 
+/// Conditional write, which is applied only if the current version of key matches
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum KeyValueCasOp {
+    /// Set value with ex if current version equals expected version, or if key is absent when expected version is None
+    Set(ValueType, Option<KeyVersion>, Option<u64>),
+    /// Delete if current version equals expected version
+    Del(KeyVersion),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum KeyValueCasResult {
+    /// Applied with new version, or deleted version
+    Applied(KeyVersion),
+    /// Condition is not matched, with current value, version and source
+    Rejected(Option<(ValueType, KeyVersion, KeySource)>),
+}
+
+/// Read part of a hashmap key, without getting all sub keys
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum KeyValueHashmapQuery {
+    /// Sub keys from cursor in sorted order with limit, cursor 0 is from the first sub key. Limit and response size are capped by remote
+    Scan(SubKeyId, u32),
+    /// Number of sub keys
+    Len,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum KeyValueHashmapQueryResult {
+    /// Sub keys and next cursor, None if no more sub keys
+    Scan(Vec<(SubKeyId, ValueType, KeyVersion, KeySource)>, Option<SubKeyId>),
+    Len(u64),
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum KeyValueSdkEvent {
     Get(u64, KeyId, u64),
@@ -34,6 +67,21 @@ pub enum KeyValueSdkEvent {
     OnGetH(u64, KeyId, Result<Option<Vec<(SubKeyId, ValueType, KeyVersion, KeySource)>>, KeyValueSdkEventError>),
     OnKeyChanged(u64, KeyId, Option<ValueType>, KeyVersion, KeySource),
     OnKeyHChanged(u64, KeyId, SubKeyId, Option<ValueType>, KeyVersion, KeySource),
+    Cas(u64, KeyId, KeyValueCasOp, u64),
+    OnCas(u64, KeyId, Result<KeyValueCasResult, KeyValueSdkEventError>),
+    QueryH(u64, KeyId, KeyValueHashmapQuery, u64),
+    OnQueryH(u64, KeyId, Result<KeyValueHashmapQueryResult, KeyValueSdkEventError>),
+    /// Hashmap keys which are stored in this node, with prefix of the first bits of key
+    KeysByPrefixH(u64, KeyId, u8),
+    OnKeysByPrefixH(u64, Vec<KeyId>),
+    Incr(u64, KeyId, i64, Option<u64>, u64),
+    IncrH(u64, KeyId, SubKeyId, i64, Option<u64>, u64),
+    /// New value of counter, None if value is not a counter or overflow
+    OnIncr(u64, KeyId, Result<Option<i64>, KeyValueSdkEventError>),
+    OnIncrH(u64, KeyId, SubKeyId, Result<Option<i64>, KeyValueSdkEventError>),
+    /// Value which is set by this node is acked by remote with version, and milliseconds since the acked value was sent.
+    /// The lease of key is kept until ex after sending
+    OnSynced(KeyId, KeyVersion, u64),
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -49,19 +97,39 @@ pub enum SimpleRemoteEvent {
     Unsub(ReqId, KeyId),
     OnKeySetAck(ReqId),
     OnKeyDelAck(ReqId),
+    /// Write to other replicas from replica 0 or read-repair, without ack because writers re-sync each sync_each_ms
+    ReplicaSet(KeyId, ValueType, KeyVersion, KeySource, Option<u64>),
+    ReplicaDel(KeyId, KeyVersion),
+    /// Set only if current version equals expected version, or key is absent if expected version is None.
+    /// The new version is assigned by the receiver (replica 0), so it is always greater than any version it stored for the key.
+    /// Response with SetAck, if applied KeyVersion is the new version, if rejected KeyVersion is current version
+    SetIf(ReqId, KeyId, ValueType, Option<u64>, Option<KeyVersion>),
+    /// Delete only if current version equals KeyVersion. Response with DelAck, NoneKeyVersion if rejected
+    DelIf(ReqId, KeyId, KeyVersion),
+    /// Increase counter by delta, absent counter is 0. Ex is same with Set
+    Incr(ReqId, KeyId, i64, Option<u64>),
+    /// Key which is streamed from the previous owner to the new owner, ex is the remaining time. Response with HandoffAck
+    Handoff(ReqId, KeyId, ValueType, KeyVersion, KeySource, Option<u64>),
+    HandoffAck(ReqId),
+    /// Fallback read from the new owner to the previous owner in the transition window
+    HandoffGet(ReqId, KeyId),
+    HandoffGetAck(ReqId, KeyId, Option<(ValueType, KeyVersion, KeySource, Option<u64>)>),
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 pub enum SimpleLocalEvent {
-    /// Response set request with key and version, if success => true, otherwise => false
+    /// Response set request with key and version, true if the version is stored, including re-syncs of the same version
     SetAck(ReqId, KeyId, KeyVersion, bool),
-    GetAck(ReqId, KeyId, Option<(ValueType, KeyVersion, KeySource)>),
+    /// Value with remaining ex, which is kept when repairing other replicas
+    GetAck(ReqId, KeyId, Option<(ValueType, KeyVersion, KeySource, Option<u64>)>),
     DelAck(ReqId, KeyId, Option<KeyVersion>),
     SubAck(ReqId, KeyId),
     /// Response unsub request with key, if success => true, otherwise => false
     UnsubAck(ReqId, KeyId, bool),
     OnKeySet(ReqId, KeyId, ValueType, KeyVersion, KeySource),
     OnKeyDel(ReqId, KeyId, KeyVersion, KeySource),
+    /// Response incr request with new value, None if value is not a counter or overflow
+    IncrAck(ReqId, KeyId, Option<i64>),
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -77,19 +145,43 @@ pub enum HashmapRemoteEvent {
     Unsub(ReqId, KeyId),
     OnKeySetAck(ReqId),
     OnKeyDelAck(ReqId),
+    /// Write to other replicas from replica 0 or read-repair, without ack because writers re-sync each sync_each_ms
+    ReplicaSet(KeyId, SubKeyId, ValueType, KeyVersion, KeySource, Option<u64>),
+    ReplicaDel(KeyId, SubKeyId, KeyVersion),
+    /// Get sub keys from cursor in sorted order with limit
+    Scan(ReqId, KeyId, SubKeyId, u32),
+    /// Get number of sub keys
+    Len(ReqId, KeyId),
+    /// Increase counter in sub key by delta, absent counter is 0. Ex is same with Set
+    Incr(ReqId, KeyId, SubKeyId, i64, Option<u64>),
+    /// Sub key which is streamed from the previous owner to the new owner, ex is the remaining time. Response with HandoffAck
+    Handoff(ReqId, KeyId, SubKeyId, ValueType, KeyVersion, KeySource, Option<u64>),
+    HandoffAck(ReqId),
+    /// Fallback read from the new owner to the previous owner in the transition window
+    HandoffGet(ReqId, KeyId),
+    HandoffGetAck(ReqId, KeyId, Vec<(SubKeyId, ValueType, KeyVersion, KeySource, Option<u64>)>),
 }
 
+/// Sub keys with remaining ex
+pub type SubKeysWithEx = Vec<(SubKeyId, ValueType, KeyVersion, KeySource, Option<u64>)>;
+
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 pub enum HashmapLocalEvent {
     /// Response set request with key and version, if success => true, otherwise => false
     SetAck(ReqId, KeyId, SubKeyId, KeyVersion, bool),
-    GetAck(ReqId, KeyId, Option<Vec<(SubKeyId, ValueType, KeyVersion, KeySource)>>),
+    /// Sub keys with remaining ex, which is kept when repairing other replicas
+    GetAck(ReqId, KeyId, Option<SubKeysWithEx>),
     DelAck(ReqId, KeyId, SubKeyId, Option<KeyVersion>),
     SubAck(ReqId, KeyId),
     /// Response unsub request with key, if success => true, otherwise => false
     UnsubAck(ReqId, KeyId, bool),
     OnKeySet(ReqId, KeyId, SubKeyId, ValueType, KeyVersion, KeySource),
     OnKeyDel(ReqId, KeyId, SubKeyId, KeyVersion, KeySource),
+    /// Response scan request with sub keys and next cursor, None if no more sub keys
+    ScanAck(ReqId, KeyId, Vec<(SubKeyId, ValueType, KeyVersion, KeySource)>, Option<SubKeyId>),
+    LenAck(ReqId, KeyId, u64),
+    /// Response incr request with new value, None if value is not a counter or overflow
+    IncrAck(ReqId, KeyId, SubKeyId, Option<i64>),
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
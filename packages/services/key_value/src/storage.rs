@@ -1,2 +1,4 @@
+pub(crate) mod append_log;
 pub(crate) mod hashmap;
+pub(crate) mod persistent;
 pub(crate) mod simple;
@@ -0,0 +1,259 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use super::persistent::{PersistentStorage, StorageRecord};
+
+const LEN_SIZE: usize = 4;
+/// Default interval for syncing the log file to disk
+pub const APPEND_LOG_SYNC_MS: u64 = 1000;
+
+/// Append-only log file on local disk, each record is stored as 4 bytes length and bincode encoded data.
+/// Appended records are buffered and written on each flush, then the file is synced to disk at most once per sync interval.
+/// A partial record at the end of file, which is caused by crashing while writing, is truncated when loading.
+pub struct AppendLogStorage {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    sync_each_ms: u64,
+    last_sync_ms: u64,
+    unsynced: bool,
+}
+
+impl AppendLogStorage {
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::open_with_sync_interval(path, APPEND_LOG_SYNC_MS)
+    }
+
+    /// Same as `open` but with custom sync interval, 0 for syncing on every flush
+    pub fn open_with_sync_interval<P: AsRef<Path>>(path: P, sync_each_ms: u64) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).read(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            sync_each_ms,
+            last_sync_ms: 0,
+            unsynced: false,
+        })
+    }
+
+    /// Sync the directory of path, so a rename in it is durable after crashing. Directories can't be synced on other platforms
+    #[cfg(unix)]
+    fn sync_dir(path: &Path) -> std::io::Result<()> {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        File::open(dir)?.sync_all()
+    }
+
+    #[cfg(not(unix))]
+    fn sync_dir(_path: &Path) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn write_record<W: Write>(writer: &mut W, record: &StorageRecord) -> std::io::Result<()> {
+        let data = bincode::serialize(record).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        writer.write_all(&(data.len() as u32).to_be_bytes())?;
+        writer.write_all(&data)
+    }
+}
+
+impl PersistentStorage for AppendLogStorage {
+    fn load(&mut self) -> std::io::Result<Vec<StorageRecord>> {
+        self.writer.flush()?;
+        let file_len = self.writer.get_ref().metadata()?.len();
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut records = vec![];
+        let mut valid_len = 0;
+        loop {
+            let mut len = [0; LEN_SIZE];
+            if reader.read_exact(&mut len).is_err() {
+                break;
+            }
+            let len = u32::from_be_bytes(len) as u64;
+            if valid_len + LEN_SIZE as u64 + len > file_len {
+                break;
+            }
+            let mut data = vec![0; len as usize];
+            if reader.read_exact(&mut data).is_err() {
+                break;
+            }
+            match bincode::deserialize::<StorageRecord>(&data) {
+                Ok(record) => records.push(record),
+                Err(_) => break,
+            }
+            valid_len += (LEN_SIZE + data.len()) as u64;
+        }
+
+        if valid_len < file_len {
+            log::warn!("[AppendLogStorage] truncate broken tail of {:?} at {}", self.path, valid_len);
+            self.writer.get_ref().set_len(valid_len)?;
+        }
+        Ok(records)
+    }
+
+    fn append(&mut self, record: &StorageRecord) -> std::io::Result<()> {
+        self.unsynced = true;
+        Self::write_record(&mut self.writer, record)
+    }
+
+    fn flush(&mut self, now_ms: u64) -> std::io::Result<()> {
+        self.writer.flush()?;
+        if self.unsynced && now_ms >= self.last_sync_ms + self.sync_each_ms {
+            self.writer.get_ref().sync_data()?;
+            self.last_sync_ms = now_ms;
+            self.unsynced = false;
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self, snapshot: &[StorageRecord]) -> std::io::Result<()> {
+        // flush pending records first, so the old log is complete if compacting fails
+        self.writer.flush()?;
+        // write to a temp file then rename, so the old log is still valid if crashing while compacting
+        let tmp_path = self.path.with_extension("compact");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        for record in snapshot {
+            Self::write_record(&mut writer, record)?;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+        std::fs::rename(&tmp_path, &self.path)?;
+        Self::sync_dir(&self.path)?;
+        self.writer = BufWriter::new(OpenOptions::new().read(true).append(true).open(&self.path)?);
+        self.unsynced = false;
+        Ok(())
+    }
+}
+
+impl Drop for AppendLogStorage {
+    fn drop(&mut self) {
+        if let Err(err) = self.writer.flush().and_then(|_| self.writer.get_ref().sync_data()) {
+            log::error!("[AppendLogStorage] flush {:?} on drop error {:?}", self.path, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::storage::persistent::{PersistentStorage, StorageRecord};
+
+    use super::AppendLogStorage;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("atm0s-kv-{}-{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn set_record(key: u64) -> StorageRecord {
+        StorageRecord::Set {
+            at: 100,
+            key,
+            sub_key: Some(1),
+            value: vec![1, 2, 3],
+            version: 10,
+            source: 1000,
+            ex: Some(5000),
+        }
+    }
+
+    #[test]
+    fn append_and_reload() {
+        let path = temp_path("append");
+        let mut storage = AppendLogStorage::open(&path).expect("Should open");
+        storage.append(&set_record(1)).expect("Should append");
+        storage
+            .append(&StorageRecord::Del {
+                at: 200,
+                key: 1,
+                sub_key: None,
+                version: 10,
+            })
+            .expect("Should append");
+        drop(storage);
+
+        let mut storage = AppendLogStorage::open(&path).expect("Should open");
+        assert_eq!(
+            storage.load().expect("Should load"),
+            vec![
+                set_record(1),
+                StorageRecord::Del {
+                    at: 200,
+                    key: 1,
+                    sub_key: None,
+                    version: 10
+                }
+            ]
+        );
+        std::fs::remove_file(&path).expect("Should remove");
+    }
+
+    #[test]
+    fn truncate_broken_tail() {
+        let path = temp_path("broken");
+        let mut storage = AppendLogStorage::open(&path).expect("Should open");
+        storage.append(&set_record(1)).expect("Should append");
+        drop(storage);
+
+        // simulate crashing while writing a record
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path).expect("Should open");
+        file.write_all(&[0, 0, 0, 100, 1, 2]).expect("Should write");
+        drop(file);
+
+        let mut storage = AppendLogStorage::open(&path).expect("Should open");
+        assert_eq!(storage.load().expect("Should load"), vec![set_record(1)]);
+        storage.append(&set_record(2)).expect("Should append");
+        assert_eq!(storage.load().expect("Should load"), vec![set_record(1), set_record(2)]);
+        std::fs::remove_file(&path).expect("Should remove");
+    }
+
+    #[test]
+    fn buffer_until_flush() {
+        let path = temp_path("flush");
+        let mut storage = AppendLogStorage::open_with_sync_interval(&path, 1000).expect("Should open");
+        storage.append(&set_record(1)).expect("Should append");
+        storage.append(&set_record(2)).expect("Should append");
+        // records are buffered in memory until flush
+        assert_eq!(std::fs::metadata(&path).expect("Should stat").len(), 0);
+
+        // flush writes records to file but only syncs after interval
+        storage.flush(100).expect("Should flush");
+        let len = std::fs::metadata(&path).expect("Should stat").len();
+        assert!(len > 0);
+        assert!(storage.unsynced);
+        storage.flush(1000).expect("Should flush");
+        assert!(!storage.unsynced);
+        assert_eq!(storage.last_sync_ms, 1000);
+
+        storage.append(&set_record(3)).expect("Should append");
+        storage.flush(1500).expect("Should flush");
+        assert!(std::fs::metadata(&path).expect("Should stat").len() > len);
+        assert!(storage.unsynced);
+        storage.flush(2000).expect("Should flush");
+        assert!(!storage.unsynced);
+        assert_eq!(storage.last_sync_ms, 2000);
+
+        assert_eq!(storage.load().expect("Should load"), vec![set_record(1), set_record(2), set_record(3)]);
+        std::fs::remove_file(&path).expect("Should remove");
+    }
+
+    #[test]
+    fn compact() {
+        let path = temp_path("compact");
+        let mut storage = AppendLogStorage::open(&path).expect("Should open");
+        for i in 0..10 {
+            storage.append(&set_record(i)).expect("Should append");
+        }
+        storage.compact(&[set_record(9)]).expect("Should compact");
+        storage.append(&set_record(10)).expect("Should append");
+        drop(storage);
+
+        let mut storage = AppendLogStorage::open(&path).expect("Should open");
+        assert_eq!(storage.load().expect("Should load"), vec![set_record(9), set_record(10)]);
+        std::fs::remove_file(&path).expect("Should remove");
+    }
+}
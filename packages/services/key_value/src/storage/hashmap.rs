@@ -1,6 +1,6 @@
 use atm0s_sdn_utils::hashmap::HashMap;
 use atm0s_sdn_utils::vec_dequeue::VecDeque;
-use std::collections::hash_map;
+use std::collections::{btree_map, hash_map, BTreeMap};
 use std::hash::Hash;
 
 #[derive(Eq, PartialEq, Debug)]
@@ -9,6 +9,12 @@ pub enum OutputEvent<Key, SubKey, Value, Source, Handler> {
     NotifyDel(Key, SubKey, Value, u64, Source, Handler),
 }
 
+/// Sub keys with value, version and source, and the next cursor
+type ScanResult<'a, SubKey, Value, Source> = (Vec<(SubKey, &'a Value, u64, Source)>, Option<SubKey>);
+
+/// Sub key with value, version, source and absolute expire time
+type ExpireValue<'a, SubKey, Value, Source> = (SubKey, &'a Value, u64, Source, Option<u64>);
+
 struct HandlerSlot {
     #[allow(dead_code)]
     added_at: u64,
@@ -20,8 +26,9 @@ struct ValueSlot<Value, Source> {
     expire_at: Option<u64>,
 }
 
+/// Sub keys are ordered for scanning from a cursor without sorting all sub keys
 struct HashSlot<SubKey, Value, Source, Handler> {
-    keys: HashMap<SubKey, ValueSlot<Value, Source>>,
+    keys: BTreeMap<SubKey, ValueSlot<Value, Source>>,
     listeners: HashMap<Handler, HandlerSlot>,
 }
 
@@ -33,7 +40,7 @@ pub struct HashmapKeyValue<Key, SubKey, Value, Source, Handlder> {
 impl<Key, SubKey, Value, Source, Handler> HashmapKeyValue<Key, SubKey, Value, Source, Handler>
 where
     Key: PartialEq + Eq + Hash + Clone,
-    SubKey: PartialEq + Eq + Hash + Clone,
+    SubKey: PartialEq + Eq + Hash + Clone + Ord,
     Value: Clone,
     Source: Clone,
     Handler: PartialEq + Eq + Hash + Clone,
@@ -50,6 +57,19 @@ where
         self.maps.len()
     }
 
+    /// All values with absolute expire time, used for persistent snapshot
+    pub fn snapshot(&self) -> Vec<(Key, SubKey, Value, u64, Source, Option<u64>)> {
+        let mut result = vec![];
+        for (key, map) in self.maps.iter() {
+            for (sub_key, slot) in map.keys.iter() {
+                if let Some((value, version, source)) = &slot.value {
+                    result.push((key.clone(), sub_key.clone(), value.clone(), *version, source.clone(), slot.expire_at));
+                }
+            }
+        }
+        result
+    }
+
     /// This function is call in each tick miliseconds, this function will check all expire time
     /// and clear all expired data, and fire all expire events
     /// It also clear expired handlers
@@ -103,7 +123,7 @@ where
     pub fn set(&mut self, now_ms: u64, key: Key, sub_key: SubKey, value: Value, version: u64, source: Source, ex: Option<u64>) -> bool {
         match self.maps.get_mut(&key) {
             Some(map) => match map.keys.entry(sub_key.clone()) {
-                hash_map::Entry::Occupied(mut slot) => {
+                btree_map::Entry::Occupied(mut slot) => {
                     let slot_inner = slot.get_mut();
                     slot_inner.expire_at = ex.map(|ex| now_ms + ex);
                     if let Some((old_value, old_version, old_source)) = &mut slot_inner.value {
@@ -122,7 +142,7 @@ where
                         true
                     }
                 }
-                hash_map::Entry::Vacant(slot) => {
+                btree_map::Entry::Vacant(slot) => {
                     let slot_inner = slot.insert(ValueSlot {
                         value: Some((value, version, source)),
                         expire_at: ex.map(|ex| now_ms + ex),
@@ -137,7 +157,7 @@ where
                     expire_at: ex.map(|ex| now_ms + ex),
                 };
                 let map = HashSlot {
-                    keys: HashMap::from([(sub_key, slot)]),
+                    keys: BTreeMap::from([(sub_key, slot)]),
                     listeners: Default::default(),
                 };
                 self.maps.insert(key, map);
@@ -161,6 +181,49 @@ where
         }
     }
 
+    /// Sub keys with absolute expire time
+    pub fn get_with_expire(&self, key: &Key) -> Vec<ExpireValue<'_, SubKey, Value, Source>> {
+        let mut result = vec![];
+        if let Some(map) = self.maps.get(key) {
+            for (sub_key, slot) in map.keys.iter() {
+                if let Some((value, version, source)) = &slot.value {
+                    result.push((sub_key.clone(), value, *version, source.clone(), slot.expire_at));
+                }
+            }
+        }
+        result
+    }
+
+    /// Remove all sub keys without firing del events, return removed sub keys with version.
+    /// Listeners are kept until they are unsubscribed or expired
+    pub fn remove(&mut self, key: &Key) -> Vec<(SubKey, u64)> {
+        let map = match self.maps.get_mut(key) {
+            Some(map) => map,
+            None => return vec![],
+        };
+        let removed = map
+            .keys
+            .iter()
+            .filter_map(|(sub_key, slot)| slot.value.as_ref().map(|(_, version, _)| (sub_key.clone(), *version)))
+            .collect();
+        map.keys = BTreeMap::new();
+        if map.listeners.is_empty() {
+            self.maps.remove(key);
+        }
+        removed
+    }
+
+    /// Absolute expire time of sub key which has value, None if it has no value
+    pub fn sub_expire_at(&self, key: &Key, sub_key: &SubKey) -> Option<Option<u64>> {
+        let slot = self.maps.get(key)?.keys.get(sub_key)?;
+        slot.value.as_ref().map(|_| slot.expire_at)
+    }
+
+    pub fn get_sub(&self, key: &Key, sub_key: &SubKey) -> Option<(&Value, u64, Source)> {
+        let (value, version, source) = self.maps.get(key)?.keys.get(sub_key)?.value.as_ref()?;
+        Some((value, *version, source.clone()))
+    }
+
     pub fn del(&mut self, key: &Key, sub_key: &SubKey, request_version: u64) -> Option<(Value, u64, Source)> {
         let map = self.maps.get_mut(key)?;
         if map.keys.is_empty() {
@@ -177,8 +240,12 @@ where
         }
 
         let slot = map.keys.remove(sub_key)?;
-        if map.listeners.is_empty() && map.keys.is_empty() {
-            self.maps.remove(key);
+        if map.keys.is_empty() {
+            // empty BTreeMap still holds its node allocation
+            map.keys = BTreeMap::new();
+            if map.listeners.is_empty() {
+                self.maps.remove(key);
+            }
         }
         slot.value
     }
@@ -206,7 +273,7 @@ where
                 self.maps.insert(
                     key.clone(),
                     HashSlot {
-                        keys: HashMap::new(),
+                        keys: BTreeMap::new(),
                         listeners: HashMap::from([(
                             handler_uuid,
                             HandlerSlot {
@@ -276,6 +343,38 @@ where
             false
         }
     }
+
+    /// Number of sub keys which have value
+    pub fn map_len(&self, key: &Key) -> usize {
+        self.maps.get(key).map(|map| map.keys.values().filter(|slot| slot.value.is_some()).count()).unwrap_or(0)
+    }
+
+    /// Keys which have at least one sub key with value
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.maps.iter().filter(|(_, map)| map.keys.values().any(|slot| slot.value.is_some())).map(|(key, _)| key)
+    }
+
+    /// Sub keys from cursor in sorted order, at most limit sub keys and stop before the total size of values exceeds max_size, at least one sub key is returned even if it is larger than max_size.
+    /// Return next cursor which is the first sub key after returned sub keys, None if no more sub keys
+    pub fn scan(&self, key: &Key, cursor: &SubKey, limit: usize, max_size: usize, size_of: impl Fn(&Value) -> usize) -> ScanResult<'_, SubKey, Value, Source> {
+        let map = match self.maps.get(key) {
+            Some(map) => map,
+            None => return (vec![], None),
+        };
+        let limit = limit.max(1);
+        let mut size = 0;
+        let mut result = vec![];
+        for (sub_key, slot) in map.keys.range(cursor.clone()..) {
+            if let Some((value, version, source)) = &slot.value {
+                size += size_of(value);
+                if result.len() >= limit || (size > max_size && !result.is_empty()) {
+                    return (result, Some(sub_key.clone()));
+                }
+                result.push((sub_key.clone(), value, *version, source.clone()));
+            }
+        }
+        (result, None)
+    }
 }
 
 #[cfg(test)]
@@ -606,4 +705,43 @@ mod tests {
         });
         assert_eq!(info.count_current, 0);
     }
+
+    #[test]
+    fn scan_and_len() {
+        let mut store = HashmapKeyValue::<u32, u32, u32, u32, u32>::new();
+        for sub_key in [15, 11, 13, 12, 14] {
+            store.set(0, 1, sub_key, sub_key * 10, 1, 1000, None);
+        }
+        store.set(0, 2, 11, 1, 1, 1000, None);
+        store.del(&2, &11, 1);
+
+        assert_eq!(store.map_len(&1), 5);
+        assert_eq!(store.map_len(&2), 0);
+        assert_eq!(store.keys().collect::<Vec<_>>(), vec![&1]);
+
+        let size_of = |value: &u32| *value as usize;
+        assert_eq!(store.scan(&1, &0, 2, usize::MAX, size_of), (vec![(11, &110, 1, 1000), (12, &120, 1, 1000)], Some(13)));
+        assert_eq!(store.scan(&1, &13, 2, usize::MAX, size_of), (vec![(13, &130, 1, 1000), (14, &140, 1, 1000)], Some(15)));
+        assert_eq!(store.scan(&1, &15, 2, usize::MAX, size_of), (vec![(15, &150, 1, 1000)], None));
+        assert_eq!(store.scan(&2, &0, 2, usize::MAX, size_of), (vec![], None));
+
+        //stop before exceeding max size, but at least one sub key
+        assert_eq!(store.scan(&1, &0, 10, 250, size_of), (vec![(11, &110, 1, 1000), (12, &120, 1, 1000)], Some(13)));
+        assert_eq!(store.scan(&1, &15, 10, 100, size_of), (vec![(15, &150, 1, 1000)], None));
+    }
+
+    #[test]
+    fn remove_without_events() {
+        let mut store = HashmapKeyValue::<u32, u32, u32, u32, u32>::new();
+        store.subscribe(0, &1, 2000, None);
+        store.set(0, 1, 2, 10, 5, 1000, Some(100));
+        assert_eq!(store.poll(), Some(OutputEvent::NotifySet(1, 2, 10, 5, 1000, 2000)));
+        assert_eq!(store.get_with_expire(&1), vec![(2, &10, 5, 1000, Some(100))]);
+
+        assert_eq!(store.remove(&1), vec![(2, 5)]);
+        assert_eq!(store.remove(&1), vec![]);
+        assert_eq!(store.get_with_expire(&1), vec![]);
+        assert_eq!(store.keys().count(), 0);
+        assert_eq!(store.poll(), None);
+    }
 }
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{KeyId, KeySource, KeyVersion, SubKeyId, ValueType};
+
+/// Log is compacted only after this number of records, for avoiding compacting small logs too often
+pub const COMPACT_MIN_RECORDS: usize = 1000;
+
+/// A change of remote storage, which is persisted and replayed in order after restart.
+/// Time of change `at` is kept for correct expire time when replaying, sub_key is None for simple keys.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StorageRecord {
+    Set {
+        at: u64,
+        key: KeyId,
+        sub_key: Option<SubKeyId>,
+        value: ValueType,
+        version: KeyVersion,
+        source: KeySource,
+        ex: Option<u64>,
+    },
+    Del {
+        at: u64,
+        key: KeyId,
+        sub_key: Option<SubKeyId>,
+        version: KeyVersion,
+    },
+}
+
+impl StorageRecord {
+    pub fn at(&self) -> u64 {
+        match self {
+            StorageRecord::Set { at, .. } => *at,
+            StorageRecord::Del { at, .. } => *at,
+        }
+    }
+}
+
+/// Backend for persisting data of remote storages, for example a log file on local disk
+pub trait PersistentStorage: Send + Sync {
+    /// Load all records in appended order
+    fn load(&mut self) -> std::io::Result<Vec<StorageRecord>>;
+    /// Append a record, which may be buffered until next flush
+    fn append(&mut self, record: &StorageRecord) -> std::io::Result<()>;
+    /// Write buffered records, called on each tick
+    fn flush(&mut self, _now_ms: u64) -> std::io::Result<()> {
+        Ok(())
+    }
+    /// Replace all records with a snapshot of current data
+    fn compact(&mut self, snapshot: &[StorageRecord]) -> std::io::Result<()>;
+}
+
+/// Append records to a persistent storage and compact it when it grows twice larger than the last snapshot.
+/// Errors are only logged, the in-memory storage keeps working without persistent.
+pub struct PersistentLog {
+    storage: Box<dyn PersistentStorage>,
+    records: usize,
+    compact_at: usize,
+}
+
+impl PersistentLog {
+    /// Create and load all persisted records of storage
+    pub fn new(mut storage: Box<dyn PersistentStorage>) -> (Self, Vec<StorageRecord>) {
+        let records = match storage.load() {
+            Ok(records) => records,
+            Err(err) => {
+                log::error!("[PersistentLog] load error {:?}", err);
+                vec![]
+            }
+        };
+        let log = Self {
+            storage,
+            records: records.len(),
+            compact_at: COMPACT_MIN_RECORDS,
+        };
+        (log, records)
+    }
+
+    pub fn append(&mut self, record: StorageRecord) {
+        if let Err(err) = self.storage.append(&record) {
+            log::error!("[PersistentLog] append error {:?}", err);
+        }
+        self.records += 1;
+    }
+
+    pub fn flush(&mut self, now_ms: u64) {
+        if let Err(err) = self.storage.flush(now_ms) {
+            log::error!("[PersistentLog] flush error {:?}", err);
+        }
+    }
+
+    pub fn should_compact(&self) -> bool {
+        self.records >= self.compact_at
+    }
+
+    pub fn compact(&mut self, snapshot: Vec<StorageRecord>) {
+        log::info!("[PersistentLog] compact {} records to {} records", self.records, snapshot.len());
+        if let Err(err) = self.storage.compact(&snapshot) {
+            log::error!("[PersistentLog] compact error {:?}", err);
+        }
+        self.records = snapshot.len();
+        self.compact_at = COMPACT_MIN_RECORDS.max(snapshot.len() * 2);
+    }
+}
+
+/// In-memory storage which is shared between clones, used for simulating restart in tests
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub(crate) struct MemoryStorage {
+    pub(crate) records: std::sync::Arc<parking_lot::Mutex<Vec<StorageRecord>>>,
+}
+
+#[cfg(test)]
+impl PersistentStorage for MemoryStorage {
+    fn load(&mut self) -> std::io::Result<Vec<StorageRecord>> {
+        Ok(self.records.lock().clone())
+    }
+
+    fn append(&mut self, record: &StorageRecord) -> std::io::Result<()> {
+        self.records.lock().push(record.clone());
+        Ok(())
+    }
+
+    fn compact(&mut self, snapshot: &[StorageRecord]) -> std::io::Result<()> {
+        *self.records.lock() = snapshot.to_vec();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryStorage, PersistentLog, StorageRecord, COMPACT_MIN_RECORDS};
+
+    fn del_record(key: u64) -> StorageRecord {
+        StorageRecord::Del {
+            at: 0,
+            key,
+            sub_key: None,
+            version: 0,
+        }
+    }
+
+    #[test]
+    fn compact_after_grow() {
+        let storage = MemoryStorage::default();
+        let (mut log, records) = PersistentLog::new(Box::new(storage.clone()));
+        assert_eq!(records, vec![]);
+
+        for i in 0..COMPACT_MIN_RECORDS - 1 {
+            log.append(del_record(i as u64));
+        }
+        assert!(!log.should_compact());
+        log.append(del_record(0));
+        assert!(log.should_compact());
+
+        let snapshot = (0..COMPACT_MIN_RECORDS).map(|i| del_record(i as u64)).collect::<Vec<_>>();
+        log.compact(snapshot.clone());
+        assert_eq!(*storage.records.lock(), snapshot);
+
+        // next compact is after log grows twice larger than the snapshot
+        for i in 0..COMPACT_MIN_RECORDS - 1 {
+            log.append(del_record(i as u64));
+        }
+        assert!(!log.should_compact());
+        log.append(del_record(0));
+        assert!(log.should_compact());
+    }
+}
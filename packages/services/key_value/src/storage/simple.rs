@@ -45,6 +45,17 @@ where
         self.keys.len()
     }
 
+    /// All values with absolute expire time, used for persistent snapshot
+    pub fn snapshot(&self) -> Vec<(Key, Value, u64, Source, Option<u64>)> {
+        self.keys
+            .iter()
+            .filter_map(|(key, slot)| {
+                let (value, version, source) = slot.value.as_ref()?;
+                Some((key.clone(), value.clone(), *version, source.clone(), slot.expire_at))
+            })
+            .collect()
+    }
+
     /// This function is call in each tick miliseconds, this function will check all expire time
     /// and clear all expired data, and fire all expire events
     /// It also clear expired handlers
@@ -132,6 +143,30 @@ where
         }
     }
 
+    /// Value with absolute expire time
+    pub fn get_with_expire(&self, key: &Key) -> Option<(&Value, u64, Source, Option<u64>)> {
+        let slot = self.keys.get(key)?;
+        let (value, version, source) = slot.value.as_ref()?;
+        Some((value, *version, source.clone(), slot.expire_at))
+    }
+
+    /// Keys which have value
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.keys.iter().filter(|(_, slot)| slot.value.is_some()).map(|(key, _)| key)
+    }
+
+    /// Remove value without firing del events, return removed version.
+    /// Listeners are kept until they are unsubscribed or expired
+    pub fn remove(&mut self, key: &Key) -> Option<u64> {
+        let slot = self.keys.get_mut(key)?;
+        let (_, version, _) = slot.value.take()?;
+        slot.expire_at = None;
+        if slot.listeners.is_empty() {
+            self.keys.remove(key);
+        }
+        Some(version)
+    }
+
     pub fn del(&mut self, key: &Key, request_version: u64) -> Option<(Value, u64, Source)> {
         if let Some(slot) = self.keys.get_mut(key) {
             if slot.value.is_none() {
@@ -531,4 +566,20 @@ mod tests {
         });
         assert_eq!(info.count_current, 0);
     }
+
+    #[test]
+    fn remove_without_events() {
+        let mut store = SimpleKeyValue::<u32, u32, u32, u32>::new();
+        store.subscribe(0, &1, 2000, None);
+        store.set(0, 1, 10, 5, 1000, Some(100));
+        assert_eq!(store.poll(), Some(OutputEvent::NotifySet(1, 10, 5, 1000, 2000)));
+        assert_eq!(store.get_with_expire(&1), Some((&10, 5, 1000, Some(100))));
+        assert_eq!(store.keys().collect::<Vec<_>>(), vec![&1]);
+
+        assert_eq!(store.remove(&1), Some(5));
+        assert_eq!(store.remove(&1), None);
+        assert_eq!(store.get(&1), None);
+        assert_eq!(store.keys().count(), 0);
+        assert_eq!(store.poll(), None);
+    }
 }
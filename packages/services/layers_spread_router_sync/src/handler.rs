@@ -13,6 +13,7 @@ pub struct LayersSpreadRouterSyncHandler<BE, HE> {
     router: SharedRouter,
     wait_sync: Option<RouterSync>,
     metric: Option<Metric>,
+    jitter_x16: u32,
     actions: VecDeque<ConnectionHandlerAction<BE, HE>>,
 }
 
@@ -26,6 +27,7 @@ where
             router,
             wait_sync: None,
             metric: None,
+            jitter_x16: 0,
             actions: VecDeque::new(),
         }
     }
@@ -95,7 +97,13 @@ where
                     ctx.conn_id,
                     stats.rtt_ms
                 );
-                let metric = Metric::new(stats.rtt_ms, vec![ctx.remote_node_id, ctx.local_node_id], stats.send_est_kbps);
+                //jitter is smoothed rtt variation, scaled by 16 like RFC 3550 interarrival jitter
+                if let Some(prev) = &self.metric {
+                    let diff = (stats.rtt_ms as i32 - prev.latency as i32).unsigned_abs();
+                    self.jitter_x16 = self.jitter_x16 + diff - ((self.jitter_x16 + 8) >> 4);
+                }
+                let jitter = (self.jitter_x16 >> 4).min(u16::MAX as u32) as u16;
+                let metric = Metric::new(stats.rtt_ms, vec![ctx.remote_node_id, ctx.local_node_id], stats.send_est_kbps).with_quality(stats.loss_percent.min(100) as u8, jitter);
                 self.router.set_direct(ctx.conn_id, ctx.remote_node_id, metric.clone());
                 if let Some(sync) = self.wait_sync.take() {
                     //first time => send sync
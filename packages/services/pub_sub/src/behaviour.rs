@@ -13,12 +13,23 @@ use atm0s_sdn_utils::Timer;
 use crate::{
     handler::{PubsubServiceConnectionHandler, CONTROL_META_TYPE, FEEDBACK_TYPE},
     msg::{PubsubServiceBehaviourEvent, PubsubServiceHandlerEvent},
-    relay::{local::LocalRelayAction, logic::PubsubRelayLogicOutput, source_binding::SourceBindingAction, PubsubRelay},
+    relay::{channel_name::directory_shard, local::LocalRelayAction, logic::PubsubRelayLogicOutput, source_binding::SourceBindingAction, ChannelIdentify, ChannelUuid, PubsubRelay},
     PubsubSdk, PUBSUB_SERVICE_ID,
 };
 
 const KEY_VALUE_TIMEOUT_MS: u64 = 30000;
 const KEY_VALUE_SUB_UUID: u64 = 0;
+/// Hashmaps of named channels, one per directory shard, sub key is node and channel uuid, value is the name.
+/// Channel keys are u32 so these keys never collide with them
+const KEY_VALUE_DIRECTORY_KEY: u64 = 1 << 32;
+
+fn directory_key(shard: u32) -> u64 {
+    KEY_VALUE_DIRECTORY_KEY | shard as u64
+}
+
+fn directory_sub_key(node_id: NodeId, channel: ChannelUuid) -> u64 {
+    ((node_id as u64) << 32) | channel as u64
+}
 
 pub struct PubsubServiceBehaviour<BE, HE, SE> {
     _tmp: PhantomData<BE>,
@@ -55,6 +66,7 @@ where
                     let header = MsgHeader::build(PUBSUB_SERVICE_ID, PUBSUB_SERVICE_ID, RouteRule::Direct).set_meta(FEEDBACK_TYPE);
                     TransportMsg::from_payload_bincode(header, &fb)
                 }
+                PubsubRelayLogicOutput::Retransmit(msg) => msg,
             };
 
             //Should be send to correct conn, if that conn not exits => fallback by finding to origin source node
@@ -81,6 +93,26 @@ where
                         KeyValueSdkEvent::DelH(channel as u64, self.node_id as u64).into(),
                     ));
                 }
+                LocalRelayAction::Advertise(channel, name) => {
+                    log::info!("[PubSubServiceBehaviour {}] on added named channel {} {} => set directory field", self.node_id, channel, name);
+                    self.outputs.push_back(NetworkBehaviorAction::ToSdkService(
+                        KEY_VALUE_SERVICE_ID,
+                        KeyValueSdkEvent::SetH(
+                            directory_key(directory_shard(&name)),
+                            directory_sub_key(self.node_id, channel),
+                            name.into_bytes(),
+                            Some(KEY_VALUE_TIMEOUT_MS),
+                        )
+                        .into(),
+                    ));
+                }
+                LocalRelayAction::Unadvertise(channel, name) => {
+                    log::info!("[PubSubServiceBehaviour {}] on removed named channel {} {} => del directory field", self.node_id, channel, name);
+                    self.outputs.push_back(NetworkBehaviorAction::ToSdkService(
+                        KEY_VALUE_SERVICE_ID,
+                        KeyValueSdkEvent::DelH(directory_key(directory_shard(&name)), directory_sub_key(self.node_id, channel)).into(),
+                    ));
+                }
             }
         }
 
@@ -100,6 +132,20 @@ where
                         KeyValueSdkEvent::UnsubH(KEY_VALUE_SUB_UUID, channel as u64).into(),
                     ));
                 }
+                SourceBindingAction::SubscribeDirectory(shard) => {
+                    log::info!("[PubSubServiceBehaviour {}] will sub named channel directory shard {}", self.node_id, shard);
+                    self.outputs.push_back(NetworkBehaviorAction::ToSdkService(
+                        KEY_VALUE_SERVICE_ID,
+                        KeyValueSdkEvent::SubH(KEY_VALUE_SUB_UUID, directory_key(shard), Some(KEY_VALUE_TIMEOUT_MS)).into(),
+                    ));
+                }
+                SourceBindingAction::UnsubscribeDirectory(shard) => {
+                    log::info!("[PubSubServiceBehaviour {}] will unsub named channel directory shard {}", self.node_id, shard);
+                    self.outputs.push_back(NetworkBehaviorAction::ToSdkService(
+                        KEY_VALUE_SERVICE_ID,
+                        KeyValueSdkEvent::UnsubH(KEY_VALUE_SUB_UUID, directory_key(shard)).into(),
+                    ));
+                }
             }
         }
     }
@@ -181,6 +227,15 @@ where
 
         if let Ok(event) = event.try_into() {
             match event {
+                KeyValueSdkEvent::OnKeyHChanged(_uuid, key, sub_key, value, _version, source) if key >= KEY_VALUE_DIRECTORY_KEY => {
+                    let channel = ChannelIdentify::new(sub_key as u32, source);
+                    match value.map(String::from_utf8) {
+                        Some(Ok(name)) => self.relay.on_named_source_added(channel, name),
+                        Some(Err(_)) => log::warn!("[PubSubServiceBehaviour {}] invalid name of named channel {}", self.node_id, channel),
+                        None => self.relay.on_named_source_removed(channel),
+                    }
+                    self.pop_all_events(ctx);
+                }
                 KeyValueSdkEvent::OnKeyHChanged(_uuid, key, _sub_key, value, _version, source) => {
                     if value.is_some() {
                         self.relay.on_source_added(key as u32, source);
@@ -213,10 +268,14 @@ mod test {
     use atm0s_sdn_utils::{awaker::MockAwaker, MockTimer, Timer};
 
     use crate::{
-        behaviour::{KEY_VALUE_SUB_UUID, KEY_VALUE_TIMEOUT_MS},
+        behaviour::{directory_key, KEY_VALUE_SUB_UUID, KEY_VALUE_TIMEOUT_MS},
         handler::CONTROL_META_TYPE,
-        ChannelIdentify, PubsubRemoteEvent, PubsubServiceBehaviourEvent, PubsubServiceHandlerEvent, PUBSUB_SERVICE_ID,
+        named_channel_uuid,
+        relay::{channel_name::directory_shard, fragment::build_data_msgs, reliable::build_reliable_msgs},
+        ChannelIdentify, ChannelPresence, Feedback, FeedbackType, NumberInfo, OverflowPolicy, PresenceEvent, PubsubRemoteEvent, PubsubServiceBehaviourEvent, PubsubServiceHandlerEvent,
+        SubscribeOptions, PUBSUB_FRAGMENT_MAX_PART_LEN, PUBSUB_OVERFLOW_FEEDBACK_ID, PUBSUB_SERVICE_ID,
     };
+    use bytes::Bytes;
 
     type BE = PubsubServiceBehaviourEvent;
     type HE = PubsubServiceHandlerEvent;
@@ -341,4 +400,267 @@ mod test {
             ))
         );
     }
+
+    #[test]
+    fn named_publish_should_set_del_directory() {
+        let local_node_id = 1;
+        let channel = named_channel_uuid("room/1/audio");
+        let timer = Arc::new(MockTimer::default());
+        let (mut behaviour, sdk) = super::PubsubServiceBehaviour::<BE, HE, SE>::new(local_node_id, timer.clone());
+
+        let ctx = BehaviorContext {
+            service_id: PUBSUB_SERVICE_ID,
+            node_id: local_node_id,
+            awaker: Arc::new(MockAwaker::default()),
+        };
+
+        behaviour.on_started(&ctx, 0);
+
+        let publisher = sdk.create_publisher_named("room/1/audio");
+        behaviour.on_awake(&ctx, timer.now_ms());
+        assert_eq!(
+            behaviour.pop_action(),
+            Some(NetworkBehaviorAction::ToSdkService(
+                KEY_VALUE_SERVICE_ID,
+                KeyValueSdkEvent::SetH(channel as u64, local_node_id as u64, vec![], Some(KEY_VALUE_TIMEOUT_MS)).into()
+            ))
+        );
+        let sub_key = ((local_node_id as u64) << 32) | channel as u64;
+        assert_eq!(
+            behaviour.pop_action(),
+            Some(NetworkBehaviorAction::ToSdkService(
+                KEY_VALUE_SERVICE_ID,
+                KeyValueSdkEvent::SetH(directory_key(directory_shard("room")), sub_key, "room/1/audio".as_bytes().to_vec(), Some(KEY_VALUE_TIMEOUT_MS)).into()
+            ))
+        );
+
+        drop(publisher);
+
+        behaviour.on_awake(&ctx, timer.now_ms());
+        assert_eq!(
+            behaviour.pop_action(),
+            Some(NetworkBehaviorAction::ToSdkService(
+                KEY_VALUE_SERVICE_ID,
+                KeyValueSdkEvent::DelH(channel as u64, local_node_id as u64).into()
+            ))
+        );
+        assert_eq!(
+            behaviour.pop_action(),
+            Some(NetworkBehaviorAction::ToSdkService(
+                KEY_VALUE_SERVICE_ID,
+                KeyValueSdkEvent::DelH(directory_key(directory_shard("room")), sub_key).into()
+            ))
+        );
+    }
+
+    #[test]
+    fn pattern_sub_should_follow_directory() {
+        let local_node_id = 1;
+        let source_node_id = 10;
+        let channel_uuid = named_channel_uuid("room/1/audio");
+        let channel = ChannelIdentify::new(channel_uuid, source_node_id);
+        let timer = Arc::new(MockTimer::default());
+        let (mut behaviour, sdk) = super::PubsubServiceBehaviour::<BE, HE, SE>::new(local_node_id, timer.clone());
+
+        let ctx = BehaviorContext {
+            service_id: PUBSUB_SERVICE_ID,
+            node_id: local_node_id,
+            awaker: Arc::new(MockAwaker::default()),
+        };
+
+        behaviour.on_started(&ctx, 0);
+
+        let consumer = sdk.create_consumer_pattern("room/*/audio", None);
+        behaviour.on_awake(&ctx, timer.now_ms());
+        assert_eq!(
+            behaviour.pop_action(),
+            Some(NetworkBehaviorAction::ToSdkService(
+                KEY_VALUE_SERVICE_ID,
+                KeyValueSdkEvent::SubH(KEY_VALUE_SUB_UUID, directory_key(directory_shard("room")), Some(KEY_VALUE_TIMEOUT_MS)).into()
+            ))
+        );
+        assert_eq!(behaviour.pop_action(), None);
+
+        let sub_key = ((source_node_id as u64) << 32) | channel_uuid as u64;
+        behaviour.on_sdk_msg(
+            &ctx,
+            timer.now_ms(),
+            KEY_VALUE_SERVICE_ID,
+            KeyValueSdkEvent::OnKeyHChanged(
+                KEY_VALUE_SUB_UUID,
+                directory_key(directory_shard("room")),
+                sub_key,
+                Some("room/1/audio".as_bytes().to_vec()),
+                0,
+                source_node_id,
+            ),
+        );
+        behaviour.on_awake(&ctx, timer.now_ms());
+        let expected_header = MsgHeader::build(PUBSUB_SERVICE_ID, PUBSUB_SERVICE_ID, RouteRule::Direct).set_meta(CONTROL_META_TYPE);
+        let expected_msg = TransportMsg::from_payload_bincode(expected_header, &PubsubRemoteEvent::Sub(channel));
+        assert_eq!(behaviour.pop_action(), Some(NetworkBehaviorAction::ToNetNode(source_node_id, expected_msg)));
+        assert_eq!(consumer.name_of(source_node_id, channel_uuid), Some("room/1/audio".to_string()));
+
+        behaviour
+            .relay
+            .on_event(timer.now_ms(), source_node_id, ConnId::from_in(0, 0), PubsubRemoteEvent::SubAck(channel, true));
+        behaviour.on_sdk_msg(
+            &ctx,
+            timer.now_ms(),
+            KEY_VALUE_SERVICE_ID,
+            KeyValueSdkEvent::OnKeyHChanged(KEY_VALUE_SUB_UUID, directory_key(directory_shard("room")), sub_key, None, 0, source_node_id),
+        );
+        behaviour.on_awake(&ctx, timer.now_ms());
+        let expected_header = MsgHeader::build(PUBSUB_SERVICE_ID, PUBSUB_SERVICE_ID, RouteRule::Direct).set_meta(CONTROL_META_TYPE);
+        let expected_msg = TransportMsg::from_payload_bincode(expected_header, &PubsubRemoteEvent::Unsub(channel));
+        assert_eq!(behaviour.pop_action(), Some(NetworkBehaviorAction::ToNetConn(ConnId::from_in(0, 0), expected_msg)));
+        assert_eq!(consumer.name_of(source_node_id, channel_uuid), None);
+    }
+
+    #[async_std::test]
+    async fn slow_consumer_should_feedback_overflow() {
+        let local_node_id = 1;
+        let channel = ChannelIdentify::new(1000, local_node_id);
+        let timer = Arc::new(MockTimer::default());
+        let (mut behaviour, sdk) = super::PubsubServiceBehaviour::<BE, HE, SE>::new(local_node_id, timer.clone());
+
+        let ctx = BehaviorContext {
+            service_id: PUBSUB_SERVICE_ID,
+            node_id: local_node_id,
+            awaker: Arc::new(MockAwaker::default()),
+        };
+
+        behaviour.on_started(&ctx, 0);
+
+        let publisher = sdk.create_publisher(channel.uuid());
+        let options = SubscribeOptions {
+            overflow: OverflowPolicy::DropOldest,
+            ..Default::default()
+        };
+        let consumer = sdk.create_consumer_single_with_options(channel, Some(1), options);
+
+        publisher.send(Bytes::from("1"));
+        publisher.send(Bytes::from("2"));
+        publisher.send(Bytes::from("3"));
+        assert_eq!(consumer.recv().await, Some((consumer.uuid(), local_node_id, channel.uuid(), Bytes::from("3"))));
+
+        behaviour.on_tick(&ctx, 1000, 1000);
+        assert_eq!(
+            publisher.recv_feedback().await,
+            Some(Feedback {
+                channel,
+                id: PUBSUB_OVERFLOW_FEEDBACK_ID,
+                feedback_type: FeedbackType::Number {
+                    window_ms: 1000,
+                    info: NumberInfo { count: 1, sum: 2, max: 2, min: 2 },
+                },
+            })
+        );
+    }
+
+    #[async_std::test]
+    async fn publisher_should_receive_presence() {
+        let local_node_id = 1;
+        let channel = ChannelIdentify::new(1000, local_node_id);
+        let timer = Arc::new(MockTimer::default());
+        let (mut behaviour, sdk) = super::PubsubServiceBehaviour::<BE, HE, SE>::new(local_node_id, timer.clone());
+
+        let ctx = BehaviorContext {
+            service_id: PUBSUB_SERVICE_ID,
+            node_id: local_node_id,
+            awaker: Arc::new(MockAwaker::default()),
+        };
+
+        behaviour.on_started(&ctx, 0);
+
+        let publisher = sdk.create_publisher(channel.uuid());
+        assert_eq!(publisher.presence(), ChannelPresence::default());
+
+        let consumer = sdk.create_consumer_single(channel, None);
+        let expected = ChannelPresence {
+            subscribers: 1,
+            nodes: vec![local_node_id],
+        };
+        assert_eq!(publisher.recv_presence().await, Some(PresenceEvent::FirstSubscriber(expected.clone())));
+        assert_eq!(publisher.presence(), expected);
+
+        drop(consumer);
+        assert_eq!(publisher.recv_presence().await, Some(PresenceEvent::LastSubscriberLeft));
+        assert_eq!(publisher.presence(), ChannelPresence::default());
+    }
+
+    #[async_std::test]
+    async fn remote_fragments_should_reassemble_for_consumer() {
+        let local_node_id = 1;
+        let source_node_id = 10;
+        let channel = ChannelIdentify::new(1000, source_node_id);
+        let timer = Arc::new(MockTimer::default());
+        let (mut behaviour, sdk) = super::PubsubServiceBehaviour::<BE, HE, SE>::new(local_node_id, timer.clone());
+
+        let ctx = BehaviorContext {
+            service_id: PUBSUB_SERVICE_ID,
+            node_id: local_node_id,
+            awaker: Arc::new(MockAwaker::default()),
+        };
+
+        behaviour.on_started(&ctx, 0);
+
+        let consumer = sdk.create_consumer_single(channel, None);
+        behaviour
+            .relay
+            .on_event(timer.now_ms(), source_node_id, ConnId::from_in(0, 0), PubsubRemoteEvent::SubAck(channel, true));
+
+        let data: Vec<u8> = (0..PUBSUB_FRAGMENT_MAX_PART_LEN * 3).map(|i| i as u8).collect();
+        let msgs = build_data_msgs(channel, &data, || 1);
+        assert_eq!(msgs.len(), 3);
+        for msg in msgs.into_iter().rev() {
+            match msg.get_payload_bincode::<PubsubRemoteEvent>() {
+                Ok(PubsubRemoteEvent::Fragment(channel, fragment_id, part)) => behaviour.relay.relay_fragment(timer.now_ms(), channel, fragment_id, &part, msg),
+                _ => panic!("Should be fragment"),
+            }
+        }
+
+        assert_eq!(consumer.recv().await, Some((consumer.uuid(), source_node_id, channel.uuid(), Bytes::from(data))));
+    }
+
+    #[async_std::test]
+    async fn remote_reliable_fragments_should_reassemble_for_consumer() {
+        let local_node_id = 1;
+        let source_node_id = 10;
+        let channel = ChannelIdentify::new(1000, source_node_id);
+        let timer = Arc::new(MockTimer::default());
+        let (mut behaviour, sdk) = super::PubsubServiceBehaviour::<BE, HE, SE>::new(local_node_id, timer.clone());
+
+        let ctx = BehaviorContext {
+            service_id: PUBSUB_SERVICE_ID,
+            node_id: local_node_id,
+            awaker: Arc::new(MockAwaker::default()),
+        };
+
+        behaviour.on_started(&ctx, 0);
+
+        let consumer = sdk.create_consumer_single_reliable(channel, None);
+        behaviour
+            .relay
+            .on_event(timer.now_ms(), source_node_id, ConnId::from_in(0, 0), PubsubRemoteEvent::SubAck(channel, true));
+
+        let first = build_reliable_msgs(channel, 1, &[1, 2, 3]);
+        let data: Vec<u8> = (0..PUBSUB_FRAGMENT_MAX_PART_LEN * 3).map(|i| i as u8).collect();
+        let msgs = build_reliable_msgs(channel, 2, &data);
+        let last = build_reliable_msgs(channel, 3, &[4, 5, 6]);
+        assert_eq!(msgs.len(), 3);
+        behaviour.relay.relay_reliable(channel, first[0].clone());
+        // the msg after the fragmented one arrives first, then parts are out of order and duplicated
+        behaviour.relay.relay_reliable(channel, last[0].clone());
+        for msg in msgs.iter().rev().chain(msgs.iter()) {
+            match msg.get_payload_bincode::<PubsubRemoteEvent>() {
+                Ok(PubsubRemoteEvent::ReliableFragment(channel, seq, fragment_id, part)) => behaviour.relay.relay_reliable_fragment(timer.now_ms(), channel, seq, fragment_id, &part, msg.clone()),
+                _ => panic!("Should be reliable fragment"),
+            }
+        }
+
+        assert_eq!(consumer.recv().await, Some((consumer.uuid(), source_node_id, channel.uuid(), Bytes::from(vec![1, 2, 3]))));
+        assert_eq!(consumer.recv().await, Some((consumer.uuid(), source_node_id, channel.uuid(), Bytes::from(data))));
+        assert_eq!(consumer.recv().await, Some((consumer.uuid(), source_node_id, channel.uuid(), Bytes::from(vec![4, 5, 6]))));
+    }
 }
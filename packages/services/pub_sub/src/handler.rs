@@ -11,6 +11,7 @@ use crate::{
 
 pub const CONTROL_META_TYPE: u8 = 1;
 pub const FEEDBACK_TYPE: u8 = 2;
+pub const RELIABLE_DATA_TYPE: u8 = 3;
 
 pub struct PubsubServiceConnectionHandler {
     pub(crate) node_id: NodeId,
@@ -31,16 +32,28 @@ where
     fn on_event(&mut self, ctx: &ConnectionContext, now_ms: u64, event: atm0s_sdn_network::transport::ConnectionEvent) {
         match event {
             ConnectionEvent::Msg(msg) => match msg.header.meta {
-                CONTROL_META_TYPE => {
-                    if let Ok(cmd) = msg.get_payload_bincode::<PubsubRemoteEvent>() {
+                CONTROL_META_TYPE => match msg.get_payload_bincode::<PubsubRemoteEvent>() {
+                    Ok(PubsubRemoteEvent::Fragment(channel, fragment_id, data)) => {
+                        self.relay.relay_fragment(now_ms, channel, fragment_id, &data, msg);
+                    }
+                    Ok(PubsubRemoteEvent::ReliableFragment(channel, seq, fragment_id, data)) => {
+                        self.relay.relay_reliable_fragment(now_ms, channel, seq, fragment_id, &data, msg);
+                    }
+                    Ok(cmd) => {
                         self.relay.on_event(now_ms, ctx.remote_node_id, ctx.conn_id, cmd);
                     }
-                }
+                    Err(_) => {}
+                },
                 FEEDBACK_TYPE => {
                     if let Ok(fb) = msg.get_payload_bincode::<Feedback>() {
                         self.relay.on_feedback(now_ms, fb.channel, ctx.remote_node_id, ctx.conn_id, fb);
                     }
                 }
+                RELIABLE_DATA_TYPE => {
+                    if let Some(from_node) = msg.header.from_node {
+                        self.relay.relay_reliable(ChannelIdentify::new(msg.header.stream_id, from_node), msg);
+                    }
+                }
                 _ => {
                     if let Some(from_node) = msg.header.from_node {
                         log::trace!(
@@ -1,6 +1,28 @@
 pub static PUBSUB_SERVICE_ID: u8 = 5;
 pub(crate) static PUBSUB_CHANNEL_RESYNC_MS: u64 = 5000;
 pub(crate) static PUBSUB_CHANNEL_TIMEOUT_MS: u64 = 20000;
+pub(crate) static PUBSUB_RELIABLE_BUFFER_SIZE: usize = 256;
+pub(crate) static PUBSUB_RELIABLE_NACK_INTERVAL_MS: u64 = 100;
+/// A missing msg is NACKed again only after this time without answer
+pub(crate) static PUBSUB_RELIABLE_NACK_RETRY_MS: u64 = 500;
+pub(crate) static PUBSUB_RELIABLE_GAP_TIMEOUT_MS: u64 = 2000;
+/// Feedback id used for reporting dropped msgs of slow consumers to publishers, sum is the number of dropped msgs
+pub static PUBSUB_OVERFLOW_FEEDBACK_ID: u8 = u8::MAX;
+pub(crate) static PUBSUB_OVERFLOW_FEEDBACK_WINDOW_MS: u32 = 1000;
+/// Msgs larger than this are split into fragments, each fragment fits into a single UDP packet
+pub static PUBSUB_FRAGMENT_MAX_PART_LEN: usize = 1100;
+pub(crate) static PUBSUB_FRAGMENT_TIMEOUT_MS: u64 = 5000;
+pub(crate) static PUBSUB_FRAGMENT_MAX_PENDING_BYTES: usize = 16 * 1024 * 1024;
+/// Replay answer is split into parts which fit into a single UDP packet
+pub(crate) static PUBSUB_HISTORY_PART_MAX_BYTES: usize = 1100;
+/// Live msgs which are hold back from a local consumer while waiting history, the oldest ones are dropped
+pub(crate) static PUBSUB_REPLAY_MAX_PENDING_MSGS: usize = 1024;
+/// Presence events which are not received by a publisher yet, the oldest ones are dropped because the latest one is the current state
+pub(crate) static PUBSUB_PRESENCE_QUEUE_SIZE: usize = 16;
+/// Presence keeps at most this number of nodes, subscribers are still counted from all nodes
+pub static PUBSUB_PRESENCE_MAX_NODES: usize = 64;
+/// Directory of named channels is sharded by the first segment of names, so it is not stored in a single key
+pub(crate) static PUBSUB_DIRECTORY_SHARDS: u32 = 64;
 
 mod behaviour;
 mod handler;
@@ -10,5 +32,12 @@ mod sdk;
 
 pub use behaviour::PubsubServiceBehaviour;
 pub use msg::{PubsubRemoteEvent, PubsubServiceBehaviourEvent, PubsubServiceHandlerEvent};
-pub use relay::{feedback::Feedback, feedback::FeedbackType, feedback::NumberInfo, ChannelIdentify, ChannelUuid, LocalPubId, LocalSubId};
-pub use sdk::{consumer::Consumer, consumer_raw::ConsumerRaw, consumer_single::ConsumerSingle, publisher::Publisher, publisher_raw::PublisherRaw, PubsubSdk};
+pub use relay::{
+    channel_name::named_channel_uuid, channel_name::ChannelPattern, channel_name::NAMED_CHANNEL_UUID_BIT, feedback::BitmaskFeedbackProcessor, feedback::Feedback, feedback::FeedbackConsumerId,
+    feedback::FeedbackProcessorFactory, feedback::FeedbackType, feedback::NumberInfo, feedback::RangeFeedbackProcessor, feedback::RateLimitedPassthroughProcessor, feedback::SingleFeedbackProcessor,
+    history::HistoryConfig, local::OverflowPolicy, presence::ChannelPresence, presence::PresenceEvent, ChannelIdentify, ChannelUuid, LocalPubId, LocalSubId,
+};
+pub use sdk::{
+    consumer::Consumer, consumer_pattern::ConsumerPattern, consumer_raw::ConsumerRaw, consumer_single::ConsumerSingle, publisher::Publisher, publisher_raw::PublisherRaw,
+    source_selection::SourceSelection, PubsubSdk, SubscribeOptions,
+};
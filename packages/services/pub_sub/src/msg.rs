@@ -1,4 +1,4 @@
-use crate::relay::ChannelIdentify;
+use crate::relay::{presence::ChannelPresence, ChannelIdentify};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, PartialEq, Eq)]
@@ -11,8 +11,14 @@ pub enum PubsubServiceHandlerEvent {}
 pub enum PubsubRemoteEvent {
     Sub(ChannelIdentify),
     Unsub(ChannelIdentify),
-    SubAck(ChannelIdentify, bool),   //did it added, incase of false, it means it already subscribed
-    UnsubAck(ChannelIdentify, bool), //did it removed, incase of false, it means it already unsubscribed
+    SubAck(ChannelIdentify, bool),                               //did it added, incase of false, it means it already subscribed
+    UnsubAck(ChannelIdentify, bool),                             //did it removed, incase of false, it means it already unsubscribed
+    Nack(ChannelIdentify, Vec<u64>),                             //missing sequences of a reliable channel, sent toward the source
+    Replay(ChannelIdentify),                                     //request history of channel, sent toward the source
+    History(ChannelIdentify, Vec<(Option<u64>, Vec<u8>)>, bool), //part of replay answer, with sequence number of reliable msgs, and whether it is the last part
+    Presence(ChannelIdentify, ChannelPresence),                  //aggregated subscribers behind the sender, sent toward the source
+    Fragment(ChannelIdentify, u32, Vec<u8>),                     //part of a large msg with fragment id (msg_id, part_index, part_count_minus_1), relayed like data
+    ReliableFragment(ChannelIdentify, u64, u32, Vec<u8>),        //part of a large reliable msg with sequence number and fragment id, relayed like reliable data
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -4,22 +4,29 @@ use atm0s_sdn_identity::{ConnId, NodeId};
 use atm0s_sdn_network::{msg::TransportMsg, transport::ConnectionSender};
 use atm0s_sdn_utils::{awaker::Awaker, Timer};
 use bytes::Bytes;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 
-use crate::{msg::PubsubRemoteEvent, PubsubSdk};
+use crate::{msg::PubsubRemoteEvent, PubsubSdk, PUBSUB_FRAGMENT_MAX_PENDING_BYTES, PUBSUB_FRAGMENT_TIMEOUT_MS, PUBSUB_OVERFLOW_FEEDBACK_ID, PUBSUB_OVERFLOW_FEEDBACK_WINDOW_MS};
 
 use self::{
     feedback::FeedbackConsumerId,
+    fragment::{parse_fragment_id, FragmentAssembler},
     local::{LocalRelay, LocalRelayAction},
-    logic::{PubsubRelayLogic, PubsubRelayLogicOutput},
+    logic::{LocalReplay, PubsubRelayLogic, PubsubRelayLogicOutput},
+    reliable::split_reliable_payload,
     remote::RemoteRelay,
     source_binding::{SourceBinding, SourceBindingAction},
 };
 
+pub(crate) mod channel_name;
 pub(crate) mod feedback;
+pub(crate) mod fragment;
+pub(crate) mod history;
 pub(crate) mod local;
 pub(crate) mod logic;
+pub(crate) mod presence;
+pub(crate) mod reliable;
 pub(crate) mod remote;
 pub(crate) mod source_binding;
 
@@ -55,6 +62,10 @@ pub struct PubsubRelay {
     remote: Arc<RwLock<RemoteRelay>>,
     local: Arc<RwLock<LocalRelay>>,
     source_binding: Arc<RwLock<SourceBinding>>,
+    fragments: Arc<Mutex<FragmentAssembler>>,
+    /// Parts of large reliable msgs are identified by sequence number, so they are assembled separately
+    reliable_fragments: Arc<Mutex<FragmentAssembler>>,
+    timer: Arc<dyn Timer>,
 }
 
 impl Clone for PubsubRelay {
@@ -64,6 +75,28 @@ impl Clone for PubsubRelay {
             remote: self.remote.clone(),
             local: self.local.clone(),
             source_binding: self.source_binding.clone(),
+            fragments: self.fragments.clone(),
+            reliable_fragments: self.reliable_fragments.clone(),
+            timer: self.timer.clone(),
+        }
+    }
+}
+
+/// Deliver history to local subscribers which requested replay and presence changes to local publishers
+pub(crate) fn flush_local_events(logic: &RwLock<PubsubRelayLogic>, local: &RwLock<LocalRelay>) {
+    loop {
+        let replay = logic.write().pop_local_replay();
+        match replay {
+            Some(LocalReplay::Start(channel, uuid)) => local.write().on_replay_start(uuid, channel.source(), channel.uuid()),
+            Some(LocalReplay::Data(channel, uuid, msgs)) => local.write().replay(uuid, channel.source(), channel.uuid(), msgs),
+            None => break,
+        }
+    }
+    loop {
+        let presence = logic.write().pop_local_presence();
+        match presence {
+            Some((channel, event)) => local.read().presence(channel.uuid(), event),
+            None => break,
         }
     }
 }
@@ -75,6 +108,9 @@ impl PubsubRelay {
             remote: Arc::new(RwLock::new(RemoteRelay::new())),
             local: Arc::new(RwLock::new(LocalRelay::new())),
             source_binding: Arc::new(RwLock::new(SourceBinding::new())),
+            fragments: Arc::new(Mutex::new(FragmentAssembler::new(PUBSUB_FRAGMENT_TIMEOUT_MS, PUBSUB_FRAGMENT_MAX_PENDING_BYTES))),
+            reliable_fragments: Arc::new(Mutex::new(FragmentAssembler::new(PUBSUB_FRAGMENT_TIMEOUT_MS, PUBSUB_FRAGMENT_MAX_PENDING_BYTES))),
+            timer: timer.clone(),
         };
         let sdk = PubsubSdk::new(node_id, s.logic.clone(), s.remote.clone(), s.local.clone(), s.source_binding.clone(), timer);
         (s, sdk)
@@ -99,6 +135,35 @@ impl PubsubRelay {
         for fb in local_fbs {
             self.local.read().feedback(fb.channel.uuid(), fb);
         }
+        self.feedback_overflows(now_ms);
+        self.fragments.lock().on_tick(now_ms);
+        self.reliable_fragments.lock().on_tick(now_ms);
+        flush_local_events(&self.logic, &self.local);
+    }
+
+    /// Report dropped msgs of slow local consumers to publishers, as number feedback with id PUBSUB_OVERFLOW_FEEDBACK_ID
+    fn feedback_overflows(&self, now_ms: u64) {
+        let overflows = self.local.write().pop_overflows();
+        for (uuid, channel, dropped) in overflows {
+            log::debug!("[PubsubRelay] local {} dropped {} msgs of channel {} => feedback", uuid, dropped, channel);
+            let dropped = dropped as i64;
+            let fb = feedback::Feedback {
+                channel,
+                id: PUBSUB_OVERFLOW_FEEDBACK_ID,
+                feedback_type: feedback::FeedbackType::Number {
+                    window_ms: PUBSUB_OVERFLOW_FEEDBACK_WINDOW_MS,
+                    info: feedback::NumberInfo {
+                        count: 1,
+                        sum: dropped,
+                        max: dropped,
+                        min: dropped,
+                    },
+                },
+            };
+            if let Some(local_fb) = self.logic.write().on_feedback(now_ms, channel, FeedbackConsumerId::Local(uuid), fb) {
+                self.local.read().feedback(channel.uuid(), local_fb);
+            }
+        }
     }
 
     pub fn on_source_added(&self, channel: ChannelUuid, source: NodeId) {
@@ -107,6 +172,7 @@ impl PubsubRelay {
             for sub in subs {
                 self.logic.write().on_local_sub(ChannelIdentify::new(channel, source), sub);
             }
+            flush_local_events(&self.logic, &self.local);
         }
     }
 
@@ -116,11 +182,35 @@ impl PubsubRelay {
             for sub in subs {
                 self.logic.write().on_local_unsub(ChannelIdentify::new(channel, source), sub);
             }
+            flush_local_events(&self.logic, &self.local);
+        }
+    }
+
+    pub fn on_named_source_added(&self, channel: ChannelIdentify, name: String) {
+        let subs = self.source_binding.write().on_named_source_added(channel, name);
+        if !subs.is_empty() {
+            log::debug!("[PubsubRelay] named channel {} added => auto sub for local pattern subs {:?}", channel, subs);
+            for sub in subs {
+                self.logic.write().on_local_sub(channel, sub);
+            }
+            flush_local_events(&self.logic, &self.local);
+        }
+    }
+
+    pub fn on_named_source_removed(&self, channel: ChannelIdentify) {
+        let subs = self.source_binding.write().on_named_source_removed(channel);
+        if !subs.is_empty() {
+            log::debug!("[PubsubRelay] named channel {} removed => auto unsub for local pattern subs {:?}", channel, subs);
+            for sub in subs {
+                self.logic.write().on_local_unsub(channel, sub);
+            }
+            flush_local_events(&self.logic, &self.local);
         }
     }
 
     pub fn on_event(&self, now_ms: u64, from: NodeId, conn: ConnId, event: PubsubRemoteEvent) {
         self.logic.write().on_event(now_ms, from, conn, event);
+        flush_local_events(&self.logic, &self.local);
     }
 
     pub fn on_feedback(&self, now_ms: u64, channel: ChannelIdentify, _from: NodeId, conn: ConnId, fb: feedback::Feedback) {
@@ -130,16 +220,99 @@ impl PubsubRelay {
     }
 
     pub fn relay(&self, channel: ChannelIdentify, msg: TransportMsg) {
+        let has_history = self.logic.read().has_history(channel);
+        if has_history {
+            self.logic.write().on_history_msg(self.timer.now_ms(), channel, None, msg.payload());
+        }
         if let Some((remotes, locals)) = self.logic.read().relay(channel) {
             self.remote.read().relay(remotes, &msg);
             if !locals.is_empty() {
-                self.local.read().relay(channel.source(), channel.uuid(), locals, Bytes::from(msg.payload().to_vec()));
+                self.local.read().relay(channel.source(), channel.uuid(), locals, None, Bytes::from(msg.payload().to_vec()));
             } else {
                 log::trace!("No local subscriber for channel {}", channel);
             }
         }
     }
 
+    /// Relay a msg of reliable channel, the msg is stored for answering NACK and duplicated msgs are dropped
+    pub fn relay_reliable(&self, channel: ChannelIdentify, msg: TransportMsg) {
+        if let Some((seq, data)) = split_reliable_payload(msg.payload()) {
+            let mut logic = self.logic.write();
+            if !logic.on_reliable_msg(channel, seq, &msg) {
+                log::trace!("Duplicated reliable msg {} for channel {}", seq, channel);
+                return;
+            }
+            logic.on_history_msg(self.timer.now_ms(), channel, Some(seq), data);
+            drop(logic);
+            if let Some((remotes, locals)) = self.logic.read().relay(channel) {
+                self.remote.read().relay(remotes, &msg);
+                if !locals.is_empty() {
+                    self.local.read().relay(channel.source(), channel.uuid(), locals, Some(seq), Bytes::from(data.to_vec()));
+                }
+            }
+        } else {
+            log::warn!("Invalid reliable msg for channel {}", channel);
+        }
+    }
+
+    /// Relay a part of a large reliable msg, parts are stored for answering NACK and forwarded as is,
+    /// the msg is only reassembled for local subscribers and history
+    pub fn relay_reliable_fragment(&self, now_ms: u64, channel: ChannelIdentify, seq: u64, fragment_id: u32, part: &[u8], msg: TransportMsg) {
+        let (_, part_index, part_count_minus_1) = parse_fragment_id(fragment_id);
+        if !self.logic.write().on_reliable_part(channel, seq, part_index as usize, part_count_minus_1 as usize + 1, &msg) {
+            log::trace!("Duplicated or invalid part {} of reliable msg {} for channel {}", part_index, seq, channel);
+            return;
+        }
+        let need_reassemble = {
+            let logic = self.logic.read();
+            if let Some((remotes, locals)) = logic.relay(channel) {
+                self.remote.read().relay(remotes, &msg);
+                !locals.is_empty() || logic.has_history(channel)
+            } else {
+                return;
+            }
+        };
+        if !need_reassemble {
+            log::trace!("No local subscriber for channel {}", channel);
+            return;
+        }
+
+        if let Some(data) = self.reliable_fragments.lock().on_fragment(now_ms, channel, fragment_id, part) {
+            self.logic.write().on_history_msg(now_ms, channel, Some(seq), &data);
+            if let Some((_, locals)) = self.logic.read().relay(channel) {
+                if !locals.is_empty() {
+                    self.local.read().relay(channel.source(), channel.uuid(), locals, Some(seq), data);
+                }
+            }
+        }
+    }
+
+    /// Relay a fragment of a large msg, the fragment is forwarded to remotes as is and only reassembled for local subscribers and history
+    pub fn relay_fragment(&self, now_ms: u64, channel: ChannelIdentify, fragment_id: u32, data: &[u8], msg: TransportMsg) {
+        let need_reassemble = {
+            let logic = self.logic.read();
+            if let Some((remotes, locals)) = logic.relay(channel) {
+                self.remote.read().relay(remotes, &msg);
+                !locals.is_empty() || logic.has_history(channel)
+            } else {
+                return;
+            }
+        };
+        if !need_reassemble {
+            log::trace!("No local subscriber for channel {}", channel);
+            return;
+        }
+
+        if let Some(data) = self.fragments.lock().on_fragment(now_ms, channel, fragment_id, data) {
+            self.logic.write().on_history_msg(now_ms, channel, None, &data);
+            if let Some((_, locals)) = self.logic.read().relay(channel) {
+                if !locals.is_empty() {
+                    self.local.read().relay(channel.source(), channel.uuid(), locals, None, data);
+                }
+            }
+        }
+    }
+
     pub fn pop_logic_action(&mut self) -> Option<(NodeId, Option<ConnId>, PubsubRelayLogicOutput)> {
         self.logic.write().pop_action()
     }
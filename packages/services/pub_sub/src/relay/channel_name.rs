@@ -0,0 +1,152 @@
+use crate::PUBSUB_DIRECTORY_SHARDS;
+
+use super::ChannelUuid;
+
+const FNV_OFFSET: u32 = 0x811c9dc5;
+const FNV_PRIME: u32 = 0x01000193;
+
+/// Uuids of named channels always have this bit set, numeric channels should use uuids below it
+pub const NAMED_CHANNEL_UUID_BIT: ChannelUuid = 1 << 31;
+
+fn fnv1a(value: &str) -> u32 {
+    value.as_bytes().iter().fold(FNV_OFFSET, |hash, byte| (hash ^ *byte as u32).wrapping_mul(FNV_PRIME))
+}
+
+/// Uuid of a named channel, which is FNV-1a hash of the name inside the reserved range, so all nodes get same uuid without coordination
+pub fn named_channel_uuid(name: &str) -> ChannelUuid {
+    fnv1a(name) | NAMED_CHANNEL_UUID_BIT
+}
+
+/// Directory shard of a named channel, which is selected by the first segment of the name
+pub(crate) fn directory_shard(name: &str) -> u32 {
+    fnv1a(name.split('/').next().unwrap_or_default()) % PUBSUB_DIRECTORY_SHARDS
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum PatternSegment {
+    Exact(String),
+    /// `*`, match exactly one segment
+    One,
+    /// `#`, match all remaining segments, including none
+    Rest,
+}
+
+/// Pattern of hierarchical channel names which are separated by `/`,
+/// `*` match a single segment and `#` match all remaining segments, for example `room/*/audio` or `room/#`.
+/// Segments after `#` are ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChannelPattern {
+    segments: Vec<PatternSegment>,
+}
+
+impl ChannelPattern {
+    pub fn new(pattern: &str) -> Self {
+        let mut segments = vec![];
+        for part in pattern.split('/') {
+            match part {
+                "*" => segments.push(PatternSegment::One),
+                "#" => {
+                    segments.push(PatternSegment::Rest);
+                    break;
+                }
+                _ => segments.push(PatternSegment::Exact(part.to_string())),
+            }
+        }
+        Self { segments }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let mut parts = name.split('/');
+        for segment in &self.segments {
+            match segment {
+                PatternSegment::Rest => return true,
+                PatternSegment::One => {
+                    if parts.next().is_none() {
+                        return false;
+                    }
+                }
+                PatternSegment::Exact(value) => {
+                    if parts.next() != Some(value.as_str()) {
+                        return false;
+                    }
+                }
+            }
+        }
+        parts.next().is_none()
+    }
+
+    /// Directory shards which can contain matched names, all shards if the first segment is a wildcard
+    pub(crate) fn directory_shards(&self) -> Vec<u32> {
+        match self.segments.first() {
+            Some(PatternSegment::Exact(value)) => vec![directory_shard(value)],
+            _ => (0..PUBSUB_DIRECTORY_SHARDS).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PUBSUB_DIRECTORY_SHARDS;
+
+    use super::{directory_shard, named_channel_uuid, ChannelPattern, NAMED_CHANNEL_UUID_BIT};
+
+    #[test]
+    fn uuid_should_be_stable() {
+        assert_eq!(named_channel_uuid(""), 0x811c9dc5);
+        assert_eq!(named_channel_uuid("a"), 0xe40c292c);
+        assert_eq!(named_channel_uuid("b"), 0xe70c2de5);
+        assert_ne!(named_channel_uuid("room/1/audio"), named_channel_uuid("room/1/video"));
+    }
+
+    #[test]
+    fn uuid_should_be_in_named_range() {
+        for name in ["", "a", "b", "room/1/audio", "room/2/video", "lobby"] {
+            assert!(named_channel_uuid(name) >= NAMED_CHANNEL_UUID_BIT);
+        }
+    }
+
+    #[test]
+    fn pattern_should_use_shard_of_first_segment() {
+        assert_eq!(directory_shard("room/1/audio"), directory_shard("room/2/video"));
+        assert_eq!(directory_shard("room"), directory_shard("room/1"));
+        assert_eq!(ChannelPattern::new("room/*/audio").directory_shards(), vec![directory_shard("room")]);
+        assert_eq!(ChannelPattern::new("room/#").directory_shards(), vec![directory_shard("room")]);
+        assert_eq!(ChannelPattern::new("*/audio").directory_shards().len(), PUBSUB_DIRECTORY_SHARDS as usize);
+        assert_eq!(ChannelPattern::new("#").directory_shards().len(), PUBSUB_DIRECTORY_SHARDS as usize);
+    }
+
+    #[test]
+    fn exact_pattern() {
+        let pattern = ChannelPattern::new("room/1/audio");
+        assert!(pattern.matches("room/1/audio"));
+        assert!(!pattern.matches("room/1"));
+        assert!(!pattern.matches("room/1/audio/extra"));
+        assert!(!pattern.matches("room/2/audio"));
+    }
+
+    #[test]
+    fn single_segment_wildcard() {
+        let pattern = ChannelPattern::new("room/*/audio");
+        assert!(pattern.matches("room/1/audio"));
+        assert!(pattern.matches("room/2/audio"));
+        assert!(!pattern.matches("room/1/video"));
+        assert!(!pattern.matches("room/audio"));
+
+        let pattern = ChannelPattern::new("room/*");
+        assert!(pattern.matches("room/1"));
+        assert!(!pattern.matches("room"));
+        assert!(!pattern.matches("room/1/audio"));
+    }
+
+    #[test]
+    fn multi_segment_wildcard() {
+        let pattern = ChannelPattern::new("room/#");
+        assert!(pattern.matches("room"));
+        assert!(pattern.matches("room/1"));
+        assert!(pattern.matches("room/1/audio"));
+        assert!(!pattern.matches("lobby/1"));
+
+        assert!(ChannelPattern::new("#").matches("anything/at/all"));
+        assert_eq!(ChannelPattern::new("room/#/ignored"), ChannelPattern::new("room/#"));
+    }
+}
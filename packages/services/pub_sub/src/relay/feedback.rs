@@ -1,12 +1,19 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use atm0s_sdn_identity::ConnId;
 use serde::{Deserialize, Serialize};
 
 use crate::ChannelIdentify;
 
+mod bitmask;
 mod number;
 mod passthrough;
+mod range;
+mod rate_limit;
+
+pub use bitmask::BitmaskFeedbackProcessor;
+pub use range::RangeFeedbackProcessor;
+pub use rate_limit::RateLimitedPassthroughProcessor;
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct NumberInfo {
@@ -19,7 +26,18 @@ pub struct NumberInfo {
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub enum FeedbackType {
     Passthrough(Vec<u8>),
-    Number { window_ms: u32, info: NumberInfo },
+    Number {
+        window_ms: u32,
+        info: NumberInfo,
+    },
+    /// Bitset, aggregated with OR of all consumers, for example requested layers
+    Bitmask(u64),
+    /// Only min and max of all consumers are kept, for example max requested bitrate
+    Range {
+        window_ms: u32,
+        min: i64,
+        max: i64,
+    },
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
@@ -35,12 +53,44 @@ pub struct Feedback {
     pub feedback_type: FeedbackType,
 }
 
-pub(crate) trait SingleFeedbackProcessor: Send + Sync {
+/// Aggregate feedbacks of a single id from all consumers of a channel, the output is sent to the next node toward the source
+pub trait SingleFeedbackProcessor: Send + Sync {
     fn on_tick(&mut self, now_ms: u64) -> Option<FeedbackType>;
     fn on_remove(&mut self, consumer_id: FeedbackConsumerId);
     fn on_feedback(&mut self, now_ms: u64, consumer_id: FeedbackConsumerId, fb: FeedbackType) -> Option<FeedbackType>;
 }
 
+pub type FeedbackProcessorFactory = Arc<dyn Fn() -> Box<dyn SingleFeedbackProcessor> + Send + Sync>;
+
+/// Custom processors by feedback id, ids without custom processor use the built-in processor of the feedback type.
+/// Relay nodes aggregate feedbacks too, so custom processors should be registered in all nodes
+#[derive(Default, Clone)]
+pub struct FeedbackRegistry {
+    factories: HashMap<u8, FeedbackProcessorFactory>,
+}
+
+impl FeedbackRegistry {
+    pub fn register(&mut self, id: u8, factory: FeedbackProcessorFactory) {
+        self.factories.insert(id, factory);
+    }
+
+    pub fn unregister(&mut self, id: u8) {
+        self.factories.remove(&id);
+    }
+
+    fn create(&self, id: u8, fb: &FeedbackType) -> Box<dyn SingleFeedbackProcessor> {
+        if let Some(factory) = self.factories.get(&id) {
+            return factory();
+        }
+        match fb {
+            FeedbackType::Passthrough(_) => Box::new(passthrough::PassthroughFeedbackProcessor()),
+            FeedbackType::Number { window_ms, info: _ } => Box::new(number::NumberFeedbackProcessor::new(*window_ms)),
+            FeedbackType::Bitmask(_) => Box::new(BitmaskFeedbackProcessor::default()),
+            FeedbackType::Range { window_ms, .. } => Box::new(RangeFeedbackProcessor::new(*window_ms)),
+        }
+    }
+}
+
 pub struct ChannelFeedbackProcessor {
     channel: ChannelIdentify,
     types: HashMap<u8, Box<dyn SingleFeedbackProcessor>>,
@@ -75,15 +125,11 @@ impl ChannelFeedbackProcessor {
         }
     }
 
-    pub fn on_feedback(&mut self, now_ms: u64, consumer_id: FeedbackConsumerId, fb: Feedback) -> Option<Feedback> {
+    pub fn on_feedback(&mut self, registry: &FeedbackRegistry, now_ms: u64, consumer_id: FeedbackConsumerId, fb: Feedback) -> Option<Feedback> {
         let res = if let Some(processor) = self.types.get_mut(&fb.id) {
             processor.on_feedback(now_ms, consumer_id, fb.feedback_type)
         } else {
-            let mut processor: Box<dyn SingleFeedbackProcessor> = match &fb.feedback_type {
-                FeedbackType::Passthrough(_) => Box::new(passthrough::PassthroughFeedbackProcessor()),
-                FeedbackType::Number { window_ms, info: _ } => Box::new(number::NumberFeedbackProcessor::new(*window_ms)),
-            };
-
+            let mut processor = registry.create(fb.id, &fb.feedback_type);
             let res = processor.on_feedback(now_ms, consumer_id, fb.feedback_type);
             self.types.insert(fb.id, processor);
             res
@@ -96,3 +142,34 @@ impl ChannelFeedbackProcessor {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::ChannelIdentify;
+
+    use super::{ChannelFeedbackProcessor, Feedback, FeedbackConsumerId, FeedbackRegistry, FeedbackType, RateLimitedPassthroughProcessor};
+
+    #[test]
+    fn registry_should_override_builtin_by_id() {
+        let channel = ChannelIdentify::new(1000, 1);
+        let mut registry = FeedbackRegistry::default();
+        registry.register(1, Arc::new(|| Box::new(RateLimitedPassthroughProcessor::new(1000))));
+
+        let mut processor = ChannelFeedbackProcessor::new(channel);
+        let fb = |id: u8| Feedback {
+            channel,
+            id,
+            feedback_type: FeedbackType::Passthrough(vec![1]),
+        };
+
+        assert_eq!(processor.on_feedback(&registry, 0, FeedbackConsumerId::Local(1), fb(1)), Some(fb(1)));
+        assert_eq!(processor.on_feedback(&registry, 100, FeedbackConsumerId::Local(1), fb(1)), None);
+        // id 2 use built-in passthrough
+        assert_eq!(processor.on_feedback(&registry, 0, FeedbackConsumerId::Local(1), fb(2)), Some(fb(2)));
+        assert_eq!(processor.on_feedback(&registry, 100, FeedbackConsumerId::Local(1), fb(2)), Some(fb(2)));
+
+        assert_eq!(processor.on_tick(1000), Some(vec![fb(1)]));
+    }
+}
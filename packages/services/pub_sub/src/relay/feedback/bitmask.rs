@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use super::{FeedbackConsumerId, FeedbackType, SingleFeedbackProcessor};
+
+/// OR of the last bitmask of each consumer, output only when the result changed
+#[derive(Default)]
+pub struct BitmaskFeedbackProcessor {
+    fb_map: HashMap<FeedbackConsumerId, u64>,
+    last_sent: Option<u64>,
+}
+
+impl BitmaskFeedbackProcessor {
+    fn output_if_changed(&mut self) -> Option<FeedbackType> {
+        let mask = self.fb_map.values().fold(0, |mask, value| mask | value);
+        if self.last_sent == Some(mask) {
+            None
+        } else {
+            self.last_sent = Some(mask);
+            Some(FeedbackType::Bitmask(mask))
+        }
+    }
+}
+
+impl SingleFeedbackProcessor for BitmaskFeedbackProcessor {
+    fn on_tick(&mut self, _now_ms: u64) -> Option<FeedbackType> {
+        if self.last_sent.is_some() {
+            self.output_if_changed()
+        } else {
+            None
+        }
+    }
+
+    fn on_remove(&mut self, consumer_id: FeedbackConsumerId) {
+        self.fb_map.remove(&consumer_id);
+    }
+
+    fn on_feedback(&mut self, _now_ms: u64, consumer_id: FeedbackConsumerId, fb: FeedbackType) -> Option<FeedbackType> {
+        match fb {
+            FeedbackType::Bitmask(mask) => {
+                self.fb_map.insert(consumer_id, mask);
+                self.output_if_changed()
+            }
+            _ => {
+                log::warn!("[BitmaskFeedbackProcessor] invalid feedback type {:?}", fb);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_or_all_consumers() {
+        let mut processor = BitmaskFeedbackProcessor::default();
+        assert_eq!(processor.on_tick(0), None);
+        assert_eq!(processor.on_feedback(0, FeedbackConsumerId::Local(1), FeedbackType::Bitmask(0b001)), Some(FeedbackType::Bitmask(0b001)));
+        assert_eq!(processor.on_feedback(0, FeedbackConsumerId::Local(2), FeedbackType::Bitmask(0b100)), Some(FeedbackType::Bitmask(0b101)));
+        assert_eq!(processor.on_feedback(0, FeedbackConsumerId::Local(2), FeedbackType::Bitmask(0b101)), None);
+        assert_eq!(processor.on_feedback(0, FeedbackConsumerId::Local(1), FeedbackType::Passthrough(vec![1])), None);
+
+        processor.on_remove(FeedbackConsumerId::Local(2));
+        assert_eq!(processor.on_tick(0), Some(FeedbackType::Bitmask(0b001)));
+        assert_eq!(processor.on_tick(0), None);
+    }
+}
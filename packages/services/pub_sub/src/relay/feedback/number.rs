@@ -58,15 +58,16 @@ impl SingleFeedbackProcessor for NumberFeedbackProcessor {
 
     fn on_feedback(&mut self, now_ms: u64, consumer_id: FeedbackConsumerId, fb: FeedbackType) -> Option<FeedbackType> {
         match &fb {
-            FeedbackType::Passthrough(_) => {
-                panic!("Should not happend")
-            }
             FeedbackType::Number { window_ms, info } => {
                 self.has_changed = true;
                 self.window_ms = *window_ms;
                 self.fb_map.insert(consumer_id, info.clone());
                 self.sumary_if_need(now_ms)
             }
+            _ => {
+                log::warn!("[NumberFeedbackProcessor] invalid feedback type {:?}", fb);
+                None
+            }
         }
     }
 }
@@ -106,4 +107,11 @@ mod tests {
         processor.on_remove(FeedbackConsumerId::Local(2));
         assert_eq!(processor.on_tick(5000), Some(build_fb(2, 3, 3, 3)));
     }
+
+    #[test]
+    fn invalid_type_should_be_ignored() {
+        let mut processor = NumberFeedbackProcessor::new(1000);
+        assert_eq!(processor.on_feedback(2000, FeedbackConsumerId::Local(1), FeedbackType::Bitmask(1)), None);
+        assert_eq!(processor.on_tick(3000), None);
+    }
 }
@@ -12,7 +12,7 @@ impl SingleFeedbackProcessor for PassthroughFeedbackProcessor {
     fn on_feedback(&mut self, _now_ms: u64, _consumer_id: FeedbackConsumerId, fb: FeedbackType) -> Option<FeedbackType> {
         match &fb {
             FeedbackType::Passthrough(_) => Some(fb),
-            FeedbackType::Number { window_ms: _, info: _ } => {
+            _ => {
                 panic!("Should not happend")
             }
         }
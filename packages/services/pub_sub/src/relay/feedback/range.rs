@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use super::{FeedbackConsumerId, FeedbackType, SingleFeedbackProcessor};
+
+/// Min of all mins and max of all maxs from the last feedback of each consumer, output at most once per window
+pub struct RangeFeedbackProcessor {
+    window_ms: u32,
+    fb_map: HashMap<FeedbackConsumerId, (i64, i64)>,
+    last_fb: u64,
+    has_changed: bool,
+}
+
+impl RangeFeedbackProcessor {
+    pub fn new(window_ms: u32) -> Self {
+        Self {
+            window_ms,
+            fb_map: Default::default(),
+            last_fb: 0,
+            has_changed: false,
+        }
+    }
+
+    fn sumary_if_need(&mut self, now_ms: u64) -> Option<FeedbackType> {
+        if self.last_fb + (self.window_ms as u64) <= now_ms && self.has_changed && !self.fb_map.is_empty() {
+            self.last_fb = now_ms;
+            self.has_changed = false;
+            let min = self.fb_map.values().map(|(min, _)| *min).min().unwrap_or(i64::MAX);
+            let max = self.fb_map.values().map(|(_, max)| *max).max().unwrap_or(i64::MIN);
+            Some(FeedbackType::Range { window_ms: self.window_ms, min, max })
+        } else {
+            None
+        }
+    }
+}
+
+impl SingleFeedbackProcessor for RangeFeedbackProcessor {
+    fn on_tick(&mut self, now_ms: u64) -> Option<FeedbackType> {
+        self.sumary_if_need(now_ms)
+    }
+
+    fn on_remove(&mut self, consumer_id: FeedbackConsumerId) {
+        if self.fb_map.remove(&consumer_id).is_some() {
+            self.has_changed = true;
+        }
+    }
+
+    fn on_feedback(&mut self, now_ms: u64, consumer_id: FeedbackConsumerId, fb: FeedbackType) -> Option<FeedbackType> {
+        match fb {
+            FeedbackType::Range { window_ms, min, max } => {
+                self.has_changed = true;
+                self.window_ms = window_ms;
+                self.fb_map.insert(consumer_id, (min, max));
+                self.sumary_if_need(now_ms)
+            }
+            _ => {
+                log::warn!("[RangeFeedbackProcessor] invalid feedback type {:?}", fb);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_fb(min: i64, max: i64) -> FeedbackType {
+        FeedbackType::Range { window_ms: 1000, min, max }
+    }
+
+    #[test]
+    fn multi() {
+        let mut processor = RangeFeedbackProcessor::new(1000);
+        assert_eq!(processor.on_feedback(2000, FeedbackConsumerId::Local(1), build_fb(100, 500)), Some(build_fb(100, 500)));
+        assert_eq!(processor.on_feedback(2500, FeedbackConsumerId::Local(2), build_fb(200, 800)), None);
+        assert_eq!(processor.on_tick(3000), Some(build_fb(100, 800)));
+        assert_eq!(processor.on_tick(4000), None);
+
+        processor.on_remove(FeedbackConsumerId::Local(1));
+        assert_eq!(processor.on_tick(5000), Some(build_fb(200, 800)));
+        processor.on_remove(FeedbackConsumerId::Local(2));
+        assert_eq!(processor.on_tick(6000), None);
+    }
+}
@@ -0,0 +1,70 @@
+use super::{FeedbackConsumerId, FeedbackType, SingleFeedbackProcessor};
+
+/// Passthrough at most one feedback per interval, for example keyframe requests.
+/// Feedbacks inside the interval are merged into one which is sent when the interval ends
+pub struct RateLimitedPassthroughProcessor {
+    interval_ms: u64,
+    last_sent_ms: Option<u64>,
+    pending: Option<FeedbackType>,
+}
+
+impl RateLimitedPassthroughProcessor {
+    pub fn new(interval_ms: u64) -> Self {
+        Self {
+            interval_ms,
+            last_sent_ms: None,
+            pending: None,
+        }
+    }
+
+    fn can_send(&self, now_ms: u64) -> bool {
+        match self.last_sent_ms {
+            Some(last) => now_ms >= last + self.interval_ms,
+            None => true,
+        }
+    }
+}
+
+impl SingleFeedbackProcessor for RateLimitedPassthroughProcessor {
+    fn on_tick(&mut self, now_ms: u64) -> Option<FeedbackType> {
+        if self.pending.is_some() && self.can_send(now_ms) {
+            self.last_sent_ms = Some(now_ms);
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+
+    fn on_remove(&mut self, _consumer_id: FeedbackConsumerId) {}
+
+    fn on_feedback(&mut self, now_ms: u64, _consumer_id: FeedbackConsumerId, fb: FeedbackType) -> Option<FeedbackType> {
+        if self.can_send(now_ms) {
+            self.last_sent_ms = Some(now_ms);
+            self.pending = None;
+            Some(fb)
+        } else {
+            self.pending = Some(fb);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_limit_rate() {
+        let mut processor = RateLimitedPassthroughProcessor::new(1000);
+        let fb = FeedbackType::Passthrough(vec![1]);
+        assert_eq!(processor.on_feedback(0, FeedbackConsumerId::Local(1), fb.clone()), Some(fb.clone()));
+        assert_eq!(processor.on_feedback(100, FeedbackConsumerId::Local(2), fb.clone()), None);
+        assert_eq!(processor.on_feedback(200, FeedbackConsumerId::Local(1), fb.clone()), None);
+        assert_eq!(processor.on_tick(500), None);
+        assert_eq!(processor.on_tick(1000), Some(fb.clone()));
+        assert_eq!(processor.on_tick(1500), None);
+        assert_eq!(processor.on_feedback(1500, FeedbackConsumerId::Local(1), fb.clone()), None);
+        assert_eq!(processor.on_feedback(2000, FeedbackConsumerId::Local(1), fb.clone()), Some(fb));
+        assert_eq!(processor.on_tick(3000), None);
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use atm0s_sdn_network::msg::{MsgHeader, TransportMsg};
+use atm0s_sdn_router::RouteRule;
+use bytes::Bytes;
+
+use crate::{handler::CONTROL_META_TYPE, msg::PubsubRemoteEvent, PUBSUB_FRAGMENT_MAX_PART_LEN, PUBSUB_SERVICE_ID};
+
+use super::ChannelIdentify;
+
+const MAX_PART_COUNT: usize = u8::MAX as usize + 1;
+
+/// Max size of a msg which can be sent with fragmentation
+pub const MAX_FRAGMENTED_MSG_LEN: usize = MAX_PART_COUNT * PUBSUB_FRAGMENT_MAX_PART_LEN;
+
+/// Build fragment id like rpc_reliable stream_id: msg_id (16bit), part_index (8bit), part_count_minus_1 (8bit)
+pub fn build_fragment_id(msg_id: u16, part_index: u8, part_count_minus_1: u8) -> u32 {
+    ((msg_id as u32) << 16) | ((part_index as u32) << 8) | (part_count_minus_1 as u32)
+}
+
+/// Parse fragment id into msg_id, part_index, part_count_minus_1
+pub fn parse_fragment_id(fragment_id: u32) -> (u16, u8, u8) {
+    ((fragment_id >> 16) as u16, (fragment_id >> 8) as u8, fragment_id as u8)
+}
+
+fn build_header(channel: ChannelIdentify) -> MsgHeader {
+    MsgHeader::build(PUBSUB_SERVICE_ID, PUBSUB_SERVICE_ID, RouteRule::Direct)
+        .set_from_node(Some(channel.source()))
+        .set_stream_id(channel.uuid())
+}
+
+/// Build msgs for sending data to remote nodes.
+/// Data larger than PUBSUB_FRAGMENT_MAX_PART_LEN is split into Fragment control msgs which share a msg_id, return empty if data is too large.
+pub fn build_data_msgs(channel: ChannelIdentify, data: &[u8], next_msg_id: impl FnOnce() -> u16) -> Vec<TransportMsg> {
+    if data.len() <= PUBSUB_FRAGMENT_MAX_PART_LEN {
+        return vec![TransportMsg::build_raw(build_header(channel), data)];
+    }
+    if data.len() > MAX_FRAGMENTED_MSG_LEN {
+        log::warn!("[PubsubFragment] msg of channel {} too large {} > {} => drop", channel, data.len(), MAX_FRAGMENTED_MSG_LEN);
+        return vec![];
+    }
+
+    let msg_id = next_msg_id();
+    let part_count_minus_1 = ((data.len() - 1) / PUBSUB_FRAGMENT_MAX_PART_LEN) as u8;
+    data.chunks(PUBSUB_FRAGMENT_MAX_PART_LEN)
+        .enumerate()
+        .map(|(index, part)| {
+            let fragment_id = build_fragment_id(msg_id, index as u8, part_count_minus_1);
+            let event = PubsubRemoteEvent::Fragment(channel, fragment_id, part.to_vec());
+            TransportMsg::from_payload_bincode(build_header(channel).set_meta(CONTROL_META_TYPE), &event)
+        })
+        .collect()
+}
+
+struct FragmentSlot {
+    started_at: u64,
+    part_received: usize,
+    size: usize,
+    parts: Vec<Option<Bytes>>,
+}
+
+impl FragmentSlot {
+    fn new(now_ms: u64, part_count: usize) -> Self {
+        Self {
+            started_at: now_ms,
+            part_received: 0,
+            size: 0,
+            parts: vec![None; part_count],
+        }
+    }
+
+    fn is_finish(&self) -> bool {
+        self.part_received == self.parts.len()
+    }
+
+    fn finalize(self) -> Bytes {
+        let mut data = Vec::with_capacity(self.size);
+        for part in self.parts.into_iter().flatten() {
+            data.extend_from_slice(&part);
+        }
+        Bytes::from(data)
+    }
+}
+
+/// Reassemble fragments of large msgs before delivering them to local subscribers.
+/// Incomplete msgs are dropped after timeout, and the oldest ones are evicted when pending bytes reach the memory limit.
+pub struct FragmentAssembler {
+    timeout_ms: u64,
+    max_bytes: usize,
+    pending_bytes: usize,
+    slots: HashMap<(ChannelIdentify, u16), FragmentSlot>,
+}
+
+impl FragmentAssembler {
+    pub fn new(timeout_ms: u64, max_bytes: usize) -> Self {
+        Self {
+            timeout_ms,
+            max_bytes,
+            pending_bytes: 0,
+            slots: HashMap::new(),
+        }
+    }
+
+    /// Bytes of received fragments which are waiting for remain parts
+    #[allow(unused)]
+    pub fn pending_bytes(&self) -> usize {
+        self.pending_bytes
+    }
+
+    /// Add a fragment, return the full msg when all parts are received
+    pub fn on_fragment(&mut self, now_ms: u64, channel: ChannelIdentify, fragment_id: u32, data: &[u8]) -> Option<Bytes> {
+        let (msg_id, part_index, part_count_minus_1) = parse_fragment_id(fragment_id);
+        if part_index > part_count_minus_1 {
+            log::warn!("[FragmentAssembler] invalid part {} of msg {} channel {}", part_index, msg_id, channel);
+            return None;
+        }
+        if part_count_minus_1 == 0 {
+            return Some(Bytes::from(data.to_vec()));
+        }
+        if data.len() > self.max_bytes {
+            log::warn!("[FragmentAssembler] fragment of channel {} larger than memory limit => drop", channel);
+            return None;
+        }
+
+        let key = (channel, msg_id);
+        let part_count = part_count_minus_1 as usize + 1;
+        if let Some(slot) = self.slots.get(&key) {
+            if slot.parts.len() != part_count {
+                log::debug!("[FragmentAssembler] msg {} of channel {} reused with other part count => drop old parts", msg_id, channel);
+                self.remove_slot(&key);
+            } else if slot.parts[part_index as usize].is_some() {
+                log::trace!("[FragmentAssembler] duplicated part {} of msg {} channel {}", part_index, msg_id, channel);
+                return None;
+            }
+        }
+
+        while self.pending_bytes + data.len() > self.max_bytes {
+            self.evict_oldest();
+        }
+
+        let slot = self.slots.entry(key).or_insert_with(|| FragmentSlot::new(now_ms, part_count));
+        slot.parts[part_index as usize] = Some(Bytes::from(data.to_vec()));
+        slot.part_received += 1;
+        slot.size += data.len();
+        self.pending_bytes += data.len();
+
+        if slot.is_finish() {
+            let slot = self.remove_slot(&key)?;
+            Some(slot.finalize())
+        } else {
+            None
+        }
+    }
+
+    /// Drop incomplete msgs which are timeout
+    pub fn on_tick(&mut self, now_ms: u64) {
+        let timeout_ms = self.timeout_ms;
+        let mut expired_bytes = 0;
+        self.slots.retain(|(channel, msg_id), slot| {
+            if slot.started_at + timeout_ms <= now_ms {
+                log::debug!(
+                    "[FragmentAssembler] msg {} of channel {} timeout with {}/{} parts",
+                    msg_id,
+                    channel,
+                    slot.part_received,
+                    slot.parts.len()
+                );
+                expired_bytes += slot.size;
+                false
+            } else {
+                true
+            }
+        });
+        self.pending_bytes -= expired_bytes;
+    }
+
+    fn remove_slot(&mut self, key: &(ChannelIdentify, u16)) -> Option<FragmentSlot> {
+        let slot = self.slots.remove(key)?;
+        self.pending_bytes -= slot.size;
+        Some(slot)
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(key) = self.slots.iter().min_by_key(|(_, slot)| slot.started_at).map(|(key, _)| *key) {
+            log::warn!("[FragmentAssembler] memory limit reached => evict msg {} of channel {}", key.1, key.0);
+            self.remove_slot(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atm0s_sdn_network::msg::TransportMsg;
+
+    use crate::{handler::CONTROL_META_TYPE, msg::PubsubRemoteEvent, relay::ChannelIdentify, PUBSUB_FRAGMENT_MAX_PART_LEN};
+
+    use super::{build_data_msgs, parse_fragment_id, FragmentAssembler, MAX_FRAGMENTED_MSG_LEN};
+
+    fn build_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    fn fragment_of(msg: &TransportMsg) -> (u32, Vec<u8>) {
+        match msg.get_payload_bincode::<PubsubRemoteEvent>() {
+            Ok(PubsubRemoteEvent::Fragment(_, fragment_id, data)) => (fragment_id, data),
+            _ => panic!("Should be fragment"),
+        }
+    }
+
+    #[test]
+    fn small_msg_not_fragmented() {
+        let channel = ChannelIdentify::new(1, 2);
+        let msgs = build_data_msgs(channel, &[1, 2, 3], || panic!("Should not allocate msg_id"));
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].header.meta, 0);
+        assert_eq!(msgs[0].payload(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn split_and_reassemble() {
+        let channel = ChannelIdentify::new(1, 2);
+        let data = build_data(PUBSUB_FRAGMENT_MAX_PART_LEN * 2 + 10);
+        let msgs = build_data_msgs(channel, &data, || 1000);
+        assert_eq!(msgs.len(), 3);
+        let mut fragments = vec![];
+        for (index, msg) in msgs.iter().enumerate() {
+            assert_eq!(msg.header.meta, CONTROL_META_TYPE);
+            assert_eq!(msg.header.stream_id, 1);
+            assert_eq!(msg.header.from_node, Some(2));
+            let (fragment_id, part) = fragment_of(msg);
+            assert_eq!(parse_fragment_id(fragment_id), (1000, index as u8, 2));
+            fragments.push((fragment_id, part));
+        }
+
+        let mut assembler = FragmentAssembler::new(1000, 1 << 20);
+        // out of order and duplicated parts
+        assert_eq!(assembler.on_fragment(0, channel, fragments[2].0, &fragments[2].1), None);
+        assert_eq!(assembler.on_fragment(0, channel, fragments[0].0, &fragments[0].1), None);
+        assert_eq!(assembler.on_fragment(0, channel, fragments[0].0, &fragments[0].1), None);
+        assert_eq!(assembler.pending_bytes(), PUBSUB_FRAGMENT_MAX_PART_LEN + 10);
+        assert_eq!(assembler.on_fragment(0, channel, fragments[1].0, &fragments[1].1).map(|d| d.to_vec()), Some(data));
+        assert_eq!(assembler.pending_bytes(), 0);
+    }
+
+    #[test]
+    fn fragment_fit_mtu() {
+        let channel = ChannelIdentify::new(1, 2);
+        let msgs = build_data_msgs(channel, &build_data(PUBSUB_FRAGMENT_MAX_PART_LEN * 3), || 1);
+        for msg in msgs {
+            assert!(msg.take().len() < 1500);
+        }
+    }
+
+    #[test]
+    fn too_large_msg() {
+        let channel = ChannelIdentify::new(1, 2);
+        assert_eq!(build_data_msgs(channel, &build_data(MAX_FRAGMENTED_MSG_LEN), || 1).len(), 256);
+        assert_eq!(build_data_msgs(channel, &build_data(MAX_FRAGMENTED_MSG_LEN + 1), || 1).len(), 0);
+    }
+
+    #[test]
+    fn invalid_part_index() {
+        let channel = ChannelIdentify::new(1, 2);
+        let mut assembler = FragmentAssembler::new(1000, 1 << 20);
+        assert_eq!(assembler.on_fragment(0, channel, super::build_fragment_id(1, 3, 2), &[1, 2, 3]), None);
+        assert_eq!(assembler.pending_bytes(), 0);
+    }
+
+    #[test]
+    fn timeout_incomplete_msg() {
+        let channel = ChannelIdentify::new(1, 2);
+        let msgs = build_data_msgs(channel, &build_data(PUBSUB_FRAGMENT_MAX_PART_LEN + 1), || 1);
+        let (id0, part0) = fragment_of(&msgs[0]);
+        let (id1, part1) = fragment_of(&msgs[1]);
+        let mut assembler = FragmentAssembler::new(1000, 1 << 20);
+        assert_eq!(assembler.on_fragment(0, channel, id0, &part0), None);
+        assembler.on_tick(500);
+        assert_eq!(assembler.pending_bytes(), PUBSUB_FRAGMENT_MAX_PART_LEN);
+        assembler.on_tick(1000);
+        assert_eq!(assembler.pending_bytes(), 0);
+        assert_eq!(assembler.on_fragment(1000, channel, id1, &part1), None);
+    }
+
+    #[test]
+    fn memory_limit_evict_oldest() {
+        let channel = ChannelIdentify::new(1, 2);
+        let data = build_data(PUBSUB_FRAGMENT_MAX_PART_LEN * 2);
+        let msgs1: Vec<_> = build_data_msgs(channel, &data, || 1).iter().map(fragment_of).collect();
+        let msgs2: Vec<_> = build_data_msgs(channel, &data, || 2).iter().map(fragment_of).collect();
+        let mut assembler = FragmentAssembler::new(1000, PUBSUB_FRAGMENT_MAX_PART_LEN * 2);
+        assert_eq!(assembler.on_fragment(0, channel, msgs1[0].0, &msgs1[0].1), None);
+        assert_eq!(assembler.on_fragment(100, channel, msgs2[0].0, &msgs2[0].1), None);
+        assert_eq!(assembler.pending_bytes(), PUBSUB_FRAGMENT_MAX_PART_LEN * 2);
+
+        // msg 1 is evicted for the last part of msg 2
+        assert_eq!(assembler.on_fragment(200, channel, msgs2[1].0, &msgs2[1].1).map(|d| d.to_vec()), Some(data));
+        assert_eq!(assembler.pending_bytes(), 0);
+        assert_eq!(assembler.on_fragment(200, channel, msgs1[1].0, &msgs1[1].1), None);
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+
+/// How many msgs of a channel are kept for replaying to late subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryConfig {
+    /// Maximum number of msgs
+    pub max_msgs: usize,
+    /// Msgs older than this are dropped, None for keeping until replaced by newer msgs
+    pub max_age_ms: Option<u64>,
+}
+
+/// Ring buffer of last msgs of a channel, with the sequence number if the msg is reliable
+pub struct ChannelHistory {
+    config: HistoryConfig,
+    msgs: VecDeque<(u64, Option<u64>, Bytes)>,
+}
+
+impl ChannelHistory {
+    pub fn new(config: HistoryConfig) -> Self {
+        Self { config, msgs: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, now_ms: u64, seq: Option<u64>, data: Bytes) {
+        if self.config.max_msgs == 0 {
+            return;
+        }
+        while self.msgs.len() >= self.config.max_msgs {
+            self.msgs.pop_front();
+        }
+        self.msgs.push_back((now_ms, seq, data));
+    }
+
+    /// Drop expired msgs
+    pub fn on_tick(&mut self, now_ms: u64) {
+        if let Some(max_age_ms) = self.config.max_age_ms {
+            while let Some((ts, _, _)) = self.msgs.front() {
+                if now_ms < ts + max_age_ms {
+                    break;
+                }
+                self.msgs.pop_front();
+            }
+        }
+    }
+
+    /// All stored msgs, oldest first, expired msgs are dropped in `on_tick`
+    pub fn snapshot(&self) -> Vec<(Option<u64>, Bytes)> {
+        self.msgs.iter().map(|(_, seq, data)| (*seq, data.clone())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{ChannelHistory, HistoryConfig};
+
+    #[test]
+    fn should_keep_last_msgs() {
+        let mut history = ChannelHistory::new(HistoryConfig { max_msgs: 2, max_age_ms: None });
+        history.push(0, None, Bytes::from("1"));
+        history.push(0, Some(2), Bytes::from("2"));
+        history.push(0, Some(3), Bytes::from("3"));
+        assert_eq!(history.snapshot(), vec![(Some(2), Bytes::from("2")), (Some(3), Bytes::from("3"))]);
+    }
+
+    #[test]
+    fn should_drop_expired_msgs() {
+        let mut history = ChannelHistory::new(HistoryConfig { max_msgs: 10, max_age_ms: Some(100) });
+        history.push(0, None, Bytes::from("1"));
+        history.push(50, None, Bytes::from("2"));
+        history.on_tick(99);
+        assert_eq!(history.snapshot().len(), 2);
+        history.on_tick(100);
+        assert_eq!(history.snapshot(), vec![(None, Bytes::from("2"))]);
+        history.on_tick(150);
+        assert_eq!(history.snapshot(), vec![]);
+    }
+
+    #[test]
+    fn zero_size_should_keep_nothing() {
+        let mut history = ChannelHistory::new(HistoryConfig { max_msgs: 0, max_age_ms: None });
+        history.push(0, None, Bytes::from("1"));
+        assert_eq!(history.snapshot(), vec![]);
+    }
+}
@@ -1,24 +1,85 @@
 use std::{
     collections::{hash_map::Entry, HashMap, VecDeque},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
-use async_std::channel::Sender;
+use async_std::channel::{Receiver, Sender, TrySendError};
 use atm0s_sdn_identity::NodeId;
 use atm0s_sdn_utils::{awaker::Awaker, error_handle::ErrorUtils};
 use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+
+use crate::{sdk::source_selection::SourceSelector, PUBSUB_REPLAY_MAX_PENDING_MSGS};
+
+use super::{feedback::Feedback, presence::PresenceEvent, ChannelIdentify, ChannelUuid, LocalSubId};
+
+type SequencedMsg = (LocalSubId, NodeId, ChannelUuid, Option<u64>, Bytes);
+type ReplayKey = (LocalSubId, NodeId, ChannelUuid);
+type ReplayBuffer = VecDeque<(Option<u64>, Bytes)>;
+type PresenceQueue = (Sender<PresenceEvent>, Receiver<PresenceEvent>);
+
+/// What to do when the queue of a local consumer is full
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the incoming msg
+    #[default]
+    DropNewest,
+    /// Drop the oldest queued msg to make room for the incoming msg
+    DropOldest,
+    /// Close the queue, the consumer will receive None after draining queued msgs
+    Disconnect,
+    /// Wait until the consumer has room, up to timeout_ms, then drop the incoming msg.
+    /// Only `send_async` of local publishers waits, other msgs are dropped immediately for not stalling the network or sync callers
+    Block { timeout_ms: u64 },
+}
 
-use super::{feedback::Feedback, ChannelUuid, LocalSubId};
+/// Msg for a consumer with OverflowPolicy::Block which has full queue, it is sent by `send_blocked` after relay locks are released
+pub struct BlockedMsg {
+    sender: Sender<SequencedMsg>,
+    msg: SequencedMsg,
+    timeout: Duration,
+}
+
+/// Wait until blocked consumers have room, each msg is dropped after the timeout of its consumer since this call
+pub async fn send_blocked(local: &RwLock<LocalRelay>, blocked: Vec<BlockedMsg>) {
+    let started_at = Instant::now();
+    for BlockedMsg { sender, msg, timeout } in blocked {
+        let (uuid, source, channel) = (msg.0, msg.1, msg.2);
+        let remain = (started_at + timeout).saturating_duration_since(Instant::now());
+        if async_std::future::timeout(remain, sender.send(msg)).await.is_err() {
+            log::debug!("[LocalRelay] local {} queue still full after timeout, drop msg of channel {} from {}", uuid, channel, source);
+            local.read().add_overflow(uuid, ChannelIdentify::new(channel, source));
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum LocalRelayAction {
     Publish(ChannelUuid),
     Unpublish(ChannelUuid),
+    Advertise(ChannelUuid, String),
+    Unadvertise(ChannelUuid, String),
+}
+
+/// Local consumer queue, sequenced queue also receive the sequence number of reliable msgs
+enum LocalConsumer {
+    Raw(Sender<(LocalSubId, NodeId, ChannelUuid, Bytes)>),
+    Sequenced(Sender<SequencedMsg>, Receiver<SequencedMsg>, OverflowPolicy),
 }
 
 pub struct LocalRelay {
-    consumers: HashMap<u64, Sender<(LocalSubId, NodeId, ChannelUuid, Bytes)>>,
+    consumers: HashMap<u64, LocalConsumer>,
     producer_fbs: HashMap<ChannelUuid, HashMap<u64, Sender<Feedback>>>,
+    producer_seqs: HashMap<ChannelUuid, u64>,
+    producer_fragment_ids: HashMap<ChannelUuid, u16>,
+    producer_names: HashMap<ChannelUuid, String>,
+    producer_presences: HashMap<ChannelUuid, HashMap<u64, PresenceQueue>>,
+    /// Live msgs which arrive while the consumer is waiting history
+    pending_replays: Mutex<HashMap<ReplayKey, ReplayBuffer>>,
+    overflows: Mutex<HashMap<(LocalSubId, ChannelIdentify), u64>>,
+    /// Consumers which only receive msgs from selected sources
+    selectors: Mutex<HashMap<LocalSubId, SourceSelector>>,
     actions: VecDeque<LocalRelayAction>,
     awaker: Arc<dyn Awaker>,
 }
@@ -28,6 +89,13 @@ impl LocalRelay {
         Self {
             consumers: HashMap::new(),
             producer_fbs: HashMap::new(),
+            producer_seqs: HashMap::new(),
+            producer_fragment_ids: HashMap::new(),
+            producer_names: HashMap::new(),
+            producer_presences: HashMap::new(),
+            pending_replays: Mutex::new(HashMap::new()),
+            overflows: Mutex::new(HashMap::new()),
+            selectors: Mutex::new(HashMap::new()),
             actions: VecDeque::new(),
             awaker: Arc::new(atm0s_sdn_utils::awaker::MockAwaker::default()),
         }
@@ -38,11 +106,58 @@ impl LocalRelay {
     }
 
     pub fn on_local_sub(&mut self, uuid: LocalSubId, sender: Sender<(LocalSubId, NodeId, ChannelUuid, Bytes)>) {
-        self.consumers.insert(uuid, sender);
+        self.consumers.insert(uuid, LocalConsumer::Raw(sender));
+    }
+
+    /// The receiver is used for dropping oldest msgs with OverflowPolicy::DropOldest
+    pub fn on_local_sub_sequenced(&mut self, uuid: LocalSubId, sender: Sender<SequencedMsg>, receiver: Receiver<SequencedMsg>, policy: OverflowPolicy) {
+        self.consumers.insert(uuid, LocalConsumer::Sequenced(sender, receiver, policy));
+    }
+
+    /// Filter msgs of the consumer by source before they are queued, None for receiving all sources
+    pub(crate) fn set_source_selector(&mut self, uuid: LocalSubId, selector: Option<SourceSelector>) {
+        match selector {
+            Some(selector) => self.selectors.get_mut().insert(uuid, selector),
+            None => self.selectors.get_mut().remove(&uuid),
+        };
     }
 
     pub fn on_local_unsub(&mut self, uuid: LocalSubId) {
         self.consumers.remove(&uuid);
+        self.selectors.get_mut().remove(&uuid);
+        self.pending_replays.get_mut().retain(|(sub, _, _), _| *sub != uuid);
+        self.overflows.get_mut().retain(|(sub, _), _| *sub != uuid);
+    }
+
+    /// Live data from source is hold back from the consumer until history is replayed
+    pub fn on_replay_start(&mut self, uuid: LocalSubId, source: NodeId, channel: ChannelUuid) {
+        self.pending_replays.get_mut().entry((uuid, source, channel)).or_default();
+    }
+
+    /// Deliver history then the live msgs which are hold back, live msgs which are already included in the history are dropped:
+    /// reliable msgs by sequence number, other msgs by content
+    pub fn replay(&mut self, uuid: LocalSubId, source: NodeId, channel: ChannelUuid, msgs: Vec<(Option<u64>, Bytes)>) {
+        let pending = self.pending_replays.get_mut().remove(&(uuid, source, channel)).unwrap_or_default();
+        let last_seq = msgs.iter().filter_map(|(seq, _)| *seq).max();
+        let live = pending
+            .into_iter()
+            .filter(|(seq, data)| match (seq, last_seq) {
+                (Some(seq), Some(last_seq)) => *seq > last_seq,
+                (Some(_), None) => true,
+                (None, _) => !msgs.iter().any(|(_, history)| history == data),
+            })
+            .collect::<Vec<_>>();
+        log::debug!(
+            "[LocalRelay] replay {} msgs and {} live msgs of channel {} from {} to local {}",
+            msgs.len(),
+            live.len(),
+            channel,
+            source,
+            uuid
+        );
+        for (seq, data) in msgs.into_iter().chain(live) {
+            self.relay(source, channel, &[uuid], seq, data);
+        }
     }
 
     pub fn on_local_pub(&mut self, channel: ChannelUuid, local_uuid: u64, fb_sender: Sender<Feedback>) {
@@ -58,12 +173,38 @@ impl LocalRelay {
         }
     }
 
+    /// Advertise name of a local published channel in directory, for subscribers with patterns.
+    /// The name is removed after the last publisher of the channel is gone
+    pub fn on_local_advertise(&mut self, channel: ChannelUuid, name: &str) {
+        if self.producer_fbs.contains_key(&channel) && !self.producer_names.contains_key(&channel) {
+            self.producer_names.insert(channel, name.to_string());
+            self.actions.push_back(LocalRelayAction::Advertise(channel, name.to_string()));
+            self.awaker.notify();
+        }
+    }
+
+    /// Publisher which want to receive presence changes of the channel, the oldest event is dropped when the queue is full
+    pub fn on_local_pub_presence(&mut self, channel: ChannelUuid, local_uuid: u64, sender: Sender<PresenceEvent>, receiver: Receiver<PresenceEvent>) {
+        self.producer_presences.entry(channel).or_default().insert(local_uuid, (sender, receiver));
+    }
+
     pub fn on_local_unpub(&mut self, channel: ChannelUuid, local_uuid: u64) {
+        if let Some(entry) = self.producer_presences.get_mut(&channel) {
+            entry.remove(&local_uuid);
+            if entry.is_empty() {
+                self.producer_presences.remove(&channel);
+            }
+        }
         if let Some(entry) = self.producer_fbs.get_mut(&channel) {
             entry.remove(&local_uuid);
             if entry.is_empty() {
                 self.producer_fbs.remove(&channel);
+                self.producer_seqs.remove(&channel);
+                self.producer_fragment_ids.remove(&channel);
                 self.actions.push_back(LocalRelayAction::Unpublish(channel));
+                if let Some(name) = self.producer_names.remove(&channel) {
+                    self.actions.push_back(LocalRelayAction::Unadvertise(channel, name));
+                }
                 self.awaker.notify();
             }
         }
@@ -77,15 +218,124 @@ impl LocalRelay {
         }
     }
 
-    pub fn relay(&self, source: NodeId, channel: ChannelUuid, locals: &[LocalSubId], data: Bytes) {
+    pub fn presence(&self, uuid: ChannelUuid, event: PresenceEvent) {
+        if let Some(senders) = self.producer_presences.get(&uuid) {
+            for (sender, receiver) in senders.values() {
+                if let Err(TrySendError::Full(event)) = sender.try_send(event.clone()) {
+                    receiver.try_recv().ok();
+                    sender.try_send(event).print_error("Should send presence");
+                }
+            }
+        }
+    }
+
+    /// Next sequence number for reliable msgs of a local published channel, shared by all local publishers
+    pub fn next_seq(&mut self, channel: ChannelUuid) -> u64 {
+        let seq = self.producer_seqs.entry(channel).or_insert(0);
+        let current = *seq;
+        *seq += 1;
+        current
+    }
+
+    /// Next msg id for fragmented msgs of a local published channel, shared by all local publishers
+    pub fn next_fragment_id(&mut self, channel: ChannelUuid) -> u16 {
+        let id = self.producer_fragment_ids.entry(channel).or_insert(0);
+        let current = *id;
+        *id = id.wrapping_add(1);
+        current
+    }
+
+    pub fn relay(&self, source: NodeId, channel: ChannelUuid, locals: &[LocalSubId], seq: Option<u64>, data: Bytes) {
+        self.relay_inner(source, channel, locals, seq, data, false);
+    }
+
+    /// Same as `relay` but msgs for consumers with OverflowPolicy::Block and full queue are returned for `send_blocked` instead of being dropped
+    pub fn relay_blocking(&self, source: NodeId, channel: ChannelUuid, locals: &[LocalSubId], seq: Option<u64>, data: Bytes) -> Vec<BlockedMsg> {
+        self.relay_inner(source, channel, locals, seq, data, true)
+    }
+
+    fn add_overflow(&self, uuid: LocalSubId, channel: ChannelIdentify) {
+        *self.overflows.lock().entry((uuid, channel)).or_insert(0) += 1;
+    }
+
+    /// Number of dropped msgs of each local consumer and channel since the last call
+    pub fn pop_overflows(&mut self) -> Vec<(LocalSubId, ChannelIdentify, u64)> {
+        self.overflows.get_mut().drain().map(|((uuid, channel), dropped)| (uuid, channel, dropped)).collect()
+    }
+
+    fn relay_inner(&self, source: NodeId, channel: ChannelUuid, locals: &[LocalSubId], seq: Option<u64>, data: Bytes, can_block: bool) -> Vec<BlockedMsg> {
+        let mut blocked = vec![];
         for uuid in locals {
-            if let Some(sender) = self.consumers.get(uuid) {
+            if let Some(selector) = self.selectors.lock().get_mut(uuid) {
+                if !selector.accept_now(source) {
+                    log::trace!("[LocalRelay] local {} not selected source {} of channel {} => skip", uuid, source, channel);
+                    continue;
+                }
+            }
+            if let Some(pending) = self.pending_replays.lock().get_mut(&(*uuid, source, channel)) {
+                log::trace!("[LocalRelay] local {} waiting history of channel {} from {} => hold back", uuid, channel, source);
+                if pending.len() >= PUBSUB_REPLAY_MAX_PENDING_MSGS {
+                    pending.pop_front();
+                    self.add_overflow(*uuid, ChannelIdentify::new(channel, source));
+                }
+                pending.push_back((seq, data.clone()));
+                continue;
+            }
+            if let Some(consumer) = self.consumers.get(uuid) {
                 log::trace!("[LocalRelay] relay to local {}", uuid);
-                sender.try_send((*uuid, source, channel, data.clone())).print_error("Should send data");
+                let delivered = match consumer {
+                    LocalConsumer::Raw(sender) => match sender.try_send((*uuid, source, channel, data.clone())) {
+                        Ok(_) => true,
+                        Err(TrySendError::Full(_)) => false,
+                        Err(TrySendError::Closed(_)) => true,
+                    },
+                    LocalConsumer::Sequenced(sender, receiver, policy) => match Self::send_sequenced(sender, receiver, *policy, (*uuid, source, channel, seq, data.clone()), can_block) {
+                        Ok(delivered) => delivered,
+                        Err(msg) => {
+                            blocked.push(msg);
+                            true
+                        }
+                    },
+                };
+                if !delivered {
+                    log::debug!("[LocalRelay] local {} queue full, drop msg of channel {} from {}", uuid, channel, source);
+                    self.add_overflow(*uuid, ChannelIdentify::new(channel, source));
+                }
             } else {
                 log::warn!("[LocalRelay] relay channel {} from {} to local {} consumer not found", channel, source, uuid);
             }
         }
+        blocked
+    }
+
+    /// Return false if a msg is dropped because of full queue, or the msg for waiting if the consumer can block the caller
+    fn send_sequenced(sender: &Sender<SequencedMsg>, receiver: &Receiver<SequencedMsg>, policy: OverflowPolicy, msg: SequencedMsg, can_block: bool) -> Result<bool, BlockedMsg> {
+        let msg = match sender.try_send(msg) {
+            Ok(_) => return Ok(true),
+            Err(TrySendError::Closed(_)) => return Ok(true),
+            Err(TrySendError::Full(msg)) => msg,
+        };
+
+        match policy {
+            OverflowPolicy::DropNewest => {}
+            OverflowPolicy::DropOldest => {
+                receiver.try_recv().ok();
+                sender.try_send(msg).print_error("Should send data");
+            }
+            OverflowPolicy::Disconnect => {
+                log::warn!("[LocalRelay] local {} is too slow => disconnect", msg.0);
+                sender.close();
+            }
+            OverflowPolicy::Block { timeout_ms } if can_block => {
+                return Err(BlockedMsg {
+                    sender: sender.clone(),
+                    msg,
+                    timeout: Duration::from_millis(timeout_ms),
+                });
+            }
+            OverflowPolicy::Block { .. } => {}
+        }
+        Ok(false)
     }
 
     pub fn pop_action(&mut self) -> Option<LocalRelayAction> {
@@ -99,9 +349,14 @@ mod tests {
 
     use atm0s_sdn_utils::awaker::Awaker;
     use bytes::Bytes;
+    use parking_lot::RwLock;
 
     use crate::{
-        relay::{feedback::FeedbackType, local::LocalRelayAction},
+        relay::{
+            feedback::FeedbackType,
+            local::{send_blocked, LocalRelayAction, OverflowPolicy},
+            presence::{ChannelPresence, PresenceEvent},
+        },
         ChannelIdentify,
     };
 
@@ -131,6 +386,30 @@ mod tests {
         assert_eq!(relay.pop_action(), Some(LocalRelayAction::Unpublish(1)));
     }
 
+    #[test]
+    fn named_pub_should_advertise_once() {
+        let mut relay = super::LocalRelay::new();
+
+        let (tx, _rx) = async_std::channel::bounded(1);
+        relay.on_local_advertise(1, "room/1");
+        assert_eq!(relay.pop_action(), None);
+
+        relay.on_local_pub(1, 10, tx.clone());
+        relay.on_local_advertise(1, "room/1");
+        relay.on_local_pub(1, 11, tx);
+        relay.on_local_advertise(1, "room/1");
+        assert_eq!(relay.pop_action(), Some(LocalRelayAction::Publish(1)));
+        assert_eq!(relay.pop_action(), Some(LocalRelayAction::Advertise(1, "room/1".to_string())));
+        assert_eq!(relay.pop_action(), None);
+
+        relay.on_local_unpub(1, 10);
+        assert_eq!(relay.pop_action(), None);
+        relay.on_local_unpub(1, 11);
+        assert_eq!(relay.pop_action(), Some(LocalRelayAction::Unpublish(1)));
+        assert_eq!(relay.pop_action(), Some(LocalRelayAction::Unadvertise(1, "room/1".to_string())));
+        assert_eq!(relay.pop_action(), None);
+    }
+
     #[test]
     fn should_relay_to_all_consumers() {
         let mut relay = super::LocalRelay::new();
@@ -142,18 +421,151 @@ mod tests {
         relay.on_local_sub(11, tx2);
 
         let data1 = Bytes::from("hello1");
-        relay.relay(1, 1000, &[10, 11], data1.clone());
+        relay.relay(1, 1000, &[10, 11], None, data1.clone());
         assert_eq!(rx1.try_recv(), Ok((10, 1, 1000, data1.clone())));
         assert_eq!(rx2.try_recv(), Ok((11, 1, 1000, data1.clone())));
 
         let data2 = Bytes::from("hello2");
         let data3 = Bytes::from("hello2");
-        relay.relay(1, 1000, &[10], data2.clone());
-        relay.relay(1, 1000, &[11], data3.clone());
+        relay.relay(1, 1000, &[10], None, data2.clone());
+        relay.relay(1, 1000, &[11], None, data3.clone());
         assert_eq!(rx1.try_recv(), Ok((10, 1, 1000, data2.clone())));
         assert_eq!(rx2.try_recv(), Ok((11, 1, 1000, data3.clone())));
     }
 
+    #[test]
+    fn should_relay_seq_to_sequenced_consumers() {
+        let mut relay = super::LocalRelay::new();
+
+        let (tx1, rx1) = async_std::channel::bounded(1);
+        let (tx2, rx2) = async_std::channel::bounded(1);
+
+        relay.on_local_sub(10, tx1);
+        relay.on_local_sub_sequenced(11, tx2, rx2.clone(), Default::default());
+
+        let data = Bytes::from("hello");
+        relay.relay(1, 1000, &[10, 11], Some(5), data.clone());
+        assert_eq!(rx1.try_recv(), Ok((10, 1, 1000, data.clone())));
+        assert_eq!(rx2.try_recv(), Ok((11, 1, 1000, Some(5), data.clone())));
+    }
+
+    #[test]
+    fn seq_should_reset_after_last_unpub() {
+        let mut relay = super::LocalRelay::new();
+
+        let (tx, _rx) = async_std::channel::bounded(1);
+        relay.on_local_pub(1, 10, tx);
+        assert_eq!(relay.next_seq(1), 0);
+        assert_eq!(relay.next_seq(1), 1);
+        assert_eq!(relay.next_seq(2), 0);
+
+        relay.on_local_unpub(1, 10);
+        assert_eq!(relay.next_seq(1), 0);
+    }
+
+    #[test]
+    fn should_hold_live_data_until_replayed() {
+        let mut relay = super::LocalRelay::new();
+
+        let (tx, rx) = async_std::channel::bounded(10);
+        relay.on_local_sub_sequenced(10, tx, rx.clone(), Default::default());
+        relay.on_replay_start(10, 1, 1000);
+
+        relay.relay(1, 1000, &[10], Some(2), Bytes::from("h2"));
+        relay.relay(1, 1000, &[10], Some(3), Bytes::from("live"));
+        relay.relay(1, 1000, &[10], None, Bytes::from("h0"));
+        relay.relay(1, 1000, &[10], None, Bytes::from("live unreliable"));
+        relay.relay(2, 1000, &[10], None, Bytes::from("other source"));
+        assert_eq!(rx.try_recv(), Ok((10, 2, 1000, None, Bytes::from("other source"))));
+        assert!(rx.try_recv().is_err());
+
+        // live msgs are delivered after history, without the ones already in history
+        relay.replay(10, 1, 1000, vec![(None, Bytes::from("h0")), (Some(1), Bytes::from("h1")), (Some(2), Bytes::from("h2"))]);
+        assert_eq!(rx.try_recv(), Ok((10, 1, 1000, None, Bytes::from("h0"))));
+        assert_eq!(rx.try_recv(), Ok((10, 1, 1000, Some(1), Bytes::from("h1"))));
+        assert_eq!(rx.try_recv(), Ok((10, 1, 1000, Some(2), Bytes::from("h2"))));
+        assert_eq!(rx.try_recv(), Ok((10, 1, 1000, Some(3), Bytes::from("live"))));
+        assert_eq!(rx.try_recv(), Ok((10, 1, 1000, None, Bytes::from("live unreliable"))));
+        assert!(rx.try_recv().is_err());
+
+        relay.relay(1, 1000, &[10], Some(4), Bytes::from("live"));
+        assert_eq!(rx.try_recv(), Ok((10, 1, 1000, Some(4), Bytes::from("live"))));
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let mut relay = super::LocalRelay::new();
+
+        let (tx1, rx1) = async_std::channel::bounded(1);
+        let (tx2, rx2) = async_std::channel::bounded(1);
+        let (tx3, rx3) = async_std::channel::bounded(1);
+        let (tx4, rx4) = async_std::channel::bounded(1);
+        relay.on_local_sub_sequenced(10, tx1, rx1.clone(), OverflowPolicy::DropNewest);
+        relay.on_local_sub_sequenced(11, tx2, rx2.clone(), OverflowPolicy::DropOldest);
+        relay.on_local_sub_sequenced(12, tx3, rx3.clone(), OverflowPolicy::Disconnect);
+        relay.on_local_sub_sequenced(13, tx4, rx4.clone(), OverflowPolicy::Block { timeout_ms: 10 });
+
+        relay.relay(1, 1000, &[10, 11, 12, 13], None, Bytes::from("1"));
+        relay.relay(1, 1000, &[10, 11, 12, 13], None, Bytes::from("2"));
+        let blocked = relay.relay_blocking(1, 1000, &[10, 11, 12, 13], None, Bytes::from("3"));
+        assert_eq!(blocked.len(), 1);
+        let relay = RwLock::new(relay);
+        async_std::task::block_on(send_blocked(&relay, blocked));
+        let mut relay = relay.into_inner();
+
+        assert_eq!(rx1.try_recv(), Ok((10, 1, 1000, None, Bytes::from("1"))));
+        assert_eq!(rx2.try_recv(), Ok((11, 1, 1000, None, Bytes::from("3"))));
+        assert_eq!(rx3.try_recv(), Ok((12, 1, 1000, None, Bytes::from("1"))));
+        assert!(rx3.is_closed());
+        assert_eq!(rx4.try_recv(), Ok((13, 1, 1000, None, Bytes::from("1"))));
+
+        let mut overflows = relay.pop_overflows();
+        overflows.sort_by_key(|(uuid, _, _)| *uuid);
+        let channel = ChannelIdentify::new(1000, 1);
+        assert_eq!(overflows, vec![(10, channel, 2), (11, channel, 2), (12, channel, 1), (13, channel, 2)]);
+        assert_eq!(relay.pop_overflows(), vec![]);
+    }
+
+    #[async_std::test]
+    async fn block_policy_should_wait_for_consumer() {
+        let relay = Arc::new(RwLock::new(super::LocalRelay::new()));
+
+        let (tx, rx) = async_std::channel::bounded(1);
+        relay.write().on_local_sub_sequenced(10, tx, rx.clone(), OverflowPolicy::Block { timeout_ms: 1000 });
+        assert!(relay.read().relay_blocking(1, 1000, &[10], None, Bytes::from("1")).is_empty());
+
+        let blocked = relay.read().relay_blocking(1, 1000, &[10], None, Bytes::from("2"));
+        assert_eq!(blocked.len(), 1);
+
+        // the relay lock is not held while waiting, so others can still use it
+        let rx_c = rx.clone();
+        let relay_c = relay.clone();
+        let consumer = async_std::task::spawn(async move {
+            async_std::task::sleep(std::time::Duration::from_millis(20)).await;
+            assert_eq!(relay_c.write().pop_overflows(), vec![]);
+            rx_c.try_recv().expect("Should have msg")
+        });
+        send_blocked(&relay, blocked).await;
+        assert_eq!(consumer.await, (10, 1, 1000, None, Bytes::from("1")));
+        assert_eq!(rx.try_recv(), Ok((10, 1, 1000, None, Bytes::from("2"))));
+        assert_eq!(relay.write().pop_overflows(), vec![]);
+    }
+
+    #[test]
+    fn presence_should_drop_oldest_when_full() {
+        let mut relay = super::LocalRelay::new();
+
+        let (tx, rx) = async_std::channel::bounded(2);
+        relay.on_local_pub_presence(1, 10, tx, rx.clone());
+        relay.presence(1, PresenceEvent::Changed(ChannelPresence { subscribers: 1, nodes: vec![] }));
+        relay.presence(1, PresenceEvent::Changed(ChannelPresence { subscribers: 2, nodes: vec![] }));
+        relay.presence(1, PresenceEvent::LastSubscriberLeft);
+
+        assert_eq!(rx.try_recv(), Ok(PresenceEvent::Changed(ChannelPresence { subscribers: 2, nodes: vec![] })));
+        assert_eq!(rx.try_recv(), Ok(PresenceEvent::LastSubscriberLeft));
+        assert!(rx.try_recv().is_err());
+    }
+
     #[test]
     fn should_feedback_to_all_publishers() {
         let mut relay = super::LocalRelay::new();
@@ -1,15 +1,20 @@
 use std::{
-    collections::{hash_map::Entry, HashMap, VecDeque},
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
     sync::Arc,
 };
 
 use atm0s_sdn_identity::{ConnId, NodeId};
+use atm0s_sdn_network::msg::TransportMsg;
 use atm0s_sdn_utils::awaker::{Awaker, MockAwaker};
+use bytes::Bytes;
 
-use crate::{msg::PubsubRemoteEvent, PUBSUB_CHANNEL_RESYNC_MS, PUBSUB_CHANNEL_TIMEOUT_MS};
+use crate::{msg::PubsubRemoteEvent, PUBSUB_CHANNEL_RESYNC_MS, PUBSUB_CHANNEL_TIMEOUT_MS, PUBSUB_HISTORY_PART_MAX_BYTES, PUBSUB_RELIABLE_BUFFER_SIZE};
 
 use super::{
-    feedback::{ChannelFeedbackProcessor, Feedback, FeedbackConsumerId},
+    feedback::{ChannelFeedbackProcessor, Feedback, FeedbackConsumerId, FeedbackProcessorFactory, FeedbackRegistry},
+    history::{ChannelHistory, HistoryConfig},
+    presence::{ChannelPresence, PresenceEvent},
+    reliable::RetransmitBuffer,
     ChannelIdentify, LocalSubId,
 };
 
@@ -26,12 +31,71 @@ struct ChannelContainer {
     remote_subscribers_ts: HashMap<ConnId, u64>,
     local_subscribers: Vec<LocalSubId>,
     feedback_processor: ChannelFeedbackProcessor,
+    retransmit: RetransmitBuffer,
+    history: Option<ChannelHistory>,
+    pending_replays: Vec<(ReplayRequester, Option<u64>)>,
+    /// Received parts of the replay answer from next node
+    replay_parts: Vec<(Option<u64>, Bytes)>,
+    remote_presences: HashMap<ConnId, ChannelPresence>,
+    presence_sent: Option<(ConnId, ChannelPresence)>,
+}
+
+impl ChannelContainer {
+    fn new(channel: ChannelIdentify, history: Option<HistoryConfig>) -> Self {
+        Self {
+            source: channel.source(),
+            acked: None,
+            remote_subscribers: vec![],
+            remote_subscribers_ts: HashMap::new(),
+            local_subscribers: vec![],
+            feedback_processor: ChannelFeedbackProcessor::new(channel),
+            retransmit: RetransmitBuffer::new(PUBSUB_RELIABLE_BUFFER_SIZE),
+            history: history.map(ChannelHistory::new),
+            pending_replays: vec![],
+            replay_parts: vec![],
+            remote_presences: HashMap::new(),
+            presence_sent: None,
+        }
+    }
+
+    /// Local subscribers and reported presence of remote subscribers,
+    /// a remote subscriber which has not reported yet is counted as a single subscriber
+    fn presence(&self, node_id: NodeId) -> ChannelPresence {
+        let mut presence = ChannelPresence::default();
+        if !self.local_subscribers.is_empty() {
+            presence.merge(&ChannelPresence {
+                subscribers: self.local_subscribers.len() as u64,
+                nodes: vec![node_id],
+            });
+        }
+        for conn in &self.remote_subscribers {
+            match self.remote_presences.get(conn) {
+                Some(remote) => presence.merge(remote),
+                None => presence.subscribers += 1,
+            }
+        }
+        presence
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReplayRequester {
+    Local(LocalSubId),
+    Remote(NodeId, ConnId),
+}
+
+/// Replay history to a local subscriber, live data of the channel is hold back from that subscriber between `Start` and `Data`
+#[derive(Debug, PartialEq, Eq)]
+pub enum LocalReplay {
+    Start(ChannelIdentify, LocalSubId),
+    Data(ChannelIdentify, LocalSubId, Vec<(Option<u64>, Bytes)>),
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum PubsubRelayLogicOutput {
     Event(PubsubRemoteEvent),
     Feedback(Feedback),
+    Retransmit(TransportMsg),
 }
 
 pub struct PubsubRelayLogic {
@@ -39,6 +103,13 @@ pub struct PubsubRelayLogic {
     node_id: NodeId,
     channels: HashMap<ChannelIdentify, ChannelContainer>,
     output_events: VecDeque<(NodeId, Option<ConnId>, PubsubRelayLogicOutput)>,
+    history_configs: HashMap<ChannelIdentify, HistoryConfig>,
+    relay_history: Option<HistoryConfig>,
+    replay_subs: HashSet<LocalSubId>,
+    local_replays: VecDeque<LocalReplay>,
+    feedback_registry: FeedbackRegistry,
+    presences: HashMap<ChannelIdentify, ChannelPresence>,
+    local_presences: VecDeque<(ChannelIdentify, PresenceEvent)>,
 }
 
 impl PubsubRelayLogic {
@@ -48,6 +119,13 @@ impl PubsubRelayLogic {
             node_id,
             channels: Default::default(),
             output_events: Default::default(),
+            history_configs: Default::default(),
+            relay_history: None,
+            replay_subs: Default::default(),
+            local_replays: Default::default(),
+            feedback_registry: Default::default(),
+            presences: Default::default(),
+            local_presences: Default::default(),
         }
     }
 
@@ -78,6 +156,7 @@ impl PubsubRelayLogic {
                     slot.remote_subscribers.swap_remove(index);
                 }
                 slot.remote_subscribers_ts.remove(&conn);
+                slot.remote_presences.remove(&conn);
             }
 
             if let Some(mut fbs) = slot.feedback_processor.on_tick(now_ms) {
@@ -90,8 +169,31 @@ impl PubsubRelayLogic {
                 }
             }
 
+            if let Some(history) = &mut slot.history {
+                history.on_tick(now_ms);
+            }
+            let mut expired_replays = vec![];
+            slot.pending_replays.retain_mut(|(requester, since)| {
+                let since = *since.get_or_insert(now_ms);
+                if now_ms - since >= PUBSUB_CHANNEL_RESYNC_MS {
+                    expired_replays.push(*requester);
+                    false
+                } else {
+                    true
+                }
+            });
+            if slot.pending_replays.is_empty() {
+                slot.replay_parts.clear();
+            }
+            for requester in expired_replays {
+                log::warn!("[PubsubRelayLogic {}] replay {} for {:?} timeout", self.node_id, channel, requester);
+                if let ReplayRequester::Local(handler) = requester {
+                    self.local_replays.push_back(LocalReplay::Data(*channel, handler, vec![]));
+                }
+            }
+
             if channel.source() == self.node_id {
-                if slot.remote_subscribers.len() == 0 && slot.local_subscribers.len() == 0 {
+                if slot.remote_subscribers.len() == 0 && slot.local_subscribers.len() == 0 && slot.history.is_none() {
                     log::info!("[PubsubRelayLogic {}] channel {} empty in source node => clear", self.node_id, channel);
                     need_clear_channels.push(*channel);
                 }
@@ -100,6 +202,7 @@ impl PubsubRelayLogic {
             if slot.remote_subscribers.len() + slot.local_subscribers.len() > 0 {
                 if let Some(acked) = &slot.acked {
                     let now_ms = now_ms;
+                    let (next_node, next_conn) = (acked.from_node, acked.from_conn);
                     if now_ms - acked.at_ms >= PUBSUB_CHANNEL_RESYNC_MS {
                         log::info!(
                             "[PubsubRelayLogic {}] resend sub {} event to next node {} in each resync cycle {} ms",
@@ -111,6 +214,16 @@ impl PubsubRelayLogic {
                         //Should be send to correct conn, if that conn not exits => fallback by finding to origin source
                         self.output_events
                             .push_back((channel.source(), Some(acked.from_conn), PubsubRelayLogicOutput::Event(PubsubRemoteEvent::Sub(*channel))));
+                        // next node may have lost our presence, for example after timeout
+                        slot.presence_sent = None;
+                    }
+
+                    let presence = slot.presence(self.node_id);
+                    if slot.presence_sent.as_ref() != Some(&(next_conn, presence.clone())) {
+                        log::debug!("[PubsubRelayLogic {}] presence {} changed {:?} => send to next node {}", self.node_id, channel, presence, next_node);
+                        self.output_events
+                            .push_back((next_node, Some(next_conn), PubsubRelayLogicOutput::Event(PubsubRemoteEvent::Presence(*channel, presence.clone()))));
+                        slot.presence_sent = Some((next_conn, presence));
                     }
                 } else {
                     log::info!(
@@ -141,13 +254,54 @@ impl PubsubRelayLogic {
             self.channels.remove(&channel);
         }
 
+        let local_channels: Vec<ChannelIdentify> = self.channels.keys().filter(|channel| channel.source() == self.node_id).copied().collect();
+        for channel in local_channels {
+            self.check_local_presence(channel);
+        }
+        let gone_channels: Vec<ChannelIdentify> = self.presences.keys().filter(|channel| !self.channels.contains_key(channel)).copied().collect();
+        for channel in gone_channels {
+            self.check_local_presence(channel);
+        }
+
         local_fbs
     }
 
+    /// Subscribers of a channel which is published from this node
+    pub fn presence(&self, channel: ChannelIdentify) -> ChannelPresence {
+        self.presences.get(&channel).cloned().unwrap_or_default()
+    }
+
+    /// Detect presence change of a channel which is published from this node, the event is delivered to local publishers
+    fn check_local_presence(&mut self, channel: ChannelIdentify) {
+        if channel.source() != self.node_id {
+            return;
+        }
+        let current = self.channels.get(&channel).map(|slot| slot.presence(self.node_id)).unwrap_or_default();
+        let prev = self.presences.get(&channel).cloned().unwrap_or_default();
+        if let Some(event) = PresenceEvent::from_change(&prev, &current) {
+            log::info!("[PubsubRelayLogic {}] presence {} changed {:?}", self.node_id, channel, event);
+            if current.subscribers == 0 {
+                self.presences.remove(&channel);
+            } else {
+                self.presences.insert(channel, current);
+            }
+            self.local_presences.push_back((channel, event));
+        }
+    }
+
+    /// Use a custom processor for feedbacks with the id, only affect channels which haven't received that feedback id yet
+    pub fn register_feedback_processor(&mut self, id: u8, factory: FeedbackProcessorFactory) {
+        self.feedback_registry.register(id, factory);
+    }
+
+    pub fn unregister_feedback_processor(&mut self, id: u8) {
+        self.feedback_registry.unregister(id);
+    }
+
     /// Process feedback from consumer, return Some(fb) if need to call local publisher feedback
     pub fn on_feedback(&mut self, now_ms: u64, channel: ChannelIdentify, consumer_id: FeedbackConsumerId, fb: Feedback) -> Option<Feedback> {
         if let Some(slot) = self.channels.get_mut(&channel) {
-            if let Some(fb) = slot.feedback_processor.on_feedback(now_ms, consumer_id, fb) {
+            if let Some(fb) = slot.feedback_processor.on_feedback(&self.feedback_registry, now_ms, consumer_id, fb) {
                 if let Some(remote) = &slot.acked {
                     self.output_events.push_back((remote.from_node, Some(remote.from_conn), PubsubRelayLogicOutput::Feedback(fb)));
                     self.awaker.notify();
@@ -166,10 +320,160 @@ impl PubsubRelayLogic {
         }
     }
 
+    /// Keep last msgs of a channel which is published from this node, None for disable.
+    /// The channel is kept even without subscribers while history is enabled
+    pub fn set_history(&mut self, channel: ChannelIdentify, config: Option<HistoryConfig>) {
+        if let Some(config) = config {
+            self.history_configs.insert(channel, config);
+            let slot = self.channels.entry(channel).or_insert_with(|| ChannelContainer::new(channel, None));
+            if slot.history.is_none() {
+                slot.history = Some(ChannelHistory::new(config));
+            }
+        } else {
+            self.history_configs.remove(&channel);
+            if let Some(slot) = self.channels.get_mut(&channel) {
+                slot.history = None;
+            }
+        }
+    }
+
+    /// Keep last msgs of channels relayed by this node, for answering replay without asking the source
+    pub fn set_relay_history(&mut self, config: Option<HistoryConfig>) {
+        self.relay_history = config;
+    }
+
+    /// Local subscriber which want history of channel before live data
+    pub fn set_local_replay(&mut self, handler: LocalSubId, replay: bool) {
+        if replay {
+            self.replay_subs.insert(handler);
+        } else {
+            self.replay_subs.remove(&handler);
+        }
+    }
+
+    /// Whether msgs of the channel are stored for replaying
+    pub fn has_history(&self, channel: ChannelIdentify) -> bool {
+        self.channels.get(&channel).map(|slot| slot.history.is_some()).unwrap_or(false)
+    }
+
+    /// Store a msg into channel history if enabled
+    pub fn on_history_msg(&mut self, now_ms: u64, channel: ChannelIdentify, seq: Option<u64>, data: &[u8]) {
+        if let Some(history) = self.channels.get_mut(&channel).and_then(|slot| slot.history.as_mut()) {
+            history.push(now_ms, seq, Bytes::from(data.to_vec()));
+        }
+    }
+
+    fn history_config(&self, channel: ChannelIdentify) -> Option<HistoryConfig> {
+        if channel.source() == self.node_id {
+            self.history_configs.get(&channel).copied()
+        } else {
+            self.relay_history
+        }
+    }
+
+    /// Answer replay from history if this node has it, otherwise ask next node.
+    /// Relay history is only used if it is not empty, because it may be created after the msgs are published
+    fn request_replay(&mut self, channel: ChannelIdentify, requester: ReplayRequester) {
+        let slot = match self.channels.get_mut(&channel) {
+            Some(slot) => slot,
+            None => return,
+        };
+        let msgs = slot.history.as_ref().map(|history| history.snapshot()).unwrap_or_default();
+        if !msgs.is_empty() || channel.source() == self.node_id {
+            self.answer_replay(channel, requester, msgs);
+            return;
+        }
+
+        let first = slot.pending_replays.is_empty();
+        slot.pending_replays.push((requester, None));
+        let (next_node, next_conn) = slot.acked.as_ref().map(|acked| (acked.from_node, Some(acked.from_conn))).unwrap_or((channel.source(), None));
+        if let ReplayRequester::Local(handler) = requester {
+            self.local_replays.push_back(LocalReplay::Start(channel, handler));
+        }
+        if first {
+            log::info!("[PubsubRelayLogic {}] replay {} => ask next node {}", self.node_id, channel, next_node);
+            self.output_events.push_back((next_node, next_conn, PubsubRelayLogicOutput::Event(PubsubRemoteEvent::Replay(channel))));
+        }
+        self.awaker.notify();
+    }
+
+    fn answer_replay(&mut self, channel: ChannelIdentify, requester: ReplayRequester, msgs: Vec<(Option<u64>, Bytes)>) {
+        match requester {
+            ReplayRequester::Local(handler) => self.local_replays.push_back(LocalReplay::Data(channel, handler, msgs)),
+            ReplayRequester::Remote(node, conn) => {
+                let parts = history_parts(msgs);
+                let last_index = parts.len() - 1;
+                for (index, part) in parts.into_iter().enumerate() {
+                    self.output_events
+                        .push_back((node, Some(conn), PubsubRelayLogicOutput::Event(PubsubRemoteEvent::History(channel, part, index == last_index))));
+                }
+            }
+        }
+        self.awaker.notify();
+    }
+
+    /// Store a reliable msg for answering NACK from subscribers, return false if it is already relayed
+    pub fn on_reliable_msg(&mut self, channel: ChannelIdentify, seq: u64, msg: &TransportMsg) -> bool {
+        if let Some(slot) = self.channels.get_mut(&channel) {
+            slot.retransmit.push(seq, msg)
+        } else {
+            true
+        }
+    }
+
+    /// Same as `on_reliable_msg` but for a part of a large msg, parts are deduplicated separately
+    pub fn on_reliable_part(&mut self, channel: ChannelIdentify, seq: u64, part_index: usize, part_count: usize, msg: &TransportMsg) -> bool {
+        if let Some(slot) = self.channels.get_mut(&channel) {
+            slot.retransmit.push_part(seq, part_index, part_count, msg)
+        } else {
+            true
+        }
+    }
+
+    /// Local consumer missing some msgs, return msgs which are still stored in this node,
+    /// others will be requested from next node
+    pub fn on_local_nack(&mut self, channel: ChannelIdentify, seqs: Vec<u64>) -> Vec<TransportMsg> {
+        let mut found = vec![];
+        if let Some(slot) = self.channels.get(&channel) {
+            let mut missing = vec![];
+            for seq in seqs {
+                if let Some(msgs) = slot.retransmit.get(seq) {
+                    found.extend(msgs);
+                } else {
+                    missing.push(seq);
+                }
+            }
+            if self.nack_next_node(channel, missing) {
+                self.awaker.notify();
+            }
+        } else {
+            log::warn!("[PubsubRelayLogic {}] local nack {} event but no channel found", self.node_id, channel);
+        }
+        found
+    }
+
+    /// Request missing msgs from next node, return true if a NACK is queued
+    fn nack_next_node(&mut self, channel: ChannelIdentify, missing: Vec<u64>) -> bool {
+        if missing.is_empty() {
+            return false;
+        }
+        if let Some(remote) = self.channels.get(&channel).and_then(|slot| slot.acked.as_ref()) {
+            log::debug!("[PubsubRelayLogic {}] nack {} missing {:?} => send to next node {}", self.node_id, channel, missing, remote.from_node);
+            self.output_events
+                .push_back((remote.from_node, Some(remote.from_conn), PubsubRelayLogicOutput::Event(PubsubRemoteEvent::Nack(channel, missing))));
+            true
+        } else {
+            log::debug!("[PubsubRelayLogic {}] nack {} missing {:?} but no next node => lost", self.node_id, channel, missing);
+            false
+        }
+    }
+
     /// This node subscribe that channel,
     /// then we must to check if that channel already exist or not,
     /// if not, we must to send a sub event to the source node
     pub fn on_local_sub(&mut self, channel: ChannelIdentify, handler: LocalSubId) {
+        let history = self.history_config(channel);
+        let mut added = true;
         let maybe_sub = match self.channels.entry(channel) {
             Entry::Occupied(mut entry) => {
                 let value = entry.get_mut();
@@ -180,19 +484,15 @@ impl PubsubRelayLogic {
                     log::info!("[PubsubRelayLogic {}] local sub {} event from {} pushed to list", self.node_id, channel, handler);
                 } else {
                     log::info!("[PubsubRelayLogic {}] local sub {} event from {} allready in list", self.node_id, channel, handler);
+                    added = false;
                 }
                 false
             }
             Entry::Vacant(entry) => {
                 log::info!("[PubsubRelayLogic {}] local sub {} event from {} pushed to list, new relay", self.node_id, channel, handler);
-                entry.insert(ChannelContainer {
-                    source: channel.source(),
-                    acked: None,
-                    remote_subscribers: vec![],
-                    remote_subscribers_ts: HashMap::new(),
-                    local_subscribers: vec![handler],
-                    feedback_processor: ChannelFeedbackProcessor::new(channel),
-                });
+                let mut slot = ChannelContainer::new(channel, history);
+                slot.local_subscribers.push(handler);
+                entry.insert(slot);
                 true
             }
         };
@@ -208,6 +508,11 @@ impl PubsubRelayLogic {
             self.output_events.push_back((channel.source(), None, PubsubRelayLogicOutput::Event(PubsubRemoteEvent::Sub(channel))));
             self.awaker.notify();
         }
+
+        if added && self.replay_subs.contains(&handler) {
+            self.request_replay(channel, ReplayRequester::Local(handler));
+        }
+        self.check_local_presence(channel);
     }
 
     /// This node unsubscribe that channle,
@@ -216,6 +521,7 @@ impl PubsubRelayLogic {
     pub fn on_local_unsub(&mut self, channel: ChannelIdentify, handler: LocalSubId) {
         if let Some(slot) = self.channels.get_mut(&channel) {
             slot.feedback_processor.on_unsub(FeedbackConsumerId::Local(handler));
+            slot.pending_replays.retain(|(requester, _)| *requester != ReplayRequester::Local(handler));
             if let Some(index) = slot.local_subscribers.iter().position(|&x| x == handler) {
                 log::info!("[PubsubRelayLogic {}] local unsub {} event from {} removed from list", self.node_id, channel, handler);
                 slot.local_subscribers.swap_remove(index);
@@ -235,6 +541,8 @@ impl PubsubRelayLogic {
                     self.output_events
                         .push_back((info.from_node, Some(info.from_conn), PubsubRelayLogicOutput::Event(PubsubRemoteEvent::Unsub(channel))));
                     self.awaker.notify();
+                } else if slot.history.is_some() {
+                    log::info!("[PubsubRelayLogic {}] local unsub {} event from {} list empty => keep for history", self.node_id, channel, handler);
                 } else {
                     if self.node_id != channel.source() {
                         log::warn!("[PubsubRelayLogic {}] local unsub {} event from {} list empty => but no next node", self.node_id, channel, handler);
@@ -249,6 +557,7 @@ impl PubsubRelayLogic {
                     self.channels.remove(&channel);
                 }
             }
+            self.check_local_presence(channel);
         } else {
             log::warn!("[PubsubRelayLogic {}] local unsub {} event from {} but no channel found", self.node_id, channel, handler);
         }
@@ -257,6 +566,7 @@ impl PubsubRelayLogic {
     pub fn on_event(&mut self, now_ms: u64, from: NodeId, conn: ConnId, event: PubsubRemoteEvent) {
         match event {
             PubsubRemoteEvent::Sub(id) => {
+                let history = self.history_config(id);
                 let maybe_sub = match self.channels.entry(id) {
                     Entry::Occupied(mut entry) => {
                         let value = entry.get_mut();
@@ -275,14 +585,10 @@ impl PubsubRelayLogic {
                     }
                     Entry::Vacant(entry) => {
                         log::info!("[PubsubRelayLogic {}] sub {} event from {} pushed to list, new relay", self.node_id, id, from);
-                        entry.insert(ChannelContainer {
-                            source: id.source(),
-                            acked: None,
-                            remote_subscribers: vec![conn],
-                            remote_subscribers_ts: HashMap::from([(conn, now_ms)]),
-                            local_subscribers: vec![],
-                            feedback_processor: ChannelFeedbackProcessor::new(id),
-                        });
+                        let mut slot = ChannelContainer::new(id, history);
+                        slot.remote_subscribers.push(conn);
+                        slot.remote_subscribers_ts.insert(conn, now_ms);
+                        entry.insert(slot);
                         self.output_events.push_back((from, Some(conn), PubsubRelayLogicOutput::Event(PubsubRemoteEvent::SubAck(id, true))));
                         true
                     }
@@ -299,11 +605,13 @@ impl PubsubRelayLogic {
                     self.output_events.push_back((id.source(), None, PubsubRelayLogicOutput::Event(PubsubRemoteEvent::Sub(id))));
                 }
 
+                self.check_local_presence(id);
                 self.awaker.notify();
             }
             PubsubRemoteEvent::Unsub(id) => {
                 if let Some(slot) = self.channels.get_mut(&id) {
                     slot.feedback_processor.on_unsub(FeedbackConsumerId::Remote(conn));
+                    slot.remote_presences.remove(&conn);
                     if let Some(index) = slot.remote_subscribers.iter().position(|&x| x == conn) {
                         slot.remote_subscribers.swap_remove(index);
                         log::info!("[PubsubRelayLogic {}] unsub {} event from {} removed from list", self.node_id, id, from);
@@ -333,11 +641,12 @@ impl PubsubRelayLogic {
                                     log::info!("[PubsubRelayLogic {}] unsub {} event from {} list empty in source node => removed", self.node_id, id, from);
                                 }
                             }
-                        } else {
+                        } else if slot.history.is_none() {
                             self.channels.remove(&id);
                             log::info!("[PubsubRelayLogic {}] unsub {} event from {} list empty in source node => removed", self.node_id, id, from);
                         }
                     }
+                    self.check_local_presence(id);
                     self.awaker.notify();
                 } else {
                     log::warn!("[PubsubRelayLogic {}] unsub {} event from {} but no channel found", self.node_id, id, from);
@@ -355,6 +664,65 @@ impl PubsubRelayLogic {
                     log::warn!("[PubsubRelayLogic {}] sub_ack {} event from {} but channel not found", self.node_id, id, from);
                 }
             }
+            PubsubRemoteEvent::Nack(id, seqs) => {
+                if let Some(slot) = self.channels.get(&id) {
+                    let mut missing = vec![];
+                    for seq in seqs {
+                        if let Some(msgs) = slot.retransmit.get(seq) {
+                            for msg in msgs {
+                                self.output_events.push_back((from, Some(conn), PubsubRelayLogicOutput::Retransmit(msg)));
+                            }
+                        } else {
+                            missing.push(seq);
+                        }
+                    }
+                    self.nack_next_node(id, missing);
+                    self.awaker.notify();
+                } else {
+                    log::warn!("[PubsubRelayLogic {}] nack {} event from {} but channel not found", self.node_id, id, from);
+                }
+            }
+            PubsubRemoteEvent::Replay(id) => {
+                log::info!("[PubsubRelayLogic {}] replay {} event from {}", self.node_id, id, from);
+                if self.channels.contains_key(&id) {
+                    self.request_replay(id, ReplayRequester::Remote(from, conn));
+                } else {
+                    self.answer_replay(id, ReplayRequester::Remote(from, conn), vec![]);
+                }
+            }
+            PubsubRemoteEvent::History(id, msgs, last) => {
+                if let Some(slot) = self.channels.get_mut(&id) {
+                    log::info!("[PubsubRelayLogic {}] history {} event from {} with {} msgs, last {}", self.node_id, id, from, msgs.len(), last);
+                    if slot.pending_replays.is_empty() {
+                        log::warn!("[PubsubRelayLogic {}] history {} event from {} but no replay is waiting", self.node_id, id, from);
+                        return;
+                    }
+                    slot.replay_parts.extend(msgs.into_iter().map(|(seq, data)| (seq, Bytes::from(data))));
+                    if !last {
+                        return;
+                    }
+                    let requesters = std::mem::take(&mut slot.pending_replays);
+                    let msgs = std::mem::take(&mut slot.replay_parts);
+                    for (requester, _) in requesters {
+                        self.answer_replay(id, requester, msgs.clone());
+                    }
+                } else {
+                    log::warn!("[PubsubRelayLogic {}] history {} event from {} but channel not found", self.node_id, id, from);
+                }
+            }
+            PubsubRemoteEvent::Presence(id, presence) => match self.channels.get_mut(&id) {
+                Some(slot) if slot.remote_subscribers.contains(&conn) => {
+                    log::debug!("[PubsubRelayLogic {}] presence {} event from {} {:?}", self.node_id, id, from, presence);
+                    slot.remote_presences.insert(conn, presence);
+                    self.check_local_presence(id);
+                }
+                _ => {
+                    log::warn!("[PubsubRelayLogic {}] presence {} event from {} but not subscribed", self.node_id, id, from);
+                }
+            },
+            PubsubRemoteEvent::Fragment(id, _, _) | PubsubRemoteEvent::ReliableFragment(id, _, _, _) => {
+                log::warn!("[PubsubRelayLogic {}] fragment {} event from {} should be relayed as data", self.node_id, id, from);
+            }
             PubsubRemoteEvent::UnsubAck(id, _removed) => {
                 if self.channels.remove(&id).is_some() {
                     log::info!("[PubsubRelayLogic {}] unsub_ack {} event from {}", self.node_id, id, from);
@@ -374,6 +742,34 @@ impl PubsubRelayLogic {
     pub fn pop_action(&mut self) -> Option<(NodeId, Option<ConnId>, PubsubRelayLogicOutput)> {
         self.output_events.pop_front()
     }
+
+    pub fn pop_local_replay(&mut self) -> Option<LocalReplay> {
+        self.local_replays.pop_front()
+    }
+
+    pub fn pop_local_presence(&mut self) -> Option<(ChannelIdentify, PresenceEvent)> {
+        self.local_presences.pop_front()
+    }
+}
+
+/// Split history into parts which fit into a single packet, there is always at least one part.
+/// A msg which is larger than a part is not replayed to remote nodes
+fn history_parts(msgs: Vec<(Option<u64>, Bytes)>) -> Vec<Vec<(Option<u64>, Vec<u8>)>> {
+    let mut parts = vec![vec![]];
+    let mut part_bytes = 0;
+    for (seq, data) in msgs {
+        if data.len() > PUBSUB_HISTORY_PART_MAX_BYTES {
+            log::warn!("[PubsubRelayLogic] history msg with {} bytes is too large for replaying => skip", data.len());
+            continue;
+        }
+        if part_bytes + data.len() > PUBSUB_HISTORY_PART_MAX_BYTES {
+            parts.push(vec![]);
+            part_bytes = 0;
+        }
+        part_bytes += data.len();
+        parts.last_mut().expect("Should have part").push((seq, data.to_vec()));
+    }
+    parts
 }
 
 #[cfg(test)]
@@ -381,18 +777,27 @@ mod tests {
     use std::sync::Arc;
 
     use atm0s_sdn_identity::{ConnId, NodeId};
+    use atm0s_sdn_network::msg::TransportMsg;
     use atm0s_sdn_utils::awaker::{Awaker, MockAwaker};
+    use bytes::Bytes;
 
     use crate::{
         msg::PubsubRemoteEvent,
         relay::{
             feedback::{Feedback, FeedbackConsumerId, FeedbackType, NumberInfo},
+            history::HistoryConfig,
+            presence::{ChannelPresence, PresenceEvent},
+            reliable::build_reliable_msg,
             ChannelIdentify, LocalSubId,
         },
-        PUBSUB_CHANNEL_RESYNC_MS, PUBSUB_CHANNEL_TIMEOUT_MS,
+        PUBSUB_CHANNEL_RESYNC_MS, PUBSUB_CHANNEL_TIMEOUT_MS, PUBSUB_HISTORY_PART_MAX_BYTES,
     };
 
-    use super::{PubsubRelayLogic, PubsubRelayLogicOutput};
+    use super::{LocalReplay, PubsubRelayLogic, PubsubRelayLogicOutput};
+
+    fn presence(subscribers: u64, nodes: Vec<NodeId>) -> ChannelPresence {
+        ChannelPresence { subscribers, nodes }
+    }
 
     enum Event {
         Tick(u64, Vec<Feedback>),
@@ -400,10 +805,19 @@ mod tests {
         InLocalUnsub(ChannelIdentify, LocalSubId),
         In(u64, NodeId, ConnId, PubsubRemoteEvent),
         InFb(u64, ChannelIdentify, FeedbackConsumerId, Feedback, Option<Feedback>),
+        InReliable(ChannelIdentify, u64, TransportMsg, bool),
+        InLocalNack(ChannelIdentify, Vec<u64>, Vec<TransportMsg>),
+        InSetHistory(ChannelIdentify, Option<HistoryConfig>),
+        InSetRelayHistory(Option<HistoryConfig>),
+        InSetLocalReplay(LocalSubId),
+        InHistoryMsg(u64, ChannelIdentify, Option<u64>, Vec<u8>),
+        OutLocalReplay(Option<LocalReplay>),
+        OutLocalPresence(Option<(ChannelIdentify, PresenceEvent)>),
         OutAwake(usize),
         OutNone,
         Out(NodeId, Option<ConnId>, PubsubRemoteEvent),
         OutFb(NodeId, Option<ConnId>, Feedback),
+        OutRetransmit(NodeId, Option<ConnId>, TransportMsg),
         Validate(Box<dyn FnOnce(&PubsubRelayLogic) -> bool>),
     }
 
@@ -419,10 +833,19 @@ mod tests {
                 Event::InLocalUnsub(channel, handler) => logic.on_local_unsub(channel, handler),
                 Event::In(now_ms, from, conn, event) => logic.on_event(now_ms, from, conn, event),
                 Event::InFb(now_ms, channel, consumer, fb, out) => assert_eq!(logic.on_feedback(now_ms, channel, consumer, fb), out),
+                Event::InReliable(channel, seq, msg, out) => assert_eq!(logic.on_reliable_msg(channel, seq, &msg), out),
+                Event::InLocalNack(channel, seqs, out) => assert_eq!(logic.on_local_nack(channel, seqs), out),
+                Event::InSetHistory(channel, config) => logic.set_history(channel, config),
+                Event::InSetRelayHistory(config) => logic.set_relay_history(config),
+                Event::InSetLocalReplay(handler) => logic.set_local_replay(handler, true),
+                Event::InHistoryMsg(now_ms, channel, seq, data) => logic.on_history_msg(now_ms, channel, seq, &data),
+                Event::OutLocalReplay(replay) => assert_eq!(logic.pop_local_replay(), replay),
+                Event::OutLocalPresence(presence) => assert_eq!(logic.pop_local_presence(), presence),
                 Event::OutAwake(count) => assert_eq!(awake.pop_awake_count(), count),
                 Event::OutNone => assert_eq!(logic.pop_action(), None),
                 Event::Out(from, conn, event) => assert_eq!(logic.pop_action(), Some((from, conn, PubsubRelayLogicOutput::Event(event)))),
                 Event::OutFb(from, conn, fb) => assert_eq!(logic.pop_action(), Some((from, conn, PubsubRelayLogicOutput::Feedback(fb)))),
+                Event::OutRetransmit(from, conn, msg) => assert_eq!(logic.pop_action(), Some((from, conn, PubsubRelayLogicOutput::Retransmit(msg)))),
                 Event::Validate(validator) => assert_eq!(validator(&logic), true),
             }
         }
@@ -595,6 +1018,7 @@ mod tests {
                 Event::OutNone,
                 Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::SubAck(channel, true)),
                 Event::Tick(0, vec![]),
+                Event::Out(next_node_id, Some(next_conn_id), PubsubRemoteEvent::Presence(channel, presence(1, vec![]))),
                 Event::OutNone,
                 Event::Validate(Box::new(move |logic| -> bool {
                     assert_eq!(logic.relay(channel), Some((vec![remote_conn_id].as_slice(), vec![].as_slice())));
@@ -666,6 +1090,7 @@ mod tests {
                 Event::OutNone,
                 Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::SubAck(channel, true)),
                 Event::Tick(0, vec![]),
+                Event::Out(next_node_id, Some(next_conn_id), PubsubRemoteEvent::Presence(channel, presence(1, vec![]))),
                 Event::OutNone,
             ],
         );
@@ -695,9 +1120,11 @@ mod tests {
                 Event::OutNone,
                 Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::SubAck(channel, true)),
                 Event::Tick(0, vec![]),
+                Event::Out(next_node_id, Some(next_conn_id), PubsubRemoteEvent::Presence(channel, presence(1, vec![]))),
                 Event::OutNone,
                 Event::Tick(PUBSUB_CHANNEL_RESYNC_MS, vec![]),
                 Event::Out(channel.source(), Some(next_conn_id), PubsubRemoteEvent::Sub(channel)),
+                Event::Out(next_node_id, Some(next_conn_id), PubsubRemoteEvent::Presence(channel, presence(1, vec![]))),
                 Event::OutAwake(0),
                 Event::OutNone,
             ],
@@ -763,6 +1190,7 @@ mod tests {
                 Event::OutNone,
                 Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::SubAck(channel, true)),
                 Event::Tick(0, vec![]),
+                Event::Out(next_node_id, Some(next_conn_id), PubsubRemoteEvent::Presence(channel, presence(1, vec![]))),
                 Event::OutNone,
                 Event::Validate(Box::new(move |logic| -> bool {
                     assert_eq!(logic.relay(channel), Some((vec![remote_conn_id].as_slice(), vec![].as_slice())));
@@ -836,6 +1264,7 @@ mod tests {
                 Event::OutNone,
                 Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::SubAck(channel, true)),
                 Event::Tick(0, vec![]),
+                Event::Out(next_node_id, Some(next_conn_id), PubsubRemoteEvent::Presence(channel, presence(1, vec![node_id]))),
                 Event::OutNone,
                 Event::Validate(Box::new(move |logic| -> bool {
                     assert_eq!(logic.relay(channel), Some((vec![].as_slice(), vec![handler].as_slice())));
@@ -856,6 +1285,251 @@ mod tests {
         );
     }
 
+    /// This test case ensure relay node answer nack with stored msgs and forward missing to next node
+    #[test]
+    fn in_relay_reliable_nack() {
+        let node_id = 0;
+
+        let channel = ChannelIdentify::new(111, 100);
+        let handler = 1000;
+
+        let next_node_id = 2;
+        let next_conn_id = ConnId::from_in(10, 3);
+
+        let remote_node_id = 1;
+        let remote_conn_id = ConnId::from_in(10, 2);
+
+        let msg1 = build_reliable_msg(channel, 1, &[1]);
+        let msg2 = build_reliable_msg(channel, 2, &[2]);
+
+        test(
+            node_id,
+            vec![
+                Event::InLocalSub(channel, handler),
+                Event::OutAwake(1),
+                Event::Out(channel.source(), None, PubsubRemoteEvent::Sub(channel)),
+                Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::SubAck(channel, true)),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Sub(channel)),
+                Event::OutAwake(1),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::SubAck(channel, true)),
+                Event::OutNone,
+                Event::InReliable(channel, 1, msg1.clone(), true),
+                Event::InReliable(channel, 2, msg2.clone(), true),
+                Event::InReliable(channel, 2, msg2.clone(), false),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Nack(channel, vec![1, 3])),
+                Event::OutAwake(1),
+                Event::OutRetransmit(remote_node_id, Some(remote_conn_id), msg1.clone()),
+                Event::Out(next_node_id, Some(next_conn_id), PubsubRemoteEvent::Nack(channel, vec![3])),
+                Event::OutNone,
+                Event::InLocalNack(channel, vec![2, 4], vec![msg2]),
+                Event::OutAwake(1),
+                Event::Out(next_node_id, Some(next_conn_id), PubsubRemoteEvent::Nack(channel, vec![4])),
+                Event::OutNone,
+                Event::InLocalNack(channel, vec![1], vec![msg1]),
+                Event::OutAwake(0),
+                Event::OutNone,
+            ],
+        );
+    }
+
+    /// This test case ensure source node don't forward nack for lost msgs
+    #[test]
+    fn in_source_reliable_nack_lost() {
+        let node_id = 0;
+
+        let channel = ChannelIdentify::new(111, node_id);
+
+        let remote_node_id = 1;
+        let remote_conn_id = ConnId::from_in(10, 2);
+
+        test(
+            node_id,
+            vec![
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Sub(channel)),
+                Event::OutAwake(1),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::SubAck(channel, true)),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Nack(channel, vec![1])),
+                Event::OutAwake(1),
+                Event::OutNone,
+            ],
+        );
+    }
+
+    /// This test case ensure source node keep history without subscribers and replay it to local and remote subscribers
+    #[test]
+    fn in_source_history_replay() {
+        let node_id = 0;
+
+        let channel = ChannelIdentify::new(111, node_id);
+        let handler = 1000;
+
+        let remote_node_id = 1;
+        let remote_conn_id = ConnId::from_in(10, 2);
+
+        let config = HistoryConfig { max_msgs: 2, max_age_ms: Some(1000) };
+
+        test(
+            node_id,
+            vec![
+                Event::InSetHistory(channel, Some(config)),
+                Event::InHistoryMsg(0, channel, None, vec![1]),
+                Event::InHistoryMsg(0, channel, None, vec![2]),
+                Event::InHistoryMsg(100, channel, Some(3), vec![3]),
+                Event::Tick(500, vec![]),
+                Event::OutNone,
+                Event::InSetLocalReplay(handler),
+                Event::InLocalSub(channel, handler),
+                Event::OutLocalReplay(Some(LocalReplay::Data(channel, handler, vec![(None, Bytes::from(vec![2])), (Some(3), Bytes::from(vec![3]))]))),
+                Event::OutLocalReplay(None),
+                Event::InLocalUnsub(channel, handler),
+                Event::Tick(1000, vec![]),
+                Event::In(1000, remote_node_id, remote_conn_id, PubsubRemoteEvent::Sub(channel)),
+                Event::In(1000, remote_node_id, remote_conn_id, PubsubRemoteEvent::Replay(channel)),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::SubAck(channel, true)),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::History(channel, vec![(Some(3), vec![3])], true)),
+                Event::OutNone,
+                Event::In(1000, remote_node_id, remote_conn_id, PubsubRemoteEvent::Unsub(channel)),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::UnsubAck(channel, true)),
+                Event::Tick(2000, vec![]),
+                Event::Validate(Box::new(move |logic| -> bool {
+                    assert_eq!(logic.relay(channel), Some((vec![].as_slice(), vec![].as_slice())));
+                    true
+                })),
+                Event::InSetHistory(channel, None),
+                Event::Tick(2000, vec![]),
+                Event::Validate(Box::new(move |logic| -> bool {
+                    assert_eq!(logic.relay(channel), None);
+                    true
+                })),
+            ],
+        );
+    }
+
+    /// This test case ensure relay node ask next node for history and forward it to all waiting subscribers
+    #[test]
+    fn in_relay_history_replay() {
+        let node_id = 0;
+
+        let channel = ChannelIdentify::new(111, 100);
+        let handler = 1000;
+
+        let next_node_id = 2;
+        let next_conn_id = ConnId::from_in(10, 3);
+
+        let remote_node_id = 1;
+        let remote_conn_id = ConnId::from_in(10, 2);
+
+        test(
+            node_id,
+            vec![
+                Event::InSetRelayHistory(Some(HistoryConfig { max_msgs: 10, max_age_ms: None })),
+                Event::InSetLocalReplay(handler),
+                Event::InLocalSub(channel, handler),
+                Event::Out(channel.source(), None, PubsubRemoteEvent::Sub(channel)),
+                Event::Out(channel.source(), None, PubsubRemoteEvent::Replay(channel)),
+                Event::OutNone,
+                Event::OutLocalReplay(Some(LocalReplay::Start(channel, handler))),
+                Event::OutLocalReplay(None),
+                Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::SubAck(channel, true)),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Sub(channel)),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::SubAck(channel, true)),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Replay(channel)),
+                Event::OutNone,
+                Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::History(channel, vec![(None, vec![1])], true)),
+                Event::OutLocalReplay(Some(LocalReplay::Data(channel, handler, vec![(None, Bytes::from(vec![1]))]))),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::History(channel, vec![(None, vec![1])], true)),
+                Event::OutNone,
+                // after relay history is filled, replay is answered locally
+                Event::InHistoryMsg(0, channel, None, vec![2]),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Replay(channel)),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::History(channel, vec![(None, vec![2])], true)),
+                Event::OutNone,
+            ],
+        );
+    }
+
+    /// This test case ensure history is sent in parts which fit into a packet, and the relay node waits for the last part
+    #[test]
+    fn in_relay_history_replay_parts() {
+        let node_id = 0;
+
+        let channel = ChannelIdentify::new(111, 100);
+        let handler = 1000;
+
+        let next_node_id = 2;
+        let next_conn_id = ConnId::from_in(10, 3);
+
+        let remote_node_id = 1;
+        let remote_conn_id = ConnId::from_in(10, 2);
+
+        let big1 = vec![1; PUBSUB_HISTORY_PART_MAX_BYTES - 100];
+        let big2 = vec![2; 200];
+
+        test(
+            node_id,
+            vec![
+                Event::InSetLocalReplay(handler),
+                Event::InLocalSub(channel, handler),
+                Event::Out(channel.source(), None, PubsubRemoteEvent::Sub(channel)),
+                Event::Out(channel.source(), None, PubsubRemoteEvent::Replay(channel)),
+                Event::OutNone,
+                Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::SubAck(channel, true)),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Sub(channel)),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::SubAck(channel, true)),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Replay(channel)),
+                Event::OutNone,
+                Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::History(channel, vec![(Some(1), big1.clone())], false)),
+                Event::OutNone,
+                Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::History(channel, vec![(Some(2), big2.clone())], true)),
+                Event::OutLocalReplay(Some(LocalReplay::Start(channel, handler))),
+                Event::OutLocalReplay(Some(LocalReplay::Data(
+                    channel,
+                    handler,
+                    vec![(Some(1), Bytes::from(big1.clone())), (Some(2), Bytes::from(big2.clone()))],
+                ))),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::History(channel, vec![(Some(1), big1)], false)),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::History(channel, vec![(Some(2), big2)], true)),
+                Event::OutNone,
+                // late part without waiting replay is ignored
+                Event::In(0, next_node_id, next_conn_id, PubsubRemoteEvent::History(channel, vec![(Some(3), vec![3])], true)),
+                Event::OutLocalReplay(None),
+                Event::OutNone,
+            ],
+        );
+    }
+
+    #[test]
+    fn history_parts_should_fit_packet() {
+        assert_eq!(super::history_parts(vec![]), vec![vec![]]);
+        let small = Bytes::from(vec![1; 10]);
+        let half = Bytes::from(vec![2; PUBSUB_HISTORY_PART_MAX_BYTES / 2]);
+        let too_large = Bytes::from(vec![3; PUBSUB_HISTORY_PART_MAX_BYTES + 1]);
+        let parts = super::history_parts(vec![(None, half.clone()), (Some(1), too_large), (None, half.clone()), (Some(2), small.clone())]);
+        assert_eq!(parts, vec![vec![(None, half.to_vec()), (None, half.to_vec())], vec![(Some(2), small.to_vec())]]);
+    }
+
+    /// This test case ensure local subscriber is released if history don't arrive in time
+    #[test]
+    fn in_relay_history_replay_timeout() {
+        let node_id = 0;
+
+        let channel = ChannelIdentify::new(111, 100);
+        let handler = 1000;
+
+        test(
+            node_id,
+            vec![
+                Event::InSetLocalReplay(handler),
+                Event::InLocalSub(channel, handler),
+                Event::OutLocalReplay(Some(LocalReplay::Start(channel, handler))),
+                Event::Tick(0, vec![]),
+                Event::OutLocalReplay(None),
+                Event::Tick(PUBSUB_CHANNEL_RESYNC_MS, vec![]),
+                Event::OutLocalReplay(Some(LocalReplay::Data(channel, handler, vec![]))),
+            ],
+        );
+    }
+
     /// This test case ensure sub event to source node only generate sub ack
     #[test]
     fn in_source_hybrid_multi() {
@@ -896,4 +1570,43 @@ mod tests {
             ],
         );
     }
+
+    /// This test case ensure presence of remote and local subscribers is aggregated in source node
+    #[test]
+    fn in_source_presence() {
+        let node_id = 0;
+        let channel = ChannelIdentify::new(111, node_id);
+        let handler = 1000;
+
+        let remote_node_id = 1;
+        let remote_conn_id = ConnId::from_in(10, 2);
+
+        test(
+            node_id,
+            vec![
+                Event::InLocalSub(channel, handler),
+                Event::OutLocalPresence(Some((channel, PresenceEvent::FirstSubscriber(presence(1, vec![node_id]))))),
+                Event::OutLocalPresence(None),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Sub(channel)),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::SubAck(channel, true)),
+                Event::OutLocalPresence(Some((channel, PresenceEvent::Changed(presence(2, vec![node_id]))))),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Presence(channel, presence(3, vec![1, 5]))),
+                Event::OutLocalPresence(Some((channel, PresenceEvent::Changed(presence(4, vec![node_id, 1, 5]))))),
+                Event::Validate(Box::new(move |logic| -> bool {
+                    assert_eq!(logic.presence(channel), presence(4, vec![node_id, 1, 5]));
+                    true
+                })),
+                Event::InLocalUnsub(channel, handler),
+                Event::OutLocalPresence(Some((channel, PresenceEvent::Changed(presence(3, vec![1, 5]))))),
+                Event::In(0, remote_node_id, remote_conn_id, PubsubRemoteEvent::Unsub(channel)),
+                Event::Out(remote_node_id, Some(remote_conn_id), PubsubRemoteEvent::UnsubAck(channel, true)),
+                Event::OutLocalPresence(Some((channel, PresenceEvent::LastSubscriberLeft))),
+                Event::OutLocalPresence(None),
+                Event::Validate(Box::new(move |logic| -> bool {
+                    assert_eq!(logic.presence(channel), presence(0, vec![]));
+                    true
+                })),
+            ],
+        );
+    }
 }
@@ -0,0 +1,90 @@
+use atm0s_sdn_identity::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::PUBSUB_PRESENCE_MAX_NODES;
+
+/// Subscribers of a channel, aggregated from all nodes in the relay tree
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelPresence {
+    /// Number of subscribers in all nodes
+    pub subscribers: u64,
+    /// Nodes which have subscribers, sorted, only the first `PUBSUB_PRESENCE_MAX_NODES` nodes are kept
+    pub nodes: Vec<NodeId>,
+}
+
+impl ChannelPresence {
+    pub fn merge(&mut self, other: &ChannelPresence) {
+        self.subscribers += other.subscribers;
+        for node in &other.nodes {
+            if let Err(index) = self.nodes.binary_search(node) {
+                if index < PUBSUB_PRESENCE_MAX_NODES {
+                    self.nodes.insert(index, *node);
+                    self.nodes.truncate(PUBSUB_PRESENCE_MAX_NODES);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PresenceEvent {
+    FirstSubscriber(ChannelPresence),
+    Changed(ChannelPresence),
+    LastSubscriberLeft,
+}
+
+impl PresenceEvent {
+    /// Event for publishers when presence changed from `prev` to `next`, None if nothing changed
+    pub fn from_change(prev: &ChannelPresence, next: &ChannelPresence) -> Option<Self> {
+        match (prev.subscribers, next.subscribers) {
+            _ if prev == next => None,
+            (0, _) => Some(Self::FirstSubscriber(next.clone())),
+            (_, 0) => Some(Self::LastSubscriberLeft),
+            _ => Some(Self::Changed(next.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PUBSUB_PRESENCE_MAX_NODES;
+
+    use super::{ChannelPresence, PresenceEvent};
+
+    fn presence(subscribers: u64, nodes: Vec<u32>) -> ChannelPresence {
+        ChannelPresence { subscribers, nodes }
+    }
+
+    #[test]
+    fn merge_should_sum_and_dedup_nodes() {
+        let mut value = presence(2, vec![1, 3]);
+        value.merge(&presence(3, vec![2, 3]));
+        assert_eq!(value, presence(5, vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn merge_should_cap_nodes() {
+        let max = PUBSUB_PRESENCE_MAX_NODES as u32;
+        let mut value = presence(1, vec![max + 1]);
+        value.merge(&presence(max as u64, (1..=max).collect()));
+        assert_eq!(value, presence(max as u64 + 1, (1..=max).collect()));
+        value.merge(&presence(1, vec![max + 2]));
+        value.merge(&presence(1, vec![0]));
+        assert_eq!(value.subscribers, max as u64 + 3);
+        assert_eq!(value.nodes, (0..max).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn event_from_change() {
+        assert_eq!(PresenceEvent::from_change(&presence(0, vec![]), &presence(0, vec![])), None);
+        assert_eq!(
+            PresenceEvent::from_change(&presence(0, vec![]), &presence(1, vec![2])),
+            Some(PresenceEvent::FirstSubscriber(presence(1, vec![2])))
+        );
+        assert_eq!(
+            PresenceEvent::from_change(&presence(1, vec![2]), &presence(2, vec![2])),
+            Some(PresenceEvent::Changed(presence(2, vec![2])))
+        );
+        assert_eq!(PresenceEvent::from_change(&presence(2, vec![2]), &presence(0, vec![])), Some(PresenceEvent::LastSubscriberLeft));
+    }
+}
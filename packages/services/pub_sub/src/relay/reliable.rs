@@ -0,0 +1,413 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use atm0s_sdn_network::msg::{MsgHeader, TransportMsg};
+use atm0s_sdn_router::RouteRule;
+use bytes::Bytes;
+
+use crate::{
+    handler::{CONTROL_META_TYPE, RELIABLE_DATA_TYPE},
+    msg::PubsubRemoteEvent,
+    PUBSUB_FRAGMENT_MAX_PART_LEN, PUBSUB_SERVICE_ID,
+};
+
+use super::{
+    fragment::{build_fragment_id, MAX_FRAGMENTED_MSG_LEN},
+    ChannelIdentify,
+};
+
+const SEQ_SIZE: usize = 8;
+
+fn build_header(channel: ChannelIdentify, meta: u8) -> MsgHeader {
+    MsgHeader::build(PUBSUB_SERVICE_ID, PUBSUB_SERVICE_ID, RouteRule::Direct)
+        .set_meta(meta)
+        .set_from_node(Some(channel.source()))
+        .set_stream_id(channel.uuid())
+}
+
+/// Build a reliable data msg, the sequence number of the source is prepended to the payload.
+pub fn build_reliable_msg(channel: ChannelIdentify, seq: u64, data: &[u8]) -> TransportMsg {
+    let mut payload = Vec::with_capacity(SEQ_SIZE + data.len());
+    payload.extend_from_slice(&seq.to_be_bytes());
+    payload.extend_from_slice(data);
+    TransportMsg::build_raw(build_header(channel, RELIABLE_DATA_TYPE), &payload)
+}
+
+/// Build reliable msgs for sending data to remote nodes.
+/// Data larger than PUBSUB_FRAGMENT_MAX_PART_LEN is split into ReliableFragment control msgs which share the sequence number, return empty if data is too large.
+pub fn build_reliable_msgs(channel: ChannelIdentify, seq: u64, data: &[u8]) -> Vec<TransportMsg> {
+    if data.len() <= PUBSUB_FRAGMENT_MAX_PART_LEN {
+        return vec![build_reliable_msg(channel, seq, data)];
+    }
+    if data.len() > MAX_FRAGMENTED_MSG_LEN {
+        log::warn!("[PubsubReliable] msg of channel {} too large {} > {} => drop", channel, data.len(), MAX_FRAGMENTED_MSG_LEN);
+        return vec![];
+    }
+
+    let part_count_minus_1 = ((data.len() - 1) / PUBSUB_FRAGMENT_MAX_PART_LEN) as u8;
+    data.chunks(PUBSUB_FRAGMENT_MAX_PART_LEN)
+        .enumerate()
+        .map(|(index, part)| {
+            let fragment_id = build_fragment_id(seq as u16, index as u8, part_count_minus_1);
+            let event = PubsubRemoteEvent::ReliableFragment(channel, seq, fragment_id, part.to_vec());
+            TransportMsg::from_payload_bincode(build_header(channel, CONTROL_META_TYPE), &event)
+        })
+        .collect()
+}
+
+/// Split a reliable data payload into sequence number and data.
+pub fn split_reliable_payload(payload: &[u8]) -> Option<(u64, &[u8])> {
+    if payload.len() < SEQ_SIZE {
+        return None;
+    }
+    let mut seq = [0; SEQ_SIZE];
+    seq.copy_from_slice(&payload[0..SEQ_SIZE]);
+    Some((u64::from_be_bytes(seq), &payload[SEQ_SIZE..]))
+}
+
+/// Last sent reliable msgs of a channel, which are used for answering NACK from subscribers.
+/// Large msgs are stored as parts, capacity is counted in parts and a msg is only available after all of its parts are stored.
+pub struct RetransmitBuffer {
+    capacity: usize,
+    parts: usize,
+    msgs: VecDeque<(u64, Vec<Option<TransportMsg>>)>,
+}
+
+impl RetransmitBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            parts: 0,
+            msgs: VecDeque::new(),
+        }
+    }
+
+    /// Store a msg, return false if it is already stored
+    pub fn push(&mut self, seq: u64, msg: &TransportMsg) -> bool {
+        self.push_part(seq, 0, 1, msg)
+    }
+
+    /// Store a part of a msg, return false if it is already stored or not match the stored part count
+    pub fn push_part(&mut self, seq: u64, part_index: usize, part_count: usize, msg: &TransportMsg) -> bool {
+        if part_index >= part_count {
+            return false;
+        }
+        match self.msgs.iter_mut().rev().find(|(s, _)| *s == seq) {
+            Some((_, parts)) => {
+                if parts.len() != part_count || parts[part_index].is_some() {
+                    return false;
+                }
+                parts[part_index] = Some(msg.clone());
+            }
+            None => {
+                let mut parts = vec![None; part_count];
+                parts[part_index] = Some(msg.clone());
+                self.msgs.push_back((seq, parts));
+            }
+        }
+        self.parts += 1;
+        while self.parts > self.capacity {
+            match self.msgs.pop_front() {
+                Some((_, parts)) => self.parts -= parts.iter().flatten().count(),
+                None => break,
+            }
+        }
+        true
+    }
+
+    /// All parts of a msg, None if some parts are missing
+    pub fn get(&self, seq: u64) -> Option<Vec<TransportMsg>> {
+        let (_, parts) = self.msgs.iter().rev().find(|(s, _)| *s == seq)?;
+        parts.iter().cloned().collect()
+    }
+}
+
+/// Reorder reliable msgs of a single source, detect gaps and drop duplicates.
+///
+/// The first received msg is used as start point, so a late consumer don't ask for the history.
+/// If a msg with a much lower sequence arrives, the source is considered restarted.
+pub struct ReliableReceiver {
+    window: u64,
+    next_seq: Option<u64>,
+    pending: BTreeMap<u64, Bytes>,
+    ready: VecDeque<Bytes>,
+    gap_since_ms: Option<u64>,
+    /// Missing seqs which are NACKed, with the last NACK time
+    nacked: BTreeMap<u64, u64>,
+}
+
+impl ReliableReceiver {
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            next_seq: None,
+            pending: BTreeMap::new(),
+            ready: VecDeque::new(),
+            gap_since_ms: None,
+            nacked: BTreeMap::new(),
+        }
+    }
+
+    /// Process received msg, return false if it is a duplicate
+    pub fn on_msg(&mut self, now_ms: u64, seq: u64, data: Bytes) -> bool {
+        let next_seq = match self.next_seq {
+            Some(next_seq) if seq.saturating_add(self.window) < next_seq => {
+                log::warn!("[ReliableReceiver] seq {} too far behind {} => source restarted", seq, next_seq);
+                self.pending.clear();
+                self.nacked.clear();
+                seq
+            }
+            Some(next_seq) => next_seq,
+            None => seq,
+        };
+
+        if seq < next_seq || self.pending.contains_key(&seq) {
+            return false;
+        }
+
+        self.pending.insert(seq, data);
+        self.nacked.remove(&seq);
+        self.next_seq = Some(next_seq);
+        self.flush(now_ms);
+        if self.pending.len() as u64 > self.window {
+            self.skip_gap(now_ms);
+        }
+        true
+    }
+
+    /// Missing sequences between the last delivered msg and the highest received msg, at most window sequences
+    pub fn missing(&self) -> Vec<u64> {
+        match (self.next_seq, self.pending.keys().next_back()) {
+            (Some(next_seq), Some(last)) => (next_seq..*last).filter(|seq| !self.pending.contains_key(seq)).take(self.window as usize).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Missing sequences which should be NACKed now, a sequence is NACKed again only after retry_ms without answer
+    pub fn take_nacks(&mut self, now_ms: u64, retry_ms: u64) -> Vec<u64> {
+        let nacks = self
+            .missing()
+            .into_iter()
+            .filter(|seq| !matches!(self.nacked.get(seq), Some(sent_ms) if now_ms < sent_ms + retry_ms))
+            .collect::<Vec<_>>();
+        for seq in &nacks {
+            self.nacked.insert(*seq, now_ms);
+        }
+        nacks
+    }
+
+    /// Timestamp of the first time the current gap was detected
+    pub fn gap_since(&self) -> Option<u64> {
+        self.gap_since_ms
+    }
+
+    /// Give up on the current gap, deliver up to the next missing msg
+    pub fn skip_gap(&mut self, now_ms: u64) {
+        if let Some(first) = self.pending.keys().next() {
+            log::warn!("[ReliableReceiver] skip gap {:?} -> {}", self.next_seq, first);
+            self.next_seq = Some(*first);
+            self.flush(now_ms);
+        }
+    }
+
+    pub fn pop_ready(&mut self) -> Option<Bytes> {
+        self.ready.pop_front()
+    }
+
+    fn flush(&mut self, now_ms: u64) {
+        if let Some(mut next_seq) = self.next_seq {
+            while let Some(data) = self.pending.remove(&next_seq) {
+                self.ready.push_back(data);
+                next_seq = next_seq.saturating_add(1);
+            }
+            self.next_seq = Some(next_seq);
+            self.nacked = self.nacked.split_off(&next_seq);
+        }
+        if self.pending.is_empty() {
+            self.gap_since_ms = None;
+        } else if self.gap_since_ms.is_none() {
+            self.gap_since_ms = Some(now_ms);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atm0s_sdn_network::msg::TransportMsg;
+    use bytes::Bytes;
+
+    use crate::{
+        handler::{CONTROL_META_TYPE, RELIABLE_DATA_TYPE},
+        msg::PubsubRemoteEvent,
+        relay::fragment::parse_fragment_id,
+        ChannelIdentify, PUBSUB_FRAGMENT_MAX_PART_LEN,
+    };
+
+    use super::{build_reliable_msg, build_reliable_msgs, split_reliable_payload, ReliableReceiver, RetransmitBuffer};
+
+    fn msg(seq: u64) -> TransportMsg {
+        build_reliable_msg(ChannelIdentify::new(1000, 1), seq, &[seq as u8])
+    }
+
+    #[test]
+    fn build_and_split() {
+        let msg = build_reliable_msg(ChannelIdentify::new(1000, 1), 258, &[1, 2, 3]);
+        assert_eq!(msg.header.meta, RELIABLE_DATA_TYPE);
+        assert_eq!(msg.header.stream_id, 1000);
+        assert_eq!(msg.header.from_node, Some(1));
+        assert_eq!(split_reliable_payload(msg.payload()), Some((258, [1, 2, 3].as_slice())));
+        assert_eq!(split_reliable_payload(&[1, 2]), None);
+    }
+
+    #[test]
+    fn retransmit_buffer_should_keep_last_msgs() {
+        let mut buffer = RetransmitBuffer::new(2);
+        assert!(buffer.push(1, &msg(1)));
+        assert!(!buffer.push(1, &msg(1)));
+        assert!(buffer.push(2, &msg(2)));
+        assert!(buffer.push(3, &msg(3)));
+        assert_eq!(buffer.get(1), None);
+        assert_eq!(buffer.get(2), Some(vec![msg(2)]));
+        assert_eq!(buffer.get(3), Some(vec![msg(3)]));
+    }
+
+    #[test]
+    fn build_and_split_fragments() {
+        let channel = ChannelIdentify::new(1000, 1);
+        assert_eq!(build_reliable_msgs(channel, 1, &[1, 2, 3]), vec![build_reliable_msg(channel, 1, &[1, 2, 3])]);
+
+        let data: Vec<u8> = (0..PUBSUB_FRAGMENT_MAX_PART_LEN * 2 + 1).map(|i| i as u8).collect();
+        let msgs = build_reliable_msgs(channel, 258, &data);
+        assert_eq!(msgs.len(), 3);
+        let mut joined = vec![];
+        for (index, msg) in msgs.iter().enumerate() {
+            assert_eq!(msg.header.meta, CONTROL_META_TYPE);
+            assert_eq!(msg.header.stream_id, 1000);
+            assert_eq!(msg.header.from_node, Some(1));
+            match msg.get_payload_bincode::<PubsubRemoteEvent>() {
+                Ok(PubsubRemoteEvent::ReliableFragment(fragment_channel, seq, fragment_id, part)) => {
+                    assert_eq!(fragment_channel, channel);
+                    assert_eq!(seq, 258);
+                    assert_eq!(parse_fragment_id(fragment_id), (258, index as u8, 2));
+                    joined.extend_from_slice(&part);
+                }
+                _ => panic!("Should be reliable fragment"),
+            }
+        }
+        assert_eq!(joined, data);
+    }
+
+    #[test]
+    fn retransmit_buffer_should_keep_all_parts() {
+        let msgs = build_reliable_msgs(ChannelIdentify::new(1000, 1), 1, &vec![1; PUBSUB_FRAGMENT_MAX_PART_LEN * 2]);
+        let mut buffer = RetransmitBuffer::new(3);
+        assert!(buffer.push_part(1, 1, 2, &msgs[1]));
+        assert!(!buffer.push_part(1, 1, 2, &msgs[1]));
+        assert!(!buffer.push_part(1, 0, 3, &msgs[0]));
+        assert_eq!(buffer.get(1), None);
+        assert!(buffer.push_part(1, 0, 2, &msgs[0]));
+        assert_eq!(buffer.get(1), Some(msgs.clone()));
+
+        // capacity is counted in parts
+        assert!(buffer.push(2, &msg(2)));
+        assert_eq!(buffer.get(1), Some(msgs));
+        assert!(buffer.push(3, &msg(3)));
+        assert_eq!(buffer.get(1), None);
+        assert_eq!(buffer.get(2), Some(vec![msg(2)]));
+    }
+
+    #[test]
+    fn receiver_in_order() {
+        let mut receiver = ReliableReceiver::new(10);
+        assert!(receiver.on_msg(0, 5, Bytes::from("5")));
+        assert!(receiver.on_msg(0, 6, Bytes::from("6")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("5")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("6")));
+        assert_eq!(receiver.pop_ready(), None);
+        assert_eq!(receiver.missing(), Vec::<u64>::new());
+        assert_eq!(receiver.gap_since(), None);
+    }
+
+    #[test]
+    fn receiver_reorder_and_dedup() {
+        let mut receiver = ReliableReceiver::new(10);
+        assert!(receiver.on_msg(0, 1, Bytes::from("1")));
+        assert!(receiver.on_msg(100, 4, Bytes::from("4")));
+        assert!(!receiver.on_msg(100, 4, Bytes::from("4")));
+        assert!(!receiver.on_msg(100, 1, Bytes::from("1")));
+        assert_eq!(receiver.missing(), vec![2, 3]);
+        assert_eq!(receiver.gap_since(), Some(100));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("1")));
+        assert_eq!(receiver.pop_ready(), None);
+
+        assert!(receiver.on_msg(200, 3, Bytes::from("3")));
+        assert_eq!(receiver.missing(), vec![2]);
+        assert!(receiver.on_msg(200, 2, Bytes::from("2")));
+        assert_eq!(receiver.missing(), Vec::<u64>::new());
+        assert_eq!(receiver.gap_since(), None);
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("2")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("3")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("4")));
+    }
+
+    #[test]
+    fn receiver_skip_gap() {
+        let mut receiver = ReliableReceiver::new(10);
+        assert!(receiver.on_msg(0, 1, Bytes::from("1")));
+        assert!(receiver.on_msg(0, 3, Bytes::from("3")));
+        receiver.skip_gap(100);
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("1")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("3")));
+        assert!(!receiver.on_msg(100, 2, Bytes::from("2")));
+    }
+
+    #[test]
+    fn receiver_window_overflow_should_skip() {
+        let mut receiver = ReliableReceiver::new(2);
+        assert!(receiver.on_msg(0, 1, Bytes::from("1")));
+        assert!(receiver.on_msg(0, 3, Bytes::from("3")));
+        assert!(receiver.on_msg(0, 4, Bytes::from("4")));
+        assert!(receiver.on_msg(0, 5, Bytes::from("5")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("1")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("3")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("4")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("5")));
+    }
+
+    #[test]
+    fn receiver_should_not_overflow() {
+        let mut receiver = ReliableReceiver::new(2);
+        assert!(receiver.on_msg(0, 1, Bytes::from("1")));
+        assert!(receiver.on_msg(0, u64::MAX - 1, Bytes::from("max")));
+        assert_eq!(receiver.missing(), vec![2, 3]);
+        assert!(!receiver.on_msg(0, 1, Bytes::from("1")));
+
+        let mut receiver = ReliableReceiver::new(2);
+        assert!(receiver.on_msg(0, u64::MAX, Bytes::from("max")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("max")));
+    }
+
+    #[test]
+    fn receiver_should_dedup_nacks() {
+        let mut receiver = ReliableReceiver::new(10);
+        assert!(receiver.on_msg(0, 1, Bytes::from("1")));
+        assert!(receiver.on_msg(0, 4, Bytes::from("4")));
+        assert_eq!(receiver.take_nacks(0, 500), vec![2, 3]);
+        assert_eq!(receiver.take_nacks(100, 500), Vec::<u64>::new());
+
+        // new gap is NACKed at once, old missing ones after retry time
+        assert!(receiver.on_msg(100, 6, Bytes::from("6")));
+        assert_eq!(receiver.take_nacks(100, 500), vec![5]);
+        assert!(receiver.on_msg(200, 2, Bytes::from("2")));
+        assert_eq!(receiver.take_nacks(500, 500), vec![3]);
+        assert_eq!(receiver.take_nacks(600, 500), vec![5]);
+    }
+
+    #[test]
+    fn receiver_source_restarted() {
+        let mut receiver = ReliableReceiver::new(2);
+        assert!(receiver.on_msg(0, 100, Bytes::from("100")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("100")));
+        assert!(receiver.on_msg(0, 0, Bytes::from("0")));
+        assert_eq!(receiver.pop_ready(), Some(Bytes::from("0")));
+    }
+}
@@ -6,21 +6,35 @@ use std::{
 use atm0s_sdn_identity::NodeId;
 use atm0s_sdn_utils::awaker::Awaker;
 
-use super::{ChannelUuid, LocalSubId};
+use super::{
+    channel_name::{directory_shard, ChannelPattern},
+    ChannelIdentify, ChannelUuid, LocalSubId,
+};
 
 struct ChannelContainer {
     sources: Vec<NodeId>,
     subs: Vec<LocalSubId>,
 }
 
+struct PatternContainer {
+    pattern: ChannelPattern,
+    subs: Vec<LocalSubId>,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum SourceBindingAction {
     Subscribe(ChannelUuid),
     Unsubscribe(ChannelUuid),
+    SubscribeDirectory(u32),
+    UnsubscribeDirectory(u32),
 }
 
 pub struct SourceBinding {
     channels: HashMap<ChannelUuid, ChannelContainer>,
+    patterns: HashMap<String, PatternContainer>,
+    named_sources: HashMap<ChannelIdentify, String>,
+    /// Number of patterns which need each directory shard
+    directory_shards: HashMap<u32, usize>,
     actions: VecDeque<SourceBindingAction>,
     awaker: Arc<dyn Awaker>,
 }
@@ -29,6 +43,9 @@ impl SourceBinding {
     pub fn new() -> Self {
         Self {
             channels: HashMap::new(),
+            patterns: HashMap::new(),
+            named_sources: HashMap::new(),
+            directory_shards: HashMap::new(),
             actions: VecDeque::new(),
             awaker: Arc::new(atm0s_sdn_utils::awaker::MockAwaker::default()),
         }
@@ -127,6 +144,99 @@ impl SourceBinding {
         }
     }
 
+    /// Subscribe all named channels which match the pattern, directory shards which can contain matched names are subscribed with the first pattern need them.
+    /// Return already known channels which match the pattern
+    pub fn on_local_sub_pattern(&mut self, pattern: &str, sub: LocalSubId) -> Vec<ChannelIdentify> {
+        let container = match self.patterns.entry(pattern.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let pattern = ChannelPattern::new(pattern);
+                for shard in pattern.directory_shards() {
+                    let count = self.directory_shards.entry(shard).or_insert(0);
+                    *count += 1;
+                    if *count == 1 {
+                        self.actions.push_back(SourceBindingAction::SubscribeDirectory(shard));
+                        self.awaker.notify();
+                    }
+                }
+                entry.insert(PatternContainer { pattern, subs: vec![] })
+            }
+        };
+        if container.subs.contains(&sub) {
+            return vec![];
+        }
+        container.subs.push(sub);
+        self.named_sources.iter().filter(|(_, name)| container.pattern.matches(name)).map(|(channel, _)| *channel).collect()
+    }
+
+    /// Return channels which are subscribed by the pattern
+    pub fn on_local_unsub_pattern(&mut self, pattern: &str, sub: LocalSubId) -> Vec<ChannelIdentify> {
+        let container = match self.patterns.get_mut(pattern) {
+            Some(container) => container,
+            None => return vec![],
+        };
+        let index = match container.subs.iter().position(|x| *x == sub) {
+            Some(index) => index,
+            None => return vec![],
+        };
+        container.subs.remove(index);
+        let matched = self.named_sources.iter().filter(|(_, name)| container.pattern.matches(name)).map(|(channel, _)| *channel).collect();
+
+        if container.subs.is_empty() {
+            if let Some(container) = self.patterns.remove(pattern) {
+                for shard in container.pattern.directory_shards() {
+                    if let Some(count) = self.directory_shards.get_mut(&shard) {
+                        *count -= 1;
+                        if *count == 0 {
+                            self.directory_shards.remove(&shard);
+                            self.actions.push_back(SourceBindingAction::UnsubscribeDirectory(shard));
+                            self.awaker.notify();
+                        }
+                    }
+                }
+                let directory_shards = &self.directory_shards;
+                self.named_sources.retain(|_, name| directory_shards.contains_key(&directory_shard(name)));
+            }
+        }
+        matched
+    }
+
+    /// A named channel is advertised in directory, return pattern subscribers which match the name.
+    /// A name which has same uuid with other known name is ignored, because msgs of them can not be distinguished
+    pub fn on_named_source_added(&mut self, channel: ChannelIdentify, name: String) -> Vec<LocalSubId> {
+        if !self.directory_shards.contains_key(&directory_shard(&name)) || self.named_sources.contains_key(&channel) {
+            return vec![];
+        }
+        if let Some((other, other_name)) = self.named_sources.iter().find(|(other, other_name)| other.uuid() == channel.uuid() && **other_name != name) {
+            log::warn!("[SourceBinding] named channel {} {} collides with {} {} => ignore", channel, name, other, other_name);
+            return vec![];
+        }
+        let subs = self.pattern_subs_for(&name);
+        self.named_sources.insert(channel, name);
+        subs
+    }
+
+    /// A named channel is removed from directory, return pattern subscribers which match the name
+    pub fn on_named_source_removed(&mut self, channel: ChannelIdentify) -> Vec<LocalSubId> {
+        match self.named_sources.remove(&channel) {
+            Some(name) => self.pattern_subs_for(&name),
+            None => vec![],
+        }
+    }
+
+    /// Name of a named channel which is known from directory
+    pub fn name_of(&self, channel: ChannelIdentify) -> Option<String> {
+        self.named_sources.get(&channel).cloned()
+    }
+
+    fn pattern_subs_for(&self, name: &str) -> Vec<LocalSubId> {
+        self.patterns
+            .values()
+            .filter(|container| container.pattern.matches(name))
+            .flat_map(|container| container.subs.iter().copied())
+            .collect()
+    }
+
     pub fn sources_for(&self, channel: ChannelUuid) -> Vec<NodeId> {
         self.channels.get(&channel).map(|x| x.sources.clone()).unwrap_or_default()
     }
@@ -146,7 +256,10 @@ mod tests {
 
     use atm0s_sdn_utils::awaker::Awaker;
 
-    use crate::relay::source_binding::SourceBindingAction;
+    use crate::{
+        relay::{channel_name::directory_shard, source_binding::SourceBindingAction},
+        ChannelIdentify, PUBSUB_DIRECTORY_SHARDS,
+    };
 
     use super::SourceBinding;
 
@@ -220,4 +333,96 @@ mod tests {
         assert_eq!(bindding.on_source_removed(1, 1000), None); // already removed
         assert_eq!(bindding.on_source_removed(1, 1001), None); // already removed
     }
+
+    #[test]
+    fn pattern_sub_unsub_should_correct() {
+        let awake = Arc::new(atm0s_sdn_utils::awaker::MockAwaker::default());
+        let mut bindding = SourceBinding::new();
+        bindding.set_awaker(awake.clone());
+
+        let audio1 = ChannelIdentify::new(1, 1000);
+        let audio2 = ChannelIdentify::new(2, 1001);
+        let video1 = ChannelIdentify::new(3, 1000);
+
+        // directory is not subscribed => ignore
+        assert_eq!(bindding.on_named_source_added(audio1, "room/1/audio".to_string()), vec![]);
+
+        assert_eq!(bindding.on_local_sub_pattern("room/*/audio", 10), vec![]);
+        assert_eq!(bindding.pop_action(), Some(SourceBindingAction::SubscribeDirectory(directory_shard("room"))));
+        assert_eq!(bindding.pop_action(), None);
+        assert_eq!(awake.pop_awake_count(), 1);
+
+        assert_eq!(bindding.on_named_source_added(audio1, "room/1/audio".to_string()), vec![10]);
+        assert_eq!(bindding.on_named_source_added(audio1, "room/1/audio".to_string()), vec![]); // already added
+        assert_eq!(bindding.on_named_source_added(video1, "room/1/video".to_string()), vec![]);
+        assert_eq!(bindding.name_of(video1), Some("room/1/video".to_string()));
+
+        let mut channels = bindding.on_local_sub_pattern("room/#", 11);
+        channels.sort_by_key(|c| c.uuid());
+        assert_eq!(channels, vec![audio1, video1]);
+        assert_eq!(bindding.pop_action(), None);
+
+        let mut subs = bindding.on_named_source_added(audio2, "room/2/audio".to_string());
+        subs.sort();
+        assert_eq!(subs, vec![10, 11]);
+        assert_eq!(bindding.on_named_source_removed(video1), vec![11]);
+        assert_eq!(bindding.on_named_source_removed(video1), vec![]); // already removed
+
+        let mut channels = bindding.on_local_unsub_pattern("room/*/audio", 10);
+        channels.sort_by_key(|c| c.uuid());
+        assert_eq!(channels, vec![audio1, audio2]);
+        assert_eq!(bindding.pop_action(), None);
+
+        assert_eq!(bindding.on_local_unsub_pattern("room/*/audio", 10), vec![]); // already unsub
+        assert_eq!(bindding.on_local_unsub_pattern("room/#", 11).len(), 2);
+        assert_eq!(bindding.pop_action(), Some(SourceBindingAction::UnsubscribeDirectory(directory_shard("room"))));
+        assert_eq!(bindding.name_of(audio1), None);
+    }
+
+    #[test]
+    fn pattern_should_sub_needed_directory_shards() {
+        let mut bindding = SourceBinding::new();
+        let room = directory_shard("room");
+        let lobby = directory_shard("lobby");
+        assert_ne!(room, lobby);
+
+        bindding.on_local_sub_pattern("room/#", 10);
+        assert_eq!(bindding.pop_action(), Some(SourceBindingAction::SubscribeDirectory(room)));
+        bindding.on_local_sub_pattern("room/*/audio", 11);
+        assert_eq!(bindding.pop_action(), None);
+
+        // wildcard first segment need all shards, only missing ones are subscribed
+        bindding.on_local_sub_pattern("*/audio", 12);
+        let mut shards = vec![];
+        while let Some(action) = bindding.pop_action() {
+            match action {
+                SourceBindingAction::SubscribeDirectory(shard) => shards.push(shard),
+                _ => panic!("unexpected action {:?}", action),
+            }
+        }
+        assert_eq!(shards.len(), PUBSUB_DIRECTORY_SHARDS as usize - 1);
+        assert!(!shards.contains(&room));
+
+        let lobby_audio = ChannelIdentify::new(1, 1000);
+        assert_eq!(bindding.on_named_source_added(lobby_audio, "lobby/audio".to_string()), vec![12]);
+        assert_eq!(bindding.on_local_unsub_pattern("*/audio", 12), vec![lobby_audio]);
+        assert_eq!(bindding.pop_action().map(|a| matches!(a, SourceBindingAction::UnsubscribeDirectory(_))), Some(true));
+        // names in unsubscribed shards are forgotten
+        assert_eq!(bindding.name_of(lobby_audio), None);
+        assert_eq!(bindding.on_named_source_added(lobby_audio, "lobby/audio".to_string()), vec![]);
+    }
+
+    #[test]
+    fn named_source_collision_should_be_ignored() {
+        let mut bindding = SourceBinding::new();
+        bindding.on_local_sub_pattern("room/#", 10);
+
+        let first = ChannelIdentify::new(1, 1000);
+        let second = ChannelIdentify::new(1, 1001);
+        let other = ChannelIdentify::new(1, 1002);
+        assert_eq!(bindding.on_named_source_added(first, "room/1".to_string()), vec![10]);
+        assert_eq!(bindding.on_named_source_added(second, "room/1".to_string()), vec![10]);
+        assert_eq!(bindding.on_named_source_added(other, "room/2".to_string()), vec![]);
+        assert_eq!(bindding.name_of(other), None);
+    }
 }
@@ -6,15 +6,38 @@ use atm0s_sdn_utils::Timer;
 use bytes::Bytes;
 use parking_lot::RwLock;
 
-use crate::relay::{feedback::Feedback, local::LocalRelay, logic::PubsubRelayLogic, remote::RemoteRelay, source_binding::SourceBinding, ChannelIdentify, ChannelUuid, LocalSubId};
+use crate::relay::{
+    channel_name::named_channel_uuid,
+    feedback::{Feedback, FeedbackProcessorFactory},
+    history::HistoryConfig,
+    local::{LocalRelay, OverflowPolicy},
+    logic::PubsubRelayLogic,
+    remote::RemoteRelay,
+    source_binding::SourceBinding,
+    ChannelIdentify, ChannelUuid, LocalSubId,
+};
 
-use self::{consumer::Consumer, consumer_raw::ConsumerRaw, consumer_single::ConsumerSingle, publisher::Publisher, publisher_raw::PublisherRaw};
+use self::{consumer::Consumer, consumer_pattern::ConsumerPattern, consumer_raw::ConsumerRaw, consumer_single::ConsumerSingle, publisher::Publisher, publisher_raw::PublisherRaw};
 
 pub(crate) mod consumer;
+pub(crate) mod consumer_pattern;
 pub(crate) mod consumer_raw;
 pub(crate) mod consumer_single;
 pub(crate) mod publisher;
 pub(crate) mod publisher_raw;
+pub(crate) mod reliable_rx;
+pub(crate) mod source_selection;
+
+/// Options of a consumer
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SubscribeOptions {
+    /// Deliver msgs of reliable publishers in order and without duplicates, missing msgs are requested with NACK
+    pub reliable: bool,
+    /// Replay history of the channel before live data, history is enabled by the source with `PubsubSdk::set_history`
+    pub replay: bool,
+    /// What to do when the consumer queue is full, dropped msgs are reported to publishers as feedback with id `PUBSUB_OVERFLOW_FEEDBACK_ID`
+    pub overflow: OverflowPolicy,
+}
 
 pub struct PubsubSdk {
     node_id: NodeId,
@@ -63,19 +86,95 @@ impl PubsubSdk {
         }
     }
 
+    /// Keep last msgs of a channel published from this node for replaying to late subscribers, None for disable
+    pub fn set_history(&self, channel: ChannelUuid, config: Option<HistoryConfig>) {
+        self.logic.write().set_history(ChannelIdentify::new(channel, self.node_id), config);
+    }
+
+    /// Aggregate feedbacks with the id by a custom processor, for example `RateLimitedPassthroughProcessor` for keyframe requests.
+    /// Relay nodes aggregate feedbacks too, so the processor should be registered in all nodes
+    pub fn register_feedback_processor(&self, id: u8, factory: FeedbackProcessorFactory) {
+        self.logic.write().register_feedback_processor(id, factory);
+    }
+
+    pub fn unregister_feedback_processor(&self, id: u8) {
+        self.logic.write().unregister_feedback_processor(id);
+    }
+
+    /// Keep last msgs of channels relayed by this node, then replay requests can be answered without asking the source
+    pub fn set_relay_history(&self, config: Option<HistoryConfig>) {
+        self.logic.write().set_relay_history(config);
+    }
+
     pub fn create_publisher(&self, channel: ChannelUuid) -> Publisher {
         let uuid = self.pub_uuid_seed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        Publisher::new(uuid, ChannelIdentify::new(channel, self.node_id), self.logic.clone(), self.remote.clone(), self.local.clone())
+        Publisher::new(
+            uuid,
+            ChannelIdentify::new(channel, self.node_id),
+            self.logic.clone(),
+            self.remote.clone(),
+            self.local.clone(),
+            self.timer.clone(),
+            false,
+        )
+    }
+
+    /// Create a publisher for reliable channel, msgs are sequenced and retransmitted on NACK from reliable consumers
+    pub fn create_publisher_reliable(&self, channel: ChannelUuid) -> Publisher {
+        let uuid = self.pub_uuid_seed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Publisher::new(
+            uuid,
+            ChannelIdentify::new(channel, self.node_id),
+            self.logic.clone(),
+            self.remote.clone(),
+            self.local.clone(),
+            self.timer.clone(),
+            true,
+        )
+    }
+
+    /// Create a publisher for a hierarchical named channel like `room/1/audio`, which can be subscribed with patterns.
+    /// The channel uuid is derived from the name with `named_channel_uuid`
+    pub fn create_publisher_named(&self, name: &str) -> Publisher {
+        let publisher = self.create_publisher(named_channel_uuid(name));
+        self.local.write().on_local_advertise(named_channel_uuid(name), name);
+        publisher
     }
 
     pub fn create_publisher_raw(&self, channel: ChannelUuid, fb_tx: async_std::channel::Sender<Feedback>) -> PublisherRaw {
         let uuid = self.pub_uuid_seed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        PublisherRaw::new(uuid, ChannelIdentify::new(channel, self.node_id), self.logic.clone(), self.remote.clone(), self.local.clone(), fb_tx)
+        PublisherRaw::new(
+            uuid,
+            ChannelIdentify::new(channel, self.node_id),
+            self.logic.clone(),
+            self.remote.clone(),
+            self.local.clone(),
+            fb_tx,
+            self.timer.clone(),
+        )
     }
 
     pub fn create_consumer_single(&self, channel: ChannelIdentify, max_queue_size: Option<usize>) -> ConsumerSingle {
         let uuid = self.sub_uuid_seed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        ConsumerSingle::new(uuid, channel, self.logic.clone(), self.local.clone(), max_queue_size.unwrap_or(100), self.timer.clone())
+        ConsumerSingle::new(
+            uuid,
+            channel,
+            self.logic.clone(),
+            self.local.clone(),
+            max_queue_size.unwrap_or(100),
+            self.timer.clone(),
+            Default::default(),
+        )
+    }
+
+    /// Create a consumer which receive msgs of reliable publisher in order and without duplicates
+    pub fn create_consumer_single_reliable(&self, channel: ChannelIdentify, max_queue_size: Option<usize>) -> ConsumerSingle {
+        self.create_consumer_single_with_options(channel, max_queue_size, SubscribeOptions { reliable: true, ..Default::default() })
+    }
+
+    pub fn create_consumer_single_with_options(&self, channel: ChannelIdentify, max_queue_size: Option<usize>, options: SubscribeOptions) -> ConsumerSingle {
+        let uuid = self.sub_uuid_seed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        ConsumerSingle::new(uuid, channel, self.logic.clone(), self.local.clone(), max_queue_size.unwrap_or(100), self.timer.clone(), options)
     }
 
     pub fn create_consumer_raw(&self, channel: ChannelUuid, tx: Sender<(LocalSubId, NodeId, ChannelUuid, Bytes)>) -> ConsumerRaw {
@@ -83,6 +182,12 @@ impl PubsubSdk {
         ConsumerRaw::new(uuid, channel, self.logic.clone(), self.local.clone(), self.source_binding.clone(), tx, self.timer.clone())
     }
 
+    /// Create a consumer of all named channels matching the pattern, `*` matches one segment and `#` matches all remaining segments
+    pub fn create_consumer_pattern(&self, pattern: &str, max_queue_size: Option<usize>) -> ConsumerPattern {
+        let uuid = self.sub_uuid_seed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        ConsumerPattern::new(uuid, pattern, self.logic.clone(), self.local.clone(), self.source_binding.clone(), max_queue_size.unwrap_or(100))
+    }
+
     pub fn create_consumer(&self, channel: ChannelUuid, max_queue_size: Option<usize>) -> Consumer {
         let uuid = self.sub_uuid_seed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         Consumer::new(
@@ -93,6 +198,26 @@ impl PubsubSdk {
             self.source_binding.clone(),
             max_queue_size.unwrap_or(100),
             self.timer.clone(),
+            Default::default(),
+        )
+    }
+
+    /// Create a consumer which receive msgs of reliable publishers in order and without duplicates, each source is ordered separately
+    pub fn create_consumer_reliable(&self, channel: ChannelUuid, max_queue_size: Option<usize>) -> Consumer {
+        self.create_consumer_with_options(channel, max_queue_size, SubscribeOptions { reliable: true, ..Default::default() })
+    }
+
+    pub fn create_consumer_with_options(&self, channel: ChannelUuid, max_queue_size: Option<usize>, options: SubscribeOptions) -> Consumer {
+        let uuid = self.sub_uuid_seed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Consumer::new(
+            uuid,
+            channel,
+            self.logic.clone(),
+            self.local.clone(),
+            self.source_binding.clone(),
+            max_queue_size.unwrap_or(100),
+            self.timer.clone(),
+            options,
         )
     }
 }
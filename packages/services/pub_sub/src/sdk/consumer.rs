@@ -13,6 +13,13 @@ use crate::relay::{
     ChannelIdentify, ChannelUuid, LocalSubId,
 };
 
+use super::{
+    reliable_rx::ReliableRx,
+    source_selection::{SourceSelection, SourceSelector},
+    SubscribeOptions,
+};
+use crate::relay::flush_local_events;
+
 #[derive(Clone)]
 pub struct Consumer {
     uuid: LocalSubId,
@@ -20,7 +27,7 @@ pub struct Consumer {
     logic: Arc<RwLock<PubsubRelayLogic>>,
     local: Arc<RwLock<LocalRelay>>,
     source_binding: Arc<RwLock<SourceBinding>>,
-    rx: async_std::channel::Receiver<(LocalSubId, NodeId, ChannelUuid, Bytes)>,
+    rx: Arc<ReliableRx>,
     timer: Arc<dyn Timer>,
 }
 
@@ -33,15 +40,19 @@ impl Consumer {
         source_binding: Arc<RwLock<SourceBinding>>,
         max_queue_size: usize,
         timer: Arc<dyn Timer>,
+        options: SubscribeOptions,
     ) -> Self {
         let (tx, rx) = async_std::channel::bounded(max_queue_size);
-        local.write().on_local_sub(uuid, tx);
+        local.write().on_local_sub_sequenced(uuid, tx, rx.clone(), options.overflow);
+        let rx = Arc::new(ReliableRx::new(uuid, channel, rx, options.reliable, logic.clone(), timer.clone()));
+        logic.write().set_local_replay(uuid, options.replay);
         if let Some(sources) = source_binding.write().on_local_sub(channel, uuid) {
             for source in sources {
                 let channel = ChannelIdentify::new(channel, source);
                 logic.write().on_local_sub(channel, uuid);
             }
         }
+        flush_local_events(&logic, &local);
 
         Self {
             uuid,
@@ -73,8 +84,17 @@ impl Consumer {
         }
     }
 
+    /// Which sources are delivered when the channel has multiple sources, msgs from other sources do not use the queue
+    pub fn set_source_selection(&self, selection: SourceSelection) {
+        let selector = match selection {
+            SourceSelection::All => None,
+            selection => Some(SourceSelector::new(selection, self.timer.clone())),
+        };
+        self.local.write().set_source_selector(self.uuid, selector);
+    }
+
     pub async fn recv(&self) -> Option<(LocalSubId, NodeId, ChannelUuid, Bytes)> {
-        self.rx.recv().await.ok()
+        self.rx.recv().await
     }
 }
 
@@ -87,6 +107,8 @@ impl Drop for Consumer {
                 self.logic.write().on_local_unsub(channel, self.uuid);
             }
         }
+        self.logic.write().set_local_replay(self.uuid, false);
+        flush_local_events(&self.logic, &self.local);
     }
 }
 
@@ -97,9 +119,11 @@ mod test {
     use atm0s_sdn_utils::MockTimer;
     use parking_lot::RwLock;
 
+    use bytes::Bytes;
+
     use crate::{
         relay::{local::LocalRelay, logic::PubsubRelayLogic, source_binding::SourceBinding},
-        ChannelIdentify, Consumer,
+        ChannelIdentify, Consumer, SourceSelection,
     };
     #[test]
     fn correct_create_and_destroy() {
@@ -114,7 +138,7 @@ mod test {
         let local = Arc::new(RwLock::new(LocalRelay::new()));
         let timer = Arc::new(MockTimer::default());
         let sub_uuid = 10000;
-        let consumer = Consumer::new(sub_uuid, channel, logic, local, source_binding, 100, timer);
+        let consumer = Consumer::new(sub_uuid, channel, logic, local, source_binding, 100, timer, Default::default());
 
         assert_eq!(
             consumer.logic.read().relay(ChannelIdentify::new(channel, channel_source)),
@@ -129,4 +153,35 @@ mod test {
         assert_eq!(logic.read().relay(ChannelIdentify::new(channel, channel_source)), None);
         assert_eq!(sb.read().consumers_for(channel), vec![]);
     }
+
+    #[async_std::test]
+    async fn primary_source_should_failover() {
+        let channel = 1111;
+        let source_binding = Arc::new(RwLock::new(SourceBinding::new()));
+        source_binding.write().on_source_added(channel, 2);
+        source_binding.write().on_source_added(channel, 3);
+
+        let logic = Arc::new(RwLock::new(PubsubRelayLogic::new(1)));
+        let local = Arc::new(RwLock::new(LocalRelay::new()));
+        let timer = Arc::new(MockTimer::default());
+        let sub_uuid = 10000;
+        // queue is smaller than the number of msgs, msgs of the standby source must not fill it
+        let consumer = Consumer::new(sub_uuid, channel, logic, local.clone(), source_binding, 3, timer.clone(), Default::default());
+        consumer.set_source_selection(SourceSelection::Primary { priority: vec![3], timeout_ms: 1000 });
+
+        local.read().relay(2, channel, &[sub_uuid], None, Bytes::from("standby"));
+        local.read().relay(3, channel, &[sub_uuid], None, Bytes::from("primary1"));
+        local.read().relay(2, channel, &[sub_uuid], None, Bytes::from("standby"));
+        local.read().relay(2, channel, &[sub_uuid], None, Bytes::from("standby"));
+        local.read().relay(3, channel, &[sub_uuid], None, Bytes::from("primary2"));
+        assert_eq!(local.write().pop_overflows(), vec![]);
+        assert_eq!(consumer.recv().await, Some((sub_uuid, 2, channel, Bytes::from("standby"))));
+        assert_eq!(consumer.recv().await, Some((sub_uuid, 3, channel, Bytes::from("primary1"))));
+        assert_eq!(consumer.recv().await, Some((sub_uuid, 3, channel, Bytes::from("primary2"))));
+
+        // primary stopped
+        timer.fake(1000);
+        local.read().relay(2, channel, &[sub_uuid], None, Bytes::from("standby2"));
+        assert_eq!(consumer.recv().await, Some((sub_uuid, 2, channel, Bytes::from("standby2"))));
+    }
 }
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use atm0s_sdn_identity::NodeId;
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::relay::{flush_local_events, local::LocalRelay, logic::PubsubRelayLogic, source_binding::SourceBinding, ChannelIdentify, ChannelUuid, LocalSubId};
+
+/// Consumer of all named channels which match a pattern like `room/*/audio` or `room/#`.
+/// Channels are subscribed and unsubscribed automatically when their publishers appear and disappear.
+pub struct ConsumerPattern {
+    uuid: LocalSubId,
+    pattern: String,
+    logic: Arc<RwLock<PubsubRelayLogic>>,
+    local: Arc<RwLock<LocalRelay>>,
+    source_binding: Arc<RwLock<SourceBinding>>,
+    rx: async_std::channel::Receiver<(LocalSubId, NodeId, ChannelUuid, Option<u64>, Bytes)>,
+}
+
+impl ConsumerPattern {
+    pub fn new(uuid: LocalSubId, pattern: &str, logic: Arc<RwLock<PubsubRelayLogic>>, local: Arc<RwLock<LocalRelay>>, source_binding: Arc<RwLock<SourceBinding>>, max_queue_size: usize) -> Self {
+        let (tx, rx) = async_std::channel::bounded(max_queue_size);
+        local.write().on_local_sub_sequenced(uuid, tx, rx.clone(), Default::default());
+        let channels = source_binding.write().on_local_sub_pattern(pattern, uuid);
+        for channel in channels {
+            logic.write().on_local_sub(channel, uuid);
+        }
+        flush_local_events(&logic, &local);
+
+        Self {
+            uuid,
+            pattern: pattern.to_string(),
+            logic,
+            local,
+            source_binding,
+            rx,
+        }
+    }
+
+    pub fn uuid(&self) -> LocalSubId {
+        self.uuid
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Name of a matched channel, which is the channel of a received msg
+    pub fn name_of(&self, source: NodeId, channel: ChannelUuid) -> Option<String> {
+        self.source_binding.read().name_of(ChannelIdentify::new(channel, source))
+    }
+
+    pub async fn recv(&self) -> Option<(LocalSubId, NodeId, ChannelUuid, Bytes)> {
+        let (uuid, source, channel, _seq, data) = self.rx.recv().await.ok()?;
+        Some((uuid, source, channel, data))
+    }
+}
+
+impl Drop for ConsumerPattern {
+    fn drop(&mut self) {
+        self.local.write().on_local_unsub(self.uuid);
+        let channels = self.source_binding.write().on_local_unsub_pattern(&self.pattern, self.uuid);
+        for channel in channels {
+            self.logic.write().on_local_unsub(channel, self.uuid);
+        }
+        flush_local_events(&self.logic, &self.local);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use parking_lot::RwLock;
+
+    use crate::{
+        relay::{local::LocalRelay, logic::PubsubRelayLogic, source_binding::SourceBinding},
+        ChannelIdentify, ConsumerPattern,
+    };
+
+    #[test]
+    fn correct_create_and_destroy() {
+        let node_id = 1;
+        let audio = ChannelIdentify::new(1111, 2);
+        let video = ChannelIdentify::new(2222, 2);
+        let source_binding = Arc::new(RwLock::new(SourceBinding::new()));
+        let logic = Arc::new(RwLock::new(PubsubRelayLogic::new(node_id)));
+        let local = Arc::new(RwLock::new(LocalRelay::new()));
+        let sub_uuid = 10000;
+        let consumer = ConsumerPattern::new(sub_uuid, "room/*/audio", logic.clone(), local, source_binding.clone(), 100);
+
+        assert_eq!(source_binding.write().on_named_source_added(audio, "room/1/audio".to_string()), vec![sub_uuid]);
+        assert_eq!(source_binding.write().on_named_source_added(video, "room/1/video".to_string()), vec![]);
+        assert_eq!(consumer.name_of(2, 1111), Some("room/1/audio".to_string()));
+
+        // the relay normally subscribes returned subs, do it manually here
+        logic.write().on_local_sub(audio, sub_uuid);
+        drop(consumer);
+
+        assert_eq!(logic.read().relay(audio), None);
+        assert_eq!(source_binding.read().name_of(audio), None);
+    }
+}
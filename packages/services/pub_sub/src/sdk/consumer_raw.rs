@@ -7,12 +7,15 @@ use parking_lot::RwLock;
 
 use crate::relay::{
     feedback::{Feedback, FeedbackConsumerId, FeedbackType},
+    flush_local_events,
     local::LocalRelay,
     logic::PubsubRelayLogic,
     source_binding::SourceBinding,
     ChannelIdentify, ChannelUuid, LocalSubId,
 };
 
+use super::source_selection::{SourceSelection, SourceSelector};
+
 pub struct ConsumerRaw {
     sub_uuid: LocalSubId,
     channel: ChannelUuid,
@@ -39,6 +42,7 @@ impl ConsumerRaw {
                 logic.write().on_local_sub(channel, sub_uuid);
             }
         }
+        flush_local_events(&logic, &local);
 
         Self {
             sub_uuid,
@@ -54,6 +58,15 @@ impl ConsumerRaw {
         self.sub_uuid
     }
 
+    /// Which sources are delivered when the channel has multiple sources, msgs from other sources are not sent to the channel
+    pub fn set_source_selection(&self, selection: SourceSelection) {
+        let selector = match selection {
+            SourceSelection::All => None,
+            selection => Some(SourceSelector::new(selection, self.timer.clone())),
+        };
+        self.local.write().set_source_selector(self.sub_uuid, selector);
+    }
+
     pub fn feedback(&self, id: u8, feedback_type: FeedbackType) {
         let sources = self.source_binding.read().sources_for(self.channel);
         for source in sources {
@@ -79,6 +92,7 @@ impl Drop for ConsumerRaw {
                 self.logic.write().on_local_unsub(channel, self.sub_uuid);
             }
         }
+        flush_local_events(&self.logic, &self.local);
     }
 }
 
@@ -87,11 +101,12 @@ mod test {
     use std::sync::Arc;
 
     use atm0s_sdn_utils::MockTimer;
+    use bytes::Bytes;
     use parking_lot::RwLock;
 
     use crate::{
         relay::{local::LocalRelay, logic::PubsubRelayLogic, source_binding::SourceBinding},
-        ChannelIdentify, ConsumerRaw,
+        ChannelIdentify, ConsumerRaw, SourceSelection,
     };
     #[test]
     fn correct_create_and_destroy() {
@@ -122,4 +137,28 @@ mod test {
         assert_eq!(logic.read().relay(ChannelIdentify::new(channel, channel_source)), None);
         assert_eq!(sb.read().consumers_for(channel), vec![]);
     }
+
+    #[test]
+    fn primary_source_should_filter_before_channel() {
+        let channel = 1111;
+        let source_binding = Arc::new(RwLock::new(SourceBinding::new()));
+        source_binding.write().on_source_added(channel, 2);
+        source_binding.write().on_source_added(channel, 3);
+
+        let logic = Arc::new(RwLock::new(PubsubRelayLogic::new(1)));
+        let local = Arc::new(RwLock::new(LocalRelay::new()));
+        let timer = Arc::new(MockTimer::default());
+        let sub_uuid = 10000;
+        let (tx, rx) = async_std::channel::bounded(2);
+        let consumer = ConsumerRaw::new(sub_uuid, channel, logic, local.clone(), source_binding, tx, timer);
+        consumer.set_source_selection(SourceSelection::Primary { priority: vec![3], timeout_ms: 1000 });
+
+        local.read().relay(3, channel, &[sub_uuid], None, Bytes::from("primary1"));
+        local.read().relay(2, channel, &[sub_uuid], None, Bytes::from("standby"));
+        local.read().relay(3, channel, &[sub_uuid], None, Bytes::from("primary2"));
+        assert_eq!(rx.try_recv(), Ok((sub_uuid, 3, channel, Bytes::from("primary1"))));
+        assert_eq!(rx.try_recv(), Ok((sub_uuid, 3, channel, Bytes::from("primary2"))));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(local.write().pop_overflows(), vec![]);
+    }
 }
@@ -12,20 +12,34 @@ use crate::relay::{
     ChannelIdentify, ChannelUuid, LocalSubId,
 };
 
+use super::{reliable_rx::ReliableRx, SubscribeOptions};
+use crate::relay::flush_local_events;
+
 pub struct ConsumerSingle {
     uuid: LocalSubId,
     channel: ChannelIdentify,
     logic: Arc<RwLock<PubsubRelayLogic>>,
     local: Arc<RwLock<LocalRelay>>,
-    rx: async_std::channel::Receiver<(LocalSubId, NodeId, ChannelUuid, Bytes)>,
+    rx: Arc<ReliableRx>,
     timer: Arc<dyn Timer>,
 }
 
 impl ConsumerSingle {
-    pub fn new(uuid: LocalSubId, channel: ChannelIdentify, logic: Arc<RwLock<PubsubRelayLogic>>, local: Arc<RwLock<LocalRelay>>, max_queue_size: usize, timer: Arc<dyn Timer>) -> Self {
+    pub fn new(
+        uuid: LocalSubId,
+        channel: ChannelIdentify,
+        logic: Arc<RwLock<PubsubRelayLogic>>,
+        local: Arc<RwLock<LocalRelay>>,
+        max_queue_size: usize,
+        timer: Arc<dyn Timer>,
+        options: SubscribeOptions,
+    ) -> Self {
         let (tx, rx) = async_std::channel::bounded(max_queue_size);
+        local.write().on_local_sub_sequenced(uuid, tx, rx.clone(), options.overflow);
+        let rx = Arc::new(ReliableRx::new(uuid, channel.uuid(), rx, options.reliable, logic.clone(), timer.clone()));
+        logic.write().set_local_replay(uuid, options.replay);
         logic.write().on_local_sub(channel, uuid);
-        local.write().on_local_sub(uuid, tx);
+        flush_local_events(&logic, &local);
 
         Self {
             uuid,
@@ -53,14 +67,16 @@ impl ConsumerSingle {
     }
 
     pub async fn recv(&self) -> Option<(LocalSubId, NodeId, ChannelUuid, Bytes)> {
-        self.rx.recv().await.ok()
+        self.rx.recv().await
     }
 }
 
 impl Drop for ConsumerSingle {
     fn drop(&mut self) {
         self.logic.write().on_local_unsub(self.channel, self.uuid);
+        self.logic.write().set_local_replay(self.uuid, false);
         self.local.write().on_local_unsub(self.uuid);
+        flush_local_events(&self.logic, &self.local);
     }
 }
 
@@ -86,7 +102,7 @@ mod test {
         let local = Arc::new(RwLock::new(LocalRelay::new()));
         let timer = Arc::new(MockTimer::default());
         let sub_uuid = 10000;
-        let consumer = ConsumerSingle::new(sub_uuid, ChannelIdentify::new(channel, channel_source), logic, local, 100, timer);
+        let consumer = ConsumerSingle::new(sub_uuid, ChannelIdentify::new(channel, channel_source), logic, local, 100, timer, Default::default());
 
         assert_eq!(
             consumer.logic.read().relay(ChannelIdentify::new(channel, channel_source)),
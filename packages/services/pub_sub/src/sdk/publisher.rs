@@ -1,13 +1,21 @@
 use std::sync::Arc;
 
-use atm0s_sdn_network::msg::{MsgHeader, TransportMsg};
-use atm0s_sdn_router::RouteRule;
+use atm0s_sdn_utils::Timer;
 use bytes::Bytes;
 use parking_lot::RwLock;
 
 use crate::{
-    relay::{feedback::Feedback, local::LocalRelay, logic::PubsubRelayLogic, remote::RemoteRelay, ChannelIdentify, LocalPubId},
-    PUBSUB_SERVICE_ID,
+    relay::{
+        feedback::Feedback,
+        fragment::{build_data_msgs, MAX_FRAGMENTED_MSG_LEN},
+        local::{send_blocked, LocalRelay},
+        logic::PubsubRelayLogic,
+        presence::{ChannelPresence, PresenceEvent},
+        reliable::build_reliable_msgs,
+        remote::RemoteRelay,
+        ChannelIdentify, LocalPubId, LocalSubId,
+    },
+    PUBSUB_PRESENCE_QUEUE_SIZE,
 };
 
 pub struct Publisher {
@@ -17,12 +25,25 @@ pub struct Publisher {
     remote: Arc<RwLock<RemoteRelay>>,
     local: Arc<RwLock<LocalRelay>>,
     fb_rx: async_std::channel::Receiver<Feedback>,
+    presence_rx: async_std::channel::Receiver<PresenceEvent>,
+    timer: Arc<dyn Timer>,
+    reliable: bool,
 }
 
 impl Publisher {
-    pub fn new(uuid: LocalPubId, channel: ChannelIdentify, logic: Arc<RwLock<PubsubRelayLogic>>, remote: Arc<RwLock<RemoteRelay>>, local: Arc<RwLock<LocalRelay>>) -> Self {
+    pub fn new(
+        uuid: LocalPubId,
+        channel: ChannelIdentify,
+        logic: Arc<RwLock<PubsubRelayLogic>>,
+        remote: Arc<RwLock<RemoteRelay>>,
+        local: Arc<RwLock<LocalRelay>>,
+        timer: Arc<dyn Timer>,
+        reliable: bool,
+    ) -> Self {
         let (tx, rx) = async_std::channel::bounded(100);
+        let (presence_tx, presence_rx) = async_std::channel::bounded(PUBSUB_PRESENCE_QUEUE_SIZE);
         local.write().on_local_pub(channel.uuid(), uuid, tx);
+        local.write().on_local_pub_presence(channel.uuid(), uuid, presence_tx, presence_rx.clone());
 
         Self {
             uuid,
@@ -31,6 +52,9 @@ impl Publisher {
             remote,
             local,
             fb_rx: rx,
+            presence_rx,
+            timer,
+            reliable,
         }
     }
 
@@ -38,23 +62,81 @@ impl Publisher {
         self.channel
     }
 
+    /// Consumers with OverflowPolicy::Block and full queue drop the msg, use `send_async` for waiting them
     pub fn send(&self, data: Bytes) {
-        if let Some((remotes, locals)) = self.logic.read().relay(self.channel) {
-            if remotes.len() > 0 {
-                let header = MsgHeader::build(PUBSUB_SERVICE_ID, PUBSUB_SERVICE_ID, RouteRule::Direct)
-                    .set_from_node(Some(self.channel.source()))
-                    .set_stream_id(self.channel.uuid());
-                let msg = TransportMsg::build_raw(header, &data);
+        if let Some((locals, seq)) = self.relay_remote(&data) {
+            self.local.read().relay(self.channel.source(), self.channel.uuid(), &locals, seq, data);
+        }
+    }
+
+    /// Same as `send` but wait for local consumers with OverflowPolicy::Block, the wait happens after relay locks are released
+    pub async fn send_async(&self, data: Bytes) {
+        if let Some((locals, seq)) = self.relay_remote(&data) {
+            let blocked = self.local.read().relay_blocking(self.channel.source(), self.channel.uuid(), &locals, seq, data);
+            send_blocked(&self.local, blocked).await;
+        }
+    }
+
+    /// Relay to remote nodes, return local consumers and sequence number of the msg
+    fn relay_remote(&self, data: &Bytes) -> Option<(Vec<LocalSubId>, Option<u64>)> {
+        if self.reliable {
+            return self.relay_remote_reliable(data).map(|(locals, seq)| (locals, Some(seq)));
+        }
+
+        let has_history = self.logic.read().has_history(self.channel);
+        if has_history {
+            self.logic.write().on_history_msg(self.timer.now_ms(), self.channel, None, data);
+        }
+        let logic = self.logic.read();
+        let (remotes, locals) = logic.relay(self.channel)?;
+        if !remotes.is_empty() {
+            // large msgs are sent as fragments, local consumers still receive the whole msg
+            let msgs = build_data_msgs(self.channel, data, || self.local.write().next_fragment_id(self.channel.uuid()));
+            for msg in msgs {
                 self.remote.read().relay(remotes, &msg);
             }
+        }
+        Some((locals.to_vec(), None))
+    }
 
-            self.local.read().relay(self.channel.source(), self.channel.uuid(), locals, data);
+    /// Each msg get a sequence number of the source node, relay nodes keep last msgs for answering NACK from subscribers.
+    /// Large msgs are sent as parts which share the sequence number
+    fn relay_remote_reliable(&self, data: &Bytes) -> Option<(Vec<LocalSubId>, u64)> {
+        if data.len() > MAX_FRAGMENTED_MSG_LEN {
+            log::warn!("[Publisher] reliable msg of channel {} too large {} > {} => drop", self.channel, data.len(), MAX_FRAGMENTED_MSG_LEN);
+            return None;
+        }
+        let seq = self.local.write().next_seq(self.channel.uuid());
+        let msgs = build_reliable_msgs(self.channel, seq, data);
+        let mut logic = self.logic.write();
+        for (index, msg) in msgs.iter().enumerate() {
+            if !logic.on_reliable_part(self.channel, seq, index, msgs.len(), msg) {
+                return None;
+            }
+        }
+        logic.on_history_msg(self.timer.now_ms(), self.channel, Some(seq), data);
+        let (remotes, locals) = logic.relay(self.channel)?;
+        if !remotes.is_empty() {
+            for msg in &msgs {
+                self.remote.read().relay(remotes, msg);
+            }
         }
+        Some((locals.to_vec(), seq))
     }
 
     pub async fn recv_feedback(&self) -> Option<Feedback> {
         self.fb_rx.recv().await.ok()
     }
+
+    /// Current subscribers of the channel in all nodes
+    pub fn presence(&self) -> ChannelPresence {
+        self.logic.read().presence(self.channel)
+    }
+
+    /// Wait for presence change, for example for starting encoding when the first subscriber come
+    pub async fn recv_presence(&self) -> Option<PresenceEvent> {
+        self.presence_rx.recv().await.ok()
+    }
 }
 
 impl Drop for Publisher {
@@ -1,13 +1,16 @@
 use std::sync::Arc;
 
-use atm0s_sdn_network::msg::{MsgHeader, TransportMsg};
-use atm0s_sdn_router::RouteRule;
+use atm0s_sdn_utils::Timer;
 use bytes::Bytes;
 use parking_lot::RwLock;
 
-use crate::{
-    relay::{feedback::Feedback, local::LocalRelay, logic::PubsubRelayLogic, remote::RemoteRelay, ChannelIdentify, LocalPubId},
-    PUBSUB_SERVICE_ID,
+use crate::relay::{
+    feedback::Feedback,
+    fragment::build_data_msgs,
+    local::{send_blocked, LocalRelay},
+    logic::PubsubRelayLogic,
+    remote::RemoteRelay,
+    ChannelIdentify, LocalPubId, LocalSubId,
 };
 
 pub struct PublisherRaw {
@@ -16,6 +19,7 @@ pub struct PublisherRaw {
     logic: Arc<RwLock<PubsubRelayLogic>>,
     remote: Arc<RwLock<RemoteRelay>>,
     local: Arc<RwLock<LocalRelay>>,
+    timer: Arc<dyn Timer>,
 }
 
 impl PublisherRaw {
@@ -26,28 +30,55 @@ impl PublisherRaw {
         remote: Arc<RwLock<RemoteRelay>>,
         local: Arc<RwLock<LocalRelay>>,
         fb_tx: async_std::channel::Sender<Feedback>,
+        timer: Arc<dyn Timer>,
     ) -> Self {
         local.write().on_local_pub(channel.uuid(), uuid, fb_tx);
 
-        Self { uuid, channel, logic, remote, local }
+        Self {
+            uuid,
+            channel,
+            logic,
+            remote,
+            local,
+            timer,
+        }
     }
 
     pub fn identify(&self) -> ChannelIdentify {
         self.channel
     }
 
+    /// Consumers with OverflowPolicy::Block and full queue drop the msg, use `send_async` for waiting them
     pub fn send(&self, data: Bytes) {
-        if let Some((remotes, locals)) = self.logic.read().relay(self.channel) {
-            if remotes.len() > 0 {
-                let header = MsgHeader::build(PUBSUB_SERVICE_ID, PUBSUB_SERVICE_ID, RouteRule::Direct)
-                    .set_from_node(Some(self.channel.source()))
-                    .set_stream_id(self.channel.uuid());
-                let msg = TransportMsg::build_raw(header, &data);
+        if let Some(locals) = self.relay_remote(&data) {
+            self.local.read().relay(self.channel.source(), self.channel.uuid(), &locals, None, data);
+        }
+    }
+
+    /// Same as `send` but wait for local consumers with OverflowPolicy::Block, the wait happens after relay locks are released
+    pub async fn send_async(&self, data: Bytes) {
+        if let Some(locals) = self.relay_remote(&data) {
+            let blocked = self.local.read().relay_blocking(self.channel.source(), self.channel.uuid(), &locals, None, data);
+            send_blocked(&self.local, blocked).await;
+        }
+    }
+
+    /// Relay to remote nodes, return local consumers of the msg
+    fn relay_remote(&self, data: &Bytes) -> Option<Vec<LocalSubId>> {
+        let has_history = self.logic.read().has_history(self.channel);
+        if has_history {
+            self.logic.write().on_history_msg(self.timer.now_ms(), self.channel, None, data);
+        }
+        let logic = self.logic.read();
+        let (remotes, locals) = logic.relay(self.channel)?;
+        if !remotes.is_empty() {
+            // large msgs are sent as fragments, local consumers still receive the whole msg
+            let msgs = build_data_msgs(self.channel, data, || self.local.write().next_fragment_id(self.channel.uuid()));
+            for msg in msgs {
                 self.remote.read().relay(remotes, &msg);
             }
-
-            self.local.read().relay(self.channel.source(), self.channel.uuid(), locals, data);
         }
+        Some(locals.to_vec())
     }
 }
 
@@ -0,0 +1,229 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use atm0s_sdn_identity::NodeId;
+use atm0s_sdn_utils::Timer;
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+
+use crate::{
+    relay::{
+        logic::PubsubRelayLogic,
+        reliable::{split_reliable_payload, ReliableReceiver},
+        ChannelIdentify, ChannelUuid, LocalSubId,
+    },
+    PUBSUB_RELIABLE_BUFFER_SIZE, PUBSUB_RELIABLE_GAP_TIMEOUT_MS, PUBSUB_RELIABLE_NACK_INTERVAL_MS, PUBSUB_RELIABLE_NACK_RETRY_MS,
+};
+
+struct ReliableState {
+    sources: HashMap<NodeId, ReliableReceiver>,
+    ready: VecDeque<(LocalSubId, NodeId, ChannelUuid, Bytes)>,
+    last_nack_ms: u64,
+}
+
+impl ReliableState {
+    fn on_msg(&mut self, now_ms: u64, uuid: LocalSubId, source: NodeId, channel: ChannelUuid, seq: Option<u64>, data: Bytes) {
+        if let Some(seq) = seq {
+            let receiver = self.sources.entry(source).or_insert_with(|| ReliableReceiver::new(PUBSUB_RELIABLE_BUFFER_SIZE as u64));
+            if !receiver.on_msg(now_ms, seq, data) {
+                log::trace!("[ReliableRx] drop duplicated msg {} from {}/{}", seq, channel, source);
+            }
+            while let Some(data) = receiver.pop_ready() {
+                self.ready.push_back((uuid, source, channel, data));
+            }
+        } else {
+            self.ready.push_back((uuid, source, channel, data));
+        }
+    }
+
+    fn has_gap(&self) -> bool {
+        self.sources.values().any(|r| r.gap_since().is_some())
+    }
+
+    /// Skip gaps which are too old, and return missing msgs which should be NACKed now.
+    /// NACK is checked each PUBSUB_RELIABLE_NACK_INTERVAL_MS, and each missing msg is NACKed again only after PUBSUB_RELIABLE_NACK_RETRY_MS
+    fn process_gaps(&mut self, now_ms: u64, uuid: LocalSubId, channel: ChannelUuid) -> Vec<(NodeId, Vec<u64>)> {
+        let nack = now_ms >= self.last_nack_ms + PUBSUB_RELIABLE_NACK_INTERVAL_MS;
+        let mut nacks = vec![];
+        for (source, receiver) in self.sources.iter_mut() {
+            if let Some(since) = receiver.gap_since() {
+                if now_ms >= since + PUBSUB_RELIABLE_GAP_TIMEOUT_MS {
+                    receiver.skip_gap(now_ms);
+                } else if nack {
+                    let missing = receiver.take_nacks(now_ms, PUBSUB_RELIABLE_NACK_RETRY_MS);
+                    if !missing.is_empty() {
+                        nacks.push((*source, missing));
+                    }
+                }
+            }
+            while let Some(data) = receiver.pop_ready() {
+                self.ready.push_back((uuid, *source, channel, data));
+            }
+        }
+
+        if nack {
+            self.last_nack_ms = now_ms;
+        }
+        nacks
+    }
+}
+
+/// Receiving side of a local consumer.
+///
+/// In reliable mode msgs from each source are delivered in order and without duplicates,
+/// missing msgs are requested with NACK and skipped after PUBSUB_RELIABLE_GAP_TIMEOUT_MS.
+pub(crate) struct ReliableRx {
+    uuid: LocalSubId,
+    channel: ChannelUuid,
+    rx: async_std::channel::Receiver<(LocalSubId, NodeId, ChannelUuid, Option<u64>, Bytes)>,
+    state: Option<Mutex<ReliableState>>,
+    logic: Arc<RwLock<PubsubRelayLogic>>,
+    timer: Arc<dyn Timer>,
+}
+
+impl ReliableRx {
+    pub fn new(
+        uuid: LocalSubId,
+        channel: ChannelUuid,
+        rx: async_std::channel::Receiver<(LocalSubId, NodeId, ChannelUuid, Option<u64>, Bytes)>,
+        reliable: bool,
+        logic: Arc<RwLock<PubsubRelayLogic>>,
+        timer: Arc<dyn Timer>,
+    ) -> Self {
+        let state = reliable.then(|| {
+            Mutex::new(ReliableState {
+                sources: HashMap::new(),
+                ready: VecDeque::new(),
+                last_nack_ms: 0,
+            })
+        });
+        Self {
+            uuid,
+            channel,
+            rx,
+            state,
+            logic,
+            timer,
+        }
+    }
+
+    pub async fn recv(&self) -> Option<(LocalSubId, NodeId, ChannelUuid, Bytes)> {
+        let state = match &self.state {
+            Some(state) => state,
+            None => {
+                let (uuid, source, channel, _seq, data) = self.rx.recv().await.ok()?;
+                return Some((uuid, source, channel, data));
+            }
+        };
+
+        loop {
+            if let Some(msg) = state.lock().ready.pop_front() {
+                return Some(msg);
+            }
+
+            let has_gap = state.lock().has_gap();
+            let msg = if has_gap {
+                match async_std::future::timeout(Duration::from_millis(PUBSUB_RELIABLE_NACK_INTERVAL_MS), self.rx.recv()).await {
+                    Ok(res) => Some(res.ok()?),
+                    Err(_) => None,
+                }
+            } else {
+                Some(self.rx.recv().await.ok()?)
+            };
+
+            let now_ms = self.timer.now_ms();
+            let nacks = {
+                let mut state = state.lock();
+                if let Some((uuid, source, channel, seq, data)) = msg {
+                    state.on_msg(now_ms, uuid, source, channel, seq, data);
+                }
+                state.process_gaps(now_ms, self.uuid, self.channel)
+            };
+
+            //state lock is released before taking the logic lock, which is also taken by the relay
+            for (source, missing) in nacks {
+                log::debug!("[ReliableRx] consumer {} channel {}/{} missing {:?} => nack", self.uuid, self.channel, source, missing);
+                let msgs = self.logic.write().on_local_nack(ChannelIdentify::new(self.channel, source), missing);
+                let mut state = state.lock();
+                for msg in msgs {
+                    if let Some((seq, data)) = split_reliable_payload(msg.payload()) {
+                        state.on_msg(now_ms, self.uuid, source, self.channel, Some(seq), Bytes::from(data.to_vec()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use atm0s_sdn_utils::MockTimer;
+    use bytes::Bytes;
+    use parking_lot::RwLock;
+
+    use crate::{
+        msg::PubsubRemoteEvent,
+        relay::{logic::PubsubRelayLogic, reliable::build_reliable_msg},
+        ChannelIdentify,
+    };
+
+    use super::ReliableRx;
+
+    #[async_std::test]
+    async fn should_reorder_dedup_and_recover_from_local_buffer() {
+        let channel = ChannelIdentify::new(1000, 2);
+        let logic = Arc::new(RwLock::new(PubsubRelayLogic::new(1)));
+        logic.write().on_local_sub(channel, 10);
+        let msg2 = build_reliable_msg(channel, 2, &[2]);
+        assert!(logic.write().on_reliable_msg(channel, 2, &msg2));
+
+        let timer = Arc::new(MockTimer::default());
+        let (tx, rx) = async_std::channel::bounded(10);
+        let reliable_rx = ReliableRx::new(10, 1000, rx, true, logic.clone(), timer.clone());
+
+        tx.try_send((10, 2, 1000, Some(1), Bytes::from_static(&[1]))).expect("Should send");
+        tx.try_send((10, 2, 1000, Some(3), Bytes::from_static(&[3]))).expect("Should send");
+        tx.try_send((10, 2, 1000, Some(1), Bytes::from_static(&[1]))).expect("Should send");
+        tx.try_send((10, 2, 1000, None, Bytes::from_static(&[100]))).expect("Should send");
+
+        assert_eq!(reliable_rx.recv().await, Some((10, 2, 1000, Bytes::from_static(&[1]))));
+        timer.fake(200);
+        // msg 2 is found in local retransmit buffer
+        assert_eq!(reliable_rx.recv().await, Some((10, 2, 1000, Bytes::from_static(&[2]))));
+        assert_eq!(reliable_rx.recv().await, Some((10, 2, 1000, Bytes::from_static(&[3]))));
+        assert_eq!(reliable_rx.recv().await, Some((10, 2, 1000, Bytes::from_static(&[100]))));
+        // channel not acked yet, so no NACK to next node
+        assert_eq!(
+            logic.write().pop_action(),
+            Some((2, None, crate::relay::logic::PubsubRelayLogicOutput::Event(PubsubRemoteEvent::Sub(channel))))
+        );
+        assert_eq!(logic.write().pop_action(), None);
+    }
+
+    #[async_std::test]
+    async fn should_skip_timeout_gap() {
+        let channel = ChannelIdentify::new(1000, 2);
+        let logic = Arc::new(RwLock::new(PubsubRelayLogic::new(1)));
+        logic.write().on_local_sub(channel, 10);
+
+        let timer = Arc::new(MockTimer::default());
+        let (tx, rx) = async_std::channel::bounded(10);
+        let reliable_rx = ReliableRx::new(10, 1000, rx, true, logic.clone(), timer.clone());
+
+        tx.try_send((10, 2, 1000, Some(1), Bytes::from_static(&[1]))).expect("Should send");
+        tx.try_send((10, 2, 1000, Some(3), Bytes::from_static(&[3]))).expect("Should send");
+        assert_eq!(reliable_rx.recv().await, Some((10, 2, 1000, Bytes::from_static(&[1]))));
+
+        let timer_c = timer.clone();
+        async_std::task::spawn(async move {
+            async_std::task::sleep(Duration::from_millis(50)).await;
+            timer_c.fake(crate::PUBSUB_RELIABLE_GAP_TIMEOUT_MS);
+        });
+        assert_eq!(reliable_rx.recv().await, Some((10, 2, 1000, Bytes::from_static(&[3]))));
+    }
+}
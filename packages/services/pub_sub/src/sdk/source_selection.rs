@@ -0,0 +1,102 @@
+use std::{collections::HashMap, sync::Arc};
+
+use atm0s_sdn_identity::NodeId;
+use atm0s_sdn_utils::Timer;
+
+/// How a consumer handles a channel which is published from multiple sources
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum SourceSelection {
+    /// Deliver msgs from all sources
+    #[default]
+    All,
+    /// Deliver msgs only from the best alive source, sources in `priority` come first in that order,
+    /// others follow ordered by node id. A source is alive if it sent a msg in the last `timeout_ms`,
+    /// so the consumer fails over to the next source when the primary stops and switches back when it resumes.
+    /// All sources are still subscribed for hot-standby.
+    Primary { priority: Vec<NodeId>, timeout_ms: u64 },
+}
+
+/// Per consumer state of SourceSelection, msgs are filtered before entering the consumer queue
+pub(crate) struct SourceSelector {
+    selection: SourceSelection,
+    last_seen: HashMap<NodeId, u64>,
+    current: Option<NodeId>,
+    timer: Arc<dyn Timer>,
+}
+
+impl SourceSelector {
+    pub fn new(selection: SourceSelection, timer: Arc<dyn Timer>) -> Self {
+        Self {
+            selection,
+            last_seen: HashMap::new(),
+            current: None,
+            timer,
+        }
+    }
+
+    /// Same as `accept` with current time
+    pub fn accept_now(&mut self, source: NodeId) -> bool {
+        let now_ms = self.timer.now_ms();
+        self.accept(now_ms, source)
+    }
+
+    /// Return true if the msg from source should be delivered
+    pub fn accept(&mut self, now_ms: u64, source: NodeId) -> bool {
+        let (priority, timeout_ms) = match &self.selection {
+            SourceSelection::All => return true,
+            SourceSelection::Primary { priority, timeout_ms } => (priority, *timeout_ms),
+        };
+
+        self.last_seen.insert(source, now_ms);
+        self.last_seen.retain(|_, ts| now_ms < *ts + timeout_ms);
+        let rank = |node: &NodeId| (priority.iter().position(|p| p == node).unwrap_or(priority.len()), *node);
+        let best = self.last_seen.keys().min_by_key(|node| rank(node)).copied();
+        if best != self.current {
+            log::info!("[SourceSelector] switch source {:?} => {:?}", self.current, best);
+            self.current = best;
+        }
+        self.current == Some(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use atm0s_sdn_utils::MockTimer;
+
+    use super::{SourceSelection, SourceSelector};
+
+    #[test]
+    fn all_should_accept_all() {
+        let mut selector = SourceSelector::new(SourceSelection::All, Arc::new(MockTimer::default()));
+        assert!(selector.accept(0, 1));
+        assert!(selector.accept(0, 2));
+    }
+
+    #[test]
+    fn primary_should_failover_and_back() {
+        let mut selector = SourceSelector::new(SourceSelection::Primary { priority: vec![2], timeout_ms: 100 }, Arc::new(MockTimer::default()));
+        assert!(selector.accept(0, 1));
+        assert!(selector.accept(10, 2));
+        assert!(!selector.accept(20, 1));
+        assert!(selector.accept(50, 2));
+
+        // source 2 stopped
+        assert!(!selector.accept(100, 1));
+        assert!(selector.accept(150, 1));
+        assert!(selector.accept(200, 1));
+
+        // source 2 resumed
+        assert!(selector.accept(210, 2));
+        assert!(!selector.accept(220, 1));
+    }
+
+    #[test]
+    fn unlisted_sources_ordered_by_node_id() {
+        let mut selector = SourceSelector::new(SourceSelection::Primary { priority: vec![], timeout_ms: 100 }, Arc::new(MockTimer::default()));
+        assert!(selector.accept(0, 5));
+        assert!(selector.accept(0, 3));
+        assert!(!selector.accept(0, 5));
+    }
+}
@@ -22,6 +22,9 @@ impl RpcEmitter {
         self.rpc_queue.lock().add_event(self.timer.now_ms(), to_service, rule, cmd, event);
     }
 
+    /// Send a request and wait for the answer.
+    /// With `RouteRule::ToService(ServiceSelector::Balanced(n).to_meta())` requests are spread over the n nearest nodes which run the service,
+    /// the node is selected when the request is sent and the request is routed to it.
     pub async fn request<Req: Into<Vec<u8>>, Res: for<'a> TryFrom<&'a [u8]>>(&self, to_service: u8, rule: RouteRule, cmd: &str, req: Req, timeout_ms: u64) -> Result<Res, RpcError> {
         let (tx, rx) = bounded(1);
         self.rpc_queue.lock().add_request(self.timer.now_ms(), to_service, rule, cmd, req, tx, timeout_ms);
@@ -6,7 +6,7 @@ use async_std::net::{Shutdown, TcpStream};
 use async_std::task::JoinHandle;
 use atm0s_sdn_identity::{ConnId, NodeAddr, NodeId};
 use atm0s_sdn_network::msg::TransportMsg;
-use atm0s_sdn_network::transport::{ConnectionEvent, ConnectionReceiver, ConnectionSender, ConnectionStats};
+use atm0s_sdn_network::transport::{ConnectionEvent, ConnectionReceiver, ConnectionSender, ConnectionStatsEstimator, TrafficCounter};
 use atm0s_sdn_utils::error_handle::ErrorUtils;
 use atm0s_sdn_utils::option_handle::OptionUtils;
 use atm0s_sdn_utils::Timer;
@@ -41,6 +41,7 @@ pub struct TcpConnectionSender {
     unreliable_sender: Sender<OutgoingEvent>,
     task: Option<JoinHandle<()>>,
     snow_state: Arc<Mutex<TransportState>>,
+    sent: Arc<TrafficCounter>,
     tmp_buf: Arc<Mutex<[u8; 1500]>>,
 }
 
@@ -54,6 +55,7 @@ impl TcpConnectionSender {
         mut socket: AsyncBincodeStreamU16,
         timer: Arc<dyn Timer>,
         snow_state: Arc<Mutex<TransportState>>,
+        sent: Arc<TrafficCounter>,
     ) -> (Self, Sender<OutgoingEvent>) {
         let (unreliable_sender, unr_rx) = bounded(unreliable_queue_size);
 
@@ -102,6 +104,7 @@ impl TcpConnectionSender {
                 unreliable_sender: unreliable_sender.clone(),
                 task: Some(task),
                 snow_state,
+                sent,
                 tmp_buf: Arc::new(Mutex::new([0; 1500])),
             },
             unreliable_sender,
@@ -132,9 +135,11 @@ impl ConnectionSender for TcpConnectionSender {
             msg.take()
         };
 
+        let len = buf.len();
         if let Err(e) = self.unreliable_sender.try_send(OutgoingEvent::Msg(TcpMsg::Msg(buf))) {
             log::error!("[ConnectionSender] send unreliable msg error {:?}", e);
         } else {
+            self.sent.on_packet(len);
             log::debug!("[ConnectionSender] send unreliable msg");
         }
     }
@@ -175,6 +180,7 @@ pub struct TcpConnectionReceiver {
     pub(crate) unreliable_sender: Sender<OutgoingEvent>,
     pub(crate) snow_state: Arc<Mutex<TransportState>>,
     pub(crate) snow_buf: [u8; 1500],
+    pub(crate) stats: ConnectionStatsEstimator,
 }
 
 #[async_trait::async_trait]
@@ -224,15 +230,10 @@ impl ConnectionReceiver for TcpConnectionReceiver {
                             self.unreliable_sender.try_send(OutgoingEvent::Msg(TcpMsg::Pong(sent_ts))).print_error("Should send Pong");
                         }
                         TcpMsg::Pong(ping_sent_ts) => {
-                            //TODO est speed and over_use state
                             log::debug!("[ConnectionReceiver {}/{}] on Pong", self.remote_node_id, self.conn_id);
-                            break Ok(ConnectionEvent::Stats(ConnectionStats {
-                                rtt_ms: (self.timer.now_ms() - ping_sent_ts) as u16,
-                                sending_kbps: 0,
-                                send_est_kbps: 0,
-                                loss_percent: 0,
-                                over_use: false,
-                            }));
+                            //tcp is reliable, congestion only shows as growing rtt
+                            let now_ms = self.timer.now_ms();
+                            break Ok(ConnectionEvent::Stats(self.stats.on_rtt(now_ms, (now_ms - ping_sent_ts) as u16, 0)));
                         }
                         _ => {
                             log::warn!("[ConnectionReceiver {}/{}] wrong msg type, required TcpMsg::Msg", self.remote_node_id, self.conn_id);
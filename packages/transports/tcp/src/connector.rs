@@ -7,7 +7,7 @@ use async_std::channel::Sender;
 use async_std::net::{Shutdown, TcpStream};
 use atm0s_sdn_identity::{ConnId, NodeAddr, NodeId, Protocol};
 use atm0s_sdn_network::secure::DataSecure;
-use atm0s_sdn_network::transport::{OutgoingConnectionError, TransportConnector, TransportEvent};
+use atm0s_sdn_network::transport::{ConnectionStatsEstimator, OutgoingConnectionError, TrafficCounter, TransportConnector, TransportEvent};
 use atm0s_sdn_utils::error_handle::ErrorUtils;
 use atm0s_sdn_utils::Timer;
 use parking_lot::Mutex;
@@ -74,8 +74,19 @@ impl TransportConnector for TcpConnector {
                         match outgoing_handshake(secure, remote_node_id, node_id, node_addr, &mut socket_read, conn_id, &internal_tx).await {
                             Ok(snow_state) => {
                                 let snow_state = Arc::new(Mutex::new(snow_state));
-                                let (connection_sender, unreliable_sender) =
-                                    TcpConnectionSender::new(node_id, remote_node_id, remote_node_addr.clone(), conn_id, 1000, socket_write, timer.clone(), snow_state.clone());
+                                let sent = Arc::new(TrafficCounter::default());
+                                let now_ms = timer.now_ms();
+                                let (connection_sender, unreliable_sender) = TcpConnectionSender::new(
+                                    node_id,
+                                    remote_node_id,
+                                    remote_node_addr.clone(),
+                                    conn_id,
+                                    1000,
+                                    socket_write,
+                                    timer.clone(),
+                                    snow_state.clone(),
+                                    sent.clone(),
+                                );
                                 let connection_receiver = Box::new(TcpConnectionReceiver {
                                     remote_node_id,
                                     remote_addr: remote_node_addr,
@@ -85,6 +96,7 @@ impl TransportConnector for TcpConnector {
                                     unreliable_sender,
                                     snow_state,
                                     snow_buf: [0u8; 1500],
+                                    stats: ConnectionStatsEstimator::new(now_ms, sent),
                                 });
                                 internal_tx
                                     .send(TransportEvent::Outgoing(Arc::new(connection_sender), connection_receiver))
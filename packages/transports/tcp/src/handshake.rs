@@ -10,7 +10,23 @@ use std::ops::Deref;
 use std::sync::Arc;
 use std::time::Duration;
 
-static SNOW_PATTERN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+static SNOW_PATTERN_NN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+static SNOW_PATTERN_XX: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Build the snow handshake state, with Noise_XX if DataSecure provides a static key, otherwise the legacy Noise_NN.
+/// With Noise_XX the client sends a third ConnectConfirm message, and each side checks that the remote static key
+/// belongs to the node which signed the ConnectRequest/ConnectResponse.
+fn build_snow_handshake(static_key: Option<&[u8; 32]>, initiator: bool) -> Result<snow::HandshakeState, snow::Error> {
+    let builder = match static_key {
+        Some(key) => snow::Builder::new(SNOW_PATTERN_XX.parse()?).local_private_key(key),
+        None => snow::Builder::new(SNOW_PATTERN_NN.parse()?),
+    };
+    if initiator {
+        builder.build_initiator()
+    } else {
+        builder.build_responder()
+    }
+}
 
 pub enum IncomingHandshakeError {
     SocketError,
@@ -31,17 +47,18 @@ pub async fn incoming_handshake(
     log::info!("[TcpTransport] handshake wait ConnectRequest");
 
     let mut snow_buf = [0; 1500];
-    let mut snow_responder = snow::Builder::new(SNOW_PATTERN.parse().expect("")).build_responder().expect("");
+    let static_key = secure.handshake_static_key();
+    let mut snow_responder = build_snow_handshake(static_key.as_ref(), false).map_err(|_| IncomingHandshakeError::InternalError)?;
 
     let msg = async_std::future::timeout(Duration::from_secs(5), recv_tcp_stream(socket))
         .await
         .map_err(|_| IncomingHandshakeError::Timeout)?
         .map_err(|_| IncomingHandshakeError::SocketError)?;
-    let (remote_node, remote_addr, snow_handshake) = match msg {
+    let (remote_node, remote_addr, snow_handshake, req_sig) = match msg {
         TcpMsg::ConnectRequest(req, sig) => {
-            if req.remote_node_id == my_node && ObjectSecure::verify_obj(secure.deref(), req.remote_node_id, &req, &sig) {
+            if req.remote_node_id == my_node && ObjectSecure::verify_obj_from(secure.deref(), req.node_id, req.remote_node_id, &req, &sig) {
                 log::info!("[TcpTransport] handshake from {} {}", req.node_id, req.node_addr);
-                (req.node_id, req.node_addr, req.snow_handshake)
+                (req.node_id, req.node_addr, req.snow_handshake, sig)
             } else {
                 log::warn!("[TcpTransport] handshake from wrong node info {} vs {}", req.remote_node_id, my_node);
                 let sig = ObjectSecure::sign_obj(secure.deref(), req.node_id, &HandshakeResult::AuthenticationError);
@@ -84,12 +101,41 @@ pub async fn incoming_handshake(
             HandshakeResult::AuthenticationError
         }
     };
+    let success = matches!(handshake_res, HandshakeResult::Success(_));
     let sig = ObjectSecure::sign_obj(secure.deref(), remote_node, &handshake_res);
     send_tcp_stream(socket, TcpMsg::ConnectResponse(handshake_res, sig))
         .await
         .print_error("Should send handshake response error: Ok");
+    if !success {
+        return Err(IncomingHandshakeError::ValidateError);
+    }
 
-    Ok((remote_node, remote_addr, snow_responder.into_transport_mode().expect("Should be transport mode")))
+    if static_key.is_some() {
+        log::info!("[TcpTransport] handshake wait ConnectConfirm from {}", remote_node);
+        let msg = async_std::future::timeout(Duration::from_secs(5), recv_tcp_stream(socket))
+            .await
+            .map_err(|_| IncomingHandshakeError::Timeout)?
+            .map_err(|_| IncomingHandshakeError::SocketError)?;
+        match msg {
+            TcpMsg::ConnectConfirm(snow_confirm) => {
+                if let Err(e) = snow_responder.read_message(&snow_confirm, &mut snow_buf) {
+                    log::error!("[TcpTransport] handshake snow confirm error {:?}", e);
+                    return Err(IncomingHandshakeError::WrongMsg);
+                }
+                let remote_static = snow_responder.get_remote_static().unwrap_or(&[]);
+                if !secure.verify_handshake_static_key(remote_node, remote_static, &req_sig) {
+                    log::warn!("[TcpTransport] handshake confirm from {} with wrong static key", remote_node);
+                    return Err(IncomingHandshakeError::ValidateError);
+                }
+            }
+            _ => {
+                log::warn!("[TcpTransport] handshake wrong msg, waiting ConnectConfirm");
+                return Err(IncomingHandshakeError::WrongMsg);
+            }
+        }
+    }
+
+    Ok((remote_node, remote_addr, snow_responder.into_transport_mode().map_err(|_| IncomingHandshakeError::InternalError)?))
 }
 
 #[derive(Debug)]
@@ -112,7 +158,8 @@ pub async fn outgoing_handshake(
 ) -> Result<TransportState, OutgoingHandshakeError> {
     log::info!("[TcpTransport] outgoing_handshake send ConnectRequest to {}", remote_node);
     let mut buf = [0; 1500];
-    let mut snow_initiator = snow::Builder::new(SNOW_PATTERN.parse().expect("")).build_initiator().expect("");
+    let static_key = secure.handshake_static_key();
+    let mut snow_initiator = build_snow_handshake(static_key.as_ref(), true).expect("Should build snow initiator");
     let snow_hanshake_len = snow_initiator.write_message(&[], &mut buf).expect("");
 
     let req = HandshakeRequest {
@@ -134,18 +181,31 @@ pub async fn outgoing_handshake(
         .map_err(|_| OutgoingHandshakeError::SocketError)?;
     match msg {
         TcpMsg::ConnectResponse(res, sig) => {
-            if ObjectSecure::verify_obj(secure.deref(), my_node, &res, &sig) {
+            if ObjectSecure::verify_obj_from(secure.deref(), remote_node, my_node, &res, &sig) {
                 match res {
                     HandshakeResult::Success(snow_response) => {
                         log::info!("[TcpTransport] outgoing_handshake ConnectResponse from {} success", remote_node);
                         match snow_initiator.read_message(&snow_response, &mut buf) {
-                            Ok(_) => match snow_initiator.into_transport_mode() {
-                                Ok(state) => Ok(state),
-                                Err(e) => {
-                                    log::error!("[TcpTransport] received hanshake snow into_transport_mode error {:?}", e);
-                                    Err(OutgoingHandshakeError::AuthenticationError)
+                            Ok(_) => {
+                                if static_key.is_some() {
+                                    let remote_static = snow_initiator.get_remote_static().unwrap_or(&[]);
+                                    if !secure.verify_handshake_static_key(remote_node, remote_static, &sig) {
+                                        log::warn!("[TcpTransport] outgoing_handshake ConnectResponse from {} with wrong static key", remote_node);
+                                        return Err(OutgoingHandshakeError::AuthenticationError);
+                                    }
+                                    let len = snow_initiator.write_message(&[], &mut buf).map_err(|_| OutgoingHandshakeError::AuthenticationError)?;
+                                    send_tcp_stream(socket, TcpMsg::ConnectConfirm(buf[..len].to_vec()))
+                                        .await
+                                        .map_err(|_| OutgoingHandshakeError::SocketError)?;
+                                }
+                                match snow_initiator.into_transport_mode() {
+                                    Ok(state) => Ok(state),
+                                    Err(e) => {
+                                        log::error!("[TcpTransport] received hanshake snow into_transport_mode error {:?}", e);
+                                        Err(OutgoingHandshakeError::AuthenticationError)
+                                    }
                                 }
-                            },
+                            }
                             Err(e) => {
                                 log::error!("[TcpTransport] received hanshake snow read message error {:?}", e);
                                 Err(OutgoingHandshakeError::AuthenticationError)
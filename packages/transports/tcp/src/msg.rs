@@ -23,4 +23,6 @@ pub enum TcpMsg {
     Ping(u64),
     Pong(u64),
     Msg(Vec<u8>),
+    /// Last Noise_XX handshake message, only sent in authenticated handshakes.
+    ConnectConfirm(Vec<u8>),
 }
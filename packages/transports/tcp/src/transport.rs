@@ -7,7 +7,7 @@ use async_std::channel::{unbounded, Receiver, Sender};
 use async_std::net::TcpListener;
 use atm0s_sdn_identity::{ConnId, NodeAddr, NodeAddrBuilder, NodeId, Protocol};
 use atm0s_sdn_network::secure::DataSecure;
-use atm0s_sdn_network::transport::{Transport, TransportConnector, TransportEvent};
+use atm0s_sdn_network::transport::{ConnectionStatsEstimator, TrafficCounter, Transport, TransportConnector, TransportEvent};
 use atm0s_sdn_utils::error_handle::ErrorUtils;
 use atm0s_sdn_utils::{SystemTimer, Timer};
 use futures_util::FutureExt;
@@ -105,6 +105,8 @@ impl Transport for TcpTransport {
                             match incoming_handshake(secure, node_id, &mut socket_read, conn_id, &internal_tx).await {
                                 Ok((remote_node_id, remote_addr, snow_state)) => {
                                     let snow_state = Arc::new(Mutex::new(snow_state));
+                                    let sent = Arc::new(TrafficCounter::default());
+                                    let now_ms = timer.now_ms();
                                     let (connection_sender, unreliable_sender) = TcpConnectionSender::new(
                                         node_id,
                                         remote_node_id,
@@ -114,6 +116,7 @@ impl Transport for TcpTransport {
                                         socket_write,
                                         timer.clone(),
                                         snow_state.clone(),
+                                        sent.clone(),
                                     );
                                     let connection_receiver = Box::new(TcpConnectionReceiver {
                                         remote_node_id,
@@ -124,6 +127,7 @@ impl Transport for TcpTransport {
                                         unreliable_sender,
                                         snow_state,
                                         snow_buf: [0u8; 1500],
+                                        stats: ConnectionStatsEstimator::new(now_ms, sent),
                                     });
                                     internal_tx.send(TransportEvent::Incoming(
                                         Arc::new(connection_sender),
@@ -0,0 +1,313 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use atm0s_sdn_network::msg::TransportMsg;
+use parking_lot::Mutex;
+use snow::{HandshakeState, StatelessTransportState, TransportState};
+
+/// Noise handshake payload which both sides send to agree on the datagram mode.
+/// Older nodes send and ignore empty payloads, so they keep the stateful mode.
+pub const SNOW_PAYLOAD_DATAGRAM: &[u8] = &[1];
+
+const SEQ_SIZE: usize = 8;
+const REPLAY_WINDOW_WORDS: usize = 32;
+const REPLAY_WINDOW_SIZE: u64 = (REPLAY_WINDOW_WORDS * 64) as u64;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CipherError {
+    WrongFormat,
+    Replayed,
+    Decrypt,
+}
+
+/// Sliding window of received nonces, which rejects duplicated packets and packets older than the window.
+pub struct ReplayWindow {
+    top: Option<u64>,
+    bitmap: Box<[u64; REPLAY_WINDOW_WORDS]>,
+}
+
+impl Default for ReplayWindow {
+    fn default() -> Self {
+        Self {
+            top: None,
+            bitmap: Box::new([0; REPLAY_WINDOW_WORDS]),
+        }
+    }
+}
+
+impl ReplayWindow {
+    fn slot(nonce: u64) -> (usize, u64) {
+        let index = nonce % REPLAY_WINDOW_SIZE;
+        ((index / 64) as usize, 1 << (index % 64))
+    }
+
+    /// Check if the nonce is acceptable, without marking it as received.
+    pub fn check(&self, nonce: u64) -> bool {
+        match self.top {
+            None => true,
+            Some(top) if nonce > top => true,
+            Some(top) if top - nonce >= REPLAY_WINDOW_SIZE => false,
+            Some(_) => {
+                let (word, bit) = Self::slot(nonce);
+                self.bitmap[word] & bit == 0
+            }
+        }
+    }
+
+    /// Mark the nonce as received, must be called only after the packet is authenticated.
+    pub fn update(&mut self, nonce: u64) {
+        match self.top {
+            Some(top) if nonce <= top => {}
+            Some(top) if nonce - top < REPLAY_WINDOW_SIZE => {
+                for slide in (top + 1)..=nonce {
+                    let (word, bit) = Self::slot(slide);
+                    self.bitmap[word] &= !bit;
+                }
+                self.top = Some(nonce);
+            }
+            _ => {
+                *self.bitmap = [0; REPLAY_WINDOW_WORDS];
+                self.top = Some(nonce);
+            }
+        }
+        let (word, bit) = Self::slot(nonce);
+        self.bitmap[word] |= bit;
+    }
+}
+
+/// Encryption state of a connection after the Noise handshake, which also frames the data packets.
+///
+/// Stateful mode packets are `[header][ciphertext]` or the plain message.
+/// Datagram mode packets are `[header][seq u64][ciphertext]` or the plain message, the seq is used as nonce for encryption
+/// and for loss detection. Plain messages have no seq, because an unauthenticated seq could be spoofed to fake loss stats.
+pub enum SnowCipher {
+    /// Implicit nonces, a single lost or reordered packet breaks all later packets. Used with older nodes.
+    Stateful(Mutex<TransportState>),
+    /// Explicit nonce in each packet with an anti-replay window, tolerant to loss and reordering.
+    Datagram {
+        state: StatelessTransportState,
+        replay: Mutex<ReplayWindow>,
+        next_seq: AtomicU64,
+    },
+}
+
+impl SnowCipher {
+    pub fn from_handshake(handshake: HandshakeState, datagram: bool) -> Result<Self, snow::Error> {
+        if datagram {
+            Ok(Self::Datagram {
+                state: handshake.into_stateless_transport_mode()?,
+                replay: Mutex::new(ReplayWindow::default()),
+                next_seq: AtomicU64::new(0),
+            })
+        } else {
+            Ok(Self::Stateful(Mutex::new(handshake.into_transport_mode()?)))
+        }
+    }
+
+    pub fn is_datagram(&self) -> bool {
+        matches!(self, Self::Datagram { .. })
+    }
+
+    /// Build the data packet of a message buffer into `out`, plain messages are copied as is.
+    /// Return the packet length.
+    pub fn encode(&self, msg: &[u8], out: &mut [u8]) -> Result<usize, snow::Error> {
+        if !TransportMsg::is_secure_header(msg[0]) {
+            out.get_mut(..msg.len()).ok_or(snow::Error::Input)?.copy_from_slice(msg);
+            return Ok(msg.len());
+        }
+        out[0] = msg[0];
+        match self {
+            Self::Stateful(state) => Ok(1 + state.lock().write_message(msg, &mut out[1..])?),
+            Self::Datagram { state, next_seq, .. } => {
+                // seq is only used by encrypted packets, so the receiver sees continuous seqs for loss detection
+                let seq = next_seq.fetch_add(1, Ordering::Relaxed);
+                out[1..(1 + SEQ_SIZE)].copy_from_slice(&seq.to_be_bytes());
+                Ok(1 + SEQ_SIZE + state.write_message(seq, msg, &mut out[(1 + SEQ_SIZE)..])?)
+            }
+        }
+    }
+
+    /// Restore the message buffer of a data packet built by [`SnowCipher::encode`] into `out`.
+    /// Return the packet seq of encrypted packets in datagram mode and the message length.
+    pub fn decode(&self, packet: &[u8], out: &mut [u8]) -> Result<(Option<u64>, usize), CipherError> {
+        if packet.is_empty() {
+            return Err(CipherError::WrongFormat);
+        }
+        if !TransportMsg::is_secure_header(packet[0]) {
+            out.get_mut(..packet.len()).ok_or(CipherError::WrongFormat)?.copy_from_slice(packet);
+            return Ok((None, packet.len()));
+        }
+        match self {
+            Self::Stateful(state) => {
+                let len = state.lock().read_message(&packet[1..], out).map_err(|_| CipherError::Decrypt)?;
+                Ok((None, len))
+            }
+            Self::Datagram { state, replay, .. } => {
+                if packet.len() < 1 + SEQ_SIZE {
+                    return Err(CipherError::WrongFormat);
+                }
+                let seq = u64::from_be_bytes(packet[1..(1 + SEQ_SIZE)].try_into().expect("Should be seq size"));
+                let mut replay = replay.lock();
+                if !replay.check(seq) {
+                    return Err(CipherError::Replayed);
+                }
+                let len = state.read_message(seq, &packet[(1 + SEQ_SIZE)..], out).map_err(|_| CipherError::Decrypt)?;
+                replay.update(seq);
+                Ok((Some(seq), len))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    const SECURE_HEADER: u8 = 1 << 2;
+
+    type RecvResult = Result<(Option<u64>, Vec<u8>), CipherError>;
+
+    /// In-memory socket between two ciphers, which can lose, reorder and duplicate packets.
+    #[derive(Default)]
+    struct FakeSocket {
+        queue: VecDeque<Vec<u8>>,
+    }
+
+    impl FakeSocket {
+        fn send(&mut self, cipher: &SnowCipher, msg: &[u8]) {
+            let mut buf = [0; 1500];
+            let len = cipher.encode(msg, &mut buf).expect("Should encode");
+            self.queue.push_back(buf[..len].to_vec());
+        }
+
+        fn lose(&mut self, index: usize) {
+            self.queue.remove(index);
+        }
+
+        fn swap(&mut self, a: usize, b: usize) {
+            self.queue.swap(a, b);
+        }
+
+        fn duplicate(&mut self, index: usize) {
+            let packet = self.queue[index].clone();
+            self.queue.push_back(packet);
+        }
+
+        fn recv(&mut self, cipher: &SnowCipher) -> Option<RecvResult> {
+            let packet = self.queue.pop_front()?;
+            let mut buf = [0; 1500];
+            Some(cipher.decode(&packet, &mut buf).map(|(seq, len)| (seq, buf[..len].to_vec())))
+        }
+    }
+
+    fn build_pair(datagram: bool) -> (SnowCipher, SnowCipher) {
+        let mut buf = [0; 1500];
+        let mut payload = [0; 1500];
+        let mut initiator = snow::Builder::new("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().expect("")).build_initiator().expect("");
+        let mut responder = snow::Builder::new("Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().expect("")).build_responder().expect("");
+        let len = initiator.write_message(&[], &mut buf).expect("");
+        responder.read_message(&buf[..len], &mut payload).expect("");
+        let len = responder.write_message(&[], &mut buf).expect("");
+        initiator.read_message(&buf[..len], &mut payload).expect("");
+        (SnowCipher::from_handshake(initiator, datagram).expect(""), SnowCipher::from_handshake(responder, datagram).expect(""))
+    }
+
+    #[test]
+    fn datagram_tolerates_loss_and_reorder() {
+        let (client, server) = build_pair(true);
+        let mut socket = FakeSocket::default();
+        for i in 0..5u8 {
+            socket.send(&client, &[SECURE_HEADER, i]);
+        }
+        socket.lose(1);
+        socket.swap(0, 2);
+
+        assert_eq!(socket.recv(&server), Some(Ok((Some(3), vec![SECURE_HEADER, 3]))));
+        assert_eq!(socket.recv(&server), Some(Ok((Some(2), vec![SECURE_HEADER, 2]))));
+        assert_eq!(socket.recv(&server), Some(Ok((Some(0), vec![SECURE_HEADER, 0]))));
+        assert_eq!(socket.recv(&server), Some(Ok((Some(4), vec![SECURE_HEADER, 4]))));
+        assert_eq!(socket.recv(&server), None);
+
+        socket.send(&server, &[SECURE_HEADER, 10]);
+        assert_eq!(socket.recv(&client), Some(Ok((Some(0), vec![SECURE_HEADER, 10]))));
+    }
+
+    #[test]
+    fn datagram_plain_msg_without_seq() {
+        let (client, server) = build_pair(true);
+        let mut socket = FakeSocket::default();
+        socket.send(&client, &[0, 1, 2]);
+        socket.send(&client, &[SECURE_HEADER, 3]);
+        socket.send(&client, &[0, 4]);
+        socket.send(&client, &[SECURE_HEADER, 5]);
+
+        assert_eq!(socket.recv(&server), Some(Ok((None, vec![0, 1, 2]))));
+        assert_eq!(socket.recv(&server), Some(Ok((Some(0), vec![SECURE_HEADER, 3]))));
+        assert_eq!(socket.recv(&server), Some(Ok((None, vec![0, 4]))));
+        assert_eq!(socket.recv(&server), Some(Ok((Some(1), vec![SECURE_HEADER, 5]))));
+        assert_eq!(server.decode(&[SECURE_HEADER, 0], &mut [0; 1500]), Err(CipherError::WrongFormat));
+    }
+
+    #[test]
+    fn datagram_rejects_replay() {
+        let (client, server) = build_pair(true);
+        let mut socket = FakeSocket::default();
+        socket.send(&client, &[SECURE_HEADER, 0]);
+        socket.send(&client, &[SECURE_HEADER, 1]);
+        socket.duplicate(0);
+        socket.duplicate(1);
+
+        assert_eq!(socket.recv(&server), Some(Ok((Some(0), vec![SECURE_HEADER, 0]))));
+        assert_eq!(socket.recv(&server), Some(Ok((Some(1), vec![SECURE_HEADER, 1]))));
+        assert_eq!(socket.recv(&server), Some(Err(CipherError::Replayed)));
+        assert_eq!(socket.recv(&server), Some(Err(CipherError::Replayed)));
+    }
+
+    #[test]
+    fn datagram_rejects_tampered_packet() {
+        let (client, server) = build_pair(true);
+        let mut socket = FakeSocket::default();
+        socket.send(&client, &[SECURE_HEADER, 0]);
+        socket.send(&client, &[SECURE_HEADER, 1]);
+        //change seq of the first packet to the seq of the second one, which must not mark it as received
+        socket.queue[0][8] = 1;
+
+        assert_eq!(socket.recv(&server), Some(Err(CipherError::Decrypt)));
+        assert_eq!(socket.recv(&server), Some(Ok((Some(1), vec![SECURE_HEADER, 1]))));
+        assert_eq!(server.decode(&[SECURE_HEADER, 0, 0], &mut [0; 1500]), Err(CipherError::WrongFormat));
+    }
+
+    #[test]
+    fn stateful_breaks_after_loss() {
+        let (client, server) = build_pair(false);
+        let mut socket = FakeSocket::default();
+        socket.send(&client, &[SECURE_HEADER, 0]);
+        socket.send(&client, &[SECURE_HEADER, 1]);
+        socket.send(&client, &[0, 2]);
+        socket.lose(0);
+
+        assert_eq!(socket.recv(&server), Some(Err(CipherError::Decrypt)));
+        assert_eq!(socket.recv(&server), Some(Ok((None, vec![0, 2]))));
+    }
+
+    #[test]
+    fn replay_window_slide() {
+        let mut window = ReplayWindow::default();
+        assert!(window.check(5));
+        window.update(5);
+        assert!(!window.check(5));
+        assert!(window.check(0));
+
+        window.update(REPLAY_WINDOW_SIZE + 4);
+        assert!(!window.check(4));
+        assert!(window.check(5 + 1));
+        assert!(!window.check(REPLAY_WINDOW_SIZE + 4));
+
+        //jump more than a whole window clears all slots
+        window.update(10 * REPLAY_WINDOW_SIZE);
+        assert!(!window.check(10 * REPLAY_WINDOW_SIZE));
+        assert!(window.check(10 * REPLAY_WINDOW_SIZE - 1));
+        assert!(!window.check(9 * REPLAY_WINDOW_SIZE));
+    }
+}
@@ -9,10 +9,9 @@ use async_std::channel::Sender;
 use atm0s_sdn_identity::{ConnId, NodeAddr, NodeId, Protocol};
 use atm0s_sdn_network::{
     secure::DataSecure,
-    transport::{OutgoingConnectionError, TransportConnector, TransportEvent},
+    transport::{OutgoingConnectionError, TrafficCounter, TransportConnector, TransportEvent},
 };
 use atm0s_sdn_utils::{error_handle::ErrorUtils, Timer};
-use parking_lot::Mutex;
 
 use crate::{
     handshake::{outgoing_handshake, OutgoingHandshakeError},
@@ -92,10 +91,11 @@ impl TransportConnector for UdpConnector {
                 let async_socket = unsafe { Arc::new(async_std::net::UdpSocket::from_raw_fd(socket.as_raw_fd())) };
 
                 match outgoing_handshake(secure.clone(), &async_socket, local_node_id, local_node_addr, node_id).await {
-                    Ok(snow_state) => {
+                    Ok((cipher, handshake_ack)) => {
                         let close_state = Arc::new(std::sync::atomic::AtomicBool::new(false));
                         let close_notify = Arc::new(async_notify::Notify::new());
-                        let snow_state = Arc::new(Mutex::new(snow_state));
+                        let cipher = Arc::new(cipher);
+                        let sent = Arc::new(TrafficCounter::default());
                         let sender = Arc::new(UdpClientConnectionSender::new(
                             node_id,
                             node_addr.clone(),
@@ -103,7 +103,8 @@ impl TransportConnector for UdpConnector {
                             socket,
                             close_state.clone(),
                             close_notify.clone(),
-                            snow_state.clone(),
+                            cipher.clone(),
+                            sent.clone(),
                         ));
                         let receiver = Box::new(UdpClientConnectionReceiver::new(
                             async_socket,
@@ -113,7 +114,9 @@ impl TransportConnector for UdpConnector {
                             timer,
                             close_state,
                             close_notify,
-                            snow_state.clone(),
+                            cipher.clone(),
+                            sent,
+                            handshake_ack,
                         ));
                         tx.send(TransportEvent::Outgoing(sender, receiver)).await.print_error("Should send incoming event");
                     }
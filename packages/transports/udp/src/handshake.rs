@@ -1,6 +1,9 @@
 use std::{net::SocketAddr, ops::Deref, sync::Arc, time::Duration};
 
-use crate::msg::{build_control_msg, HandshakeRequest, HandshakeResult, UdpTransportMsg};
+use crate::{
+    cipher::{SnowCipher, SNOW_PAYLOAD_DATAGRAM},
+    msg::{build_control_msg, HandshakeRequest, HandshakeResult, UdpTransportMsg},
+};
 use async_std::{
     channel::{Receiver, Sender},
     net::UdpSocket,
@@ -13,19 +16,38 @@ use atm0s_sdn_network::{
 };
 use atm0s_sdn_utils::error_handle::ErrorUtils;
 use futures_util::{select, FutureExt};
-use snow::TransportState;
 
-static SNOW_PATTERN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+static SNOW_PATTERN_NN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+static SNOW_PATTERN_XX: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Build the snow handshake state, with Noise_XX if DataSecure provides a static key, otherwise the legacy Noise_NN.
+fn build_snow_handshake(static_key: Option<&[u8; 32]>, initiator: bool) -> Result<snow::HandshakeState, snow::Error> {
+    let builder = match static_key {
+        Some(key) => snow::Builder::new(SNOW_PATTERN_XX.parse()?).local_private_key(key),
+        None => snow::Builder::new(SNOW_PATTERN_NN.parse()?),
+    };
+    if initiator {
+        builder.build_initiator()
+    } else {
+        builder.build_responder()
+    }
+}
 
 /// Connection handshake flow
 /// Client -> Server: ConnectRequest
 ///                     - Client(retry each 1s until receive ConnectResponse or Timeout)
 /// Server -> Client: ConnectResponse
-///                     - Server(retry each 1s until receive ConnectResponseAck or Timeout)
+///                     - Server(retry each 1s until receive ConnectResponseAck/ConnectConfirm or Timeout)
 ///                     - Client => Done
-/// Client -> Server: ConnectResponseAck
+/// Client -> Server: ConnectResponseAck (Noise_NN) or ConnectConfirm (Noise_XX)
 ///                     - Server => Done
 ///
+/// With Noise_XX the static keys are derived from node identity, each side checks that the remote static key
+/// belongs to the node which signed the ConnectRequest/ConnectResponse.
+///
+/// Both sides put SNOW_PAYLOAD_DATAGRAM in their first Noise message to switch to the loss-tolerant datagram cipher,
+/// a node which does not understand it replies with an empty payload and the connection keeps the stateful cipher.
+///
 
 #[derive(Debug)]
 pub enum IncomingHandshakeError {
@@ -53,13 +75,15 @@ pub async fn incoming_handshake(
     conn_id: ConnId,
     remote_addr: SocketAddr,
     socket: &UdpSocket,
-) -> Result<(NodeId, NodeAddr, TransportState), IncomingHandshakeError> {
+) -> Result<(NodeId, NodeAddr, SnowCipher), IncomingHandshakeError> {
     let mut count = 0;
-    let mut result: Option<(u32, NodeAddr, Vec<u8>)> = None;
+    let mut result: Option<(u32, NodeAddr, Vec<u8>, Vec<u8>)> = None;
     let mut timer = async_std::stream::interval(Duration::from_secs(1));
     let mut requested = false;
+    let mut datagram = false;
     let mut snow_buf = [0; 1500];
-    let mut snow_responder = snow::Builder::new(SNOW_PATTERN.parse().expect("")).build_responder().expect("");
+    let static_key = secure.handshake_static_key();
+    let mut snow_responder = build_snow_handshake(static_key.as_ref(), false).map_err(|_| IncomingHandshakeError::InternalError)?;
 
     loop {
         select! {
@@ -69,7 +93,7 @@ pub async fn incoming_handshake(
                     return Err(IncomingHandshakeError::Timeout);
                 }
 
-                if let Some((remote_node_id, _, snow_res)) = &result {
+                if let Some((remote_node_id, _, snow_res, _)) = &result {
                     let handshake_res = HandshakeResult::Success(snow_res.clone());
                     let signature = ObjectSecure::sign_obj(secure.deref(), *remote_node_id, &handshake_res);
                     socket.send_to(&build_control_msg(&UdpTransportMsg::ConnectResponse(handshake_res, signature)), remote_addr).await.map_err(|_| IncomingHandshakeError::SocketError)?;
@@ -84,7 +108,7 @@ pub async fn incoming_handshake(
                     let msg = bincode::deserialize::<UdpTransportMsg>(&msg[1..]).map_err(|_| IncomingHandshakeError::WrongMsg)?;
                     match msg {
                         UdpTransportMsg::ConnectRequest(req, sig) => {
-                            if !ObjectSecure::verify_obj(secure.deref(), req.remote_node_id, &req, &sig) {
+                            if !ObjectSecure::verify_obj_from(secure.deref(), req.node_id, req.remote_node_id, &req, &sig) {
                                 log::warn!("[UdpTransport] received handshake request wrong signature");
                                 let signature = ObjectSecure::sign_obj(secure.deref(), req.node_id, &HandshakeResult::AuthenticationError);
                                 socket.send_to(&build_control_msg(&UdpTransportMsg::ConnectResponse(HandshakeResult::AuthenticationError, signature)), remote_addr).await.map_err(|_| IncomingHandshakeError::SocketError)?;
@@ -113,14 +137,16 @@ pub async fn incoming_handshake(
 
                             requested = true;
                             match snow_responder.read_message(&req.snow_handshake, &mut snow_buf) {
-                                Ok(_) => {
-                                    match snow_responder.write_message(&[], &mut snow_buf) {
+                                Ok(payload_len) => {
+                                    datagram = &snow_buf[..payload_len] == SNOW_PAYLOAD_DATAGRAM;
+                                    let payload = if datagram { SNOW_PAYLOAD_DATAGRAM } else { &[] };
+                                    match snow_responder.write_message(payload, &mut snow_buf) {
                                         Ok(len) => {
                                             let handshake_res = HandshakeResult::Success(snow_buf[..len].to_vec());
                                             log::info!("[UdpTransport] {} {} handshake success", req.node_id, req.node_addr);
                                             let signature = ObjectSecure::sign_obj(secure.deref(), req.node_id, &handshake_res);
                                             socket.send_to(&build_control_msg(&UdpTransportMsg::ConnectResponse(handshake_res, signature)), remote_addr).await.map_err(|_| IncomingHandshakeError::SocketError)?;
-                                            result = Some((req.node_id, req.node_addr, snow_buf[..len].to_vec(),));
+                                            result = Some((req.node_id, req.node_addr, snow_buf[..len].to_vec(), sig));
                                         },
                                         Err(e) => {
                                             log::error!("[UdpTransport] received hanshake snow write message error {:?}", e);
@@ -135,16 +161,38 @@ pub async fn incoming_handshake(
                             }
                         }
                         UdpTransportMsg::ConnectResponseAck(success) => {
-                            if success {
+                            if success && static_key.is_some() {
+                                log::warn!("[UdpTransport] received handshake resonse ack but waiting ConnectConfirm");
+                                return Err(IncomingHandshakeError::WrongMsg);
+                            } else if success {
                                 log::info!("[UdpTransport] received handshake resonse ack {}", success);
-                                if let Some((node, addr, _)) = result.take() {
-                                    return Ok((node, addr, snow_responder.into_transport_mode().expect("Should be transport mode")));
+                                if let Some((node, addr, _, _)) = result.take() {
+                                    return Ok((node, addr, SnowCipher::from_handshake(snow_responder, datagram).map_err(|_| IncomingHandshakeError::InternalError)?));
                                 }
                             } else {
                                 log::warn!("[UdpTransport] received handshake resonse ack {}", success);
                                 return Err(IncomingHandshakeError::Rejected);
                             }
                         }
+                        UdpTransportMsg::ConnectConfirm(snow_confirm) => {
+                            if static_key.is_none() {
+                                log::warn!("[UdpTransport] received handshake confirm but waiting ConnectResponseAck");
+                                return Err(IncomingHandshakeError::WrongMsg);
+                            }
+                            if let Some((node, addr, _, req_sig)) = result.take() {
+                                if let Err(e) = snow_responder.read_message(&snow_confirm, &mut snow_buf) {
+                                    log::error!("[UdpTransport] received hanshake snow confirm error {:?}", e);
+                                    return Err(IncomingHandshakeError::WrongMsg);
+                                }
+                                let remote_static = snow_responder.get_remote_static().unwrap_or(&[]);
+                                if !secure.verify_handshake_static_key(node, remote_static, &req_sig) {
+                                    log::warn!("[UdpTransport] received handshake confirm from {} with wrong static key", node);
+                                    return Err(IncomingHandshakeError::Rejected);
+                                }
+                                log::info!("[UdpTransport] received handshake confirm from {}", node);
+                                return Ok((node, addr, SnowCipher::from_handshake(snow_responder, datagram).map_err(|_| IncomingHandshakeError::InternalError)?));
+                            }
+                        }
                         _ => {}
                     };
                 },
@@ -163,13 +211,14 @@ pub async fn outgoing_handshake(
     local_node_id: NodeId,
     local_node_addr: NodeAddr,
     to_node_id: NodeId,
-) -> Result<TransportState, OutgoingHandshakeError> {
+) -> Result<(SnowCipher, Vec<u8>), OutgoingHandshakeError> {
     let mut timer = async_std::stream::interval(Duration::from_secs(1));
     let mut buf = [0; 1500];
     let mut count = 0;
 
-    let mut snow_initiator = snow::Builder::new(SNOW_PATTERN.parse().expect("")).build_initiator().expect("");
-    let snow_hanshake_len = snow_initiator.write_message(&[], &mut buf).expect("");
+    let static_key = secure.handshake_static_key();
+    let mut snow_initiator = build_snow_handshake(static_key.as_ref(), true).expect("Should build snow initiator");
+    let snow_hanshake_len = snow_initiator.write_message(SNOW_PAYLOAD_DATAGRAM, &mut buf).expect("");
 
     let req = HandshakeRequest {
         node_id: local_node_id,
@@ -205,7 +254,7 @@ pub async fn outgoing_handshake(
                     let msg = bincode::deserialize::<UdpTransportMsg>(&buf[1..size]).map_err(|_| OutgoingHandshakeError::WrongMsg)?;
                     match msg {
                         UdpTransportMsg::ConnectResponse(res, signature) => {
-                            if !ObjectSecure::verify_obj(secure.deref(), local_node_id, &res, &signature) {
+                            if !ObjectSecure::verify_obj_from(secure.deref(), to_node_id, local_node_id, &res, &signature) {
                                 log::warn!("[UdpTransport] received handshake response wrong signature");
                                 return Err(OutgoingHandshakeError::AuthenticationError);
                             }
@@ -213,11 +262,23 @@ pub async fn outgoing_handshake(
                             match res {
                                 HandshakeResult::Success(snow_response) => {
                                     match snow_initiator.read_message(&snow_response, &mut buf) {
-                                        Ok(_) => {
-                                            match snow_initiator.into_transport_mode() {
-                                                Ok(state) => {
-                                                    socket.send(&build_control_msg(&UdpTransportMsg::ConnectResponseAck(true))).await.map_err(|_| OutgoingHandshakeError::SocketError)?;
-                                                    return Ok(state)
+                                        Ok(payload_len) => {
+                                            let datagram = &buf[..payload_len] == SNOW_PAYLOAD_DATAGRAM;
+                                            let ack_msg = if static_key.is_some() {
+                                                let remote_static = snow_initiator.get_remote_static().unwrap_or(&[]);
+                                                if !secure.verify_handshake_static_key(to_node_id, remote_static, &signature) {
+                                                    log::warn!("[UdpTransport] received handshake response from {} with wrong static key", to_node_id);
+                                                    return Err(OutgoingHandshakeError::AuthenticationError);
+                                                }
+                                                let len = snow_initiator.write_message(&[], &mut buf).map_err(|_| OutgoingHandshakeError::AuthenticationError)?;
+                                                build_control_msg(&UdpTransportMsg::ConnectConfirm(buf[..len].to_vec()))
+                                            } else {
+                                                build_control_msg(&UdpTransportMsg::ConnectResponseAck(true))
+                                            };
+                                            socket.send(&ack_msg).await.map_err(|_| OutgoingHandshakeError::SocketError)?;
+                                            match SnowCipher::from_handshake(snow_initiator, datagram) {
+                                                Ok(cipher) => {
+                                                    return Ok((cipher, ack_msg))
                                                 },
                                                 Err(e) => {
                                                     log::error!("[UdpTransport] received hanshake snow into_transport_mode error {:?}", e);
@@ -1,3 +1,4 @@
+mod cipher;
 mod connector;
 mod handshake;
 mod msg;
@@ -13,7 +14,31 @@ mod tests {
     use std::{net::Ipv4Addr, sync::Arc};
 
     use crate::transport::UdpTransport;
-    use atm0s_sdn_identity::{NodeAddr, NodeAddrBuilder, Protocol};
+    use atm0s_sdn_identity::{NodeAddr, NodeAddrBuilder, NodeId, Protocol};
+    use atm0s_sdn_network::secure::{ClusterCa, DataSecure, Ed25519Secure, RevocationList, SigningKey};
+
+    fn ed25519_secure(ca: &ClusterCa, node_id: NodeId, revocation: Arc<RevocationList>) -> Arc<Ed25519Secure> {
+        let key = SigningKey::generate(&mut rand::rngs::OsRng);
+        let cert = ca.issue(node_id, &key.verifying_key());
+        Arc::new(Ed25519Secure::new(key, cert, ca.public_key(), revocation).expect("Should create"))
+    }
+
+    /// Sign with the node identity but use a Noise static key which is not derived from it.
+    struct WrongStaticKeySecure(Arc<Ed25519Secure>);
+
+    impl DataSecure for WrongStaticKeySecure {
+        fn sign_msg(&self, remote_node_id: NodeId, data: &[u8]) -> Vec<u8> {
+            self.0.sign_msg(remote_node_id, data)
+        }
+
+        fn verify_msg(&self, remote_node_id: NodeId, data: &[u8], signature: &[u8]) -> bool {
+            self.0.verify_msg(remote_node_id, data, signature)
+        }
+
+        fn handshake_static_key(&self) -> Option<[u8; 32]> {
+            Some([1; 32])
+        }
+    }
 
     #[async_std::test]
     async fn simple_network() {
@@ -52,4 +77,79 @@ mod tests {
         let fake_node2_addr = NodeAddr::from_iter(3, node_addr_builder2.addr().multiaddr().iter());
         atm0s_sdn_network::transport_tests::simple::simple_network_connect_wrong_node(tran1, node_addr_builder1.addr(), tran2, fake_node2_addr).await;
     }
+
+    #[async_std::test]
+    async fn simple_network_ed25519() {
+        let ca = ClusterCa::generate();
+        let mut node_addr_builder1 = NodeAddrBuilder::new(1);
+        let sock1 = UdpTransport::prepare(0, &mut node_addr_builder1).await;
+        let tran1 = UdpTransport::new(node_addr_builder1.addr(), sock1, ed25519_secure(&ca, 1, Default::default()));
+
+        let mut node_addr_builder2 = NodeAddrBuilder::new(2);
+        let sock2 = UdpTransport::prepare(0, &mut node_addr_builder2).await;
+        let tran2 = UdpTransport::new(node_addr_builder2.addr(), sock2, ed25519_secure(&ca, 2, Default::default()));
+
+        atm0s_sdn_network::transport_tests::simple::simple_network(tran1, node_addr_builder1.addr(), tran2, node_addr_builder2.addr()).await;
+    }
+
+    #[async_std::test]
+    async fn simple_network_connect_revoked_node() {
+        let ca = ClusterCa::generate();
+        let revocation = Arc::new(RevocationList::default());
+        revocation.deny(1);
+        let mut node_addr_builder1 = NodeAddrBuilder::new(1);
+        let sock1 = UdpTransport::prepare(0, &mut node_addr_builder1).await;
+        let tran1 = UdpTransport::new(node_addr_builder1.addr(), sock1, ed25519_secure(&ca, 1, Default::default()));
+
+        let mut node_addr_builder2 = NodeAddrBuilder::new(2);
+        let sock2 = UdpTransport::prepare(0, &mut node_addr_builder2).await;
+        let tran2 = UdpTransport::new(node_addr_builder2.addr(), sock2, ed25519_secure(&ca, 2, revocation));
+
+        atm0s_sdn_network::transport_tests::simple::simple_network_connect_wrong_node(tran1, node_addr_builder1.addr(), tran2, node_addr_builder2.addr()).await;
+    }
+
+    #[async_std::test]
+    async fn simple_network_connect_wrong_node_ed25519() {
+        let ca = ClusterCa::generate();
+        let mut node_addr_builder1 = NodeAddrBuilder::new(1);
+        let sock1 = UdpTransport::prepare(0, &mut node_addr_builder1).await;
+        let tran1 = UdpTransport::new(node_addr_builder1.addr(), sock1, ed25519_secure(&ca, 1, Default::default()));
+
+        let mut node_addr_builder2 = NodeAddrBuilder::new(2);
+        let sock2 = UdpTransport::prepare(0, &mut node_addr_builder2).await;
+        let tran2 = UdpTransport::new(node_addr_builder2.addr(), sock2, ed25519_secure(&ca, 2, Default::default()));
+
+        let fake_node2_addr = NodeAddr::from_iter(3, node_addr_builder2.addr().multiaddr().iter());
+        atm0s_sdn_network::transport_tests::simple::simple_network_connect_wrong_node(tran1, node_addr_builder1.addr(), tran2, fake_node2_addr).await;
+    }
+
+    #[async_std::test]
+    async fn simple_network_ed25519_legacy_handshake() {
+        let ca = ClusterCa::generate();
+        let mut node_addr_builder1 = NodeAddrBuilder::new(1);
+        let sock1 = UdpTransport::prepare(0, &mut node_addr_builder1).await;
+        let secure1 = Arc::into_inner(ed25519_secure(&ca, 1, Default::default())).expect("Should unwrap").with_legacy_handshake();
+        let tran1 = UdpTransport::new(node_addr_builder1.addr(), sock1, Arc::new(secure1));
+
+        let mut node_addr_builder2 = NodeAddrBuilder::new(2);
+        let sock2 = UdpTransport::prepare(0, &mut node_addr_builder2).await;
+        let secure2 = Arc::into_inner(ed25519_secure(&ca, 2, Default::default())).expect("Should unwrap").with_legacy_handshake();
+        let tran2 = UdpTransport::new(node_addr_builder2.addr(), sock2, Arc::new(secure2));
+
+        atm0s_sdn_network::transport_tests::simple::simple_network(tran1, node_addr_builder1.addr(), tran2, node_addr_builder2.addr()).await;
+    }
+
+    #[async_std::test]
+    async fn simple_network_connect_wrong_static_key() {
+        let ca = ClusterCa::generate();
+        let mut node_addr_builder1 = NodeAddrBuilder::new(1);
+        let sock1 = UdpTransport::prepare(0, &mut node_addr_builder1).await;
+        let tran1 = UdpTransport::new(node_addr_builder1.addr(), sock1, ed25519_secure(&ca, 1, Default::default()));
+
+        let mut node_addr_builder2 = NodeAddrBuilder::new(2);
+        let sock2 = UdpTransport::prepare(0, &mut node_addr_builder2).await;
+        let tran2 = UdpTransport::new(node_addr_builder2.addr(), sock2, Arc::new(WrongStaticKeySecure(ed25519_secure(&ca, 2, Default::default()))));
+
+        atm0s_sdn_network::transport_tests::simple::simple_network_connect_wrong_node(tran1, node_addr_builder1.addr(), tran2, node_addr_builder2.addr()).await;
+    }
 }
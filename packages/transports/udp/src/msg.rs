@@ -24,6 +24,10 @@ pub enum UdpTransportMsg {
     Ping(u64),
     Pong(u64),
     Close,
+    /// Last Noise_XX handshake message, which replaces ConnectResponseAck in authenticated handshakes.
+    ConnectConfirm(Vec<u8>),
+    /// Loss percent of received data packets since the previous report, only sent in datagram mode.
+    LossReport(u32),
 }
 
 pub fn build_control_msg<T: Serialize>(msg: &T) -> Vec<u8> {
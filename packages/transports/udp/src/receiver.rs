@@ -7,14 +7,15 @@ use async_std::{channel::Receiver, net::UdpSocket, stream::StreamExt};
 use atm0s_sdn_identity::{ConnId, NodeAddr, NodeId};
 use atm0s_sdn_network::{
     msg::TransportMsg,
-    transport::{ConnectionEvent, ConnectionReceiver, ConnectionStats},
+    transport::{ConnectionEvent, ConnectionReceiver, ConnectionStatsEstimator, SeqLossDetector, TrafficCounter},
 };
 use atm0s_sdn_utils::{error_handle::ErrorUtils, Timer};
 use futures_util::{select, FutureExt};
-use parking_lot::Mutex;
-use snow::TransportState;
 
-use crate::msg::{build_control_msg, UdpTransportMsg};
+use crate::{
+    cipher::SnowCipher,
+    msg::{build_control_msg, UdpTransportMsg},
+};
 
 pub struct UdpServerConnectionReceiver {
     closed: bool,
@@ -29,8 +30,11 @@ pub struct UdpServerConnectionReceiver {
     close_state: Arc<AtomicBool>,
     close_notify: Arc<async_notify::Notify>,
     last_pong_ts: u64,
-    snow_state: Arc<Mutex<TransportState>>,
+    cipher: Arc<SnowCipher>,
     snow_buf: [u8; 1500],
+    loss: SeqLossDetector,
+    remote_loss_percent: u32,
+    stats: ConnectionStatsEstimator,
 }
 
 impl UdpServerConnectionReceiver {
@@ -44,7 +48,8 @@ impl UdpServerConnectionReceiver {
         timer: Arc<dyn Timer>,
         close_state: Arc<AtomicBool>,
         close_notify: Arc<async_notify::Notify>,
-        snow_state: Arc<Mutex<TransportState>>,
+        cipher: Arc<SnowCipher>,
+        sent: Arc<TrafficCounter>,
     ) -> Self {
         log::info!("[UdpServerConnectionReceiver {}/{}] new", remote_node_id, conn_id);
 
@@ -57,12 +62,15 @@ impl UdpServerConnectionReceiver {
             remote_node_id,
             remote_node_addr,
             last_pong_ts: timer.now_ms(),
+            stats: ConnectionStatsEstimator::new(timer.now_ms(), sent),
             timer,
             tick: async_std::stream::interval(std::time::Duration::from_secs(1)),
             close_state,
             close_notify,
-            snow_state,
+            cipher,
             snow_buf: [0u8; 1500],
+            loss: SeqLossDetector::default(),
+            remote_loss_percent: 0,
         }
     }
 }
@@ -99,6 +107,10 @@ impl ConnectionReceiver for UdpServerConnectionReceiver {
                     }
 
                     self.socket.send_to(&build_control_msg(&UdpTransportMsg::Ping(self.timer.now_ms())), self.socket_dest).await.print_error("Should send Ping");
+                    if self.cipher.is_datagram() {
+                        let loss_report = UdpTransportMsg::LossReport(self.loss.take_loss_percent());
+                        self.socket.send_to(&build_control_msg(&loss_report), self.socket_dest).await.print_error("Should send LossReport");
+                    }
                 },
                 e = self.rx.recv().fuse() => match e {
                     Ok((data, len)) => {
@@ -112,14 +124,10 @@ impl ConnectionReceiver for UdpServerConnectionReceiver {
                                     Ok(UdpTransportMsg::Pong(ts)) => {
                                         self.last_pong_ts = self.timer.now_ms();
                                         log::debug!("[UdpServerConnectionReceiver {}/{}] on pong received {} ms", self.remote_node_id, self.conn_id, self.last_pong_ts - ts);
-                                        //TODO est speed and over_use state
-                                        break Ok(ConnectionEvent::Stats(ConnectionStats {
-                                            rtt_ms: (self.last_pong_ts - ts) as u16,
-                                            sending_kbps: 0,
-                                            send_est_kbps: 0,
-                                            loss_percent: 0,
-                                            over_use: false,
-                                        }));
+                                        break Ok(ConnectionEvent::Stats(self.stats.on_rtt(self.last_pong_ts, (self.last_pong_ts - ts) as u16, self.remote_loss_percent)));
+                                    }
+                                    Ok(UdpTransportMsg::LossReport(loss_percent)) => {
+                                        self.remote_loss_percent = loss_percent;
                                     }
                                     Ok(UdpTransportMsg::Close) => {
                                         self.closed = true;
@@ -130,9 +138,11 @@ impl ConnectionReceiver for UdpServerConnectionReceiver {
                                     _ => {}
                                 }
                             } else {
-                                if TransportMsg::is_secure_header(data[0]) {
-                                    let mut snow_state = self.snow_state.lock();
-                                    if let Ok(len) = snow_state.read_message(&data[1..len], &mut self.snow_buf) {
+                                match self.cipher.decode(&data[..len], &mut self.snow_buf) {
+                                    Ok((seq, len)) => {
+                                        if let Some(seq) = seq {
+                                            self.loss.on_seq(seq);
+                                        }
                                         //TODO reduce to_vec memory copy
                                         match TransportMsg::from_vec(self.snow_buf[0..len].to_vec()) {
                                             Ok(msg) => break Ok(ConnectionEvent::Msg(msg)),
@@ -141,13 +151,8 @@ impl ConnectionReceiver for UdpServerConnectionReceiver {
                                             }
                                         }
                                     }
-                                } else {
-                                    //TODO reduce to_vec memory copy
-                                    match TransportMsg::from_vec(data[0..len].to_vec()) {
-                                        Ok(msg) => break Ok(ConnectionEvent::Msg(msg)),
-                                        Err(e) => {
-                                            log::error!("[UdpServerConnectionReceiver {}/{}] wrong msg format {:?}", self.remote_node_id, self.conn_id, e);
-                                        }
+                                    Err(e) => {
+                                        log::debug!("[UdpServerConnectionReceiver {}/{}] drop data packet {:?}", self.remote_node_id, self.conn_id, e);
                                     }
                                 }
                             }
@@ -181,8 +186,13 @@ pub struct UdpClientConnectionReceiver {
     close_state: Arc<AtomicBool>,
     close_notify: Arc<async_notify::Notify>,
     last_pong_ts: u64,
-    snow_state: Arc<Mutex<TransportState>>,
+    cipher: Arc<SnowCipher>,
     snow_buf: [u8; 1500],
+    loss: SeqLossDetector,
+    remote_loss_percent: u32,
+    stats: ConnectionStatsEstimator,
+    /// Last handshake message, resent if the server did not receive it and resends ConnectResponse
+    handshake_ack: Vec<u8>,
 }
 
 impl UdpClientConnectionReceiver {
@@ -194,7 +204,9 @@ impl UdpClientConnectionReceiver {
         timer: Arc<dyn Timer>,
         close_state: Arc<AtomicBool>,
         close_notify: Arc<async_notify::Notify>,
-        snow_state: Arc<Mutex<TransportState>>,
+        cipher: Arc<SnowCipher>,
+        sent: Arc<TrafficCounter>,
+        handshake_ack: Vec<u8>,
     ) -> Self {
         log::info!("[UdpClientConnectionReceiver {}] new", remote_node_id);
 
@@ -205,12 +217,16 @@ impl UdpClientConnectionReceiver {
             remote_node_id,
             remote_node_addr,
             last_pong_ts: timer.now_ms(),
+            stats: ConnectionStatsEstimator::new(timer.now_ms(), sent),
             timer,
             tick: async_std::stream::interval(std::time::Duration::from_secs(1)),
             close_state,
             close_notify,
-            snow_state,
+            cipher,
             snow_buf: [0u8; 1500],
+            loss: SeqLossDetector::default(),
+            remote_loss_percent: 0,
+            handshake_ack,
         }
     }
 }
@@ -247,6 +263,10 @@ impl ConnectionReceiver for UdpClientConnectionReceiver {
                         break Err(());
                     }
                     self.socket.send(&build_control_msg(&UdpTransportMsg::Ping(self.timer.now_ms()))).await.print_error("Should send Ping");
+                    if self.cipher.is_datagram() {
+                        let loss_report = UdpTransportMsg::LossReport(self.loss.take_loss_percent());
+                        self.socket.send(&build_control_msg(&loss_report)).await.print_error("Should send LossReport");
+                    }
                 },
                 e = self.socket.recv(&mut data).fuse() => match e {
                     Ok(len) => {
@@ -254,10 +274,7 @@ impl ConnectionReceiver for UdpClientConnectionReceiver {
                             if data[0] == 255 {
                                 match bincode::deserialize::<UdpTransportMsg>(&data[1..len]) {
                                     Ok(UdpTransportMsg::ConnectResponse(_, _)) => {
-                                        self.socket
-                                            .send(&build_control_msg(&UdpTransportMsg::ConnectResponseAck(true)))
-                                            .await
-                                            .print_error("Should send ConnectResponseAck");
+                                        self.socket.send(&self.handshake_ack).await.print_error("Should send ConnectResponseAck");
                                     }
                                     Ok(UdpTransportMsg::Ping(ts)) => {
                                         log::debug!("[UdpClientConnectionReceiver {}] on ping received {}", self.remote_node_id, ts);
@@ -266,14 +283,10 @@ impl ConnectionReceiver for UdpClientConnectionReceiver {
                                     Ok(UdpTransportMsg::Pong(ts)) => {
                                         self.last_pong_ts = self.timer.now_ms();
                                         log::debug!("[UdpClientConnectionReceiver {}] on pong received {} ms", self.remote_node_id, self.last_pong_ts - ts);
-                                        //TODO est speed and over_use state
-                                        break Ok(ConnectionEvent::Stats(ConnectionStats {
-                                            rtt_ms: (self.timer.now_ms() - ts) as u16,
-                                            sending_kbps: 0,
-                                            send_est_kbps: 0,
-                                            loss_percent: 0,
-                                            over_use: false,
-                                        }));
+                                        break Ok(ConnectionEvent::Stats(self.stats.on_rtt(self.last_pong_ts, (self.last_pong_ts - ts) as u16, self.remote_loss_percent)));
+                                    }
+                                    Ok(UdpTransportMsg::LossReport(loss_percent)) => {
+                                        self.remote_loss_percent = loss_percent;
                                     }
                                     Ok(UdpTransportMsg::Close) => {
                                         self.closed = true;
@@ -284,9 +297,11 @@ impl ConnectionReceiver for UdpClientConnectionReceiver {
                                     _ => {}
                                 }
                             } else {
-                                if TransportMsg::is_secure_header(data[0]) {
-                                    let mut snow_state = self.snow_state.lock();
-                                    if let Ok(len) = snow_state.read_message(&data[1..len], &mut self.snow_buf) {
+                                match self.cipher.decode(&data[..len], &mut self.snow_buf) {
+                                    Ok((seq, len)) => {
+                                        if let Some(seq) = seq {
+                                            self.loss.on_seq(seq);
+                                        }
                                         //TODO reduce to_vec memory copy
                                         match TransportMsg::from_vec(self.snow_buf[0..len].to_vec()) {
                                             Ok(msg) => break Ok(ConnectionEvent::Msg(msg)),
@@ -295,13 +310,8 @@ impl ConnectionReceiver for UdpClientConnectionReceiver {
                                             }
                                         }
                                     }
-                                } else {
-                                    //TODO reduce to_vec memory copy
-                                    match TransportMsg::from_vec(data[0..len].to_vec()) {
-                                        Ok(msg) => break Ok(ConnectionEvent::Msg(msg)),
-                                        Err(e) => {
-                                            log::error!("[UdpClientConnectionReceiver {}] wrong msg format {:?}", self.remote_node_id, e);
-                                        }
+                                    Err(e) => {
+                                        log::debug!("[UdpClientConnectionReceiver {}] drop data packet {:?}", self.remote_node_id, e);
                                     }
                                 }
                             }
@@ -1,14 +1,19 @@
 use std::{net::SocketAddr, sync::atomic::AtomicBool};
 
 use atm0s_sdn_identity::{ConnId, NodeAddr, NodeId};
-use atm0s_sdn_network::{msg::TransportMsg, transport::ConnectionSender};
+use atm0s_sdn_network::{
+    msg::TransportMsg,
+    transport::{ConnectionSender, TrafficCounter},
+};
 use atm0s_sdn_utils::error_handle::ErrorUtils;
 use parking_lot::Mutex;
-use snow::TransportState;
 use std::net::UdpSocket;
 use std::sync::Arc;
 
-use crate::msg::{build_control_msg, UdpTransportMsg};
+use crate::{
+    cipher::SnowCipher,
+    msg::{build_control_msg, UdpTransportMsg},
+};
 
 pub struct UdpServerConnectionSender {
     remote_node_id: NodeId,
@@ -18,7 +23,8 @@ pub struct UdpServerConnectionSender {
     socket_dest: SocketAddr,
     close_state: Arc<AtomicBool>,
     close_notify: Arc<async_notify::Notify>,
-    snow_state: Arc<Mutex<TransportState>>,
+    cipher: Arc<SnowCipher>,
+    sent: Arc<TrafficCounter>,
     tmp_buf: Arc<Mutex<[u8; 1500]>>,
 }
 
@@ -31,7 +37,8 @@ impl UdpServerConnectionSender {
         socket_dest: SocketAddr,
         close_state: Arc<AtomicBool>,
         close_notify: Arc<async_notify::Notify>,
-        snow_state: Arc<Mutex<TransportState>>,
+        cipher: Arc<SnowCipher>,
+        sent: Arc<TrafficCounter>,
     ) -> Self {
         log::info!("[UdpServerConnectionSender {}/{}] new", remote_node_id, conn_id);
         Self {
@@ -42,7 +49,8 @@ impl UdpServerConnectionSender {
             socket_dest,
             close_state,
             close_notify,
-            snow_state,
+            cipher,
+            sent,
             tmp_buf: Arc::new(Mutex::new([0u8; 1500])),
         }
     }
@@ -62,14 +70,13 @@ impl ConnectionSender for UdpServerConnectionSender {
     }
 
     fn send(&self, msg: TransportMsg) {
-        if msg.header.secure {
-            let mut tmp_buf = self.tmp_buf.lock();
-            tmp_buf[0] = msg.get_buf()[0];
-            let snow_len = self.snow_state.lock().write_message(msg.get_buf(), &mut tmp_buf[1..]).expect("Snow write error");
-            self.socket.send_to(&tmp_buf[..(1 + snow_len)], self.socket_dest).print_error("Send error");
-        } else {
-            let buf = msg.take();
-            self.socket.send_to(&buf, self.socket_dest).print_error("Send error");
+        let mut tmp_buf = self.tmp_buf.lock();
+        match self.cipher.encode(msg.get_buf(), tmp_buf.as_mut_slice()) {
+            Ok(len) => {
+                self.sent.on_packet(len);
+                self.socket.send_to(&tmp_buf[..len], self.socket_dest).print_error("Send error");
+            }
+            Err(e) => log::error!("[UdpServerConnectionSender {}/{}] encode error {:?}", self.remote_node_id, self.conn_id, e),
         }
     }
 
@@ -102,7 +109,8 @@ pub struct UdpClientConnectionSender {
     socket: Arc<UdpSocket>,
     close_state: Arc<AtomicBool>,
     close_notify: Arc<async_notify::Notify>,
-    snow_state: Arc<Mutex<TransportState>>,
+    cipher: Arc<SnowCipher>,
+    sent: Arc<TrafficCounter>,
     tmp_buf: Arc<Mutex<[u8; 1500]>>,
 }
 
@@ -114,7 +122,8 @@ impl UdpClientConnectionSender {
         socket: Arc<UdpSocket>,
         close_state: Arc<AtomicBool>,
         close_notify: Arc<async_notify::Notify>,
-        snow_state: Arc<Mutex<TransportState>>,
+        cipher: Arc<SnowCipher>,
+        sent: Arc<TrafficCounter>,
     ) -> Self {
         log::info!("[UdpClientConnectionSender {}/{}] new", remote_node_id, conn_id);
         Self {
@@ -124,7 +133,8 @@ impl UdpClientConnectionSender {
             socket,
             close_state,
             close_notify,
-            snow_state,
+            cipher,
+            sent,
             tmp_buf: Arc::new(Mutex::new([0u8; 1500])),
         }
     }
@@ -144,14 +154,13 @@ impl ConnectionSender for UdpClientConnectionSender {
     }
 
     fn send(&self, msg: TransportMsg) {
-        if msg.header.secure {
-            let mut tmp_buf = self.tmp_buf.lock();
-            tmp_buf[0] = msg.get_buf()[0];
-            let snow_len = self.snow_state.lock().write_message(msg.get_buf(), &mut tmp_buf[1..]).expect("Snow write error");
-            self.socket.send(&tmp_buf[..(1 + snow_len)]).print_error("Send error");
-        } else {
-            let buf = msg.take();
-            self.socket.send(&buf).print_error("Send error");
+        let mut tmp_buf = self.tmp_buf.lock();
+        match self.cipher.encode(msg.get_buf(), tmp_buf.as_mut_slice()) {
+            Ok(len) => {
+                self.sent.on_packet(len);
+                self.socket.send(&tmp_buf[..len]).print_error("Send error");
+            }
+            Err(e) => log::error!("[UdpClientConnectionSender {}/{}] encode error {:?}", self.remote_node_id, self.conn_id, e),
         }
     }
 
@@ -9,11 +9,10 @@ use async_std::channel::{Receiver, Sender};
 use atm0s_sdn_identity::{ConnId, NodeAddr, NodeAddrBuilder, Protocol};
 use atm0s_sdn_network::{
     secure::DataSecure,
-    transport::{Transport, TransportConnector, TransportEvent},
+    transport::{TrafficCounter, Transport, TransportConnector, TransportEvent},
 };
 use atm0s_sdn_utils::{error_handle::ErrorUtils, SystemTimer, Timer};
 use local_ip_address::local_ip;
-use parking_lot::Mutex;
 use std::net::UdpSocket;
 
 use crate::{connector::UdpConnector, handshake::incoming_handshake, receiver::UdpServerConnectionReceiver, sender::UdpServerConnectionSender, UDP_PROTOCOL_ID};
@@ -87,10 +86,11 @@ impl UdpTransport {
                         let secure_c = secure.clone();
                         async_std::task::spawn(async move {
                             match incoming_handshake(secure_c.clone(), node_id, &tx, &msg_rx, conn_id, addr, &async_socket).await {
-                                Ok((remote_node_id, remote_node_addr, snow_state)) => {
+                                Ok((remote_node_id, remote_node_addr, cipher)) => {
                                     let close_state = Arc::new(std::sync::atomic::AtomicBool::new(false));
                                     let close_notify = Arc::new(async_notify::Notify::new());
-                                    let snow_state = Arc::new(Mutex::new(snow_state));
+                                    let cipher = Arc::new(cipher);
+                                    let sent = Arc::new(TrafficCounter::default());
                                     let sender = Arc::new(UdpServerConnectionSender::new(
                                         remote_node_id,
                                         remote_node_addr.clone(),
@@ -99,7 +99,8 @@ impl UdpTransport {
                                         addr,
                                         close_state.clone(),
                                         close_notify.clone(),
-                                        snow_state.clone(),
+                                        cipher.clone(),
+                                        sent.clone(),
                                     ));
                                     let receiver = Box::new(UdpServerConnectionReceiver::new(
                                         async_socket.clone(),
@@ -111,7 +112,8 @@ impl UdpTransport {
                                         timer.clone(),
                                         close_state,
                                         close_notify,
-                                        snow_state,
+                                        cipher,
+                                        sent,
                                     ));
                                     log::info!("[UdpTransport] on connection success handshake from {}", addr);
                                     tx.send(TransportEvent::Incoming(sender, receiver)).await.print_error("Should send incoming event");